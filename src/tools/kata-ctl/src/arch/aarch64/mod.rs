@@ -20,12 +20,20 @@ mod arch_specific {
     pub const ARCH_CPU_MODEL_FIELD: &str = "CPU architecture";
 
     // List of check functions
-    static CHECK_LIST: &[CheckItem] = &[CheckItem {
-        name: CheckType::Cpu,
-        descr: "This parameter performs the host check",
-        fp: check,
-        perm: PermissionType::NonPrivileged,
-    }];
+    static CHECK_LIST: &[CheckItem] = &[
+        CheckItem {
+            name: CheckType::Cpu,
+            descr: "This parameter performs the host check",
+            fp: check,
+            perm: PermissionType::NonPrivileged,
+        },
+        CheckItem {
+            name: CheckType::Cgroups,
+            descr: "This parameter performs the cgroup check",
+            fp: check::check_cgroups,
+            perm: PermissionType::NonPrivileged,
+        },
+    ];
 
     pub fn check(_args: &str) -> Result<()> {
         println!("INFO: check: aarch64");
@@ -70,6 +78,12 @@ mod arch_specific {
         Some(CHECK_LIST)
     }
 
+    // The virtualization support is not shown by the CPU flags, but by
+    // /dev/kvm.
+    pub fn get_cpu_features() -> Result<Vec<CpuFeature>> {
+        Ok(Vec::new())
+    }
+
     #[allow(dead_code)]
     // Guest protection is not supported on ARM64.
     pub fn available_guest_protection() -> Result<check::GuestProtection, check::ProtectionError> {
@@ -23,6 +23,10 @@ mod arch_specific {
         None
     }
 
+    pub fn get_cpu_features() -> Result<Vec<CpuFeature>> {
+        Ok(Vec::new())
+    }
+
     const PEF_SYS_FIRMWARE_DIR: &str = "/sys/firmware/ultravisor/";
 
     pub fn get_cpu_details() -> Result<(String, String)> {
@@ -20,6 +20,7 @@ mod arch_specific {
     const CPUINFO_DELIMITER: &str = "processor ";
     const CPUINFO_FEATURES_TAG: &str = "features";
     const CPU_FEATURES_REQ: &[&str] = &["sie"];
+    const CPU_FEATURES_EXAMINED: &[(&str, bool)] = &[("sie", true)];
 
     #[allow(dead_code)]
     pub const ARCH_CPU_VENDOR_FIELD: &str = check::GENERIC_CPU_VENDOR_FIELD;
@@ -60,17 +61,35 @@ mod arch_specific {
     }
 
     // List of check functions
-    static CHECK_LIST: &[CheckItem] = &[CheckItem {
-        name: CheckType::Cpu,
-        descr: "This parameter performs the cpu check",
-        fp: check,
-        perm: PermissionType::NonPrivileged,
-    }];
+    static CHECK_LIST: &[CheckItem] = &[
+        CheckItem {
+            name: CheckType::Cpu,
+            descr: "This parameter performs the cpu check",
+            fp: check,
+            perm: PermissionType::NonPrivileged,
+        },
+        CheckItem {
+            name: CheckType::Cgroups,
+            descr: "This parameter performs the cgroup check",
+            fp: check::check_cgroups,
+            perm: PermissionType::NonPrivileged,
+        },
+    ];
 
     pub fn get_checks() -> Option<&'static [CheckItem<'static>]> {
         Some(CHECK_LIST)
     }
 
+    pub fn get_cpu_features() -> Result<Vec<CpuFeature>> {
+        let cpu_info = check::get_single_cpu_info(check::PROC_CPUINFO, CPUINFO_DELIMITER)?;
+        let cpu_features = check::get_cpu_flags(&cpu_info, CPUINFO_FEATURES_TAG)?;
+
+        Ok(check::get_cpu_features(
+            &cpu_features,
+            CPU_FEATURES_EXAMINED,
+        ))
+    }
+
     #[allow(dead_code)]
     fn retrieve_cpu_facilities() -> Result<HashMap<i32, bool>> {
         let f = std::fs::File::open(check::PROC_CPUINFO)?;
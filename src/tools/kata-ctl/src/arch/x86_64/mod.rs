@@ -21,6 +21,26 @@ mod arch_specific {
     const CPUINFO_DELIMITER: &str = "\nprocessor";
     const CPUINFO_FLAGS_TAG: &str = "flags";
     const CPU_FLAGS_INTEL: &[&str] = &["lm", "sse4_1", "vmx"];
+    // Recent kernels list the VMX features on their own line.
+    const CPUINFO_VMX_FLAGS_TAG: &str = "vmx flags";
+    const CPU_VENDOR_AMD: &str = "AuthenticAMD";
+
+    // CPU flags examined by 'check all --cpu-features', with whether they
+    // are required.
+    const CPU_FEATURES_INTEL: &[(&str, bool)] = &[
+        ("vmx", true),
+        ("lm", true),
+        ("sse4_1", true),
+        ("ept", false),
+        ("vpid", false),
+        ("unrestricted_guest", false),
+    ];
+    const CPU_FEATURES_AMD: &[(&str, bool)] = &[
+        ("svm", true),
+        ("lm", true),
+        ("sse4_1", true),
+        ("npt", false),
+    ];
     const CPU_ATTRIBS_INTEL: &[&str] = &["GenuineIntel"];
     const VMM_FLAGS: &[&str] = &["hypervisor"];
 
@@ -41,6 +61,12 @@ mod arch_specific {
             fp: check_kernel_modules,
             perm: PermissionType::NonPrivileged,
         },
+        CheckItem {
+            name: CheckType::Cgroups,
+            descr: "This parameter performs the cgroup check",
+            fp: check::check_cgroups,
+            perm: PermissionType::NonPrivileged,
+        },
     ];
 
     static MODULE_LIST: &[KernelModule] = &[
@@ -114,6 +140,24 @@ mod arch_specific {
         utils::get_generic_cpu_details(check::PROC_CPUINFO)
     }
 
+    pub fn get_cpu_features() -> Result<Vec<CpuFeature>> {
+        let cpu_info = check::get_single_cpu_info(check::PROC_CPUINFO, CPUINFO_DELIMITER)?;
+
+        let cpu_flags = format!(
+            "{} {}",
+            check::get_cpu_flags(&cpu_info, CPUINFO_FLAGS_TAG)?,
+            check::get_cpu_flags(&cpu_info, CPUINFO_VMX_FLAGS_TAG)?
+        );
+
+        let examined = if cpu_info.contains(CPU_VENDOR_AMD) {
+            CPU_FEATURES_AMD
+        } else {
+            CPU_FEATURES_INTEL
+        };
+
+        Ok(check::get_cpu_features(&cpu_flags, examined))
+    }
+
     pub const TDX_SYS_FIRMWARE_DIR: &str = "/sys/firmware/tdx_seam/";
     pub const TDX_CPU_FLAG: &str = "tdx";
     pub const SEV_KVM_PARAMETER_PATH: &str = "/sys/module/kvm_amd/parameters/sev";
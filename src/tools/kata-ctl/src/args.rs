@@ -3,7 +3,11 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use regex::Regex;
+use std::ffi::OsString;
+use std::str::FromStr;
 
 use thiserror::Error;
 
@@ -12,33 +16,351 @@ use thiserror::Error;
 pub struct KataCtlCli {
     #[clap(subcommand)]
     pub command: Commands,
+
+    /// Path to the configuration.toml to use instead of the default locations
+    #[clap(long, global = true, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Increase log verbosity (-v: warn, -vv: info, -vvv: debug, -vvvv: trace)
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Format of the log records enabled by --verbose
+    #[clap(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Output format; table and csv are used for lists, others being shown
+    /// as human by table and refused by csv
+    #[clap(short, long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    pub output: OutputFormat,
+
+    /// When to color the output; auto colors a terminal unless NO_COLOR is set
+    #[clap(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Run the commands needing root privileges without them, e.g. to test
+    /// them in a sandbox
+    #[clap(long, global = true)]
+    pub allow_unprivileged: bool,
+
+    /// Run the destructive commands without asking for a confirmation, which
+    /// they refuse to run without when not on a terminal
+    #[clap(short = 'y', long, global = true)]
+    pub assume_yes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Yaml,
+    Table,
+    Csv,
+}
+
+impl OutputFormat {
+    // The per-command --json flags are deprecated aliases of --output json.
+    pub fn with_json(self, json: bool) -> Self {
+        if json {
+            OutputFormat::Json
+        } else {
+            self
+        }
+    }
+
+    pub fn with_yaml(self, yaml: bool) -> Self {
+        if yaml {
+            OutputFormat::Yaml
+        } else {
+            self
+        }
+    }
+
+    // JSON and YAML are serialized from the command results.
+    pub fn is_structured(self) -> bool {
+        self == OutputFormat::Json || self == OutputFormat::Yaml
+    }
+}
+
+impl Commands {
+    // The commands outputting a list, which can be output as CSV.
+    pub fn supports_csv(&self) -> bool {
+        matches!(
+            self,
+            Commands::ListCommands(_)
+                | Commands::Metrics(_)
+                | Commands::Ps(_)
+                | Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::List(_),
+                    ..
+                })
+        )
+    }
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
+    /// Check the agent of a sandbox
+    Agent(AgentCommand),
+
+    /// Print the commands, flags and features of kata-ctl as JSON, for tools
+    /// wrapping it
+    Capabilities,
+
     /// Test if system can run Kata Containers
     Check(CheckArgument),
 
+    /// Reclaim the resources left behind by crashed sandboxes
+    Cleanup(CleanupArgument),
+
+    /// Print the possible values of an argument, for the completion scripts
+    #[command(hide = true)]
+    Complete(CompleteArgument),
+
+    /// Generate a shell completion script
+    Completions(CompletionsArgument),
+
+    /// Validate and compare runtime configuration files
+    Config(ConfigCommand),
+
     /// Directly assign a volume to Kata Containers to manage
     DirectVolume(DirectVolumeCommand),
 
+    /// Gather the checks, settings and sandboxes of the host in a report
+    Doctor(DoctorArgument),
+
+    /// Dump the state of a sandbox to a JSON file, for a later analysis
+    Dump(DumpArgument),
+
     /// Display settings
     Env(EnvArgument),
 
-    /// Enter into guest VM by debug console
+    /// Enter into guest VM by debug console, or run a command in it
     Exec(ExecArguments),
 
     /// Manage VM factory
-    Factory,
+    Factory(FactoryCommand),
+
+    /// Show the configuration a running sandbox was started with
+    Inspect(InspectArgument),
 
     /// Manage guest VM iptables
     Iptables(IptablesCommand),
 
+    /// List the available commands, for tools wrapping kata-ctl
+    #[command(hide = true)]
+    #[allow(clippy::enum_variant_names)]
+    ListCommands(ListCommandsArgument),
+
+    /// Show the shim, agent and hypervisor logs of a sandbox
+    Logs(LogsArgument),
+
     /// Gather metrics associated with infrastructure used to run a sandbox
     Metrics(MetricsCommand),
 
+    /// List the filesystems mounted in the guest VM of a sandbox
+    Mounts(MountsArgument),
+
+    /// List the sandboxes of the host
+    #[command(alias = "list")]
+    Ps(PsArgument),
+
     /// Display version details
-    Version,
+    Version(VersionArgument),
+}
+
+#[derive(Debug, Args)]
+pub struct VersionArgument {
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+    /// Report whether a newer official release is available, exiting with
+    /// status 9 if so
+    #[arg(long)]
+    pub check_update: bool,
+    /// Reuse the cached release metadata if it is younger than this
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 3600,
+        requires = "check_update"
+    )]
+    pub cache_ttl: u64,
+    /// Always download the release metadata, ignoring the cache
+    #[arg(long, requires = "check_update")]
+    pub no_cache: bool,
+    /// Proxy to download the release metadata through, overriding the
+    /// HTTPS_PROXY, HTTP_PROXY and NO_PROXY environment variables
+    #[arg(long, value_name = "URL", requires = "check_update")]
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct AgentCommand {
+    #[clap(subcommand)]
+    pub agent_cmd: AgentSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AgentSubcommand {
+    /// Check that the agent of a sandbox is reachable and serving, exiting
+    /// with a non-zero code if not
+    Ping(AgentPingArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct AgentPingArgs {
+    /// pod sandbox ID, or a unique prefix of it.
+    pub sandbox_id: String,
+    /// Timeout in seconds of each request to the agent
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct CleanupArgument {
+    /// Only list the resources that would be reclaimed
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgument {
+    /// Also run the checks needing network access
+    #[arg(long)]
+    pub network: bool,
+    /// Write the parts of the report, the configuration and the logs of the
+    /// sandboxes to separate files of a bundle directory in this directory
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<String>,
+    /// Write the bundle to a tarball instead of a directory
+    #[arg(long, requires = "output_dir")]
+    pub archive: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DumpArgument {
+    /// pod sandbox ID, or a unique prefix of it.
+    pub sandbox_id: String,
+    /// File to write the dump to instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub file: Option<String>,
+    /// kata debug console vport same as configuration, default is 1026.
+    #[arg(short = 'p', long = "kata-debug-port", default_value_t = 1026)]
+    pub vport: u32,
+    /// Timeout in seconds of each request to the guest, a section of the
+    /// dump being null when it expires
+    #[arg(long, value_name = "SECONDS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgument {
+    /// pod sandbox ID, or a unique prefix of it.
+    pub sandbox_id: String,
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PsArgument {
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+    /// Also show the hypervisor version, kernel, memory, vCPUs and shim
+    /// management socket of the sandboxes
+    #[arg(short, long)]
+    pub wide: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LogsArgument {
+    /// pod sandbox ID.
+    pub sandbox_id: String,
+    /// Keep printing new log entries
+    #[arg(short, long)]
+    pub follow: bool,
+    /// Number of past log entries to show, all by default
+    #[arg(short = 'n', long)]
+    pub lines: Option<usize>,
+    /// Only show the entries logged at or after this time, an RFC3339
+    /// timestamp or a duration before now, e.g. -10m
+    #[arg(long, value_name = "TIME", allow_hyphen_values = true)]
+    pub since: Option<String>,
+    /// Only show the entries logged at or before this time, an RFC3339
+    /// timestamp or a duration before now, e.g. -10m
+    #[arg(
+        long,
+        value_name = "TIME",
+        allow_hyphen_values = true,
+        conflicts_with = "follow"
+    )]
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct MountsArgument {
+    /// pod sandbox ID, or a unique prefix of it.
+    pub sandbox_id: String,
+    /// kata debug console vport same as configuration, default is 1026.
+    #[arg(short = 'p', long = "kata-debug-port", default_value_t = 1026)]
+    pub vport: u32,
+    /// Connect to the debug console through this host Unix socket instead
+    /// of the kata debug console vport.
+    #[arg(long, value_name = "PATH", conflicts_with = "vport")]
+    pub socket: Option<String>,
+    /// Timeout in seconds for connecting to and reading from the debug
+    /// console, exiting with code 124 when it expires. Default is no timeout.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListCommandsArgument {
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CompleteArgument {
+    /// Argument to print the possible values of
+    #[arg(value_enum)]
+    pub value: CompleteValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompleteValue {
+    SandboxId,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgument {
+    /// Shell to generate the completion script for
+    #[arg(value_enum)]
+    pub shell: Shell,
 }
 
 #[derive(Debug, Args, Error)]
@@ -51,33 +373,314 @@ pub struct CheckArgument {
 #[derive(Debug, Subcommand)]
 pub enum CheckSubCommand {
     /// Run all checks
-    All,
+    ///
+    /// Exit codes: 0 if all checks passed, 2 if the hardware is not supported,
+    /// 3 if a dependency is missing, 4 if a check needs more privileges and 1
+    /// on any other error. When several checks fail, the exit code is the one
+    /// of the first failing check.
+    All(CheckAllArgs),
 
     /// Run all checks but excluding network checks.
-    NoNetworkChecks,
+    ///
+    /// Deprecated, use 'check all --skip-network' instead.
+    #[command(hide = true)]
+    NoNetworkChecks(CheckAllArgs),
 
     /// Only compare the current and latest available versions
-    CheckVersionOnly,
+    CheckVersionOnly(CheckReleaseArgs),
 
     /// List official release packages
-    OnlyListReleases,
+    OnlyListReleases(CheckReleaseArgs),
 
     /// List all official and pre-release packages
-    IncludeAllReleases,
+    IncludeAllReleases(CheckReleaseArgs),
 
     /// List all available checks
-    List,
+    List(CheckListArgs),
+
+    /// Run a single check, as named by the list subcommand
+    Run(CheckRunArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct CheckRunArgs {
+    /// Name of the check to run
+    pub name: String,
+    /// Also check the binary of this hypervisor, which must be configured,
+    /// before running the check
+    #[arg(long, value_name = "NAME", value_enum)]
+    pub hypervisor: Option<HypervisorName>,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckAllArgs {
+    /// Do not print anything, only set the exit code
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Format the check results as JSON (deprecated, use --output json)
+    #[arg(long, conflicts_with = "quiet")]
+    pub json: bool,
+    /// Do not run the network checks
+    #[arg(long, alias = "no-network-checks")]
+    pub skip_network: bool,
+    /// Run the network checks through this network interface, which must
+    /// be up, instead of the default route
+    #[arg(long, value_name = "NAME", conflicts_with = "skip_network")]
+    pub interface: Option<String>,
+    /// Seconds to wait for a connection of the network checks before they
+    /// fail
+    #[arg(long, value_name = "SECONDS", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub network_timeout: u64,
+    /// Reuse the results of the hardware checks while the CPU, kernel and
+    /// virtualization features are unchanged
+    #[arg(long)]
+    pub cache: bool,
+    /// Run the hardware checks again, updating the cached results
+    #[arg(long, requires = "cache")]
+    pub refresh: bool,
+    /// Report the CPU flags examined by the CPU check, and whether they are
+    /// present
+    #[arg(long)]
+    pub cpu_features: bool,
+    /// Run up to N independent checks at once, 0 for the number of CPUs
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub parallel: usize,
+    /// Stop at the first failing check, exiting with its failure code
+    #[arg(long, conflicts_with = "parallel")]
+    pub fail_fast: bool,
+    /// Also check the binary of this hypervisor, which must be configured
+    #[arg(long, value_name = "NAME", value_enum)]
+    pub hypervisor: Option<HypervisorName>,
+    /// Do not run the external checks
+    #[arg(long)]
+    pub no_external: bool,
+    /// Directory of the external checks, executables whose exit status is
+    /// the result of the check and whose last line of output may be a JSON
+    /// object with a "detail" string
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "/etc/kata-containers/checks.d",
+        conflicts_with = "no_external"
+    )]
+    pub external_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckReleaseArgs {
+    /// Reuse the cached release metadata if it is younger than this
+    #[arg(long, value_name = "SECONDS", default_value_t = 3600)]
+    pub cache_ttl: u64,
+    /// Always download the release metadata, ignoring the cache
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Proxy to download the release metadata through, overriding the
+    /// HTTPS_PROXY, HTTP_PROXY and NO_PROXY environment variables
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+    /// Seconds to wait for the connection to the release server and for its
+    /// response before failing
+    #[arg(long, value_name = "SECONDS", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub network_timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckListArgs {
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct EnvArgument {
-    /// Format output as JSON
+    /// Format output as JSON (deprecated, use --output json)
     #[arg(long)]
     pub json: bool,
-    /// File to write env output to
+    /// Format output as YAML (deprecated, use --output yaml)
+    #[arg(long, conflicts_with = "json")]
+    pub yaml: bool,
+    /// File to write env output to ("-" for stdout)
     #[arg(short = 'f', long = "file")]
     pub file: Option<String>,
+    /// Only show the given section, may be repeated
+    #[arg(
+        long = "filter",
+        value_name = "SECTION",
+        value_enum,
+        ignore_case = true
+    )]
+    pub filter: Vec<EnvSection>,
+    /// Only show the differences with a report saved with 'env --json'
+    #[arg(long, value_name = "FILE", conflicts_with = "file")]
+    pub compare: Option<String>,
+    /// Report on this hypervisor rather than the default one of the
+    /// configuration
+    #[arg(long, value_name = "NAME", value_enum)]
+    pub hypervisor: Option<HypervisorName>,
+    /// Mask the values which may be sensitive, for the output to be shared:
+    /// the settings named like a key, token or secret, the credentials of
+    /// the URLs and the home directories in the paths
+    #[arg(long, conflicts_with = "compare")]
+    pub redact: bool,
+    /// Also mask the matches of this regular expression, may be repeated
+    #[arg(long, value_name = "REGEX", requires = "redact", value_parser = Regex::new)]
+    pub redact_extra: Vec<Regex>,
+    /// Output the env information in the shape of this schema version,
+    /// MAJOR.MINOR, the older ones being deprecated. The current schema
+    /// version is 1.5
+    #[arg(long, value_name = "VERSION", conflicts_with = "compare")]
+    pub schema_version: Option<String>,
+    /// Only report the configuration, without probing the host or running
+    /// the hypervisor, the probed fields being null
+    #[arg(long, conflicts_with = "compare")]
+    pub no_probe: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EnvSection {
+    Kernel,
+    Meta,
+    Image,
+    Initrd,
+    Hypervisor,
+    Runtime,
+    Host,
+    Agent,
+    Hypervisors,
+}
+
+impl EnvSection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvSection::Kernel => "kernel",
+            EnvSection::Meta => "meta",
+            EnvSection::Image => "image",
+            EnvSection::Initrd => "initrd",
+            EnvSection::Hypervisor => "hypervisor",
+            EnvSection::Runtime => "runtime",
+            EnvSection::Host => "host",
+            EnvSection::Agent => "agent",
+            EnvSection::Hypervisors => "hypervisors",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HypervisorName {
+    Qemu,
+    #[value(alias = "clh")]
+    CloudHypervisor,
+    #[value(alias = "fc")]
+    Firecracker,
+    Dragonball,
+    Acrn,
+}
+
+impl HypervisorName {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HypervisorName::Qemu => "qemu",
+            HypervisorName::CloudHypervisor => "cloud-hypervisor",
+            HypervisorName::Firecracker => "firecracker",
+            HypervisorName::Dragonball => "dragonball",
+            HypervisorName::Acrn => "acrn",
+        }
+    }
+
+    // The names of the hypervisor sections of the configuration, which
+    // differ between the Go and the Rust runtimes.
+    pub fn config_names(&self) -> &'static [&'static str] {
+        match self {
+            HypervisorName::CloudHypervisor => &["cloud-hypervisor", "clh"],
+            HypervisorName::Firecracker => &["firecracker", "fc"],
+            HypervisorName::Qemu => &["qemu"],
+            HypervisorName::Dragonball => &["dragonball"],
+            HypervisorName::Acrn => &["acrn"],
+        }
+    }
 }
+#[derive(Debug, Args)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    pub config_cmd: ConfigSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigSubCommand {
+    /// Report all the problems of a configuration file, exiting with 5 if
+    /// any of them is an error
+    Validate(ConfigValidateArgs),
+
+    /// Show the settings that differ between two configuration files,
+    /// exiting with 8 if there are any
+    Diff(ConfigDiffArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigValidateArgs {
+    /// Path of the configuration file
+    pub path: String,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigDiffArgs {
+    /// Path of the old configuration file
+    pub old: String,
+    /// Path of the new configuration file
+    pub new: String,
+}
+
+#[derive(Debug, Args)]
+pub struct FactoryCommand {
+    #[clap(subcommand)]
+    pub factory_cmd: FactorySubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FactorySubCommand {
+    /// Query the status of the VM factory
+    Status(FactoryStatusArgs),
+
+    /// Initialize a VM factory based on the runtime configuration
+    Init,
+
+    /// Destroy the VM factory
+    Destroy,
+
+    /// Share a prebuilt VM template between hosts
+    Template(FactoryTemplateCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct FactoryStatusArgs {
+    /// Format the status as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FactoryTemplateCommand {
+    #[clap(subcommand)]
+    pub template_cmd: FactoryTemplateSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FactoryTemplateSubCommand {
+    /// Write the manifest of the VM template, with the checksums of its
+    /// files and guest assets
+    Export(FactoryTemplateArgs),
+
+    /// Verify the files of an exported VM template and activate it
+    Import(FactoryTemplateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct FactoryTemplateArgs {
+    /// The template manifest file
+    #[arg(value_name = "FILE")]
+    pub file: String,
+}
+
 #[derive(Debug, Args)]
 pub struct MetricsCommand {
     #[clap(subcommand)]
@@ -86,8 +689,67 @@ pub struct MetricsCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum MetricsSubCommand {
-    /// Arguments for metrics
-    MetricsArgs,
+    /// Collect CPU and memory metrics of the shim and hypervisor of a sandbox
+    Collect(MetricsCollectArgs),
+
+    /// List the collected metrics
+    List,
+
+    /// Show the change of the metrics of a sandbox between two samples
+    Diff(MetricsDiffArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct MetricsDiffArgs {
+    /// pod sandbox ID.
+    pub sandbox_id: String,
+    /// Seconds between the two samples, instead of waiting for Enter to be
+    /// pressed
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub duration: Option<u64>,
+    /// Output the deltas as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MetricsCollectArgs {
+    /// pod sandbox ID.
+    pub sandbox_id: String,
+    /// Seconds between samples
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: u64,
+    /// Number of samples to collect
+    #[arg(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub count: u64,
+    /// Output format, overriding --output; prometheus prints the last
+    /// sample only
+    #[arg(long, value_enum)]
+    pub format: Option<MetricsFormat>,
+    /// Output the samples as a JSON array (deprecated, use --output json)
+    #[arg(long, conflicts_with = "format")]
+    pub json: bool,
+    /// Show the latest sample on a cleared terminal, every interval and until
+    /// interrupted, instead of printing the samples one after the other
+    #[arg(short, long, conflicts_with_all = ["count", "json"])]
+    pub watch: bool,
+    /// With the JSON output, print each sample on its own line as soon as
+    /// it is collected, instead of an array of all the samples at the end
+    #[arg(long, conflicts_with = "watch")]
+    pub stream: bool,
+    /// Collect the samples even when --count times --interval is more than
+    /// an hour, up to a week
+    #[arg(long, conflicts_with = "watch")]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetricsFormat {
+    Human,
+    Json,
+    Yaml,
+    Csv,
+    Prometheus,
 }
 
 // #[derive(Parser, Debug)]
@@ -99,14 +761,157 @@ pub struct IptablesCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum IpTablesArguments {
-    /// Configure iptables
-    Metrics,
+    /// Get iptables from the guest VM
+    Get(IpTablesGetArgs),
+
+    /// Set iptables in the guest VM
+    Set(IpTablesSetArgs),
+
+    /// Save the iptables of all the running sandboxes to a directory
+    Snapshot(IpTablesSnapshotArgs),
+
+    /// Set the iptables saved by 'iptables snapshot' in the running sandboxes
+    Restore(IpTablesRestoreArgs),
+
+    /// Remove all the rules and chains of the guest VM, back to the
+    /// default iptables the guest boots with
+    Reset(IpTablesResetArgs),
+}
+
+// Parses the arguments of the iptables subcommand on their own, for FromStr.
+#[derive(Debug, Parser)]
+#[clap(no_binary_name = true)]
+struct IpTablesArgumentsParser {
+    #[clap(subcommand)]
+    iptables: IpTablesArguments,
+}
+
+// Parse a string such as "get --sandbox-id ID --v6" or
+// "set --sandbox-id ID FILE" into fully populated arguments.
+impl FromStr for IpTablesArguments {
+    type Err = clap::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IpTablesArgumentsParser::try_parse_from(s.split_whitespace()).map(|p| p.iptables)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IpTablesTable {
+    Filter,
+    Nat,
+    Mangle,
+}
+
+impl IpTablesTable {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpTablesTable::Filter => "filter",
+            IpTablesTable::Nat => "nat",
+            IpTablesTable::Mangle => "mangle",
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct IpTablesGetArgs {
+    /// pod sandbox ID, or a unique prefix of it. Repeat it, or give a comma
+    /// separated list, to get the iptables of several sandboxes.
+    #[arg(long, alias = "sand-box", required = true, value_delimiter = ',')]
+    pub sandbox_id: Vec<String>,
+    /// Get ip6tables instead of iptables
+    #[arg(long)]
+    pub v6: bool,
+    /// Get both iptables and ip6tables, one after the other
+    #[arg(long, conflicts_with_all = ["v6", "diff"])]
+    pub both: bool,
+    /// Table to get
+    #[arg(long, value_enum, default_value_t = IpTablesTable::Filter)]
+    pub table: IpTablesTable,
+    /// File to write the iptables to instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub file: Option<String>,
+    /// Print the differences with a baseline file instead of the iptables,
+    /// exiting with 8 if there are any. Packet counters are ignored.
+    #[arg(long, value_name = "FILE", conflicts_with = "file")]
+    pub diff: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct IpTablesSetArgs {
+    /// pod sandbox ID, or a unique prefix of it.
+    #[arg(long, alias = "sand-box", required_unless_present = "check")]
+    pub sandbox_id: Option<String>,
+    /// Set ip6tables instead of iptables
+    #[arg(long)]
+    pub v6: bool,
+    /// Table to set, other tables in the file are ignored
+    #[arg(long, value_enum, default_value_t = IpTablesTable::Filter)]
+    pub table: IpTablesTable,
+    /// File containing the iptables to set
+    #[arg(required_unless_present = "stdin")]
+    pub file: Option<String>,
+    /// Read the iptables to set from stdin instead of a file
+    #[arg(long, alias = "from-stdin", conflicts_with = "file")]
+    pub stdin: bool,
+    /// Only check the syntax of the file, without contacting the guest
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct IpTablesSnapshotArgs {
+    /// Directory to write a file per sandbox to, named after the sandbox ID
+    #[arg(long, value_name = "DIR")]
+    pub dir: String,
+    /// Save ip6tables instead of iptables, to <sandbox ID>.v6 files
+    #[arg(long)]
+    pub v6: bool,
+    /// Save both iptables and ip6tables
+    #[arg(long, conflicts_with = "v6")]
+    pub both: bool,
+    /// Table to save
+    #[arg(long, value_enum, default_value_t = IpTablesTable::Filter)]
+    pub table: IpTablesTable,
+}
+
+#[derive(Debug, Args)]
+pub struct IpTablesRestoreArgs {
+    /// Directory written by 'iptables snapshot'
+    #[arg(long, value_name = "DIR")]
+    pub dir: String,
+    /// Restore ip6tables instead of iptables, from <sandbox ID>.v6 files
+    #[arg(long)]
+    pub v6: bool,
+    /// Restore both iptables and ip6tables
+    #[arg(long, conflicts_with = "v6")]
+    pub both: bool,
+    /// Table to restore
+    #[arg(long, value_enum, default_value_t = IpTablesTable::Filter)]
+    pub table: IpTablesTable,
+}
+
+#[derive(Debug, Args)]
+pub struct IpTablesResetArgs {
+    /// pod sandbox ID, or a unique prefix of it.
+    #[arg(long, alias = "sand-box")]
+    pub sandbox_id: String,
+    /// Reset ip6tables instead of iptables
+    #[arg(long)]
+    pub v6: bool,
+    /// Remove the rules without asking, same as --assume-yes
+    #[arg(long)]
+    pub confirm: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct DirectVolumeCommand {
     #[clap(subcommand)]
     pub directvol_cmd: DirectVolSubcommand,
+    /// Seconds to wait for the volumes being changed by another kata-ctl,
+    /// exiting with code 124 when they still are
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 10)]
+    pub lock_timeout: u64,
 }
 
 #[derive(Debug, Subcommand)]
@@ -122,35 +927,825 @@ pub enum DirectVolSubcommand {
 
     /// Resize a direct assigned block volume
     Resize(DirectVolResizeArgs),
+
+    /// List the direct assigned volumes
+    List(DirectVolListArgs),
 }
 
 #[derive(Debug, Args)]
 pub struct DirectVolAddArgs {
     pub volume_path: String,
-    pub mount_info: String,
+    #[arg(required_unless_present = "mount_info_file")]
+    pub mount_info: Option<String>,
+    /// Read the mount info from a file instead of the command line
+    #[arg(long, value_name = "PATH", conflicts_with = "mount_info")]
+    pub mount_info_file: Option<String>,
+    /// Only validate the mount info, without adding the volume
+    #[arg(long)]
+    pub validate_only: bool,
+    /// Show what would be done, without doing it
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Wait for up to this many seconds for the device of the volume to be
+    /// attached and mounted in the guest, exiting with code 124 when it is
+    /// not. The volume is added either way.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["validate_only", "dry_run"]
+    )]
+    pub wait: Option<u64>,
 }
 
 #[derive(Debug, Args)]
 pub struct DirectVolRemoveArgs {
-    pub volume_path: String,
+    #[arg(required_unless_present = "all")]
+    pub volume_path: Option<String>,
+    /// Remove all the direct assigned volumes
+    #[arg(long, conflicts_with = "volume_path")]
+    pub all: bool,
+    /// Show what would be done, without doing it
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct DirectVolStatsArgs {
     pub volume_path: String,
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+    /// Show the sizes in IEC units instead of bytes, ignored by the JSON and
+    /// YAML outputs
+    #[arg(long)]
+    pub human: bool,
+    /// Query the stats again every interval until interrupted, on a cleared
+    /// terminal, or as one JSON object per line with the JSON output
+    #[arg(short, long)]
+    pub watch: bool,
+    /// Seconds between two queries of --watch
+    #[arg(
+        short,
+        long,
+        value_name = "SECONDS",
+        default_value_t = 2,
+        requires = "watch",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub interval: u64,
+    /// kata debug console vport used to find the guest device of the
+    /// volume, default is 1026.
+    #[arg(short = 'p', long = "kata-debug-port", default_value_t = 1026)]
+    pub vport: u32,
+    #[command(flatten)]
+    pub retry: RetryArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DirectVolListArgs {
+    /// Format output as JSON (deprecated, use --output json)
+    #[arg(long)]
+    pub json: bool,
+    /// Also find the guest devices of the volumes used by a sandbox, through
+    /// the debug console of the sandbox.
+    #[arg(long)]
+    pub guest_devices: bool,
+    /// kata debug console vport used to find the guest devices of the
+    /// volumes, default is 1026.
+    #[arg(
+        short = 'p',
+        long = "kata-debug-port",
+        default_value_t = 1026,
+        requires = "guest_devices"
+    )]
+    pub vport: u32,
 }
 
 #[derive(Debug, Args)]
 pub struct DirectVolResizeArgs {
     pub volume_path: String,
+    /// New size, in bytes or with a unit suffix (K, M, G, T, Ki, Mi, Gi, Ti)
+    #[arg(value_parser = crate::utils::parse_volume_size)]
     pub resize_size: u64,
+    /// Also grow the filesystem of the volume in the guest, which must be
+    /// ext2, ext3, ext4 or xfs
+    #[arg(long)]
+    pub fs_grow: bool,
+    /// kata debug console vport used to grow the filesystem, default is 1026.
+    #[arg(
+        short = 'p',
+        long = "kata-debug-port",
+        default_value_t = 1026,
+        requires = "fs_grow"
+    )]
+    pub vport: u32,
+    /// Show what would be done, without doing it
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub retry: RetryArgs,
+}
+
+// The requests to the runtime are retried while its shim is starting.
+#[derive(Debug, Args)]
+pub struct RetryArgs {
+    /// Number of times a request to the runtime is retried when it cannot be
+    /// reached
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+    /// Milliseconds before the first retry, doubled for each of the next ones
+    #[arg(long, value_name = "MS", default_value_t = 200)]
+    pub retry_interval: u64,
 }
 
 #[derive(Debug, Args)]
 pub struct ExecArguments {
-    /// pod sandbox ID.
+    /// pod sandbox ID, or a unique prefix of it.
     pub sandbox_id: String,
     #[clap(short = 'p', long = "kata-debug-port", default_value_t = 1026)]
     /// kata debug console vport same as configuration, default is 1026.
     pub vport: u32,
+    /// Connect to the debug console through this host Unix socket instead
+    /// of the kata debug console vport.
+    #[arg(long, value_name = "PATH", conflicts_with = "vport")]
+    pub socket: Option<String>,
+    /// Timeout in seconds for connecting to and reading from the debug
+    /// console, exiting with code 124 when it expires. It is an idle
+    /// timeout: the interactive console, and a command, run for as long as
+    /// there is input or output more often. Default is no timeout.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: Option<u64>,
+    /// Print the exit code, standard output and standard error of the
+    /// command as JSON, ignored by the interactive console.
+    #[arg(long)]
+    pub json: bool,
+    /// Encoding of the standard output in the JSON result, base64 preserving
+    /// binary output.
+    #[arg(long, value_enum, value_name = "ENCODING", default_value_t = ExecStdoutEncoding::Raw)]
+    pub stdout_encoding: ExecStdoutEncoding,
+    /// Set an environment variable of the command, or of the interactive
+    /// console, as KEY=VALUE. May be repeated.
+    #[arg(short, long = "env", value_name = "KEY=VALUE", value_parser = crate::utils::parse_env_var)]
+    pub env: Vec<(String, String)>,
+    /// Run the command, or start the interactive console, in this guest
+    /// directory.
+    #[arg(short, long, value_name = "PATH")]
+    pub workdir: Option<String>,
+    /// Number of rows of the terminal of the interactive console, instead
+    /// of the number of rows of the host terminal.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), conflicts_with = "command")]
+    pub rows: Option<u16>,
+    /// Number of columns of the terminal of the interactive console, instead
+    /// of the number of columns of the host terminal.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), conflicts_with = "command")]
+    pub cols: Option<u16>,
+    /// Command to run in the guest instead of starting an interactive console.
+    #[arg(last = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExecStdoutEncoding {
+    Raw,
+    Base64,
+}
+
+// The -o/--output of 'iptables get' was the file to write the iptables to
+// before it became the global output format. Given a value which is not an
+// output format, it is taken as the file, rewritten to --file with a
+// warning, as the global option cannot have an alias of its own there.
+pub fn rewrite_deprecated_args(mut args: Vec<OsString>) -> (Vec<OsString>, Option<String>) {
+    let position = |name: &str, from: usize| {
+        args.iter()
+            .skip(from)
+            .position(|arg| arg == name)
+            .map(|i| i + from)
+    };
+    let get = match position("iptables", 1).and_then(|i| position("get", i + 1)) {
+        Some(get) => get,
+        None => return (args, None),
+    };
+    let is_format = |value: &str| OutputFormat::from_str(value, false).is_ok();
+
+    let mut warning = None;
+    let mut i = get + 1;
+    while i < args.len() {
+        let arg = args[i].to_str().unwrap_or_default().to_string();
+        if arg == "--" {
+            break;
+        }
+
+        let file = match arg.as_str() {
+            "-o" | "--output" => {
+                // The value is not an option of its own.
+                i += 1;
+                match args
+                    .get(i)
+                    .and_then(|value| value.to_str())
+                    .map(str::to_string)
+                {
+                    Some(value) if !is_format(&value) => {
+                        args[i - 1] = OsString::from("--file");
+                        Some(value)
+                    }
+                    _ => None,
+                }
+            }
+            _ => arg
+                .strip_prefix("--output=")
+                .or_else(|| arg.strip_prefix("-o").filter(|value| !value.is_empty()))
+                .filter(|value| !is_format(value))
+                .map(|value| {
+                    args[i] = OsString::from(format!("--file={}", value));
+                    value.to_string()
+                }),
+        };
+        if let Some(file) = file {
+            warning = Some(format!(
+                "WARNING: 'iptables get --output {}' is deprecated, use 'iptables get --file {}' instead",
+                file, file
+            ));
+        }
+        i += 1;
+    }
+
+    (args, warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_deprecated_args() {
+        let rewrite = |cmdline: &str| {
+            let args = cmdline.split_whitespace().map(OsString::from).collect();
+            let (args, warning) = rewrite_deprecated_args(args);
+            let args: Vec<String> = args
+                .into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect();
+            (args.join(" "), warning.is_some())
+        };
+
+        for (cmdline, expected, warned) in [
+            (
+                "kata-ctl iptables get --sand-box sb1 --output /tmp/ipt",
+                "kata-ctl iptables get --sand-box sb1 --file /tmp/ipt",
+                true,
+            ),
+            (
+                "kata-ctl iptables get --sand-box sb1 -o ipt.txt",
+                "kata-ctl iptables get --sand-box sb1 --file ipt.txt",
+                true,
+            ),
+            (
+                "kata-ctl iptables get --sand-box sb1 --output=/tmp/ipt",
+                "kata-ctl iptables get --sand-box sb1 --file=/tmp/ipt",
+                true,
+            ),
+            (
+                "kata-ctl iptables get --sand-box sb1 -o/tmp/ipt",
+                "kata-ctl iptables get --sand-box sb1 --file=/tmp/ipt",
+                true,
+            ),
+            // the global output format
+            (
+                "kata-ctl iptables get --sand-box sb1 -o json",
+                "kata-ctl iptables get --sand-box sb1 -o json",
+                false,
+            ),
+            (
+                "kata-ctl -o yaml iptables get --sand-box sb1 --output=json",
+                "kata-ctl -o yaml iptables get --sand-box sb1 --output=json",
+                false,
+            ),
+            // only the output of iptables get was a file
+            (
+                "kata-ctl -o /tmp/ipt iptables get --sand-box sb1",
+                "kata-ctl -o /tmp/ipt iptables get --sand-box sb1",
+                false,
+            ),
+            (
+                "kata-ctl iptables set --sand-box sb1 -o x",
+                "kata-ctl iptables set --sand-box sb1 -o x",
+                false,
+            ),
+        ] {
+            assert_eq!(
+                rewrite(cmdline),
+                (expected.to_string(), warned),
+                "{}",
+                cmdline
+            );
+        }
+
+        let (args, _) = rewrite_deprecated_args(
+            "kata-ctl iptables get --sand-box sb1 --output /tmp/ipt"
+                .split_whitespace()
+                .map(OsString::from)
+                .collect(),
+        );
+        match KataCtlCli::try_parse_from(args).unwrap().command {
+            Commands::Iptables(IptablesCommand {
+                iptables: IpTablesArguments::Get(args),
+            }) => assert_eq!(args.file.as_deref(), Some("/tmp/ipt")),
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_iptables_arguments_from_str() {
+        match IpTablesArguments::from_str("get --sand-box sb1 --v6 --table nat").unwrap() {
+            IpTablesArguments::Get(args) => {
+                assert_eq!(args.sandbox_id, vec!["sb1"]);
+                assert!(args.v6);
+                assert_eq!(args.table, IpTablesTable::Nat);
+                assert_eq!(args.file, None);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        match IpTablesArguments::from_str("get --sand-box sb1,sb2 --sand-box sb3").unwrap() {
+            IpTablesArguments::Get(args) => {
+                assert_eq!(args.sandbox_id, vec!["sb1", "sb2", "sb3"]);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        match IpTablesArguments::from_str("set --sandbox-id sb2 rules.txt").unwrap() {
+            IpTablesArguments::Set(args) => {
+                assert_eq!(args.sandbox_id.as_deref(), Some("sb2"));
+                assert!(!args.v6);
+                assert_eq!(args.table, IpTablesTable::Filter);
+                assert_eq!(args.file.as_deref(), Some("rules.txt"));
+                assert!(!args.stdin);
+                assert!(!args.check);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        match IpTablesArguments::from_str("set --sand-box sb2 --stdin").unwrap() {
+            IpTablesArguments::Set(args) => {
+                assert_eq!(args.file, None);
+                assert!(args.stdin);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        // checking a file does not need a sandbox
+        match IpTablesArguments::from_str("set --check rules.txt").unwrap() {
+            IpTablesArguments::Set(args) => {
+                assert_eq!(args.sandbox_id, None);
+                assert!(args.check);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        match IpTablesArguments::from_str("restore --dir /backup --both").unwrap() {
+            IpTablesArguments::Restore(args) => {
+                assert_eq!(args.dir, "/backup");
+                assert!(args.both);
+                assert_eq!(args.table, IpTablesTable::Filter);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        match IpTablesArguments::from_str("reset --sand-box sb1 --v6 --confirm").unwrap() {
+            IpTablesArguments::Reset(args) => {
+                assert_eq!(args.sandbox_id, "sb1");
+                assert!(args.v6);
+                assert!(args.confirm);
+            }
+            args => panic!("unexpected arguments: {:?}", args),
+        }
+
+        let invalid = &[
+            "",
+            "get",
+            "get --v6",
+            "set --sand-box sb1",
+            "set rules.txt",
+            "set --sand-box sb1 --stdin rules.txt",
+            "get --sand-box sb1 --table raw",
+            "get --sand-box sb1 --both --v6",
+            "get --sand-box sb1 --both --diff rules.txt",
+            "list --sand-box sb1",
+            "snapshot",
+            "snapshot --dir /backup --both --v6",
+            "restore --dir /backup --sand-box sb1",
+            "reset --confirm",
+            "reset --sand-box sb1 --table nat",
+        ];
+        for (i, s) in invalid.iter().enumerate() {
+            assert!(
+                IpTablesArguments::from_str(s).is_err(),
+                "test[{}]: {:?}",
+                i,
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_all_skip_network() {
+        let tests = &[
+            ("kata-ctl check all", false),
+            ("kata-ctl check all --skip-network", true),
+            ("kata-ctl check all --no-network-checks --json", true),
+            // the deprecated command, handle_check skips the network checks
+            ("kata-ctl check no-network-checks", false),
+        ];
+
+        for (i, (cmdline, skip_network)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, cmdline);
+            let cli = KataCtlCli::try_parse_from(cmdline.split_whitespace()).unwrap();
+            match cli.command {
+                Commands::Check(CheckArgument {
+                    command: CheckSubCommand::All(args),
+                })
+                | Commands::Check(CheckArgument {
+                    command: CheckSubCommand::NoNetworkChecks(args),
+                    ..
+                }) => assert_eq!(args.skip_network, *skip_network, "{}", msg),
+                command => panic!("{}: unexpected command: {:?}", msg, command),
+            }
+        }
+
+        assert!(
+            KataCtlCli::try_parse_from(["kata-ctl", "check", "all", "--cache", "--refresh"])
+                .is_ok()
+        );
+        // there is nothing to refresh without the cache
+        assert!(KataCtlCli::try_parse_from(["kata-ctl", "check", "all", "--refresh"]).is_err());
+    }
+
+    #[test]
+    fn test_check_all_fail_fast() {
+        let cli = KataCtlCli::try_parse_from(["kata-ctl", "check", "all", "--fail-fast"]).unwrap();
+        match cli.command {
+            Commands::Check(CheckArgument {
+                command: CheckSubCommand::All(args),
+            }) => assert!(args.fail_fast && args.parallel == 1),
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        let cli = KataCtlCli::try_parse_from(["kata-ctl", "check", "all"]).unwrap();
+        match cli.command {
+            Commands::Check(CheckArgument {
+                command: CheckSubCommand::All(args),
+            }) => assert!(!args.fail_fast),
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        // the checks run at once cannot stop at the first failure
+        assert!(KataCtlCli::try_parse_from([
+            "kata-ctl",
+            "check",
+            "all",
+            "--fail-fast",
+            "--parallel",
+            "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_hypervisor_name() {
+        let tests = &[
+            ("kata-ctl env", None),
+            ("kata-ctl env --hypervisor qemu", Some(HypervisorName::Qemu)),
+            (
+                "kata-ctl env --hypervisor clh",
+                Some(HypervisorName::CloudHypervisor),
+            ),
+            (
+                "kata-ctl env --hypervisor cloud-hypervisor",
+                Some(HypervisorName::CloudHypervisor),
+            ),
+            (
+                "kata-ctl env --hypervisor fc",
+                Some(HypervisorName::Firecracker),
+            ),
+        ];
+
+        for (i, (cmdline, hypervisor)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, cmdline);
+            let cli = KataCtlCli::try_parse_from(cmdline.split_whitespace()).unwrap();
+            match cli.command {
+                Commands::Env(args) => assert_eq!(args.hypervisor, *hypervisor, "{}", msg),
+                command => panic!("{}: unexpected command: {:?}", msg, command),
+            }
+        }
+
+        let cli = KataCtlCli::try_parse_from([
+            "kata-ctl",
+            "check",
+            "run",
+            "cpu",
+            "--hypervisor",
+            "dragonball",
+        ]);
+        match cli.unwrap().command {
+            Commands::Check(CheckArgument {
+                command: CheckSubCommand::Run(args),
+            }) => assert_eq!(args.hypervisor, Some(HypervisorName::Dragonball)),
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        // the error lists the valid names
+        let err = KataCtlCli::try_parse_from(["kata-ctl", "check", "all", "--hypervisor", "xen"])
+            .unwrap_err();
+        assert!(err.to_string().contains("cloud-hypervisor"), "{}", err);
+    }
+
+    #[test]
+    fn test_output_format() {
+        let tests = &[
+            ("kata-ctl ps", OutputFormat::Human, OutputFormat::Human),
+            (
+                "kata-ctl -o yaml ps",
+                OutputFormat::Yaml,
+                OutputFormat::Yaml,
+            ),
+            (
+                "kata-ctl ps --output table",
+                OutputFormat::Table,
+                OutputFormat::Table,
+            ),
+            // the deprecated --json overrides --output
+            (
+                "kata-ctl ps --json",
+                OutputFormat::Human,
+                OutputFormat::Json,
+            ),
+            (
+                "kata-ctl -o table ps --json",
+                OutputFormat::Table,
+                OutputFormat::Json,
+            ),
+        ];
+
+        for (i, (cmdline, output, format)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, cmdline);
+            let cli = KataCtlCli::try_parse_from(cmdline.split_whitespace()).unwrap();
+            assert_eq!(cli.output, *output, "{}", msg);
+            match cli.command {
+                Commands::Ps(args) => assert_eq!(output.with_json(args.json), *format, "{}", msg),
+                command => panic!("{}: unexpected command: {:?}", msg, command),
+            }
+        }
+
+        assert!(KataCtlCli::try_parse_from(["kata-ctl", "-o", "xml", "ps"]).is_err());
+        assert!(!OutputFormat::Csv.is_structured());
+
+        for (cmdline, supports_csv) in [
+            ("kata-ctl -o csv ps", true),
+            ("kata-ctl -o csv list-commands", true),
+            ("kata-ctl -o csv metrics list", true),
+            ("kata-ctl -o csv direct-volume list", true),
+            ("kata-ctl -o csv direct-volume stats /vol", false),
+            ("kata-ctl -o csv env", false),
+            ("kata-ctl -o csv inspect sb1", false),
+        ] {
+            let cli = KataCtlCli::try_parse_from(cmdline.split_whitespace()).unwrap();
+            assert_eq!(cli.output, OutputFormat::Csv, "{}", cmdline);
+            assert_eq!(cli.command.supports_csv(), supports_csv, "{}", cmdline);
+        }
+        assert!(OutputFormat::Yaml.is_structured());
+        assert!(!OutputFormat::Table.is_structured());
+    }
+
+    #[test]
+    fn test_exec_socket_or_vport() {
+        let cli = KataCtlCli::try_parse_from(["kata-ctl", "exec", "sb1", "--socket", "/tmp/s"]);
+        match cli.unwrap().command {
+            Commands::Exec(args) => {
+                assert_eq!(args.socket.as_deref(), Some("/tmp/s"));
+                assert_eq!(args.vport, 1026);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        let cli = KataCtlCli::try_parse_from([
+            "kata-ctl", "exec", "sb1", "--socket", "/tmp/s", "-p", "1027",
+        ]);
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn test_exec_window_size() {
+        let cli = KataCtlCli::try_parse_from(["kata-ctl", "exec", "sb1", "--rows", "40"]);
+        match cli.unwrap().command {
+            Commands::Exec(args) => {
+                assert_eq!(args.rows, Some(40));
+                assert_eq!(args.cols, None);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        for size in ["0", "65536", "x"] {
+            let cli = KataCtlCli::try_parse_from(["kata-ctl", "exec", "sb1", "--cols", size]);
+            assert!(cli.is_err(), "{}", size);
+        }
+
+        // A command has no terminal to be given a size.
+        let cli =
+            KataCtlCli::try_parse_from("kata-ctl exec sb1 --rows 40 -- uname".split_whitespace());
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn test_exec_env_workdir() {
+        let cli = KataCtlCli::try_parse_from(
+            "kata-ctl exec sb1 -e A=1 --env B=x=y -w /tmp -- env".split_whitespace(),
+        );
+        match cli.unwrap().command {
+            Commands::Exec(args) => {
+                assert_eq!(
+                    args.env,
+                    vec![
+                        ("A".to_string(), "1".to_string()),
+                        ("B".to_string(), "x=y".to_string())
+                    ]
+                );
+                assert_eq!(args.workdir.as_deref(), Some("/tmp"));
+                assert_eq!(args.command, vec!["env"]);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+
+        for var in ["A", "1A=x"] {
+            let cli = KataCtlCli::try_parse_from(["kata-ctl", "exec", "sb1", "--env", var]);
+            assert!(cli.is_err(), "{}", var);
+        }
+    }
+
+    #[test]
+    fn test_direct_volume_add_mount_info() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::Add(args),
+                    ..
+                }) => (args.mount_info, args.mount_info_file),
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(
+            parse("kata-ctl direct-volume add /vol {}").unwrap(),
+            (Some("{}".to_string()), None)
+        );
+        assert_eq!(
+            parse("kata-ctl direct-volume add /vol --mount-info-file vol.json").unwrap(),
+            (None, Some("vol.json".to_string()))
+        );
+
+        // exactly one source of the mount info
+        assert!(parse("kata-ctl direct-volume add /vol").is_err());
+        assert!(parse("kata-ctl direct-volume add /vol {} --mount-info-file vol.json").is_err());
+    }
+
+    #[test]
+    fn test_direct_volume_add_wait() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::Add(args),
+                    ..
+                }) => args.wait,
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(parse("kata-ctl direct-volume add /vol {}").unwrap(), None);
+        assert_eq!(
+            parse("kata-ctl direct-volume add /vol {} --wait 30").unwrap(),
+            Some(30)
+        );
+
+        assert!(parse("kata-ctl direct-volume add /vol {} --wait 0").is_err());
+        assert!(parse("kata-ctl direct-volume add /vol {} --wait 30 --dry-run").is_err());
+        assert!(parse("kata-ctl direct-volume add /vol {} --wait 30 --validate-only").is_err());
+    }
+
+    #[test]
+    fn test_direct_volume_stats_watch() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::Stats(args),
+                    ..
+                }) => (args.watch, args.interval),
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(
+            parse("kata-ctl direct-volume stats /vol").unwrap(),
+            (false, 2)
+        );
+        assert_eq!(
+            parse("kata-ctl direct-volume stats /vol --watch -i 5").unwrap(),
+            (true, 5)
+        );
+
+        assert!(parse("kata-ctl direct-volume stats /vol --interval 5").is_err());
+        assert!(parse("kata-ctl direct-volume stats /vol --watch --interval 0").is_err());
+    }
+
+    #[test]
+    fn test_direct_volume_remove_all() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::Remove(args),
+                    ..
+                }) => (args.volume_path, args.all),
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(
+            parse("kata-ctl direct-volume remove /vol").unwrap(),
+            (Some("/vol".to_string()), false)
+        );
+        assert_eq!(
+            parse("kata-ctl direct-volume remove --all --dry-run").unwrap(),
+            (None, true)
+        );
+
+        // either a volume or all of them
+        assert!(parse("kata-ctl direct-volume remove").is_err());
+        assert!(parse("kata-ctl direct-volume remove /vol --all").is_err());
+    }
+
+    #[test]
+    fn test_direct_volume_list() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::List(args),
+                    ..
+                }) => (args.guest_devices, args.vport),
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(parse("kata-ctl direct-volume list").unwrap(), (false, 1026));
+        assert_eq!(
+            parse("kata-ctl direct-volume list --guest-devices -p 2000").unwrap(),
+            (true, 2000)
+        );
+        // the vport is only used to find the guest devices
+        assert!(parse("kata-ctl direct-volume list -p 2000").is_err());
+    }
+
+    #[test]
+    fn test_direct_volume_lock_timeout() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(cmd) => cmd.lock_timeout,
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(parse("kata-ctl direct-volume list").unwrap(), 10);
+        // given before or after the subcommand
+        assert_eq!(
+            parse("kata-ctl direct-volume --lock-timeout 30 remove /vol").unwrap(),
+            30
+        );
+        assert_eq!(
+            parse("kata-ctl direct-volume remove /vol --lock-timeout 0").unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_direct_volume_resize_fs_grow() {
+        let parse = |cmdline: &str| {
+            KataCtlCli::try_parse_from(cmdline.split_whitespace()).map(|cli| match cli.command {
+                Commands::DirectVolume(DirectVolumeCommand {
+                    directvol_cmd: DirectVolSubcommand::Resize(args),
+                    ..
+                }) => (args.resize_size, args.fs_grow, args.vport),
+                command => panic!("unexpected command: {:?}", command),
+            })
+        };
+
+        assert_eq!(
+            parse("kata-ctl direct-volume resize /vol 1Gi").unwrap(),
+            (1 << 30, false, 1026)
+        );
+        assert_eq!(
+            parse("kata-ctl direct-volume resize /vol 1Gi --fs-grow -p 2000").unwrap(),
+            (1 << 30, true, 2000)
+        );
+
+        // the debug console is only used to grow the filesystem
+        assert!(parse("kata-ctl direct-volume resize /vol 1Gi -p 2000").is_err());
+    }
 }
@@ -0,0 +1,179 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Errors returned by the kata-ctl commands, and their exit codes. The exit
+// codes are the same for all the commands, so that scripts can rely on them:
+//
+//   1   any other failure
+//   2   the hardware cannot run Kata Containers
+//   3   a required dependency is missing
+//   4   the command needs more privileges
+//   5   the configuration or the command line is invalid
+//   6   a network request failed
+//   7   the sandbox, volume, check... does not exist
+//   8   differences were found ('iptables get --diff', 'config diff',
+//       'env --compare')
+//   9   a newer release is available ('version --check-update')
+//   124 the command timed out
+
+use std::io;
+use thiserror::Error;
+
+pub const EXIT_CODE_FAILURE: i32 = 1;
+pub const EXIT_CODE_HARDWARE_UNSUPPORTED: i32 = 2;
+pub const EXIT_CODE_MISSING_DEPENDENCY: i32 = 3;
+pub const EXIT_CODE_PERMISSION_DENIED: i32 = 4;
+pub const EXIT_CODE_CONFIG: i32 = 5;
+pub const EXIT_CODE_NETWORK: i32 = 6;
+pub const EXIT_CODE_NOT_FOUND: i32 = 7;
+pub const EXIT_CODE_DIFFERENCES: i32 = 8;
+pub const EXIT_CODE_UPDATE_AVAILABLE: i32 = 9;
+pub const EXIT_CODE_TIMEOUT: i32 = 124;
+
+#[derive(Debug, Error)]
+pub enum KataCtlError {
+    #[error("{0:#}")]
+    Runtime(anyhow::Error),
+    #[error("{0:#}")]
+    Unsupported(anyhow::Error),
+    #[error("{0:#}")]
+    MissingDependency(anyhow::Error),
+    #[error("{0:#}")]
+    Permission(anyhow::Error),
+    #[error("{0:#}")]
+    Config(anyhow::Error),
+    #[error("{0:#}")]
+    Network(anyhow::Error),
+    #[error("{0:#}")]
+    NotFound(anyhow::Error),
+    #[error("{0:#}")]
+    Timeout(anyhow::Error),
+    // The command already reported its result, only the exit code is left,
+    // e.g. the exit status of the guest command run by 'exec'.
+    #[error("exit status {0}")]
+    Status(i32),
+}
+
+impl KataCtlError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            KataCtlError::Runtime(_) => EXIT_CODE_FAILURE,
+            KataCtlError::Unsupported(_) => EXIT_CODE_HARDWARE_UNSUPPORTED,
+            KataCtlError::MissingDependency(_) => EXIT_CODE_MISSING_DEPENDENCY,
+            KataCtlError::Permission(_) => EXIT_CODE_PERMISSION_DENIED,
+            KataCtlError::Config(_) => EXIT_CODE_CONFIG,
+            KataCtlError::Network(_) => EXIT_CODE_NETWORK,
+            KataCtlError::NotFound(_) => EXIT_CODE_NOT_FOUND,
+            KataCtlError::Timeout(_) => EXIT_CODE_TIMEOUT,
+            KataCtlError::Status(code) => *code,
+        }
+    }
+
+    // The error to report to the user, None if there is nothing to report.
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        match self {
+            KataCtlError::Runtime(e)
+            | KataCtlError::Unsupported(e)
+            | KataCtlError::MissingDependency(e)
+            | KataCtlError::Permission(e)
+            | KataCtlError::Config(e)
+            | KataCtlError::Network(e)
+            | KataCtlError::NotFound(e)
+            | KataCtlError::Timeout(e) => Some(e),
+            KataCtlError::Status(_) => None,
+        }
+    }
+}
+
+// Errors that were not classified by the command are classified from their
+// causes.
+fn classify(e: &anyhow::Error) -> fn(anyhow::Error) -> KataCtlError {
+    for cause in e.chain() {
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            match err.kind() {
+                io::ErrorKind::NotFound => return KataCtlError::NotFound,
+                io::ErrorKind::PermissionDenied => return KataCtlError::Permission,
+                io::ErrorKind::TimedOut => return KataCtlError::Timeout,
+                _ => {}
+            }
+        }
+
+        if let Some(errno) = cause.downcast_ref::<nix::errno::Errno>() {
+            match errno {
+                nix::errno::Errno::ENOENT => return KataCtlError::NotFound,
+                nix::errno::Errno::EPERM | nix::errno::Errno::EACCES => {
+                    return KataCtlError::Permission
+                }
+                _ => {}
+            }
+        }
+
+        if cause.downcast_ref::<reqwest::Error>().is_some() {
+            return KataCtlError::Network;
+        }
+    }
+
+    KataCtlError::Runtime
+}
+
+impl From<anyhow::Error> for KataCtlError {
+    fn from(e: anyhow::Error) -> Self {
+        classify(&e)(e)
+    }
+}
+
+impl From<io::Error> for KataCtlError {
+    fn from(e: io::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+impl From<serde_json::Error> for KataCtlError {
+    fn from(e: serde_json::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_kata_ctl_error_from_anyhow() {
+        let io_error = |kind: io::ErrorKind| -> anyhow::Error {
+            Err::<(), _>(io::Error::from(kind))
+                .context("failed to open")
+                .unwrap_err()
+        };
+
+        let tests = vec![
+            (anyhow!("invalid value"), EXIT_CODE_FAILURE),
+            (io_error(io::ErrorKind::NotFound), EXIT_CODE_NOT_FOUND),
+            (
+                io_error(io::ErrorKind::PermissionDenied),
+                EXIT_CODE_PERMISSION_DENIED,
+            ),
+            (io_error(io::ErrorKind::TimedOut), EXIT_CODE_TIMEOUT),
+            (io_error(io::ErrorKind::BrokenPipe), EXIT_CODE_FAILURE),
+            (
+                anyhow!(nix::errno::Errno::EACCES),
+                EXIT_CODE_PERMISSION_DENIED,
+            ),
+            (anyhow!(nix::errno::Errno::ENOENT), EXIT_CODE_NOT_FOUND),
+        ];
+
+        for (i, (e, exit_code)) in tests.into_iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, e);
+            let e = KataCtlError::from(e);
+            assert_eq!(e.exit_code(), exit_code, "{}", msg);
+            assert!(e.error().is_some(), "{}", msg);
+        }
+
+        let e = KataCtlError::Status(42);
+        assert_eq!(e.exit_code(), 42);
+        assert!(e.error().is_none());
+    }
+}
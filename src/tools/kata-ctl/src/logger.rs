@@ -0,0 +1,242 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Logger setup for kata-ctl. Log records are written to stderr so that
+// they never get mixed up with the command output, either as text or as one
+// JSON object per record for the log aggregators.
+
+use crate::args::LogFormat;
+use crate::utils;
+
+use serde_json::{Map, Value};
+use slog::{o, Drain, Level, LevelFilter, OwnedKVList, Record, KV};
+use slog_scope::GlobalLoggerGuard;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// KvFormatter appends the key/value pairs of a log record to a string.
+struct KvFormatter(String);
+
+impl slog::Serializer for KvFormatter {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push_str(&format!(" {}={}", key, val));
+        Ok(())
+    }
+}
+
+// TextDrain writes one human readable line per log record, using the same
+// "LEVEL: message" layout as the rest of the kata-ctl output.
+struct TextDrain;
+
+impl Drain for TextDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let mut kv = KvFormatter(String::new());
+        let _ = record.kv().serialize(record, &mut kv);
+        let _ = values.serialize(record, &mut kv);
+
+        let _ = writeln!(
+            io::stderr().lock(),
+            "{}: {}{}",
+            level_name(record.level()),
+            record.msg(),
+            kv.0
+        );
+
+        Ok(())
+    }
+}
+
+// JsonSerializer adds the key/value pairs of a log record to a JSON object,
+// the values being formatted as strings.
+struct JsonSerializer(Map<String, Value>);
+
+impl slog::Serializer for JsonSerializer {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        // The record fields are never overwritten.
+        self.0
+            .entry(key.to_string())
+            .or_insert_with(|| Value::String(val.to_string()));
+        Ok(())
+    }
+}
+
+// JsonDrain writes one JSON object per log record, with the timestamp,
+// level, target and message fields followed by the key/value pairs.
+struct JsonDrain<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonDrain<W> {
+    fn new(writer: W) -> Self {
+        JsonDrain {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonDrain<W> {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let mut fields = Map::new();
+        fields.insert(
+            "timestamp".to_string(),
+            Value::String(format_timestamp(SystemTime::now())),
+        );
+        fields.insert(
+            "level".to_string(),
+            Value::String(level_name(record.level()).to_string()),
+        );
+        fields.insert(
+            "target".to_string(),
+            Value::String(record.module().to_string()),
+        );
+        fields.insert(
+            "message".to_string(),
+            Value::String(record.msg().to_string()),
+        );
+
+        let mut kv = JsonSerializer(fields);
+        let _ = record.kv().serialize(record, &mut kv);
+        let _ = values.serialize(record, &mut kv);
+
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{}", Value::Object(kv.0));
+        }
+
+        Ok(())
+    }
+}
+
+// Format a time as an RFC3339 UTC timestamp, with milliseconds.
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (year, month, day) = utils::civil_from_days(secs / 86400);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "CRITICAL",
+        Level::Error => "ERROR",
+        Level::Warning => "WARNING",
+        Level::Info => "INFO",
+        Level::Debug => "DEBUG",
+        Level::Trace => "TRACE",
+    }
+}
+
+// Map the number of times the verbose flag was given to a log level.
+// Without the flag no logger is installed and log records are discarded.
+pub fn verbosity_to_level(verbosity: u8) -> Option<Level> {
+    match verbosity {
+        0 => None,
+        1 => Some(Level::Warning),
+        2 => Some(Level::Info),
+        3 => Some(Level::Debug),
+        _ => Some(Level::Trace),
+    }
+}
+
+// Install the global logger. The returned guard must be kept alive for as
+// long as logging is required.
+pub fn init(verbosity: u8, format: LogFormat) -> Option<GlobalLoggerGuard> {
+    let level = verbosity_to_level(verbosity)?;
+
+    let logger = match format {
+        LogFormat::Text => slog::Logger::root(LevelFilter::new(TextDrain, level).fuse(), o!()),
+        LogFormat::Json => slog::Logger::root(
+            LevelFilter::new(JsonDrain::new(io::stderr()), level).fuse(),
+            o!(),
+        ),
+    };
+
+    Some(slog_scope::set_global_logger(logger))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_verbosity_to_level() {
+        assert_eq!(verbosity_to_level(0), None);
+        assert_eq!(verbosity_to_level(1), Some(Level::Warning));
+        assert_eq!(verbosity_to_level(2), Some(Level::Info));
+        assert_eq!(verbosity_to_level(3), Some(Level::Debug));
+        assert_eq!(verbosity_to_level(4), Some(Level::Trace));
+        assert_eq!(verbosity_to_level(u8::MAX), Some(Level::Trace));
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_drain() {
+        let buffer = Buffer::default();
+        let drain = LevelFilter::new(JsonDrain::new(buffer.clone()), Level::Info).fuse();
+        let logger = slog::Logger::root(drain, o!("sandbox" => "sb1"));
+
+        slog::info!(logger, "connected"; "port" => 1026, "message" => "ignored");
+        slog::debug!(logger, "filtered out");
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+
+        let record: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["target"], module_path!());
+        assert_eq!(record["message"], "connected");
+        assert_eq!(record["port"], "1026");
+        assert_eq!(record["sandbox"], "sb1");
+        assert!(record["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let tests = &[
+            (0, "1970-01-01T00:00:00.000Z"),
+            (951_782_400_500, "2000-02-29T00:00:00.500Z"),
+            (1_672_567_200_000, "2023-01-01T10:00:00.000Z"),
+            (1_709_251_199_999, "2024-02-29T23:59:59.999Z"),
+        ];
+
+        for (i, (millis, expected)) in tests.iter().enumerate() {
+            let time = UNIX_EPOCH + Duration::from_millis(*millis);
+            assert_eq!(format_timestamp(time), *expected, "test[{}]", i);
+        }
+    }
+}
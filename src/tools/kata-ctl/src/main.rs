@@ -6,41 +6,104 @@
 mod arch;
 mod args;
 mod check;
+mod error;
+mod logger;
 mod ops;
+mod output;
 mod types;
 mod utils;
 
-use anyhow::Result;
+use anyhow::anyhow;
 use clap::Parser;
 use std::process::exit;
 
-use args::{Commands, KataCtlCli};
+use args::{Commands, KataCtlCli, OutputFormat};
+use error::KataCtlError;
 
-use ops::check_ops::{
-    handle_check, handle_factory, handle_iptables, handle_metrics, handle_version,
-};
+use ops::agent_ops::handle_agent;
+use ops::capabilities_ops::handle_capabilities;
+use ops::check_ops::{handle_check, handle_version};
+use ops::cleanup_ops::handle_cleanup;
+use ops::completions_ops::{handle_complete, handle_completions, handle_list_commands};
+use ops::config_ops::handle_config;
+use ops::doctor_ops::handle_doctor;
+use ops::dump_ops::handle_dump;
 use ops::env_ops::handle_env;
 use ops::exec_ops::handle_exec;
+use ops::factory_ops::handle_factory;
+use ops::inspect_ops::handle_inspect;
+use ops::iptables_ops::handle_iptables;
+use ops::logs_ops::handle_logs;
+use ops::metrics_ops::handle_metrics;
+use ops::mounts_ops::handle_mounts;
+use ops::ps_ops::handle_ps;
 use ops::volume_ops::handle_direct_volume;
 
-fn real_main() -> Result<()> {
-    let args = KataCtlCli::parse();
+fn real_main() -> Result<(), KataCtlError> {
+    let (cmdline, warning) = args::rewrite_deprecated_args(std::env::args_os().collect());
+    if let Some(warning) = warning {
+        eprintln!("{}", warning);
+    }
+
+    // clap exits with 2 on usage errors, which is taken by another error.
+    let args = match KataCtlCli::try_parse_from(cmdline) {
+        Ok(args) => args,
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            return Err(KataCtlError::Status(error::EXIT_CODE_CONFIG));
+        }
+        Err(e) => e.exit(),
+    };
+
+    let _logger_guard = logger::init(args.verbose, args.log_format);
+    utils::set_allow_unprivileged(args.allow_unprivileged);
+    utils::set_assume_yes(args.assume_yes);
+
+    let config_file = args
+        .config
+        .as_deref()
+        .map(utils::resolve_config_file)
+        .transpose()?;
+    let config = config_file.as_ref().map(|c| c.path());
+    let output = args.output;
+    output::init_color(args.color, output);
+
+    if output == OutputFormat::Csv && !args.command.supports_csv() {
+        return Err(KataCtlError::Config(anyhow!(
+            "--output csv is only supported by the commands outputting a list: ps, list-commands, metrics and direct-volume list"
+        )));
+    }
 
     match args.command {
-        Commands::Check(args) => handle_check(args),
-        Commands::DirectVolume(args) => handle_direct_volume(args),
-        Commands::Exec(args) => handle_exec(args),
-        Commands::Env(args) => handle_env(args),
-        Commands::Factory => handle_factory(),
-        Commands::Iptables(args) => handle_iptables(args),
-        Commands::Metrics(args) => handle_metrics(args),
-        Commands::Version => handle_version(),
+        Commands::Agent(args) => handle_agent(args, output),
+        Commands::Capabilities => handle_capabilities(output),
+        Commands::Check(args) => handle_check(args, config, output),
+        Commands::Cleanup(args) => handle_cleanup(args, output),
+        Commands::Complete(args) => handle_complete(args),
+        Commands::Completions(args) => handle_completions(args),
+        Commands::Config(args) => handle_config(args, output),
+        Commands::DirectVolume(args) => handle_direct_volume(args, output),
+        Commands::Doctor(args) => handle_doctor(args, config, output),
+        Commands::Dump(args) => handle_dump(args, output),
+        Commands::Exec(args) => handle_exec(args, output),
+        Commands::Env(args) => handle_env(args, config, output),
+        Commands::Factory(args) => handle_factory(args, config, output),
+        Commands::Inspect(args) => handle_inspect(args, output),
+        Commands::Iptables(args) => handle_iptables(args, output),
+        Commands::ListCommands(args) => handle_list_commands(args, output),
+        Commands::Logs(args) => handle_logs(args),
+        Commands::Metrics(args) => handle_metrics(args, output),
+        Commands::Mounts(args) => handle_mounts(args, output),
+        Commands::Ps(args) => handle_ps(args, output),
+        Commands::Version(args) => handle_version(args, config, output),
     }
 }
 
 fn main() {
     if let Err(e) = real_main() {
-        eprintln!("ERROR: {:#?}", e);
-        exit(1);
+        if let Some(e) = e.error() {
+            eprintln!("ERROR: {:#?}", e);
+        }
+        exit(e.exit_code());
     }
 }
@@ -3,8 +3,22 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+pub mod agent_ops;
+pub mod capabilities_ops;
 pub mod check_ops;
+pub mod cleanup_ops;
+pub mod completions_ops;
+pub mod config_ops;
+pub mod doctor_ops;
+pub mod dump_ops;
 pub mod env_ops;
 pub mod exec_ops;
+pub mod factory_ops;
+pub mod inspect_ops;
+pub mod iptables_ops;
+pub mod logs_ops;
+pub mod metrics_ops;
+pub mod mounts_ops;
+pub mod ps_ops;
 pub mod version;
 pub mod volume_ops;
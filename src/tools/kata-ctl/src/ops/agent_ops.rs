@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the agent commands. 'agent ping' only sends the health
+// check and version requests of the agent, which change nothing in the
+// guest, so that it can be used as a readiness probe.
+
+use crate::args::{AgentCommand, AgentPingArgs, AgentSubcommand, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::exec_ops;
+use crate::output;
+use crate::utils;
+
+use anyhow::anyhow;
+use protocols::health::{health_check_response::ServingStatus, CheckRequest};
+use protocols::health_ttrpc::HealthClient;
+use serde::Serialize;
+use std::os::unix::io::IntoRawFd;
+use std::time::{Duration, Instant};
+
+// AgentPing is the output of 'kata-ctl agent ping --json'.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AgentPing {
+    pub sandbox_id: String,
+    pub agent_version: String,
+    pub grpc_version: String,
+    // Round trip of the health check request, in milliseconds
+    pub latency_ms: f64,
+}
+
+fn format_agent_ping(ping: &AgentPing) -> String {
+    format!(
+        "agent of sandbox {} is serving: version {}, round trip {:.2} ms",
+        ping.sandbox_id, ping.agent_version, ping.latency_ms
+    )
+}
+
+// The ttrpc errors do not tell a timeout from another failure, the time the
+// request took does.
+fn agent_request_error(
+    sandbox_id: &str,
+    request: &str,
+    timeout: Duration,
+    elapsed: Duration,
+    e: ttrpc::Error,
+) -> KataCtlError {
+    let e = anyhow!(
+        "{} request to the agent of sandbox {} failed: {:?}",
+        request,
+        sandbox_id,
+        e
+    );
+
+    if elapsed >= timeout {
+        KataCtlError::Timeout(e)
+    } else {
+        KataCtlError::Runtime(e)
+    }
+}
+
+pub fn ping_agent(sandbox_id: &str, timeout: Duration) -> Result<AgentPing, KataCtlError> {
+    let stream = exec_ops::connect_agent(sandbox_id)?;
+    let client = HealthClient::new(ttrpc::Client::new(stream.into_raw_fd()));
+    let timeout_nano = timeout.as_nanos() as i64;
+    let request = CheckRequest::new();
+
+    let start = Instant::now();
+    let health = client.check(ttrpc::context::with_timeout(timeout_nano), &request);
+    let latency = start.elapsed();
+    let health =
+        health.map_err(|e| agent_request_error(sandbox_id, "health check", timeout, latency, e))?;
+
+    let status = health.status.value();
+    if status != ServingStatus::SERVING as i32 {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "agent of sandbox {} is not serving (status {})",
+            sandbox_id,
+            status
+        )));
+    }
+
+    let start = Instant::now();
+    let version = client
+        .version(ttrpc::context::with_timeout(timeout_nano), &request)
+        .map_err(|e| agent_request_error(sandbox_id, "version", timeout, start.elapsed(), e))?;
+
+    Ok(AgentPing {
+        sandbox_id: sandbox_id.to_string(),
+        agent_version: version.agent_version,
+        grpc_version: version.grpc_version,
+        latency_ms: latency.as_secs_f64() * 1000.0,
+    })
+}
+
+fn handle_agent_ping(args: AgentPingArgs, format: OutputFormat) -> Result<(), KataCtlError> {
+    utils::require_root("connecting to the agent of a sandbox")?;
+    let sandbox_id = utils::resolve_sandbox_id(&args.sandbox_id)?;
+
+    let ping = ping_agent(&sandbox_id, Duration::from_secs(args.timeout))?;
+    if !output::print_structured(format, &ping)? {
+        println!("{}", format_agent_ping(&ping));
+    }
+
+    Ok(())
+}
+
+pub fn handle_agent(agent_cmd: AgentCommand, format: OutputFormat) -> Result<(), KataCtlError> {
+    match agent_cmd.agent_cmd {
+        AgentSubcommand::Ping(args) => handle_agent_ping(args, format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_agent_ping() {
+        let ping = AgentPing {
+            sandbox_id: "abc".to_string(),
+            agent_version: "3.2.0".to_string(),
+            grpc_version: "0.0.1".to_string(),
+            latency_ms: 1.2345,
+        };
+
+        assert_eq!(
+            format_agent_ping(&ping),
+            "agent of sandbox abc is serving: version 3.2.0, round trip 1.23 ms"
+        );
+    }
+
+    #[test]
+    fn test_agent_request_error() {
+        let timeout = Duration::from_secs(5);
+        let error = || ttrpc::Error::Others("connection reset".to_string());
+
+        let e = agent_request_error(
+            "abc",
+            "health check",
+            timeout,
+            Duration::from_secs(1),
+            error(),
+        );
+        assert!(matches!(e, KataCtlError::Runtime(_)));
+        assert!(e
+            .to_string()
+            .starts_with("health check request to the agent of sandbox abc failed"));
+
+        let e = agent_request_error("abc", "version", timeout, timeout, error());
+        assert!(matches!(e, KataCtlError::Timeout(_)));
+    }
+}
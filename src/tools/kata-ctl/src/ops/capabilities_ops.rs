@@ -0,0 +1,172 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the capabilities command, telling the tools wrapping
+// kata-ctl what this binary supports: its commands and their flags, read
+// from the clap definition of the command line so that they always match
+// it, and the features whose behaviour cannot be told from the flags.
+
+use crate::args::{KataCtlCli, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::{env_ops, version};
+use crate::output;
+
+use clap::{Command, CommandFactory, ValueEnum};
+use serde::Serialize;
+
+// Bumped when a field of the capabilities is moved, renamed or removed.
+const CAPABILITIES_VERSION: u32 = 1;
+
+// The features of kata-ctl which are not a command or a flag. A feature is
+// only added, never removed, so that the tools can check for it.
+const FEATURES: &[&str] = &[
+    // the same exit codes for all the commands, 124 for the timeouts
+    "exit-codes",
+    // 'exec' exits with the exit status of the guest command
+    "exec-exit-status",
+    // the direct-volume commands lock the volumes against each other
+    "direct-volume-lock",
+    // 'direct-volume stats' and 'list' report the device numbers
+    "direct-volume-devices",
+    // 'check all' runs the cgroups check
+    "check-cgroups",
+    // the secrets are redacted from the reports of 'env' and 'doctor'
+    "redaction",
+];
+
+// The TLS implementation the releases are downloaded with.
+#[cfg(target_arch = "s390x")]
+const TLS_FEATURE: &str = "tls-native";
+#[cfg(not(target_arch = "s390x"))]
+const TLS_FEATURE: &str = "tls-rustls";
+
+// CommandCapabilities describes a command, its flags being its long options.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CommandCapabilities {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub hidden: bool,
+    pub flags: Vec<String>,
+    pub subcommands: Vec<CommandCapabilities>,
+}
+
+// Capabilities is the output of 'kata-ctl capabilities'.
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub capabilities_version: u32,
+    pub kata_ctl_version: String,
+    pub arch: &'static str,
+    pub env_schema_version: &'static str,
+    pub output_formats: Vec<String>,
+    pub global_flags: Vec<String>,
+    pub commands: Vec<CommandCapabilities>,
+    pub features: Vec<&'static str>,
+}
+
+// The global flags are only defined by the command declaring them, until
+// clap propagates them when the command line is parsed.
+fn get_flags(cmd: &Command) -> Vec<String> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{}", long))
+        .collect()
+}
+
+fn get_command_capabilities(cmd: &Command) -> CommandCapabilities {
+    CommandCapabilities {
+        name: cmd.get_name().to_string(),
+        aliases: cmd.get_all_aliases().map(str::to_string).collect(),
+        hidden: cmd.is_hide_set(),
+        flags: get_flags(cmd),
+        subcommands: cmd
+            .get_subcommands()
+            .map(get_command_capabilities)
+            .collect(),
+    }
+}
+
+fn get_capabilities() -> Capabilities {
+    let cli = KataCtlCli::command();
+
+    Capabilities {
+        capabilities_version: CAPABILITIES_VERSION,
+        kata_ctl_version: version::get().unwrap_or_default(),
+        arch: std::env::consts::ARCH,
+        env_schema_version: env_ops::ENV_SCHEMA_VERSION,
+        output_formats: OutputFormat::value_variants()
+            .iter()
+            .filter_map(|format| format.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect(),
+        global_flags: get_flags(&cli),
+        commands: cli
+            .get_subcommands()
+            .map(get_command_capabilities)
+            .collect(),
+        features: FEATURES
+            .iter()
+            .copied()
+            .chain(std::iter::once(TLS_FEATURE))
+            .collect(),
+    }
+}
+
+// The capabilities are for tools, so they are always structured.
+pub fn handle_capabilities(format: OutputFormat) -> Result<(), KataCtlError> {
+    let format = if format.is_structured() {
+        format
+    } else {
+        OutputFormat::Json
+    };
+
+    output::print_structured(format, &get_capabilities())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(commands: &'a [CommandCapabilities], name: &str) -> &'a CommandCapabilities {
+        commands
+            .iter()
+            .find(|cmd| cmd.name == name)
+            .unwrap_or_else(|| panic!("command {} not found", name))
+    }
+
+    #[test]
+    fn test_get_capabilities() {
+        let capabilities = get_capabilities();
+
+        assert_eq!(capabilities.capabilities_version, CAPABILITIES_VERSION);
+        assert_eq!(
+            capabilities.output_formats,
+            vec!["human", "json", "yaml", "table", "csv"]
+        );
+        assert!(capabilities.global_flags.contains(&"--output".to_string()));
+        assert!(capabilities.features.contains(&"direct-volume-lock"));
+        assert!(capabilities.features.contains(&TLS_FEATURE));
+
+        let exec = find(&capabilities.commands, "exec");
+        assert!(exec.flags.contains(&"--env".to_string()));
+        assert!(exec.flags.contains(&"--workdir".to_string()));
+        // the global flags are only listed once
+        assert!(!exec.flags.contains(&"--output".to_string()));
+        assert!(exec.subcommands.is_empty());
+
+        let ps = find(&capabilities.commands, "ps");
+        assert_eq!(ps.aliases, vec!["list"]);
+        assert!(!ps.hidden);
+        assert!(find(&capabilities.commands, "list-commands").hidden);
+        find(&capabilities.commands, "capabilities");
+
+        let volume = find(&capabilities.commands, "direct-volume");
+        assert!(volume.flags.contains(&"--lock-timeout".to_string()));
+        let list = find(&volume.subcommands, "list");
+        assert!(list.flags.contains(&"--kata-debug-port".to_string()));
+    }
+}
@@ -3,55 +3,1371 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::arch::arch_specific::get_checks;
+use crate::arch::arch_specific::{get_checks, get_cpu_details, get_cpu_features};
 
-use crate::args::{CheckArgument, CheckSubCommand, IptablesCommand, MetricsCommand};
+use crate::args::{
+    CheckAllArgs, CheckArgument, CheckReleaseArgs, CheckRunArgs, CheckSubCommand, HypervisorName,
+    OutputFormat, VersionArgument,
+};
 
 use crate::check;
 
+use crate::error::{
+    KataCtlError, EXIT_CODE_FAILURE, EXIT_CODE_HARDWARE_UNSUPPORTED, EXIT_CODE_MISSING_DEPENDENCY,
+    EXIT_CODE_PERMISSION_DENIED, EXIT_CODE_UPDATE_AVAILABLE,
+};
+
+use crate::ops::env_ops;
 use crate::ops::version;
 
+use crate::output::{self, Color, Table};
+
 use crate::types::*;
 
-use anyhow::{anyhow, Result};
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use kata_types::config::Hypervisor;
+use nix::unistd::{close, dup, dup2};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 const NAME: &str = "kata-ctl";
 
-// This function retrieves the cmd function passes as argument
-fn get_builtin_check_func(name: CheckType) -> Result<BuiltinCmdFp> {
-    if let Some(check_list) = get_checks() {
-        for check in check_list {
-            if check.name.eq(&name) {
-                return Ok(check.fp);
+// The name of the result of 'check all --hypervisor'.
+const HYPERVISOR_CHECK: &str = "Hypervisor";
+
+// The results of the external checks are named after their executable.
+const EXTERNAL_CHECK_PREFIX: &str = "plugin:";
+const EXTERNAL_CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The checks of 'check all' which must wait for other checks to be done,
+// e.g. because they change the host state the other checks examine.
+const CHECK_DEPENDENCIES: &[(CheckType, &[CheckType])] = &[];
+
+// The checks of the configuration, which are not architecture specific.
+// 'check all' runs them after the hypervisor check.
+static CONFIG_CHECKS: &[CheckItem] = &[
+    CheckItem {
+        name: CheckType::KernelParams,
+        descr: "This parameter performs the guest kernel parameters check",
+        fp: check_kernel_params,
+        perm: PermissionType::NonPrivileged,
+    },
+    CheckItem {
+        name: CheckType::Hugepages,
+        descr: "This parameter performs the hugepages check",
+        fp: check_hugepages,
+        perm: PermissionType::NonPrivileged,
+    },
+];
+
+// Binaries queried for the version of the installed runtime, in order.
+const RUNTIME_BINARIES: &[&str] = &["containerd-shim-kata-v2", "kata-runtime"];
+
+// Results of the hardware checks kept by 'check all --cache'.
+const CHECK_CACHE_FILE: &str = "check-results.json";
+
+// CPU flags and kernel modules that enable hardware virtualization, part of
+// the hardware fingerprint.
+const VIRT_CPU_FLAGS: &[&str] = &["vmx", "svm", "sie"];
+const VIRT_KERNEL_MODULES: &[&str] = &[
+    "kvm",
+    "kvm_intel",
+    "kvm_amd",
+    "kvm_hv",
+    "vhost",
+    "vhost_net",
+    "vhost_vsock",
+];
+const SYS_MODULE_PATH: &str = "/sys/module";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Pass,
+    // The check found a likely problem, which does not fail 'check all'.
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl CheckStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+            CheckStatus::Skip => "skip",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            CheckStatus::Pass => Color::Green,
+            CheckStatus::Warn | CheckStatus::Skip => Color::Yellow,
+            CheckStatus::Fail => Color::Red,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailureClass {
+    HardwareUnsupported,
+    MissingDependency,
+    PermissionDenied,
+    // The failures of the external checks are not known to kata-ctl.
+    External,
+}
+
+impl FailureClass {
+    fn exit_code(&self) -> i32 {
+        match self {
+            FailureClass::HardwareUnsupported => EXIT_CODE_HARDWARE_UNSUPPORTED,
+            FailureClass::MissingDependency => EXIT_CODE_MISSING_DEPENDENCY,
+            FailureClass::PermissionDenied => EXIT_CODE_PERMISSION_DENIED,
+            FailureClass::External => EXIT_CODE_FAILURE,
+        }
+    }
+
+    fn into_error(self, e: anyhow::Error) -> KataCtlError {
+        match self {
+            FailureClass::HardwareUnsupported => KataCtlError::Unsupported(e),
+            FailureClass::MissingDependency => KataCtlError::MissingDependency(e),
+            FailureClass::PermissionDenied => KataCtlError::Permission(e),
+            FailureClass::External => KataCtlError::Runtime(e),
+        }
+    }
+}
+
+// CheckResult is the outcome of a single check run by 'kata-ctl check all',
+// also shown by 'kata-ctl check all --json'.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CheckResult {
+    name: String,
+    #[serde(rename = "result")]
+    status: CheckStatus,
+    detail: String,
+    #[serde(skip)]
+    failure: Option<FailureClass>,
+    // The result was reused from a previous run on the same hardware.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    cached: bool,
+    // The CPU flags examined by the CPU check, with --cpu-features.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cpu_features: Option<Vec<CpuFeature>>,
+    // The hugepages of the host, examined by the hugepages check.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hugepages: Option<check::HugepagesInfo>,
+    // The time the check took, in milliseconds, unknown for a cached result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<f64>,
+}
+
+impl CheckResult {
+    fn skip(name: &str, detail: &str, failure: Option<FailureClass>) -> Self {
+        CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Skip,
+            detail: detail.to_string(),
+            failure,
+            cached: false,
+            cpu_features: None,
+            hugepages: None,
+            duration_ms: None,
+        }
+    }
+
+    fn from_result(name: &str, class: FailureClass, result: Result<()>) -> Self {
+        match result {
+            Ok(()) => CheckResult {
+                name: name.to_string(),
+                status: CheckStatus::Pass,
+                detail: String::new(),
+                failure: None,
+                cached: false,
+                cpu_features: None,
+                hugepages: None,
+                duration_ms: None,
+            },
+            Err(e) => CheckResult {
+                name: name.to_string(),
+                status: CheckStatus::Fail,
+                detail: format!("{:#}", e),
+                failure: Some(class),
+                cached: false,
+                cpu_features: None,
+                hugepages: None,
+                duration_ms: None,
+            },
+        }
+    }
+
+    fn from_warnings(name: &str, warnings: Vec<String>) -> Self {
+        CheckResult {
+            name: name.to_string(),
+            status: if warnings.is_empty() {
+                CheckStatus::Pass
+            } else {
+                CheckStatus::Warn
+            },
+            detail: warnings.join("; "),
+            failure: None,
+            cached: false,
+            cpu_features: None,
+            hugepages: None,
+            duration_ms: None,
+        }
+    }
+
+    // The result as the one of a check handler, the warnings being printed.
+    fn into_result(self) -> Result<()> {
+        match self.status {
+            CheckStatus::Fail => Err(anyhow!("{}", self.detail)),
+            CheckStatus::Warn => {
+                println!("WARNING: check {}: {}", self.name, self.detail);
+                Ok(())
             }
+            CheckStatus::Pass | CheckStatus::Skip => Ok(()),
+        }
+    }
+
+    fn status_name(&self) -> String {
+        if self.cached {
+            format!("{} (cached)", self.status.name())
+        } else {
+            self.status.name().to_string()
+        }
+    }
+
+    fn painted_status_name(&self) -> String {
+        output::paint(self.status.color(), &self.status_name())
+    }
+}
+
+// Runs a check, recording the time it took in its result.
+fn timed_check<F: FnOnce() -> CheckResult>(run: F) -> CheckResult {
+    let start = Instant::now();
+    let mut result = run();
+    result.duration_ms = Some(start.elapsed().as_secs_f64() * 1000.0);
+
+    result
+}
+
+// CheckSummary counts the results of 'kata-ctl check all', it is the last
+// line of its output.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct CheckSummary {
+    total: usize,
+    passed: usize,
+    warned: usize,
+    failed: usize,
+    skipped: usize,
+    // The time all the checks took, in milliseconds
+    duration_ms: f64,
+}
+
+impl CheckSummary {
+    fn new(results: &[CheckResult], elapsed: Duration) -> Self {
+        let count = |status| {
+            results
+                .iter()
+                .filter(|result| result.status == status)
+                .count()
+        };
+
+        CheckSummary {
+            total: results.len(),
+            passed: count(CheckStatus::Pass),
+            warned: count(CheckStatus::Warn),
+            failed: count(CheckStatus::Fail),
+            skipped: count(CheckStatus::Skip),
+            duration_ms: elapsed.as_secs_f64() * 1000.0,
         }
     }
+}
+
+fn format_check_summary(summary: &CheckSummary) -> String {
+    format!(
+        "{} checks: {} passed, {} warned, {} failed, {} skipped in {:.2}s",
+        summary.total,
+        summary.passed,
+        summary.warned,
+        summary.failed,
+        summary.skipped,
+        summary.duration_ms / 1000.0
+    )
+}
+
+// The output of 'kata-ctl check all --json'.
+#[derive(Debug, Serialize)]
+struct CheckAllReport<'a> {
+    checks: &'a [CheckResult],
+    summary: CheckSummary,
+}
+
+// The fingerprint of the hardware and host kernel the hardware checks ran
+// on, the order of the virtualization features being irrelevant.
+fn hardware_fingerprint(cpu_model: &str, kernel_version: &str, virt_features: &[&str]) -> String {
+    let mut features = virt_features.to_vec();
+    features.sort_unstable();
+    features.dedup();
 
-    Err(anyhow!("Invalid command: {:?}", name))
+    format!(
+        "cpu={};kernel={};virt={}",
+        cpu_model.trim(),
+        kernel_version.trim(),
+        features.join(",")
+    )
 }
 
-// This function is called from each 'kata-ctl check' argument section
-fn handle_builtin_check(check: CheckType, args: &str) -> Result<()> {
-    let f = match get_builtin_check_func(check) {
-        Ok(fp) => fp,
-        Err(e) => return Err(e),
+// Get the virtualization flags of the "flags" (x86_64) or "features"
+// (s390x) line of the cpuinfo of a single CPU.
+fn get_virt_cpu_flags(cpu_info: &str) -> Vec<&str> {
+    cpu_info
+        .lines()
+        .filter(|line| line.starts_with("flags") || line.starts_with("features"))
+        .filter_map(|line| line.split_once(':'))
+        .flat_map(|(_, flags)| flags.split_whitespace())
+        .filter(|flag| VIRT_CPU_FLAGS.contains(flag))
+        .collect()
+}
+
+fn get_hardware_fingerprint() -> Result<String> {
+    let (_, cpu_model) = get_cpu_details()?;
+    let kernel_version = utils::get_kernel_version(utils::PROC_VERSION_FILE)?;
+    let cpu_info = check::get_single_cpu_info(check::PROC_CPUINFO, "\n\n")?;
+
+    let mut features = get_virt_cpu_flags(&cpu_info);
+    features.extend(
+        VIRT_KERNEL_MODULES
+            .iter()
+            .filter(|module| Path::new(SYS_MODULE_PATH).join(module).exists()),
+    );
+
+    Ok(hardware_fingerprint(&cpu_model, &kernel_version, &features))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CachedCheckResults {
+    fingerprint: String,
+    results: Vec<CheckResult>,
+}
+
+// CheckResultCache keeps the results of the checks that only depend on the
+// hardware and the host kernel, discarding them when the fingerprint
+// changes.
+struct CheckResultCache {
+    path: PathBuf,
+    fingerprint: String,
+    results: Vec<CheckResult>,
+}
+
+impl CheckResultCache {
+    // No cache is used when the fingerprint cannot be computed.
+    fn open(refresh: bool) -> Option<Self> {
+        let path = check::user_cache_dir()?.join(CHECK_CACHE_FILE);
+        let fingerprint = get_hardware_fingerprint().ok()?;
+
+        Some(Self::load(path, fingerprint, refresh))
+    }
+
+    fn load(path: PathBuf, fingerprint: String, refresh: bool) -> Self {
+        let results = if refresh {
+            Vec::new()
+        } else {
+            fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<CachedCheckResults>(&contents).ok())
+                .filter(|cached| cached.fingerprint == fingerprint)
+                .map(|cached| cached.results)
+                .unwrap_or_default()
+        };
+
+        CheckResultCache {
+            path,
+            fingerprint,
+            results,
+        }
+    }
+
+    fn get(&self, check_type: &CheckType) -> Option<CheckResult> {
+        let name = check_type.to_string();
+        let result = self.results.iter().find(|result| result.name == name)?;
+
+        Some(CheckResult {
+            failure: match result.status {
+                CheckStatus::Fail => Some(failure_class(check_type)),
+                _ => None,
+            },
+            cached: true,
+            ..result.clone()
+        })
+    }
+
+    // A skipped check, e.g. for lack of privileges, has no result to keep.
+    fn insert(&mut self, result: &CheckResult) {
+        if result.status == CheckStatus::Skip {
+            return;
+        }
+
+        self.results.retain(|cached| cached.name != result.name);
+        self.results.push(CheckResult {
+            cached: false,
+            cpu_features: None,
+            hugepages: None,
+            duration_ms: None,
+            ..result.clone()
+        });
+    }
+
+    // The cache is only an optimization, failing to update it is not an
+    // error.
+    fn store(&self) {
+        let cached = CachedCheckResults {
+            fingerprint: self.fingerprint.clone(),
+            results: self.results.clone(),
+        };
+
+        if let (Some(dir), Ok(contents)) = (self.path.parent(), serde_json::to_string(&cached)) {
+            let _ = fs::create_dir_all(dir).and_then(|_| fs::write(&self.path, contents));
+        }
+    }
+}
+
+// StdoutSilencer redirects stdout to /dev/null for as long as it is alive,
+// as the checks print their progress directly.
+struct StdoutSilencer {
+    saved_fd: RawFd,
+}
+
+impl StdoutSilencer {
+    fn new() -> Result<Self> {
+        io::stdout().flush()?;
+
+        let null = OpenOptions::new().write(true).open("/dev/null")?;
+        let saved_fd = dup(libc::STDOUT_FILENO)?;
+        if let Err(e) = dup2(null.as_raw_fd(), libc::STDOUT_FILENO) {
+            let _ = close(saved_fd);
+            return Err(e.into());
+        }
+
+        Ok(StdoutSilencer { saved_fd })
+    }
+}
+
+impl Drop for StdoutSilencer {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = dup2(self.saved_fd, libc::STDOUT_FILENO);
+        let _ = close(self.saved_fd);
+    }
+}
+
+// CheckListItem is the machine-readable description of a check shown by
+// 'kata-ctl check list --json'.
+#[derive(Debug, Serialize)]
+struct CheckListItem {
+    name: String,
+    description: String,
+    needs_network: bool,
+    needs_root: bool,
+}
+
+// Check names are matched ignoring case and word separators, so that both
+// "KernelModules" and "kernel-modules" select the same check.
+fn normalize_check_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// The checks of the architecture, followed by the configuration checks.
+fn all_checks() -> impl Iterator<Item = &'static CheckItem<'static>> {
+    get_checks().unwrap_or_default().iter().chain(CONFIG_CHECKS)
+}
+
+fn is_config_check(check_type: CheckType) -> bool {
+    CONFIG_CHECKS.iter().any(|check| check.name == check_type)
+}
+
+fn find_check(name: &str) -> Result<&'static CheckItem<'static>> {
+    let normalized = normalize_check_name(name);
+
+    all_checks()
+        .find(|check| normalize_check_name(&check.name.to_string()) == normalized)
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown check {:?}, run '{} check list' to see the available checks",
+                name,
+                NAME
+            )
+        })
+}
+
+fn run_single_check(
+    config_file: Option<&str>,
+    args: &CheckRunArgs,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let check = find_check(&args.name).map_err(KataCtlError::NotFound)?;
+    let name = check.name.to_string();
+    let class = failure_class(&check.name);
+    if check.perm == PermissionType::Privileged {
+        utils::require_root(&format!("check {}", check.name))?;
+    }
+
+    // The builtin checks are the same for all the hypervisors, so that with
+    // --hypervisor the binary of the hypervisor is checked first, like
+    // 'check all --hypervisor' does.
+    let hypervisor = match args.hypervisor {
+        Some(name) => Some((
+            name,
+            get_hypervisor_config(config_file, name).map_err(KataCtlError::Config)?,
+        )),
+        None => None,
     };
+    let run = || {
+        if let Some((hypervisor, config)) = &hypervisor {
+            if let Err(e) = check_hypervisor(*hypervisor, config) {
+                return CheckResult::from_result(&name, FailureClass::MissingDependency, Err(e));
+            }
+        }
+
+        // The configuration checks select the hypervisor in the
+        // configuration file rather than being given its name.
+        if is_config_check(check.name) {
+            return run_config_check(check.name, config_file, args.hypervisor);
+        }
+        let arg = hypervisor
+            .as_ref()
+            .map_or("", |(hypervisor, _)| hypervisor.name());
+        CheckResult::from_result(&name, class, (check.fp)(arg))
+    };
+
+    if format.is_structured() {
+        let result = {
+            let _silencer = StdoutSilencer::new()?;
+            run()
+        };
+        output::print_structured(format, &result)?;
 
-    f(args)
+        // The failure was already reported with the result.
+        if let Some(failure) = result.failure {
+            return Err(KataCtlError::Status(failure.exit_code()));
+        }
+
+        return Ok(());
+    }
+
+    let result = run();
+    if let Some(class) = result.failure {
+        return Err(class.into_error(anyhow!("check {} failed: {}", check.name, result.detail)));
+    }
+    match result.status {
+        CheckStatus::Warn => println!("check {} passed with warnings: {}", name, result.detail),
+        CheckStatus::Skip => println!("check {} skipped: {}", name, result.detail),
+        _ => println!("check {} passed", name),
+    }
+
+    Ok(())
+}
+
+fn failure_class(check_type: &CheckType) -> FailureClass {
+    match check_type {
+        CheckType::Cpu => FailureClass::HardwareUnsupported,
+        CheckType::Network
+        | CheckType::KernelModules
+        | CheckType::Cgroups
+        | CheckType::KernelParams
+        | CheckType::Hugepages => FailureClass::MissingDependency,
+    }
+}
+
+fn run_check(
+    check_type: CheckType,
+    interface: Option<&str>,
+    network_timeout: Duration,
+) -> CheckResult {
+    let name = check_type.to_string();
+    let class = failure_class(&check_type);
+
+    // the network checks are not architecture specific
+    if check_type == CheckType::Network {
+        let mut result = CheckResult::from_result(
+            &name,
+            class,
+            check::run_network_checks(interface, network_timeout),
+        );
+        // The failures already tell the interface.
+        if let Some(interface) = interface {
+            if result.detail.is_empty() {
+                result.detail = format!("interface {}", interface);
+            }
+        }
+        return result;
+    }
+
+    // The cgroups check is not architecture specific either, and reports
+    // the version of the hierarchy.
+    if check_type == CheckType::Cgroups {
+        return match check::probe_cgroups(Path::new(check::SYS_FS_CGROUP_PATH)) {
+            Ok(version) => {
+                let mut result = CheckResult::from_result(&name, class, Ok(()));
+                result.detail = version;
+                result
+            }
+            Err(e) => CheckResult::from_result(&name, class, Err(e)),
+        };
+    }
+
+    let check = match get_checks()
+        .unwrap_or_default()
+        .iter()
+        .find(|check| check.name == check_type)
+    {
+        Some(check) => check,
+        None => return CheckResult::skip(&name, "not supported on this architecture", None),
+    };
+
+    if check.perm == PermissionType::Privileged && !utils::is_privileged() {
+        return CheckResult::skip(
+            &name,
+            "super-user privileges are required",
+            Some(FailureClass::PermissionDenied),
+        );
+    }
+
+    CheckResult::from_result(&name, class, (check.fp)(""))
+}
+
+// A job of run_jobs, with the indexes of the jobs it depends on.
+struct Job<'a, T> {
+    run: Box<dyn FnOnce() -> T + Send + 'a>,
+    after: Vec<usize>,
+}
+
+struct JobsState<'a, T> {
+    pending: Vec<Option<Job<'a, T>>>,
+    results: Vec<Option<Result<T, String>>>,
+    running: usize,
+}
+
+// The message a panic was started with.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Runs the jobs on up to workers threads, a job only starting once the jobs
+// it depends on are done. The results are in the order of the jobs, a job
+// which panicked giving the message of the panic. Should the dependencies
+// form a cycle, its first job is run once nothing else can run.
+fn run_jobs<'a, T: Send>(jobs: Vec<Job<'a, T>>, workers: usize) -> Vec<Result<T, String>> {
+    let count = jobs.len();
+    let state = Mutex::new(JobsState {
+        pending: jobs.into_iter().map(Some).collect(),
+        results: (0..count).map(|_| None).collect(),
+        running: 0,
+    });
+    let done = Condvar::new();
+
+    let worker = || loop {
+        let mut guard = state.lock().unwrap();
+        let (index, job) = loop {
+            let ready = (0..count).find(|i| {
+                guard.pending[*i].as_ref().map_or(false, |job| {
+                    job.after
+                        .iter()
+                        .all(|dep| guard.results.get(*dep).map_or(true, Option::is_some))
+                })
+            });
+            let first = guard.pending.iter().position(Option::is_some);
+
+            match (ready, first) {
+                (Some(index), _) => break (index, guard.pending[index].take().unwrap()),
+                (None, None) => return,
+                (None, Some(index)) if guard.running == 0 => {
+                    break (index, guard.pending[index].take().unwrap())
+                }
+                (None, Some(_)) => guard = done.wait(guard).unwrap(),
+            }
+        };
+        guard.running += 1;
+        drop(guard);
+
+        // The panic is caught so that the job is still counted as done, and
+        // the state is never left locked by a panicking worker.
+        let result = panic::catch_unwind(AssertUnwindSafe(job.run))
+            .map_err(|payload| panic_message(payload.as_ref()));
+
+        let mut guard = state.lock().unwrap();
+        guard.results[index] = Some(result);
+        guard.running -= 1;
+        drop(guard);
+        done.notify_all();
+    };
+
+    thread::scope(|scope| {
+        for _ in 1..workers.clamp(1, count.max(1)) {
+            scope.spawn(worker);
+        }
+        worker();
+    });
+
+    state
+        .into_inner()
+        .unwrap()
+        .results
+        .into_iter()
+        .map(|result| result.expect("job did not run"))
+        .collect()
+}
+
+// Number of checks run at once by 'check all --parallel N'.
+fn check_workers(parallel: usize) -> usize {
+    match parallel {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+// With fail_fast, the checks after the first failure are not run, nor
+// reported.
+fn run_checks(
+    network: bool,
+    interface: Option<&str>,
+    network_timeout: Duration,
+    mut cache: Option<&mut CheckResultCache>,
+    workers: usize,
+    fail_fast: bool,
+) -> Vec<CheckResult> {
+    // run architecture-specific tests, the network checks and the kernel
+    // module checks
+    let mut check_types = vec![CheckType::Cpu, CheckType::Cgroups];
+    if network {
+        check_types.push(CheckType::Network);
+        check_types.push(CheckType::KernelModules);
+    }
+
+    let mut results: Vec<Option<CheckResult>> = check_types
+        .iter()
+        .map(|check_type| match check_type {
+            // The cgroups are not part of the hardware fingerprint.
+            CheckType::Network | CheckType::Cgroups => None,
+            _ => cache.as_deref().and_then(|cache| cache.get(check_type)),
+        })
+        .collect();
+
+    // A cached failure is as good a reason to stop as a new one.
+    let stopped = AtomicBool::new(
+        fail_fast
+            && results
+                .iter()
+                .flatten()
+                .any(|result| result.failure.is_some()),
+    );
+    let to_run: Vec<usize> = (0..check_types.len())
+        .filter(|i| results[*i].is_none())
+        .collect();
+    let jobs = to_run
+        .iter()
+        .map(|i| {
+            let check_type = &check_types[*i];
+            let after = CHECK_DEPENDENCIES
+                .iter()
+                .filter(|(dependent, _)| dependent == check_type)
+                .flat_map(|(_, deps)| deps.iter())
+                .filter_map(|dep| to_run.iter().position(|j| check_types[*j] == *dep))
+                .collect();
+            let check_type = *check_type;
+            let stopped = &stopped;
+
+            Job {
+                run: Box::new(move || {
+                    if stopped.load(AtomicOrdering::SeqCst) {
+                        return None;
+                    }
+                    let result = timed_check(|| run_check(check_type, interface, network_timeout));
+                    if fail_fast && result.failure.is_some() {
+                        stopped.store(true, AtomicOrdering::SeqCst);
+                    }
+                    Some(result)
+                }),
+                after,
+            }
+        })
+        .collect();
+
+    for (i, result) in to_run.into_iter().zip(run_jobs(jobs, workers)) {
+        let result = result.unwrap_or_else(|message| {
+            let check_type = &check_types[i];
+            Some(CheckResult::from_result(
+                &check_type.to_string(),
+                failure_class(check_type),
+                Err(anyhow!("check panicked: {}", message)),
+            ))
+        });
+        if let (Some(cache), Some(result)) = (cache.as_deref_mut(), &result) {
+            if !matches!(check_types[i], CheckType::Network | CheckType::Cgroups) {
+                cache.insert(result);
+            }
+        }
+        results[i] = result;
+    }
+
+    let mut results: Vec<CheckResult> = results.into_iter().flatten().collect();
+    if fail_fast {
+        // The cached results of the checks after the failure are dropped too.
+        if let Some(index) = results.iter().position(|result| result.failure.is_some()) {
+            results.truncate(index + 1);
+            return results;
+        }
+    }
+    if !network {
+        results.push(CheckResult::skip(
+            &CheckType::Network.to_string(),
+            "network checks are disabled",
+            None,
+        ));
+    }
+
+    results
+}
+
+// ExternalCheckOutput is the last line of the output of an external check.
+#[derive(Debug, Deserialize)]
+struct ExternalCheckOutput {
+    #[serde(default)]
+    detail: String,
+}
+
+// The output of an external check is empty, or ends with a JSON object.
+fn parse_external_check_output(stdout: &str) -> Result<String> {
+    let line = match stdout.lines().rev().find(|line| !line.trim().is_empty()) {
+        Some(line) => line.trim(),
+        None => return Ok(String::new()),
+    };
+
+    let output: ExternalCheckOutput = serde_json::from_str(line)
+        .with_context(|| format!("the last line is not a JSON object: {:?}", line))?;
+
+    Ok(output.detail)
+}
+
+fn external_check_result(name: &str, status: ExitStatus, stdout: &str) -> CheckResult {
+    let detail = match parse_external_check_output(stdout) {
+        Ok(detail) => detail,
+        Err(e) => return CheckResult::skip(name, &format!("invalid output, {:#}", e), None),
+    };
+
+    let error = match status.code() {
+        Some(0) => {
+            let mut result = CheckResult::from_result(name, FailureClass::External, Ok(()));
+            result.detail = detail;
+            return result;
+        }
+        Some(code) if detail.is_empty() => anyhow!("exit status {}", code),
+        Some(_) => anyhow!("{}", detail),
+        // killed by a signal
+        None => anyhow!("{}", status),
+    };
+
+    CheckResult::from_result(name, FailureClass::External, Err(error))
+}
+
+// Runs an external check, killing it if it does not exit in time. The
+// output is read by another thread so that the check never blocks on a
+// full pipe.
+fn run_external_check(path: &Path, timeout: Duration) -> Result<(ExitStatus, String)> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to run {}", path.display()))?;
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("no output pipe"))?;
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("timed out after {} seconds", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    let output = reader
+        .join()
+        .map_err(|_| anyhow!("failed to read the output"))?;
+
+    Ok((status, output))
+}
+
+// 'check all' runs as root, so the external checks and their directory must
+// not be changed by anyone else: they must be owned by root, or by the user
+// running kata-ctl, and not be writable by the group or the others.
+fn untrusted_reason(path: &Path, uid: u32) -> Option<String> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Some(format!("{}: {}", path.display(), e)),
+    };
+
+    if metadata.uid() != 0 && metadata.uid() != uid {
+        return Some(format!(
+            "{} is owned by user {}, not root",
+            path.display(),
+            metadata.uid()
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Some(format!(
+            "{} is writable by its group or others",
+            path.display()
+        ));
+    }
+
+    None
+}
+
+// The external checks are the files of dir, in the order of their names,
+// the hidden files being ignored. The files which are not executable, or
+// which could have been changed by another user, are reported as skipped.
+// With fail_fast, the checks after the first failure are not run.
+fn run_external_checks(dir: &Path, timeout: Duration, fail_fast: bool) -> Vec<CheckResult> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            eprintln!(
+                "WARNING: cannot read the external checks in {}: {}",
+                dir.display(),
+                e
+            );
+            return Vec::new();
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .map_or(false, |name| !name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    paths.sort();
+
+    let uid = nix::unistd::Uid::effective().as_raw();
+    let untrusted_dir = untrusted_reason(dir, uid);
+
+    let mut results = Vec::new();
+    for path in &paths {
+        let result = {
+            let name = format!(
+                "{}{}",
+                EXTERNAL_CHECK_PREFIX,
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+
+            let executable = fs::metadata(path)
+                .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if let Some(reason) = untrusted_dir
+                .clone()
+                .or_else(|| untrusted_reason(path, uid))
+            {
+                CheckResult::skip(&name, &format!("not run: {}", reason), None)
+            } else if !executable {
+                CheckResult::skip(&name, "not executable", None)
+            } else {
+                timed_check(|| match run_external_check(path, timeout) {
+                    Ok((status, stdout)) => external_check_result(&name, status, &stdout),
+                    Err(e) => CheckResult::from_result(&name, FailureClass::External, Err(e)),
+                })
+            }
+        };
+
+        let failed = result.failure.is_some();
+        results.push(result);
+        if fail_fast && failed {
+            break;
+        }
+    }
+
+    results
+}
+
+// The results of 'check all' as included in the reports of other commands,
+// the output of the checks being silenced.
+pub fn get_check_all_results(network: bool) -> Result<serde_json::Value> {
+    let results = {
+        let _silencer = StdoutSilencer::new()?;
+        run_checks(
+            network,
+            None,
+            check::DEFAULT_NETWORK_TIMEOUT,
+            None,
+            1,
+            false,
+        )
+    };
+
+    Ok(serde_json::to_value(results)?)
+}
+
+fn print_check_results(results: &[CheckResult]) {
+    println!("Summary:");
+    for result in results {
+        if result.detail.is_empty() {
+            println!(" - {}: {}", result.name, result.painted_status_name());
+        } else {
+            println!(
+                " - {}: {} ({})",
+                result.name,
+                result.painted_status_name(),
+                result.detail
+            );
+        }
+
+        for feature in result.cpu_features.iter().flatten() {
+            println!(
+                "   - {}: {}{}",
+                feature.name,
+                if feature.present {
+                    "present"
+                } else {
+                    "missing"
+                },
+                if feature.required { " (required)" } else { "" }
+            );
+        }
+    }
+}
+
+fn print_check_results_table(results: &[CheckResult]) {
+    let mut table = Table::new(&["NAME", "RESULT", "DETAIL"]);
+    for result in results {
+        table.add_row(vec![
+            result.name.clone(),
+            result.painted_status_name(),
+            result.detail.clone(),
+        ]);
+    }
+
+    print!("{}", table);
+}
+
+// The configuration of the hypervisor given with --hypervisor.
+fn get_hypervisor_config(config_file: Option<&str>, name: HypervisorName) -> Result<Hypervisor> {
+    let (mut toml_config, _) = utils::load_config(config_file)?;
+    let section = utils::select_hypervisor(&mut toml_config, name)?;
+
+    Ok(toml_config.hypervisor.remove(&section).unwrap_or_default())
+}
+
+// Only warns of the guest kernel parameters which look wrong, as the
+// configuration may be right for a custom guest kernel.
+fn check_kernel_params_result(
+    config_file: Option<&str>,
+    hypervisor: Option<HypervisorName>,
+) -> CheckResult {
+    let name = CheckType::KernelParams.to_string();
+    let params = utils::load_config(config_file).and_then(|(mut toml_config, _)| {
+        if let Some(name) = hypervisor {
+            utils::select_hypervisor(&mut toml_config, name)?;
+        }
+        let config = env_ops::default_hypervisor_config(&toml_config)?;
+        env_ops::get_effective_kernel_params(&toml_config, &config.boot_info.kernel_params)
+    });
+
+    match params {
+        Ok(params) => CheckResult::from_warnings(&name, check::check_kernel_params(&params)),
+        Err(e) => CheckResult::skip(&name, &format!("{:#}", e), None),
+    }
+}
+
+// The handler of the kernel parameters check, with the default
+// configuration.
+fn check_kernel_params(_args: &str) -> Result<()> {
+    check_kernel_params_result(None, None).into_result()
+}
+
+// Fails when the guest memory cannot be backed by the free hugepages, only
+// warns of the hugepages no guest will use.
+fn check_hugepages_result(
+    config_file: Option<&str>,
+    hypervisor: Option<HypervisorName>,
+) -> CheckResult {
+    let name = CheckType::Hugepages.to_string();
+    let memory = utils::load_config(config_file).and_then(|(mut toml_config, _)| {
+        if let Some(name) = hypervisor {
+            utils::select_hypervisor(&mut toml_config, name)?;
+        }
+        let config = env_ops::default_hypervisor_config(&toml_config)?;
+        Ok((
+            config.memory_info.enable_hugepages,
+            config.memory_info.default_memory,
+        ))
+    });
+    let (enabled, memory_mb) = match memory {
+        Ok(memory) => memory,
+        Err(e) => return CheckResult::skip(&name, &format!("{:#}", e), None),
+    };
+
+    let info = match check::probe_hugepages(
+        Path::new(check::PROC_MEMINFO_PATH),
+        Path::new(check::SYS_KERNEL_MM_HUGEPAGES_PATH),
+        enabled,
+        memory_mb,
+    ) {
+        Ok(info) => info,
+        Err(e) => return CheckResult::from_result(&name, FailureClass::MissingDependency, Err(e)),
+    };
+
+    let mut result = match check::check_hugepages(&info) {
+        Ok(warnings) => CheckResult::from_warnings(&name, warnings),
+        Err(e) => CheckResult::from_result(&name, FailureClass::MissingDependency, Err(e)),
+    };
+    result.hugepages = Some(info);
+
+    result
+}
+
+// The handler of the hugepages check, with the default configuration.
+fn check_hugepages(_args: &str) -> Result<()> {
+    check_hugepages_result(None, None).into_result()
+}
+
+// Runs one of CONFIG_CHECKS with the given configuration file.
+fn run_config_check(
+    check_type: CheckType,
+    config_file: Option<&str>,
+    hypervisor: Option<HypervisorName>,
+) -> CheckResult {
+    match check_type {
+        CheckType::KernelParams => check_kernel_params_result(config_file, hypervisor),
+        CheckType::Hugepages => check_hugepages_result(config_file, hypervisor),
+        _ => CheckResult::skip(&check_type.to_string(), "not a configuration check", None),
+    }
+}
+
+// Dragonball is built into the runtime, the other hypervisors are programs.
+fn check_hypervisor(name: HypervisorName, hypervisor: &Hypervisor) -> Result<()> {
+    if name == HypervisorName::Dragonball {
+        return Ok(());
+    }
+
+    if hypervisor.path.is_empty() {
+        return Err(anyhow!("no path is configured for {}", name.name()));
+    }
+
+    let metadata = fs::metadata(&hypervisor.path)
+        .with_context(|| format!("{} not found at {}", name.name(), hypervisor.path))?;
+    if !metadata.is_file() {
+        return Err(anyhow!("{} is not a file", hypervisor.path));
+    }
+
+    Ok(())
+}
+
+fn handle_check_all(
+    config_file: Option<&str>,
+    args: &CheckAllArgs,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let start = Instant::now();
+    check_config(config_file).map_err(KataCtlError::Config)?;
+    let hypervisor = args
+        .hypervisor
+        .map(|name| get_hypervisor_config(config_file, name).map(|config| (name, config)))
+        .transpose()
+        .map_err(KataCtlError::Config)?;
+
+    let network = !args.skip_network;
+    let interface = args.interface.as_deref();
+    let network_timeout = Duration::from_secs(args.network_timeout);
+    // A missing interface is a mistake of the command line, not a failure of
+    // the network checks.
+    if let Some(interface) = interface {
+        check::check_network_interface(Path::new(check::SYS_CLASS_NET_PATH), interface)
+            .map_err(KataCtlError::Config)?;
+    }
+    let format = format.with_json(args.json);
+    let mut cache = if args.cache {
+        CheckResultCache::open(args.refresh)
+    } else {
+        None
+    };
+
+    // The output of the checks would get in the way of the results.
+    let workers = check_workers(args.parallel);
+    let mut results = if args.quiet || format != OutputFormat::Human {
+        let _silencer = StdoutSilencer::new()?;
+        run_checks(
+            network,
+            interface,
+            network_timeout,
+            cache.as_mut(),
+            workers,
+            args.fail_fast,
+        )
+    } else {
+        run_checks(
+            network,
+            interface,
+            network_timeout,
+            cache.as_mut(),
+            workers,
+            args.fail_fast,
+        )
+    };
+
+    if let Some(cache) = &cache {
+        cache.store();
+    }
+
+    let stopped = |results: &[CheckResult]| {
+        args.fail_fast && results.iter().any(|result| result.failure.is_some())
+    };
+
+    if let Some((name, config)) = hypervisor.as_ref().filter(|_| !stopped(&results)) {
+        results.push(timed_check(|| {
+            CheckResult::from_result(
+                HYPERVISOR_CHECK,
+                FailureClass::MissingDependency,
+                check_hypervisor(*name, config),
+            )
+        }));
+    }
+
+    for check in CONFIG_CHECKS {
+        if stopped(&results) {
+            break;
+        }
+        results.push(timed_check(|| {
+            run_config_check(check.name, config_file, args.hypervisor)
+        }));
+    }
+
+    if !args.no_external && !stopped(&results) {
+        results.extend(run_external_checks(
+            Path::new(&args.external_dir),
+            EXTERNAL_CHECK_TIMEOUT,
+            args.fail_fast,
+        ));
+    }
+
+    // The flags are read again rather than cached, they are cheap to get.
+    if args.cpu_features {
+        let cpu_type = CheckType::Cpu.to_string();
+        if let Some(result) = results.iter_mut().find(|result| result.name == cpu_type) {
+            result.cpu_features = Some(get_cpu_features()?);
+        }
+    }
+
+    let report = CheckAllReport {
+        checks: &results,
+        summary: CheckSummary::new(&results, start.elapsed()),
+    };
+    if !args.quiet && !output::print_structured(format, &report)? {
+        match format {
+            OutputFormat::Table => print_check_results_table(&results),
+            _ => print_check_results(&results),
+        }
+        println!("{}", format_check_summary(&report.summary));
+    }
+
+    if let Some(result) = results.iter().find(|result| result.failure.is_some()) {
+        let class = result.failure.unwrap();
+        // Only the stopping check is worth naming, the failures were
+        // otherwise already reported with the results.
+        if args.fail_fast {
+            return Err(class.into_error(anyhow!(
+                "check {} failed: {}",
+                result.name,
+                result.detail
+            )));
+        }
+        return Err(KataCtlError::Status(class.exit_code()));
+    }
+
+    Ok(())
 }
 
 fn get_client_cmd_details() -> Vec<String> {
     let mut cmds = Vec::new();
-    if let Some(check_list) = get_checks() {
-        for cmd in check_list {
-            cmds.push(format!("{} ({}. Mode: {})", cmd.name, cmd.descr, cmd.perm));
-        }
+    for cmd in all_checks() {
+        cmds.push(format!("{} ({}. Mode: {})", cmd.name, cmd.descr, cmd.perm));
     }
 
     cmds
 }
 
-fn print_check_list() -> Result<()> {
+fn get_check_list_items() -> Vec<CheckListItem> {
+    let mut items = Vec::new();
+    for cmd in all_checks() {
+        items.push(CheckListItem {
+            name: cmd.name.to_string(),
+            description: cmd.descr.to_string(),
+            needs_network: cmd.name == CheckType::Network,
+            needs_root: cmd.perm == PermissionType::Privileged,
+        });
+    }
+
+    items
+}
+
+fn print_check_list(format: OutputFormat) -> Result<()> {
+    let items = get_check_list_items();
+    if output::print_structured(format, &items)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table {
+        let mut table = Table::new(&["NAME", "DESCRIPTION", "NETWORK", "ROOT"]);
+        for item in items {
+            table.add_row(vec![
+                item.name,
+                item.description,
+                item.needs_network.to_string(),
+                item.needs_root.to_string(),
+            ]);
+        }
+        print!("{}", table);
+
+        return Ok(());
+    }
+
     let cmds = get_client_cmd_details();
 
     if cmds.is_empty() {
@@ -67,61 +1383,833 @@ fn print_check_list() -> Result<()> {
     Ok(())
 }
 
-pub fn handle_check(checkcmd: CheckArgument) -> Result<()> {
-    let command = checkcmd.command;
+// Ensure a user specified configuration is usable before running checks.
+fn check_config(config_file: Option<&str>) -> Result<()> {
+    if let Some(path) = config_file {
+        let (toml_config, _) = utils::load_config(Some(path))?;
+        toml_config
+            .validate()
+            .with_context(|| format!("invalid configuration {}", path))?;
+    }
 
-    match command {
-        CheckSubCommand::All => {
-            // run architecture-specific tests
-            handle_builtin_check(CheckType::Cpu, "")?;
+    Ok(())
+}
 
-            // run code that uses network checks
-            check::run_network_checks()?;
+fn print_releases(
+    releases: &[check::Release],
+    include_prerelease: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    if output::print_structured(format, releases)? {
+        return Ok(());
+    }
 
-            // run kernel module checks
-            handle_builtin_check(CheckType::KernelModules, "")?;
+    if format == OutputFormat::Table {
+        let mut table = Table::new(&["RELEASE", "TYPE", "CREATED", "URL"]);
+        for release in releases {
+            let release_type = if release.prerelease {
+                "prerelease"
+            } else {
+                "official"
+            };
+            table.add_row(vec![
+                release.tag_name.clone(),
+                release_type.to_string(),
+                release.created_at.clone(),
+                release.tarball_url.clone(),
+            ]);
         }
+        print!("{}", table);
 
-        CheckSubCommand::NoNetworkChecks => {
-            // run architecture-specific tests
-            handle_builtin_check(CheckType::Cpu, "")?;
+        return Ok(());
+    }
+
+    if !include_prerelease {
+        println!("Official Releases...");
+        for release in releases {
+            println!(
+                "Release {:15}; created {} ; {}",
+                release.tag_name, release.created_at, release.tarball_url
+            );
         }
 
-        CheckSubCommand::CheckVersionOnly => {
-            handle_version()?;
+        return Ok(());
+    }
+
+    for release in releases {
+        if !release.prerelease {
+            println!(
+                "Official  : Release {:15}; created {} ; {}",
+                release.tag_name, release.created_at, release.tarball_url
+            );
+        } else {
+            println!(
+                "PreRelease: Release {:15}; created {} ; {}",
+                release.tag_name, release.created_at, release.tarball_url
+            );
         }
+    }
+
+    Ok(())
+}
 
-        CheckSubCommand::List => {
-            print_check_list()?;
+fn list_releases(
+    args: &CheckReleaseArgs,
+    include_prerelease: bool,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let cache = check::ReleaseCache::new(args.cache_ttl, args.no_cache);
+    let releases = check::get_releases(
+        &cache,
+        args.proxy.as_deref(),
+        include_prerelease,
+        Duration::from_secs(args.network_timeout),
+    )
+    .map_err(KataCtlError::Network)?;
+
+    Ok(print_releases(&releases, include_prerelease, format)?)
+}
+
+pub fn handle_check(
+    checkcmd: CheckArgument,
+    config_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let command = checkcmd.command;
+
+    match command {
+        CheckSubCommand::All(args) => {
+            handle_check_all(config_file, &args, format)?;
         }
-        CheckSubCommand::OnlyListReleases => {
+
+        CheckSubCommand::NoNetworkChecks(mut args) => {
+            eprintln!(
+                "WARNING: 'check no-network-checks' is deprecated, use 'check all --skip-network' instead"
+            );
+            args.skip_network = true;
+            handle_check_all(config_file, &args, format)?;
+        }
+
+        CheckSubCommand::CheckVersionOnly(args) => {
+            let cache = check::ReleaseCache::new(args.cache_ttl, args.no_cache);
+            let timeout = Duration::from_secs(args.network_timeout);
+            print_latest_release(&cache, args.proxy.as_deref(), timeout, format)
+                .map_err(KataCtlError::Network)?;
+        }
+
+        CheckSubCommand::List(args) => {
+            print_check_list(format.with_json(args.json))?;
+        }
+        CheckSubCommand::Run(args) => {
+            run_single_check(config_file, &args, format)?;
+        }
+        CheckSubCommand::OnlyListReleases(args) => {
             // retrieve official release
-            check::check_official_releases()?;
+            list_releases(&args, false, format)?;
         }
-        CheckSubCommand::IncludeAllReleases => {
+        CheckSubCommand::IncludeAllReleases(args) => {
             // retrieve ALL releases including prerelease
-            check::check_all_releases()?;
+            list_releases(&args, true, format)?;
         }
     }
 
     Ok(())
 }
 
-pub fn handle_factory() -> Result<()> {
+fn print_version() -> Result<()> {
+    let version = version::get().unwrap();
+
+    println!("{} version {:?} (type: rust)", NAME, version);
     Ok(())
 }
 
-pub fn handle_iptables(_args: IptablesCommand) -> Result<()> {
-    Ok(())
+// LatestRelease is the structured output of 'kata-ctl check check-version-only'.
+#[derive(Debug, Serialize)]
+struct LatestRelease {
+    version: String,
+    latest_official_release: Option<String>,
 }
 
-pub fn handle_metrics(_args: MetricsCommand) -> Result<()> {
+fn print_latest_release(
+    cache: &check::ReleaseCache,
+    proxy: Option<&str>,
+    timeout: Duration,
+    format: OutputFormat,
+) -> Result<()> {
+    let version = version::get().unwrap();
+
+    if format.is_structured() {
+        let release = LatestRelease {
+            latest_official_release: check::get_latest_official_release(cache, proxy, timeout)?,
+            version,
+        };
+        output::print_structured(format, &release)?;
+
+        return Ok(());
+    }
+
+    print_version()?;
+
+    match check::get_latest_official_release(cache, proxy, timeout)? {
+        Some(latest) if latest == version => {
+            println!("{} is the latest official release", version)
+        }
+        Some(latest) => println!("Latest official release is {}", latest),
+        None => println!("No official release found"),
+    }
+
     Ok(())
 }
 
-pub fn handle_version() -> Result<()> {
-    let version = version::get().unwrap();
+// ComponentVersions is the output of 'kata-ctl version --json', a component
+// being null when its version cannot be detected.
+#[derive(Debug, Serialize)]
+struct ComponentVersions {
+    kata_ctl: Option<String>,
+    runtime: Option<String>,
+    agent: Option<String>,
+    hypervisor: Option<String>,
+    #[serde(flatten)]
+    update: Option<UpdateStatus>,
+}
+
+// UpdateStatus is added to the output of 'kata-ctl version --check-update'.
+#[derive(Debug, Clone, Serialize)]
+struct UpdateStatus {
+    latest_official_release: Option<String>,
+    update_available: bool,
+}
+
+// Splits a release tag such as "3.1.0" or "3.2.0-rc0" into its numbers
+// and its pre-release part.
+fn parse_release(tag: &str) -> Option<([u64; 3], Option<&str>)> {
+    let tag = tag.trim().trim_start_matches('v');
+    let (numbers, pre) = match tag.split_once('-') {
+        Some((numbers, pre)) => (numbers, Some(pre)),
+        None => (tag, None),
+    };
+
+    let mut parts = numbers.split('.');
+    let mut release = [0; 3];
+    for number in release.iter_mut() {
+        *number = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((release, pre))
+}
+
+// A release is newer than the pre-releases of the same version. The tags
+// which cannot be parsed are newer when they differ.
+fn is_newer_release(latest: &str, current: &str) -> bool {
+    let (latest_release, current_release) = match (parse_release(latest), parse_release(current)) {
+        (Some(latest), Some(current)) => (latest, current),
+        _ => return latest.trim() != current.trim(),
+    };
+
+    match latest_release.0.cmp(&current_release.0) {
+        Ordering::Equal => match (latest_release.1, current_release.1) {
+            (None, Some(_)) => true,
+            (Some(latest), Some(current)) => latest > current,
+            _ => false,
+        },
+        ordering => ordering == Ordering::Greater,
+    }
+}
+
+// Offline, the update cannot be checked and only the local version is
+// reported.
+fn get_update_status(args: &VersionArgument) -> Option<UpdateStatus> {
+    let cache = check::ReleaseCache::new(args.cache_ttl, args.no_cache);
+    let timeout = check::DEFAULT_NETWORK_TIMEOUT;
+    match check::get_latest_official_release(&cache, args.proxy.as_deref(), timeout) {
+        Ok(latest) => Some(UpdateStatus {
+            update_available: latest
+                .as_deref()
+                .map_or(false, |latest| is_newer_release(latest, version::VERSION)),
+            latest_official_release: latest,
+        }),
+        Err(e) => {
+            eprintln!("WARNING: cannot check for a newer release: {:#}", e);
+            None
+        }
+    }
+}
+
+fn print_update_status(update: &UpdateStatus) {
+    match &update.latest_official_release {
+        Some(latest) if update.update_available => println!(
+            "A newer official release is available: {} (installed: {})",
+            latest,
+            version::VERSION
+        ),
+        Some(latest) => println!(
+            "{} is up to date, the latest official release is {}",
+            version::VERSION,
+            latest
+        ),
+        None => println!("No official release found"),
+    }
+}
+
+// Only keep the first line of the output of '<cmd> --version'.
+pub fn parse_command_version(output: &str) -> Option<String> {
+    let version = output.lines().next().unwrap_or_default().trim();
+    if version.is_empty() || version == "unknown" {
+        return None;
+    }
+
+    Some(version.to_string())
+}
+
+fn get_component_versions(config_file: Option<&str>) -> ComponentVersions {
+    let runtime = RUNTIME_BINARIES.iter().find_map(|cmd| {
+        env_ops::get_command_version(cmd)
+            .ok()
+            .and_then(|output| parse_command_version(&output))
+    });
+
+    let hypervisor = utils::load_config(config_file)
+        .and_then(|(toml_config, _)| env_ops::get_hypervisor_info(&toml_config, true))
+        .ok()
+        .and_then(|(hypervisor_info, _, _, _)| parse_command_version(&hypervisor_info.version));
+
+    ComponentVersions {
+        kata_ctl: version::get().ok(),
+        runtime,
+        // The agent runs inside the guest image, so its version is not known
+        // without a running sandbox.
+        agent: None,
+        hypervisor,
+        update: None,
+    }
+}
+
+pub fn handle_version(
+    args: VersionArgument,
+    config_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let format = format.with_json(args.json);
+    let update = if args.check_update {
+        get_update_status(&args)
+    } else {
+        None
+    };
+
+    if format.is_structured() {
+        let mut versions = get_component_versions(config_file);
+        versions.update = update.clone();
+        output::print_structured(format, &versions)?;
+    } else {
+        print_version()?;
+        if let Some(update) = &update {
+            print_update_status(update);
+        }
+    }
+
+    // The update was already reported.
+    if update.map_or(false, |update| update.update_available) {
+        return Err(KataCtlError::Status(EXIT_CODE_UPDATE_AVAILABLE));
+    }
 
-    println!("{} version {:?} (type: rust)", NAME, version);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_normalize_check_name() {
+        assert_eq!(normalize_check_name("KernelModules"), "kernelmodules");
+        assert_eq!(normalize_check_name("kernel-modules"), "kernelmodules");
+        assert_eq!(normalize_check_name("kernel_modules"), "kernelmodules");
+    }
+
+    #[test]
+    fn test_check_result() {
+        let result = CheckResult::from_result("Cpu", FailureClass::HardwareUnsupported, Ok(()));
+        assert_eq!(result.status, CheckStatus::Pass);
+        assert_eq!(result.failure, None);
+
+        let result = CheckResult::from_result(
+            "KernelModules",
+            FailureClass::MissingDependency,
+            Err(anyhow!("kvm not loaded")),
+        );
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(result.detail, "kvm not loaded");
+        assert_eq!(
+            result.failure.map(|f| f.exit_code()),
+            Some(EXIT_CODE_MISSING_DEPENDENCY)
+        );
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "name": "KernelModules",
+                "result": "fail",
+                "detail": "kvm not loaded",
+            })
+        );
+
+        let mut result = CheckResult::from_result("Cpu", FailureClass::HardwareUnsupported, Ok(()));
+        result.cpu_features = Some(vec![CpuFeature {
+            name: "vmx".to_string(),
+            present: true,
+            required: true,
+        }]);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({
+                "name": "Cpu",
+                "result": "pass",
+                "detail": "",
+                "cpu_features": [{ "name": "vmx", "present": true, "required": true }],
+            })
+        );
+
+        let mut result = CheckResult::from_warnings("Hugepages", vec![]);
+        result.hugepages = Some(check::HugepagesInfo {
+            default_size_kb: Some(2048),
+            pools: vec![check::HugepagesPool {
+                size_kb: 2048,
+                total: 1024,
+                free: 1024,
+                ..Default::default()
+            }],
+            enabled: true,
+            needed: 1024,
+        });
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["hugepages"]["needed"], 1024);
+        assert_eq!(json["hugepages"]["pools"][0]["free"], 1024);
+
+        // the hugepages check needs the configuration
+        let result = check_hugepages_result(Some("/no/such/configuration.toml"), None);
+        assert_eq!(result.status, CheckStatus::Skip);
+        assert!(result.hugepages.is_none());
+
+        assert_eq!(
+            failure_class(&CheckType::Cpu).exit_code(),
+            EXIT_CODE_HARDWARE_UNSUPPORTED
+        );
+        assert_eq!(
+            FailureClass::PermissionDenied.exit_code(),
+            EXIT_CODE_PERMISSION_DENIED
+        );
+    }
+
+    #[test]
+    fn test_check_summary() {
+        let results = vec![
+            CheckResult::from_result("Cpu", FailureClass::HardwareUnsupported, Ok(())),
+            CheckResult::from_result("plugin:10-ok", FailureClass::External, Ok(())),
+            CheckResult::from_result(
+                "KernelModules",
+                FailureClass::MissingDependency,
+                Err(anyhow!("kvm not loaded")),
+            ),
+            CheckResult::skip("Network", "network checks are disabled", None),
+            CheckResult::from_warnings("KernelParams", vec!["a".to_string(), "b".to_string()]),
+        ];
+        assert_eq!(results[4].status, CheckStatus::Warn);
+        assert_eq!(results[4].detail, "a; b");
+        assert!(results[4].failure.is_none());
+        assert_eq!(
+            CheckResult::from_warnings("KernelParams", vec![]).status,
+            CheckStatus::Pass
+        );
+
+        let summary = CheckSummary::new(&results, Duration::from_millis(1234));
+        assert_eq!(
+            summary,
+            CheckSummary {
+                total: 5,
+                passed: 2,
+                warned: 1,
+                failed: 1,
+                skipped: 1,
+                duration_ms: 1234.0,
+            }
+        );
+        assert_eq!(
+            format_check_summary(&summary),
+            "5 checks: 2 passed, 1 warned, 1 failed, 1 skipped in 1.23s"
+        );
+        assert_eq!(
+            format_check_summary(&CheckSummary::new(&[], Duration::ZERO)),
+            "0 checks: 0 passed, 0 warned, 0 failed, 0 skipped in 0.00s"
+        );
+
+        let result = timed_check(|| CheckResult::skip("Network", "", None));
+        assert!(result.duration_ms.is_some());
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json["duration_ms"].is_number());
+    }
+
+    #[test]
+    fn test_hardware_fingerprint() {
+        let fingerprint = hardware_fingerprint("Xeon", "6.1.0", &["vmx", "kvm", "kvm_intel"]);
+        assert_eq!(fingerprint, "cpu=Xeon;kernel=6.1.0;virt=kvm,kvm_intel,vmx");
+
+        // the order of the features does not matter
+        assert_eq!(
+            hardware_fingerprint("Xeon", "6.1.0\n", &["kvm_intel", "vmx", "kvm", "kvm"]),
+            fingerprint
+        );
+
+        for (cpu_model, kernel_version, features) in [
+            ("EPYC", "6.1.0", &["vmx", "kvm", "kvm_intel"][..]),
+            ("Xeon", "6.2.0", &["vmx", "kvm", "kvm_intel"][..]),
+            ("Xeon", "6.1.0", &["vmx", "kvm"][..]),
+        ] {
+            assert_ne!(
+                hardware_fingerprint(cpu_model, kernel_version, features),
+                fingerprint,
+                "{} {} {:?}",
+                cpu_model,
+                kernel_version,
+                features
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_virt_cpu_flags() {
+        let cpu_info = "processor\t: 0\nflags\t\t: fpu vme lm vmx sse4_1\nbugs\t\t: spectre_v1\n";
+        assert_eq!(get_virt_cpu_flags(cpu_info), vec!["vmx"]);
+
+        let cpu_info = "vendor_id       : IBM/S390\nfeatures\t: esan3 zarch sie\n";
+        assert_eq!(get_virt_cpu_flags(cpu_info), vec!["sie"]);
+
+        assert!(get_virt_cpu_flags("flags : fpu lm\n").is_empty());
+    }
+
+    #[test]
+    fn test_run_jobs() {
+        let started = Mutex::new(Vec::new());
+        let job = |i: usize, after: Vec<usize>| Job {
+            run: Box::new({
+                let started = &started;
+                move || {
+                    started.lock().unwrap().push(i);
+                    i * 10
+                }
+            }),
+            after,
+        };
+
+        for workers in [1, 2, 8] {
+            started.lock().unwrap().clear();
+            let jobs = vec![
+                job(0, vec![2]),
+                job(1, vec![0, 3]),
+                job(2, vec![]),
+                job(3, vec![]),
+                // a cycle does not deadlock
+                job(4, vec![5]),
+                job(5, vec![4]),
+            ];
+            assert_eq!(
+                run_jobs(jobs, workers),
+                vec![Ok(0), Ok(10), Ok(20), Ok(30), Ok(40), Ok(50)],
+                "{}",
+                workers
+            );
+
+            let started = started.lock().unwrap();
+            let position = |i| started.iter().position(|j| *j == i).unwrap();
+            assert!(position(2) < position(0), "{}: {:?}", workers, started);
+            assert!(position(0) < position(1), "{}: {:?}", workers, started);
+            assert!(position(3) < position(1), "{}: {:?}", workers, started);
+        }
+
+        assert!(run_jobs::<usize>(Vec::new(), 4).is_empty());
+
+        // a panicking job fails alone, its dependents still being run
+        for workers in [1, 2] {
+            let jobs = vec![
+                Job {
+                    run: Box::new(|| -> usize { panic!("job 0") }),
+                    after: vec![],
+                },
+                job(1, vec![0]),
+                job(2, vec![]),
+            ];
+            assert_eq!(
+                run_jobs(jobs, workers),
+                vec![Err("job 0".to_string()), Ok(10), Ok(20)],
+                "{}",
+                workers
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_result_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kata-ctl").join(CHECK_CACHE_FILE);
+
+        let mut cache = CheckResultCache::load(path.clone(), "fp1".to_string(), false);
+        assert!(cache.get(&CheckType::Cpu).is_none());
+
+        cache.insert(&CheckResult::from_result(
+            "Cpu",
+            FailureClass::HardwareUnsupported,
+            Err(anyhow!("no vmx")),
+        ));
+        cache.insert(&CheckResult::skip(
+            "KernelModules",
+            "super-user privileges are required",
+            Some(FailureClass::PermissionDenied),
+        ));
+        cache.store();
+
+        let cache = CheckResultCache::load(path.clone(), "fp1".to_string(), false);
+        let result = cache.get(&CheckType::Cpu).unwrap();
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(result.detail, "no vmx");
+        assert_eq!(result.failure, Some(FailureClass::HardwareUnsupported));
+        assert!(result.cached);
+        assert_eq!(result.status_name(), "fail (cached)");
+        assert!(cache.get(&CheckType::KernelModules).is_none());
+
+        // a different hardware, or a refresh, runs the checks again
+        let cache = CheckResultCache::load(path.clone(), "fp2".to_string(), false);
+        assert!(cache.get(&CheckType::Cpu).is_none());
+        let cache = CheckResultCache::load(path, "fp1".to_string(), true);
+        assert!(cache.get(&CheckType::Cpu).is_none());
+    }
+
+    #[test]
+    fn test_parse_command_version() {
+        assert_eq!(
+            parse_command_version("QEMU emulator version 7.2.0\nCopyright (c) 2003-2022\n"),
+            Some("QEMU emulator version 7.2.0".to_string())
+        );
+        assert_eq!(parse_command_version(""), None);
+        assert_eq!(parse_command_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_find_check() {
+        let e = find_check("no-such-check").err().unwrap();
+        assert!(format!("{}", e).contains("check list"));
+
+        for item in get_check_list_items() {
+            let check = find_check(&item.name.to_lowercase()).unwrap();
+            assert_eq!(check.name.to_string(), item.name);
+        }
+
+        // The configuration checks are not architecture specific.
+        let check = find_check("kernel-params").unwrap();
+        assert_eq!(check.name, CheckType::KernelParams);
+        let check = find_check("hugepages").unwrap();
+        assert_eq!(check.name, CheckType::Hugepages);
+    }
+
+    #[test]
+    fn test_run_config_check() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("configuration.toml");
+        fs::write(
+            &config,
+            "[hypervisor.qemu]\nkernel_params = \"console=ttyS0 console=hvc0\"\n[agent.kata]\n[runtime]\nagent_name = \"kata\"\nhypervisor_name = \"qemu\"\n",
+        )
+        .unwrap();
+        let config = config.display().to_string();
+
+        let result = run_config_check(CheckType::KernelParams, Some(&config), None);
+        assert_eq!(result.name, "KernelParams");
+        assert_eq!(result.status, CheckStatus::Warn, "{:?}", result);
+        assert!(result.detail.contains("console"), "{:?}", result);
+
+        // An unconfigured hypervisor skips the check.
+        let result = run_config_check(
+            CheckType::KernelParams,
+            Some(&config),
+            Some(HypervisorName::CloudHypervisor),
+        );
+        assert_eq!(result.status, CheckStatus::Skip, "{:?}", result);
+
+        // The hugepages are not enabled, so that the check does not fail
+        // whatever the hugepages of the host.
+        let result = run_config_check(CheckType::Hugepages, Some(&config), None);
+        assert_eq!(result.name, "Hugepages");
+        assert!(result.failure.is_none(), "{:?}", result);
+        assert!(result.hugepages.is_some(), "{:?}", result);
+    }
+
+    #[test]
+    fn test_check_hypervisor() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("qemu-system-x86_64");
+        fs::write(&path, "").unwrap();
+
+        let mut hypervisor = Hypervisor {
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        assert!(check_hypervisor(HypervisorName::Qemu, &hypervisor).is_ok());
+
+        hypervisor.path = dir.path().display().to_string();
+        assert!(check_hypervisor(HypervisorName::Qemu, &hypervisor).is_err());
+
+        hypervisor.path = dir.path().join("missing").display().to_string();
+        assert!(check_hypervisor(HypervisorName::CloudHypervisor, &hypervisor).is_err());
+
+        // dragonball does not need a binary
+        hypervisor.path = String::new();
+        assert!(check_hypervisor(HypervisorName::Qemu, &hypervisor).is_err());
+        assert!(check_hypervisor(HypervisorName::Dragonball, &hypervisor).is_ok());
+    }
+
+    #[test]
+    fn test_run_single_check_hypervisor() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("configuration.toml");
+        fs::write(
+            &config,
+            "[hypervisor.qemu]\npath = \"/no/such/qemu\"\n[agent.kata]\n[runtime]\nagent_name = \"kata\"\nhypervisor_name = \"qemu\"\n",
+        )
+        .unwrap();
+        let config = config.display().to_string();
+
+        let check = get_check_list_items()
+            .into_iter()
+            .find(|item| !item.needs_root)
+            .unwrap();
+        let args = CheckRunArgs {
+            name: check.name,
+            hypervisor: Some(HypervisorName::Qemu),
+        };
+
+        // The missing binary fails the check before it is run.
+        let e = run_single_check(Some(&config), &args, OutputFormat::Human).unwrap_err();
+        assert!(matches!(e, KataCtlError::MissingDependency(_)), "{:?}", e);
+        assert!(format!("{:?}", e).contains("/no/such/qemu"), "{:?}", e);
+
+        // The hypervisor must be configured.
+        let args = CheckRunArgs {
+            hypervisor: Some(HypervisorName::CloudHypervisor),
+            ..args
+        };
+        let e = run_single_check(Some(&config), &args, OutputFormat::Human).unwrap_err();
+        assert!(matches!(e, KataCtlError::Config(_)), "{:?}", e);
+    }
+
+    #[test]
+    fn test_is_newer_release() {
+        let tests = &[
+            ("3.1.0", "3.0.2", true),
+            ("3.1.0", "3.1.0", false),
+            ("3.0.2", "3.1.0", false),
+            ("3.10.0", "3.9.1", true),
+            ("v3.1.0", "3.1.0", false),
+            // a release is newer than its pre-releases
+            ("3.2.0", "3.2.0-alpha1", true),
+            ("3.2.0-rc0", "3.2.0-alpha1", true),
+            ("3.2.0-alpha0", "3.2.0", false),
+            ("3.1.0", "3.2.0-alpha0", false),
+            // tags which cannot be parsed
+            ("latest", "3.1.0", true),
+            ("3.1", "3.1", false),
+        ];
+
+        for (i, (latest, current, newer)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?} {:?}", i, latest, current);
+            assert_eq!(is_newer_release(latest, current), *newer, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_run_external_checks() {
+        let dir = tempdir().unwrap();
+        let script = |name: &str, contents: &str, mode: u32| {
+            let path = dir.path().join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", contents)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        script("10-pass", "echo '{\"detail\": \"all good\"}'", 0o755);
+        script(
+            "20-fail",
+            "echo checking\necho '{\"detail\": \"no hugepages\"}'\nexit 3",
+            0o755,
+        );
+        script("30-status", "exit 1", 0o755);
+        script("40-invalid", "echo not json", 0o755);
+        script("50-slow", "sleep 10", 0o755);
+        script("60-no-exec", "exit 0", 0o644);
+        script(".hidden", "exit 1", 0o755);
+        script("80-writable", "exit 0", 0o777);
+        fs::create_dir(dir.path().join("70-dir")).unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        let writable = dir.path().join("80-writable");
+
+        let results = run_external_checks(dir.path(), Duration::from_secs(1), false);
+        let results: Vec<(&str, CheckStatus, &str)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.status, r.detail.as_str()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("plugin:10-pass", CheckStatus::Pass, "all good"),
+                ("plugin:20-fail", CheckStatus::Fail, "no hugepages"),
+                ("plugin:30-status", CheckStatus::Fail, "exit status 1"),
+                (
+                    "plugin:40-invalid",
+                    CheckStatus::Skip,
+                    "invalid output, the last line is not a JSON object: \"not json\": expected ident at line 1 column 2"
+                ),
+                (
+                    "plugin:50-slow",
+                    CheckStatus::Fail,
+                    "timed out after 1 seconds"
+                ),
+                ("plugin:60-no-exec", CheckStatus::Skip, "not executable"),
+                (
+                    "plugin:80-writable",
+                    CheckStatus::Skip,
+                    &format!(
+                        "not run: {} is writable by its group or others",
+                        writable.display()
+                    )
+                ),
+            ]
+        );
+
+        // nothing is run from a directory others can write to
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        let results = run_external_checks(dir.path(), Duration::from_secs(1), false);
+        assert_eq!(results.len(), 7);
+        for result in &results {
+            assert_eq!(result.status, CheckStatus::Skip, "{}", result.name);
+            assert!(
+                result
+                    .detail
+                    .ends_with("is writable by its group or others"),
+                "{}",
+                result.detail
+            );
+        }
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        // no external checks
+        assert!(
+            run_external_checks(&dir.path().join("missing"), EXTERNAL_CHECK_TIMEOUT, false)
+                .is_empty()
+        );
+
+        // the checks stop at the first failure
+        let results = run_external_checks(dir.path(), Duration::from_secs(1), true);
+        let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["plugin:10-pass", "plugin:20-fail"]);
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the cleanup command, reclaiming what crashed sandboxes
+// leave behind: their state and VM directories, with the sockets in them,
+// the mounts of their shared directory and their direct-volume records. A
+// sandbox is only cleaned up once neither its shim nor its hypervisor is
+// running anymore.
+
+use crate::args::{CleanupArgument, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::ps_ops;
+use crate::output::{self, Table};
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use kata_types::mount::{KATA_DIRECT_VOLUME_ROOT_PATH, KATA_MOUNT_INFO_FILE_NAME};
+use nix::mount::{umount2, MntFlags};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Directories of the hypervisors, holding their sockets, for runtime-rs and
+// the go runtime.
+const VM_PATHS: &[&str] = &["/run/vc/vm", "/run/vc/firecracker"];
+
+// Directory holding the directories shared with the guests, and the bind
+// mounts in them.
+const SHARED_SANDBOXES_PATH: &str = "/run/kata-containers/shared/sandboxes";
+
+const PROC_MOUNTINFO: &str = "/proc/self/mountinfo";
+
+// The paths the resources of the sandboxes are found in.
+struct CleanupPaths<'a> {
+    state_paths: &'a [&'a str],
+    vm_paths: &'a [&'a str],
+    shared_sandboxes_path: &'a str,
+    direct_volumes_path: &'a str,
+}
+
+const CLEANUP_PATHS: CleanupPaths<'static> = CleanupPaths {
+    state_paths: utils::SANDBOX_STATE_PATHS,
+    vm_paths: VM_PATHS,
+    shared_sandboxes_path: SHARED_SANDBOXES_PATH,
+    direct_volumes_path: KATA_DIRECT_VOLUME_ROOT_PATH,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupKind {
+    Unmount,
+    RemoveDirectory,
+    RemoveVolumeRecord,
+}
+
+impl CleanupKind {
+    fn name(&self) -> &'static str {
+        match self {
+            CleanupKind::Unmount => "unmount",
+            CleanupKind::RemoveDirectory => "remove directory",
+            CleanupKind::RemoveVolumeRecord => "remove direct-volume record",
+        }
+    }
+}
+
+// CleanupAction is a resource of a dead sandbox to reclaim, error being set
+// when reclaiming it failed.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CleanupAction {
+    pub sandbox_id: String,
+    pub kind: CleanupKind,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CleanupAction {
+    fn new(sandbox_id: &str, kind: CleanupKind, path: &Path) -> Self {
+        CleanupAction {
+            sandbox_id: sandbox_id.to_string(),
+            kind,
+            path: path.display().to_string(),
+            error: None,
+        }
+    }
+}
+
+// CleanupReport is the output of 'kata-ctl cleanup'.
+#[derive(Debug, Serialize)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    pub actions: Vec<CleanupAction>,
+    pub reclaimed: usize,
+}
+
+// Decode the octal escapes of the spaces, tabs, newlines and backslashes of
+// the paths of mountinfo.
+fn unescape_mount_path(path: &str) -> String {
+    path.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+// Get the mount points under a directory, the innermost ones first so that
+// they can be unmounted in order.
+fn parse_mount_points(mountinfo: &str, dir: &Path) -> Vec<PathBuf> {
+    let mut mount_points: Vec<PathBuf> = mountinfo
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .map(|path| PathBuf::from(unescape_mount_path(path)))
+        .filter(|path| path.starts_with(dir))
+        .collect();
+
+    mount_points.sort();
+    mount_points.dedup();
+    mount_points.reverse();
+
+    mount_points
+}
+
+// The sandboxes are found from all the places they leave resources in, as
+// their state directory may already be gone.
+fn find_sandbox_ids(paths: &CleanupPaths) -> Result<BTreeSet<String>> {
+    let mut ids = utils::list_sandbox_ids(paths.state_paths)?;
+    ids.extend(utils::list_sandbox_ids(&[paths.shared_sandboxes_path])?);
+
+    for record in list_volume_records(paths.direct_volumes_path)? {
+        if let Some(id) = record.file_name().and_then(|name| name.to_str()) {
+            ids.insert(id.to_string());
+        }
+    }
+
+    Ok(ids)
+}
+
+// The sandbox using a direct volume is recorded as a file named after the
+// sandbox ID in the volume directory, next to the mount info.
+fn list_volume_records(direct_volumes_path: &str) -> Result<Vec<PathBuf>> {
+    let mut records = Vec::new();
+
+    if !Path::new(direct_volumes_path).exists() {
+        return Ok(records);
+    }
+
+    for volume in fs::read_dir(direct_volumes_path)? {
+        let volume = volume?;
+        if !volume.file_type()?.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(volume.path())? {
+            let entry = entry?;
+            if entry.file_name() != KATA_MOUNT_INFO_FILE_NAME && entry.file_type()?.is_file() {
+                records.push(entry.path());
+            }
+        }
+    }
+
+    records.sort();
+
+    Ok(records)
+}
+
+// List what to reclaim for the dead sandboxes, the mounts of a directory
+// coming before its removal.
+fn plan_cleanup(
+    paths: &CleanupPaths,
+    dead_ids: &BTreeSet<String>,
+    mountinfo: &str,
+) -> Result<Vec<CleanupAction>> {
+    let mut actions = Vec::new();
+    let volume_records = list_volume_records(paths.direct_volumes_path)?;
+
+    for id in dead_ids {
+        let dirs = paths
+            .state_paths
+            .iter()
+            .chain(paths.vm_paths.iter())
+            .chain(std::iter::once(&paths.shared_sandboxes_path))
+            .map(|path| Path::new(path).join(id))
+            .filter(|dir| dir.is_dir());
+
+        for dir in dirs {
+            for mount_point in parse_mount_points(mountinfo, &dir) {
+                actions.push(CleanupAction::new(id, CleanupKind::Unmount, &mount_point));
+            }
+            actions.push(CleanupAction::new(id, CleanupKind::RemoveDirectory, &dir));
+        }
+
+        for record in &volume_records {
+            if record.file_name().and_then(|name| name.to_str()) == Some(id.as_str()) {
+                actions.push(CleanupAction::new(
+                    id,
+                    CleanupKind::RemoveVolumeRecord,
+                    record,
+                ));
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+fn run_cleanup_action(action: &CleanupAction) -> Result<()> {
+    let path = Path::new(&action.path);
+
+    match action.kind {
+        CleanupKind::Unmount => umount2(path, MntFlags::MNT_DETACH)
+            .with_context(|| format!("failed to unmount {}", action.path)),
+        CleanupKind::RemoveDirectory => {
+            // Never remove the files of a filesystem still mounted in the
+            // directory.
+            let mountinfo = fs::read_to_string(PROC_MOUNTINFO)
+                .with_context(|| format!("failed to read {}", PROC_MOUNTINFO))?;
+            if !parse_mount_points(&mountinfo, path).is_empty() {
+                return Err(anyhow!("{} still has mounts", action.path));
+            }
+
+            fs::remove_dir_all(path).with_context(|| format!("failed to remove {}", action.path))
+        }
+        CleanupKind::RemoveVolumeRecord => {
+            fs::remove_file(path).with_context(|| format!("failed to remove {}", action.path))
+        }
+    }
+}
+
+fn print_cleanup_action(action: &CleanupAction, dry_run: bool) {
+    let prefix = if dry_run { "would " } else { "" };
+
+    match &action.error {
+        Some(e) => eprintln!("WARNING: sandbox {}: {}", action.sandbox_id, e),
+        None => println!(
+            "sandbox {}: {}{} {}",
+            action.sandbox_id,
+            prefix,
+            action.kind.name(),
+            action.path
+        ),
+    }
+}
+
+fn print_cleanup_report(report: &CleanupReport, format: OutputFormat) -> Result<()> {
+    if output::print_structured(format, report)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table {
+        let mut table = Table::new(&["SANDBOX ID", "ACTION", "PATH", "ERROR"]);
+        for action in &report.actions {
+            table.add_row(vec![
+                action.sandbox_id.clone(),
+                action.kind.name().to_string(),
+                action.path.clone(),
+                action.error.clone().unwrap_or_default(),
+            ]);
+        }
+        print!("{}", table);
+
+        return Ok(());
+    }
+
+    if report.dry_run {
+        println!("{} items would be reclaimed", report.reclaimed);
+    } else {
+        println!("{} items reclaimed", report.reclaimed);
+    }
+
+    Ok(())
+}
+
+pub fn handle_cleanup(args: CleanupArgument, format: OutputFormat) -> Result<(), KataCtlError> {
+    let format = format.with_json(args.json);
+
+    // --dry-run only lists what would be reclaimed.
+    if !args.dry_run {
+        utils::require_root("cleaning up the sandboxes")?;
+    }
+
+    let paths = &CLEANUP_PATHS;
+    let ids = find_sandbox_ids(paths)?;
+    let live_ids = ps_ops::find_live_sandboxes(&ids)?;
+    let dead_ids: BTreeSet<String> = ids.difference(&live_ids).cloned().collect();
+
+    let mountinfo = fs::read_to_string(PROC_MOUNTINFO)
+        .with_context(|| format!("failed to read {}", PROC_MOUNTINFO))?;
+    let mut actions = plan_cleanup(paths, &dead_ids, &mountinfo)?;
+
+    if !args.dry_run && !actions.is_empty() {
+        utils::confirm(&format!(
+            "reclaim {} resources of {} sandboxes no longer running",
+            actions.len(),
+            dead_ids.len()
+        ))?;
+    }
+
+    for action in &mut actions {
+        if !args.dry_run {
+            action.error = run_cleanup_action(action).err().map(|e| format!("{:#}", e));
+        }
+
+        // Each action is printed once done, in case a later one hangs.
+        if format == OutputFormat::Human {
+            print_cleanup_action(action, args.dry_run);
+        }
+    }
+
+    let failed = actions
+        .iter()
+        .filter(|action| action.error.is_some())
+        .count();
+    let report = CleanupReport {
+        dry_run: args.dry_run,
+        reclaimed: actions.len() - failed,
+        actions,
+    };
+    print_cleanup_report(&report, format)?;
+
+    if failed > 0 {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "failed to reclaim {} items",
+            failed
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_mount_points() {
+        let mountinfo = "\
+22 1 0:21 / /run rw - tmpfs tmpfs rw
+50 22 0:40 / /run/sb/sb1 rw - tmpfs tmpfs rw
+51 50 8:1 /data /run/sb/sb1/mounts/a\\040b rw - ext4 /dev/sda1 rw
+52 22 8:1 /data /run/sb/sb10 rw - ext4 /dev/sda1 rw
+";
+
+        assert_eq!(
+            parse_mount_points(mountinfo, Path::new("/run/sb/sb1")),
+            vec![
+                PathBuf::from("/run/sb/sb1/mounts/a b"),
+                PathBuf::from("/run/sb/sb1"),
+            ]
+        );
+        assert!(parse_mount_points(mountinfo, Path::new("/run/sb/sb2")).is_empty());
+        assert!(parse_mount_points("", Path::new("/run")).is_empty());
+    }
+
+    #[test]
+    fn test_plan_cleanup() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let (state, vm, shared, volumes) =
+            (path("state"), path("vm"), path("shared"), path("volumes"));
+
+        for sandbox_dir in [
+            format!("{}/sb1", state),
+            format!("{}/sb2", state),
+            format!("{}/sb1", vm),
+            format!("{}/sb3", shared),
+            format!("{}/dm9sdmx1bWU/", volumes),
+        ] {
+            fs::create_dir_all(sandbox_dir).unwrap();
+        }
+        fs::write(
+            format!("{}/dm9sdmx1bWU/{}", volumes, KATA_MOUNT_INFO_FILE_NAME),
+            "{}",
+        )
+        .unwrap();
+        fs::write(format!("{}/dm9sdmx1bWU/sb2", volumes), "").unwrap();
+
+        let state_paths = [state.as_str()];
+        let vm_paths = [vm.as_str()];
+        let paths = CleanupPaths {
+            state_paths: &state_paths,
+            vm_paths: &vm_paths,
+            shared_sandboxes_path: &shared,
+            direct_volumes_path: &volumes,
+        };
+
+        let ids = find_sandbox_ids(&paths).unwrap();
+        assert_eq!(
+            ids.iter().map(|id| id.as_str()).collect::<Vec<_>>(),
+            vec!["sb1", "sb2", "sb3"]
+        );
+
+        // sb1 is alive, its resources are left alone
+        let dead_ids: BTreeSet<String> = ["sb2", "sb3"].iter().map(|id| id.to_string()).collect();
+        let mountinfo = format!("50 22 0:40 / {}/sb3/mounts rw - tmpfs tmpfs rw\n", shared);
+        let actions = plan_cleanup(&paths, &dead_ids, &mountinfo).unwrap();
+
+        let actions: Vec<(&str, CleanupKind, String)> = actions
+            .iter()
+            .map(|a| (a.sandbox_id.as_str(), a.kind, a.path.clone()))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (
+                    "sb2",
+                    CleanupKind::RemoveDirectory,
+                    format!("{}/sb2", state)
+                ),
+                (
+                    "sb2",
+                    CleanupKind::RemoveVolumeRecord,
+                    format!("{}/dm9sdmx1bWU/sb2", volumes)
+                ),
+                (
+                    "sb3",
+                    CleanupKind::Unmount,
+                    format!("{}/sb3/mounts", shared)
+                ),
+                (
+                    "sb3",
+                    CleanupKind::RemoveDirectory,
+                    format!("{}/sb3", shared)
+                ),
+            ]
+        );
+
+        let action = CleanupAction::new(
+            "sb2",
+            CleanupKind::RemoveVolumeRecord,
+            Path::new(&format!("{}/dm9sdmx1bWU/sb2", volumes)),
+        );
+        run_cleanup_action(&action).unwrap();
+        let ids = find_sandbox_ids(&paths).unwrap();
+        assert!(ids.contains("sb2"));
+        assert!(Path::new(&format!(
+            "{}/dm9sdmx1bWU/{}",
+            volumes, KATA_MOUNT_INFO_FILE_NAME
+        ))
+        .exists());
+
+        let action = CleanupAction::new(
+            "sb2",
+            CleanupKind::RemoveDirectory,
+            Path::new(&format!("{}/sb2", state)),
+        );
+        run_cleanup_action(&action).unwrap();
+        assert!(!find_sandbox_ids(&paths).unwrap().contains("sb2"));
+    }
+}
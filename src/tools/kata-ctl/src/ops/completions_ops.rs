@@ -0,0 +1,369 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Generation of the shell completion scripts of kata-ctl, and of the list
+// of its commands. Both are built from the clap definition of the command
+// line, so that they always match it.
+//
+// The sandbox IDs are only known at completion time: the bash, zsh and fish
+// scripts wrap the generated completion to run 'kata-ctl complete
+// sandbox-id' for the sandbox ID arguments.
+
+use crate::args::{
+    CompleteArgument, CompleteValue, CompletionsArgument, KataCtlCli, ListCommandsArgument,
+    OutputFormat,
+};
+use crate::error::KataCtlError;
+use crate::ops::ps_ops::{self, SandboxState};
+use crate::output::{self, Table};
+
+use anyhow::Result;
+use clap::{Command, CommandFactory};
+use clap_complete::{generate, Shell};
+use serde::Serialize;
+use std::io::{self, Write};
+
+const SANDBOX_ID_ARG: &str = "sandbox_id";
+
+const BASH_SANDBOX_ID_COMPLETION: &str = r#"
+_kata-ctl_sandbox_ids() {
+    kata-ctl complete sandbox-id 2>/dev/null
+}
+
+_kata-ctl_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local cmd="" i
+
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        if [[ ${COMP_WORDS[i]} != -* ]]; then
+            cmd="${cmd:+$cmd }${COMP_WORDS[i]}"
+        fi
+    done
+
+    case "$cmd|$prev" in
+        @OPTIONS@)
+            COMPREPLY=($(compgen -W "$(_kata-ctl_sandbox_ids)" -- "$cur"))
+            return 0
+            ;;
+    esac
+    if [[ $cur != -* ]]; then
+        case "$cmd" in
+            @POSITIONALS@)
+                COMPREPLY=($(compgen -W "$(_kata-ctl_sandbox_ids)" -- "$cur"))
+                return 0
+                ;;
+        esac
+    fi
+
+    _kata-ctl "$@"
+}
+
+complete -F _kata-ctl_dynamic -o bashdefault -o default kata-ctl
+"#;
+
+const ZSH_SANDBOX_ID_COMPLETION: &str = r#"
+_kata-ctl_sandbox_ids() {
+    compadd -- ${(f)"$(kata-ctl complete sandbox-id 2>/dev/null)"}
+}
+
+_kata-ctl_dynamic() {
+    local cmd="" i
+
+    for ((i = 2; i < CURRENT; i++)); do
+        if [[ ${words[i]} != -* ]]; then
+            cmd="${cmd:+$cmd }${words[i]}"
+        fi
+    done
+
+    case "$cmd|${words[CURRENT-1]}" in
+        (@OPTIONS@)
+            _kata-ctl_sandbox_ids
+            return
+            ;;
+    esac
+    if [[ ${words[CURRENT]} != -* ]]; then
+        case "$cmd" in
+            (@POSITIONALS@)
+                _kata-ctl_sandbox_ids
+                return
+                ;;
+        esac
+    fi
+
+    _kata-ctl "$@"
+}
+
+compdef _kata-ctl_dynamic kata-ctl
+"#;
+
+const FISH_SANDBOX_ID_COMPLETION: &str = r#"
+function __kata_ctl_sandbox_ids
+    kata-ctl complete sandbox-id 2>/dev/null
+end
+
+function __kata_ctl_using_command
+    set -l cmd
+    for word in (commandline -opc)[2..-1]
+        string match -q -- '-*' $word; or set -a cmd $word
+    end
+    for path in $argv
+        string match -q -- "* $path" " $cmd"; and return 0
+    end
+    return 1
+end
+
+complete -c kata-ctl -n "__kata_ctl_using_command @POSITIONALS@" -f -a "(__kata_ctl_sandbox_ids)"
+"#;
+
+// A sandbox ID argument of the subcommands in path, e.g. "iptables get",
+// long being its option name or None for a positional argument.
+#[derive(Debug, PartialEq)]
+struct SandboxIdArg {
+    path: String,
+    long: Option<String>,
+}
+
+fn find_sandbox_id_args(cmd: &Command, path: &str, found: &mut Vec<SandboxIdArg>) {
+    for arg in cmd.get_arguments() {
+        if arg.get_id().as_str() == SANDBOX_ID_ARG {
+            found.push(SandboxIdArg {
+                path: path.to_string(),
+                long: arg.get_long().map(|l| l.to_string()),
+            });
+        }
+    }
+
+    for subcmd in cmd.get_subcommands() {
+        let subpath = format!("{} {}", path, subcmd.get_name());
+        find_sandbox_id_args(subcmd, &subpath, found);
+    }
+}
+
+fn get_sandbox_id_args() -> Vec<SandboxIdArg> {
+    let mut found = Vec::new();
+
+    for cmd in KataCtlCli::command().get_subcommands() {
+        find_sandbox_id_args(cmd, cmd.get_name(), &mut found);
+    }
+
+    found
+}
+
+// A shell case pattern matching value, after the values of the global
+// options too.
+fn case_pattern(value: &str) -> String {
+    format!("\"{0}\"|*\" {0}\"", value)
+}
+
+fn sandbox_id_completion(shell: Shell, args: &[SandboxIdArg]) -> Option<String> {
+    let positionals = args.iter().filter(|arg| arg.long.is_none());
+    let options = args
+        .iter()
+        .filter_map(|arg| arg.long.as_ref().map(|long| (&arg.path, long)));
+
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            let template = if shell == Shell::Bash {
+                BASH_SANDBOX_ID_COMPLETION
+            } else {
+                ZSH_SANDBOX_ID_COMPLETION
+            };
+            let positionals: Vec<String> = positionals.map(|arg| case_pattern(&arg.path)).collect();
+            let options: Vec<String> = options
+                .map(|(path, long)| case_pattern(&format!("{}|--{}", path, long)))
+                .collect();
+
+            Some(
+                template
+                    .replace("@OPTIONS@", &options.join("|"))
+                    .replace("@POSITIONALS@", &positionals.join("|")),
+            )
+        }
+        Shell::Fish => {
+            let positionals: Vec<String> =
+                positionals.map(|arg| format!("'{}'", arg.path)).collect();
+            let mut completion =
+                FISH_SANDBOX_ID_COMPLETION.replace("@POSITIONALS@", &positionals.join(" "));
+            for (path, long) in options {
+                completion.push_str(&format!(
+                    "complete -c kata-ctl -n \"__kata_ctl_using_command '{}'\" -l {} -x -a \"(__kata_ctl_sandbox_ids)\"\n",
+                    path, long
+                ));
+            }
+
+            Some(completion)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub about: String,
+}
+
+// Hidden commands are internal and not listed.
+fn get_commands() -> Vec<CommandInfo> {
+    KataCtlCli::command()
+        .get_subcommands()
+        .filter(|cmd| !cmd.is_hide_set())
+        .map(|cmd| CommandInfo {
+            name: cmd.get_name().to_string(),
+            about: cmd.get_about().map(|a| a.to_string()).unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn write_completions(shell: Shell, writer: &mut dyn Write) -> io::Result<()> {
+    let mut cmd = KataCtlCli::command();
+    let name = cmd.get_name().to_string();
+
+    generate(shell, &mut cmd, name, writer);
+
+    match sandbox_id_completion(shell, &get_sandbox_id_args()) {
+        Some(completion) => writer.write_all(completion.as_bytes()),
+        None => Ok(()),
+    }
+}
+
+pub fn handle_completions(args: CompletionsArgument) -> Result<(), KataCtlError> {
+    write_completions(args.shell, &mut io::stdout())?;
+
+    Ok(())
+}
+
+// Called by the completion scripts, so errors only mean no completions.
+pub fn handle_complete(args: CompleteArgument) -> Result<(), KataCtlError> {
+    let mut stdout = io::stdout();
+
+    match args.value {
+        CompleteValue::SandboxId => {
+            let sandboxes = ps_ops::list_sandboxes().unwrap_or_default();
+            for sandbox in sandboxes {
+                if sandbox.state == SandboxState::Running {
+                    let _ = writeln!(stdout, "{}", sandbox.sandbox_id);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn handle_list_commands(
+    args: ListCommandsArgument,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let commands = get_commands();
+    let format = format.with_json(args.json);
+
+    if output::print_structured(format, &commands)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table || format == OutputFormat::Csv {
+        let mut table = Table::new(&["NAME", "ABOUT"]);
+        for cmd in commands {
+            table.add_row(vec![cmd.name, cmd.about]);
+        }
+        output::print_table(format, &table);
+    } else {
+        for cmd in commands {
+            println!("{}", cmd.name);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_completions() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut output = Vec::new();
+            write_completions(shell, &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("kata-ctl"), "{:?}", shell);
+            assert!(output.contains("direct-volume"), "{:?}", shell);
+
+            let dynamic = shell != Shell::PowerShell;
+            assert_eq!(
+                output.contains("kata-ctl complete sandbox-id"),
+                dynamic,
+                "{:?}",
+                shell
+            );
+            assert!(!output.contains("@OPTIONS@"), "{:?}", shell);
+            assert!(!output.contains("@POSITIONALS@"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn test_get_sandbox_id_args() {
+        let args = get_sandbox_id_args();
+
+        for path in ["exec", "inspect", "logs", "metrics collect", "metrics diff"] {
+            let arg = SandboxIdArg {
+                path: path.to_string(),
+                long: None,
+            };
+            assert!(args.contains(&arg), "{}", path);
+        }
+        for path in ["iptables get", "iptables set"] {
+            let arg = SandboxIdArg {
+                path: path.to_string(),
+                long: Some("sandbox-id".to_string()),
+            };
+            assert!(args.contains(&arg), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_sandbox_id_completion() {
+        let args = vec![
+            SandboxIdArg {
+                path: "exec".to_string(),
+                long: None,
+            },
+            SandboxIdArg {
+                path: "iptables get".to_string(),
+                long: Some("sandbox-id".to_string()),
+            },
+        ];
+
+        let bash = sandbox_id_completion(Shell::Bash, &args).unwrap();
+        assert!(
+            bash.contains(r#"        "iptables get|--sandbox-id"|*" iptables get|--sandbox-id")"#)
+        );
+        assert!(bash.contains(r#"            "exec"|*" exec")"#));
+
+        let zsh = sandbox_id_completion(Shell::Zsh, &args).unwrap();
+        assert!(zsh.contains(r#"            ("exec"|*" exec")"#));
+
+        let fish = sandbox_id_completion(Shell::Fish, &args).unwrap();
+        assert!(fish.contains(r#"-n "__kata_ctl_using_command 'exec'" -f"#));
+        assert!(fish.contains(r#"-n "__kata_ctl_using_command 'iptables get'" -l sandbox-id -x"#));
+
+        assert_eq!(sandbox_id_completion(Shell::PowerShell, &args), None);
+    }
+
+    #[test]
+    fn test_get_commands() {
+        let commands = get_commands();
+        let names: Vec<&str> = commands.iter().map(|c| c.name.as_str()).collect();
+
+        assert!(names.contains(&"check"));
+        assert!(names.contains(&"direct-volume"));
+        assert!(!names.contains(&"list-commands"));
+
+        let check = commands.iter().find(|c| c.name == "check").unwrap();
+        assert_eq!(check.about, "Test if system can run Kata Containers");
+    }
+}
@@ -0,0 +1,618 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the config commands. A configuration file is checked
+// for the problems the runtime would only report when starting a sandbox:
+// missing sections, files that do not exist and resources the host cannot
+// provide. All the problems are reported at once, rather than the first one
+// only.
+//
+// Two configuration files are compared once loaded as the runtime does, so
+// that formatting, comments and the order of the settings do not matter.
+
+use crate::args::{
+    ConfigCommand, ConfigDiffArgs, ConfigSubCommand, ConfigValidateArgs, OutputFormat,
+};
+use crate::error::{KataCtlError, EXIT_CODE_CONFIG, EXIT_CODE_DIFFERENCES};
+use crate::output::{self, Color, Table};
+
+use anyhow::{Context, Result};
+use kata_types::config::default::MIN_QEMU_MEMORY_SIZE_MB;
+use kata_types::config::{Hypervisor, TomlConfig, HYPERVISOR_NAME_DRAGONBALL};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// The smallest guest memory the hypervisors can boot with.
+const MIN_MEMORY_SIZE_MB: u32 = MIN_QEMU_MEMORY_SIZE_MB;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// Problem is an issue found in a configuration file, key being the dotted
+// path of the setting, empty for the whole file.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub key: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(key: &str, message: String) -> Self {
+        Problem {
+            severity: Severity::Error,
+            key: key.to_string(),
+            message,
+        }
+    }
+
+    fn warning(key: &str, message: String) -> Self {
+        Problem {
+            severity: Severity::Warning,
+            key: key.to_string(),
+            message,
+        }
+    }
+}
+
+// HostLimits are the host resources the configuration is checked against.
+#[derive(Debug)]
+pub struct HostLimits {
+    pub cpus: u32,
+    pub memory_mb: u64,
+}
+
+fn get_host_limits() -> Option<HostLimits> {
+    let cpus = sys_info::cpu_num().ok()?;
+    let memory = sys_info::mem_info().ok()?;
+
+    Some(HostLimits {
+        cpus,
+        memory_mb: memory.total / 1024,
+    })
+}
+
+// An empty path is left to the runtime defaults, so only a path that is set
+// must exist.
+fn check_file(problems: &mut Vec<Problem>, key: &str, path: &str) {
+    if !path.is_empty() && !Path::new(path).exists() {
+        problems.push(Problem::error(key, format!("{} does not exist", path)));
+    }
+}
+
+fn check_hypervisor(
+    problems: &mut Vec<Problem>,
+    name: &str,
+    hypervisor: &Hypervisor,
+    host: Option<&HostLimits>,
+) {
+    let key = |field: &str| format!("hypervisor.{}.{}", name, field);
+
+    // dragonball is built into the runtime
+    if name != HYPERVISOR_NAME_DRAGONBALL {
+        if hypervisor.path.is_empty() {
+            problems.push(Problem::error(
+                &key("path"),
+                "the hypervisor path is required".to_string(),
+            ));
+        } else {
+            check_file(problems, &key("path"), &hypervisor.path);
+
+            if !hypervisor.valid_hypervisor_paths.is_empty() {
+                if let Err(e) = hypervisor.validate_hypervisor_path(&hypervisor.path) {
+                    problems.push(Problem::error(&key("path"), format!("{}", e)));
+                }
+            }
+        }
+    }
+
+    let boot_info = &hypervisor.boot_info;
+    if boot_info.kernel.is_empty() {
+        problems.push(Problem::error(
+            &key("kernel"),
+            "the guest kernel is required".to_string(),
+        ));
+    }
+    check_file(problems, &key("kernel"), &boot_info.kernel);
+    check_file(problems, &key("image"), &boot_info.image);
+    check_file(problems, &key("initrd"), &boot_info.initrd);
+    check_file(problems, &key("firmware"), &boot_info.firmware);
+
+    match (boot_info.image.is_empty(), boot_info.initrd.is_empty()) {
+        (true, true) => problems.push(Problem::error(
+            &key("image"),
+            "one of image or initrd is required to boot the guest".to_string(),
+        )),
+        (false, false) => problems.push(Problem::error(
+            &key("initrd"),
+            "image and initrd cannot be both set".to_string(),
+        )),
+        _ => {}
+    }
+
+    let cpu_info = &hypervisor.cpu_info;
+    if cpu_info.default_maxvcpus > 0 && cpu_info.default_vcpus > cpu_info.default_maxvcpus as i32 {
+        problems.push(Problem::error(
+            &key("default_vcpus"),
+            format!(
+                "{} vCPUs is more than default_maxvcpus ({})",
+                cpu_info.default_vcpus, cpu_info.default_maxvcpus
+            ),
+        ));
+    }
+
+    let memory_info = &hypervisor.memory_info;
+    if memory_info.default_memory > 0 && memory_info.default_memory < MIN_MEMORY_SIZE_MB {
+        problems.push(Problem::error(
+            &key("default_memory"),
+            format!(
+                "{} MiB is less than the minimum of {} MiB",
+                memory_info.default_memory, MIN_MEMORY_SIZE_MB
+            ),
+        ));
+    }
+
+    // The runtime caps the values to the host resources, so they are only
+    // worth a warning.
+    if let Some(host) = host {
+        if cpu_info.default_vcpus > host.cpus as i32 {
+            problems.push(Problem::warning(
+                &key("default_vcpus"),
+                format!(
+                    "{} vCPUs is more than the {} CPUs of the host",
+                    cpu_info.default_vcpus, host.cpus
+                ),
+            ));
+        }
+
+        if memory_info.default_memory as u64 > host.memory_mb {
+            problems.push(Problem::warning(
+                &key("default_memory"),
+                format!(
+                    "{} MiB is more than the {} MiB of the host",
+                    memory_info.default_memory, host.memory_mb
+                ),
+            ));
+        }
+    }
+}
+
+fn check_config(config: &TomlConfig, host: Option<&HostLimits>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if config.hypervisor.is_empty() {
+        problems.push(Problem::error(
+            "hypervisor",
+            "no hypervisor is configured".to_string(),
+        ));
+    }
+
+    let hypervisor_name = &config.runtime.hypervisor_name;
+    if !hypervisor_name.is_empty() && !config.hypervisor.contains_key(hypervisor_name) {
+        problems.push(Problem::error(
+            "runtime.hypervisor_name",
+            format!("hypervisor {} is not configured", hypervisor_name),
+        ));
+    }
+
+    if config.agent.is_empty() {
+        problems.push(Problem::error(
+            "agent",
+            "no agent is configured".to_string(),
+        ));
+    }
+
+    let agent_name = &config.runtime.agent_name;
+    if !agent_name.is_empty() && !config.agent.contains_key(agent_name) {
+        problems.push(Problem::error(
+            "runtime.agent_name",
+            format!("agent {} is not configured", agent_name),
+        ));
+    }
+
+    // sort the hypervisors for a stable output
+    let mut hypervisors: Vec<_> = config.hypervisor.iter().collect();
+    hypervisors.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, hypervisor) in hypervisors {
+        check_hypervisor(&mut problems, name, hypervisor, host);
+    }
+
+    problems
+}
+
+pub fn validate_config(contents: &str, host: Option<&HostLimits>) -> Vec<Problem> {
+    let config: TomlConfig = match toml::from_str(contents) {
+        Ok(config) => config,
+        Err(e) => return vec![Problem::error("", format!("invalid configuration: {}", e))],
+    };
+
+    let mut problems = check_config(&config, host);
+
+    // The checks of the runtime stop at the first problem, they are only
+    // needed for the problems that were not found above.
+    if problems.iter().all(|p| p.severity != Severity::Error) {
+        if let Err(e) = TomlConfig::load(contents).and_then(|config| config.validate()) {
+            problems.push(Problem::error("", format!("{}", e)));
+        }
+    }
+
+    problems
+}
+
+fn print_problems(path: &str, problems: &[Problem], format: OutputFormat) -> Result<()> {
+    if output::print_structured(format, problems)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table {
+        let mut table = Table::new(&["SEVERITY", "KEY", "MESSAGE"]);
+        for problem in problems {
+            table.add_row(vec![
+                problem.severity.name().to_string(),
+                problem.key.clone(),
+                problem.message.clone(),
+            ]);
+        }
+        print!("{}", table);
+
+        return Ok(());
+    }
+
+    if problems.is_empty() {
+        println!("{} is valid", path);
+
+        return Ok(());
+    }
+
+    for problem in problems {
+        let severity = problem.severity.name().to_uppercase();
+        if problem.key.is_empty() {
+            println!("{}: {}", severity, problem.message);
+        } else {
+            println!("{}: {}: {}", severity, problem.key, problem.message);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_config_validate(
+    args: &ConfigValidateArgs,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let contents =
+        fs::read_to_string(&args.path).with_context(|| format!("failed to read {}", args.path))?;
+
+    let problems = validate_config(&contents, get_host_limits().as_ref());
+    print_problems(&args.path, &problems, format)?;
+
+    // The problems were already reported.
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        return Err(KataCtlError::Status(EXIT_CODE_CONFIG));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Changed => "changed",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            ChangeKind::Added => Color::Green,
+            ChangeKind::Removed => Color::Red,
+            ChangeKind::Changed => Color::Yellow,
+        }
+    }
+}
+
+// Change is a setting that differs between two configuration files, old
+// being None for an added setting and new None for a removed one.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+// Load a configuration file into the model of the runtime. The settings it
+// does not define get their default values, so that they compare equal to
+// the same settings defined with the default values.
+fn load_config_model(contents: &str) -> Result<Value> {
+    let config: TomlConfig = toml::from_str(contents)?;
+
+    Ok(serde_json::to_value(&config)?)
+}
+
+// Flatten the settings to their dotted keys, arrays being single settings.
+fn flatten_settings(prefix: &str, value: &Value, settings: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                let key = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", prefix, name)
+                };
+                flatten_settings(&key, value, settings);
+            }
+        }
+        _ => {
+            settings.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+pub fn diff_configs(old: &Value, new: &Value) -> Vec<Change> {
+    let mut old_settings = BTreeMap::new();
+    flatten_settings("", old, &mut old_settings);
+    let mut new_settings = BTreeMap::new();
+    flatten_settings("", new, &mut new_settings);
+
+    let mut changes = Vec::new();
+    for (key, old_value) in &old_settings {
+        match new_settings.get(key) {
+            Some(new_value) if new_value == old_value => {}
+            Some(new_value) => changes.push(Change {
+                kind: ChangeKind::Changed,
+                key: key.clone(),
+                old: Some(old_value.clone()),
+                new: Some(new_value.clone()),
+            }),
+            None => changes.push(Change {
+                kind: ChangeKind::Removed,
+                key: key.clone(),
+                old: Some(old_value.clone()),
+                new: None,
+            }),
+        }
+    }
+
+    for (key, new_value) in &new_settings {
+        if !old_settings.contains_key(key) {
+            changes.push(Change {
+                kind: ChangeKind::Added,
+                key: key.clone(),
+                old: None,
+                new: Some(new_value.clone()),
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+
+    changes
+}
+
+fn format_setting(value: &Option<Value>) -> String {
+    value
+        .as_ref()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn print_changes(changes: &[Change], format: OutputFormat) -> Result<()> {
+    if output::print_structured(format, changes)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table {
+        let mut table = Table::new(&["CHANGE", "KEY", "OLD", "NEW"]);
+        for change in changes {
+            table.add_row(vec![
+                output::paint(change.kind.color(), change.kind.name()),
+                change.key.clone(),
+                format_setting(&change.old),
+                format_setting(&change.new),
+            ]);
+        }
+        print!("{}", table);
+
+        return Ok(());
+    }
+
+    for change in changes {
+        let line = match change.kind {
+            ChangeKind::Added => format!("+ {} = {}", change.key, format_setting(&change.new)),
+            ChangeKind::Removed => format!("- {} = {}", change.key, format_setting(&change.old)),
+            ChangeKind::Changed => format!(
+                "~ {}: {} -> {}",
+                change.key,
+                format_setting(&change.old),
+                format_setting(&change.new)
+            ),
+        };
+        println!("{}", output::paint(change.kind.color(), &line));
+    }
+
+    Ok(())
+}
+
+fn read_config_model(path: &str) -> Result<Value, KataCtlError> {
+    let contents = fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+
+    load_config_model(&contents)
+        .with_context(|| format!("invalid configuration {}", path))
+        .map_err(KataCtlError::Config)
+}
+
+fn handle_config_diff(args: &ConfigDiffArgs, format: OutputFormat) -> Result<(), KataCtlError> {
+    let old = read_config_model(&args.old)?;
+    let new = read_config_model(&args.new)?;
+
+    let changes = diff_configs(&old, &new);
+    print_changes(&changes, format)?;
+
+    // The changes were already reported.
+    if !changes.is_empty() {
+        return Err(KataCtlError::Status(EXIT_CODE_DIFFERENCES));
+    }
+
+    Ok(())
+}
+
+pub fn handle_config(config_cmd: ConfigCommand, format: OutputFormat) -> Result<(), KataCtlError> {
+    match config_cmd.config_cmd {
+        ConfigSubCommand::Validate(args) => handle_config_validate(&args, format),
+        ConfigSubCommand::Diff(args) => handle_config_diff(&args, format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_validate_config() {
+        let problems = validate_config("[hypervisor.qemu\n", None);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+        assert!(problems[0].message.contains("invalid configuration"));
+
+        let problems = validate_config("", None);
+        let keys: Vec<&str> = problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, vec!["hypervisor", "agent"]);
+    }
+
+    #[test]
+    fn test_check_config() {
+        let dir = tempdir().unwrap();
+        let kernel = dir.path().join("vmlinux");
+        fs::write(&kernel, "").unwrap();
+
+        let contents = format!(
+            r#"
+[hypervisor.qemu]
+kernel = "{}"
+image = "/no/such/image"
+initrd = "/no/such/initrd"
+default_vcpus = 8
+default_maxvcpus = 4
+default_memory = 32
+
+[agent.kata]
+
+[runtime]
+agent_name = "kata"
+hypervisor_name = "clh"
+"#,
+            kernel.display()
+        );
+        let config: TomlConfig = toml::from_str(&contents).unwrap();
+        let host = HostLimits {
+            cpus: 2,
+            memory_mb: 1024,
+        };
+
+        let problems = check_config(&config, Some(&host));
+        let found: Vec<(Severity, &str)> = problems
+            .iter()
+            .map(|p| (p.severity, p.key.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Error, "runtime.hypervisor_name"),
+                (Severity::Error, "hypervisor.qemu.path"),
+                (Severity::Error, "hypervisor.qemu.image"),
+                (Severity::Error, "hypervisor.qemu.initrd"),
+                (Severity::Error, "hypervisor.qemu.initrd"),
+                (Severity::Error, "hypervisor.qemu.default_vcpus"),
+                (Severity::Error, "hypervisor.qemu.default_memory"),
+                (Severity::Warning, "hypervisor.qemu.default_vcpus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_configs() {
+        let old = r#"
+# the agent
+[agent.kata]
+enable_debug = true
+
+[hypervisor.qemu]
+path = "/usr/bin/qemu"
+default_memory = 2048
+"#;
+        let new = r#"
+[hypervisor.qemu]
+default_memory   =   2048 # same value, other formatting
+path = "/usr/bin/qemu"
+
+[hypervisor.clh]
+path = "/usr/bin/cloud-hypervisor"
+
+[agent.kata]
+enable_debug = false
+"#;
+        let old = load_config_model(old).unwrap();
+        let new = load_config_model(new).unwrap();
+
+        assert!(diff_configs(&old, &old).is_empty());
+
+        let changes = diff_configs(&old, &new);
+        let changed: Vec<&Change> = changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Changed)
+            .collect();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].key, "agent.kata.enable_debug");
+        assert_eq!(changed[0].old, Some(Value::Bool(true)));
+        assert_eq!(changed[0].new, Some(Value::Bool(false)));
+
+        // all the settings of the new hypervisor are added
+        assert!(changes
+            .iter()
+            .filter(|c| c.key.starts_with("hypervisor.clh."))
+            .all(|c| c.kind == ChangeKind::Added && c.old.is_none()));
+        assert!(changes.iter().any(|c| c.key == "hypervisor.clh.path"
+            && c.new == Some(Value::from("/usr/bin/cloud-hypervisor"))));
+        assert!(!changes
+            .iter()
+            .any(|c| c.key.starts_with("hypervisor.qemu.")));
+
+        // and removed the other way around
+        let changes = diff_configs(&new, &old);
+        assert!(changes
+            .iter()
+            .filter(|c| c.key.starts_with("hypervisor.clh."))
+            .all(|c| c.kind == ChangeKind::Removed && c.new.is_none()));
+
+        assert!(load_config_model("[hypervisor.qemu]\npath = 1\n").is_err());
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the doctor command, gathering in a single report what
+// is needed to look into a problem: the results of 'check all', the 'env'
+// report, the sandboxes of the host and the configuration. The parts which
+// cannot be gathered are null, with the error in the errors of the report.
+//
+// The values of the settings whose name looks like a secret, and the
+// credentials of the URLs, are redacted.
+//
+// With --output-dir, each part of the report is written to its own file of
+// a bundle directory, along with the recent logs of the sandboxes and a
+// manifest of the files, and --archive turns the bundle into a tarball.
+
+use crate::args::{DoctorArgument, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::{check_ops, env_ops, logs_ops, ps_ops, version};
+use crate::output;
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const CHECKS_FILE: &str = "checks.json";
+const ENV_FILE: &str = "env.json";
+const SANDBOXES_FILE: &str = "sandboxes.json";
+const CONFIG_FILE: &str = "configuration.toml";
+const LOGS_DIR: &str = "logs";
+const TAR: &str = "tar";
+
+// The number of log lines of each sandbox in a bundle.
+const BUNDLE_LOG_LINES: usize = 1000;
+
+// DoctorReport is the output of 'kata-ctl doctor'.
+#[derive(Debug, Default, Serialize)]
+pub struct DoctorReport {
+    pub kata_ctl_version: String,
+    pub timestamp: u64,
+    pub config_path: Option<String>,
+    pub checks: Option<Value>,
+    pub env: Option<Value>,
+    pub sandboxes: Option<Value>,
+    pub config: Option<Value>,
+    // The logs of the sandboxes, only gathered for a bundle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<Value>,
+    pub errors: BTreeMap<String, String>,
+}
+
+// BundleManifest is the manifest.json file of a bundle, listing its other
+// files.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BundleManifest {
+    pub kata_ctl_version: String,
+    pub timestamp: u64,
+    pub config_path: Option<String>,
+    pub files: Vec<String>,
+    pub errors: BTreeMap<String, String>,
+}
+
+// Also used by 'kata-ctl dump', whose sections are gathered the same way.
+pub fn collect<T: Serialize>(
+    errors: &mut BTreeMap<String, String>,
+    name: &str,
+    result: Result<T>,
+) -> Option<Value> {
+    match result.and_then(|value| Ok(serde_json::to_value(value)?)) {
+        Ok(mut value) => {
+            utils::redact(&mut value, &[]);
+            Some(value)
+        }
+        Err(e) => {
+            errors.insert(name.to_string(), format!("{:#}", e));
+            None
+        }
+    }
+}
+
+fn get_doctor_report(config_file: Option<&str>, network: bool, logs: bool) -> DoctorReport {
+    let mut errors = BTreeMap::new();
+
+    let checks = collect(
+        &mut errors,
+        "checks",
+        check_ops::get_check_all_results(network),
+    );
+
+    let config = utils::load_config(config_file);
+    let env = collect(
+        &mut errors,
+        "env",
+        config
+            .as_ref()
+            .map_err(|e| anyhow!("{:#}", e))
+            .and_then(|(toml_config, config_path)| env_ops::get_env_info(toml_config, config_path)),
+    );
+
+    let sandboxes = collect(&mut errors, "sandboxes", ps_ops::list_sandboxes());
+
+    let logs = if logs {
+        collect(
+            &mut errors,
+            "logs",
+            utils::list_sandbox_ids(utils::SANDBOX_STATE_PATHS).and_then(|ids| {
+                let ids: Vec<String> = ids.into_iter().collect();
+                logs_ops::get_sandboxes_logs(&ids, BUNDLE_LOG_LINES)
+            }),
+        )
+    } else {
+        None
+    };
+
+    let config_path = config.as_ref().ok().map(|(_, path)| path.clone());
+    let config = collect(
+        &mut errors,
+        "config",
+        config
+            .map_err(|e| anyhow!("{:#}", e))
+            .and_then(|(_, path)| {
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                let value: toml::Value = toml::from_str(&contents)
+                    .with_context(|| format!("invalid configuration {}", path.display()))?;
+                Ok(value)
+            }),
+    );
+
+    DoctorReport {
+        kata_ctl_version: version::get().unwrap_or_default(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        config_path: config_path.map(|path| path.display().to_string()),
+        checks,
+        env,
+        sandboxes,
+        config,
+        logs,
+        errors,
+    }
+}
+
+// Writes the already redacted parts of the report to the bundle directory,
+// the manifest listing them last.
+fn write_doctor_bundle(report: &DoctorReport, bundle_dir: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    let mut write = |name: String, contents: String| -> Result<()> {
+        let path = bundle_dir.join(&name);
+        fs::write(&path, contents)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        files.push(name);
+        Ok(())
+    };
+
+    for (name, value) in [
+        (CHECKS_FILE, &report.checks),
+        (ENV_FILE, &report.env),
+        (SANDBOXES_FILE, &report.sandboxes),
+    ] {
+        if let Some(value) = value {
+            write(name.to_string(), serde_json::to_string_pretty(value)?)?;
+        }
+    }
+
+    if let Some(config) = &report.config {
+        let config = toml::to_string(config).context("failed to serialize the configuration")?;
+        write(CONFIG_FILE.to_string(), config)?;
+    }
+
+    if let Some(Value::Object(logs)) = &report.logs {
+        let logs_dir = bundle_dir.join(LOGS_DIR);
+        fs::create_dir_all(&logs_dir)
+            .with_context(|| format!("failed to create {}", logs_dir.display()))?;
+
+        for (sandbox_id, lines) in logs {
+            let lines: Vec<&str> = lines
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|line| line.as_str())
+                .collect();
+            let mut contents = lines.join("\n");
+            contents.push('\n');
+            write(format!("{}/{}.log", LOGS_DIR, sandbox_id), contents)?;
+        }
+    }
+
+    let manifest = BundleManifest {
+        kata_ctl_version: report.kata_ctl_version.clone(),
+        timestamp: report.timestamp,
+        config_path: report.config_path.clone(),
+        files,
+        errors: report.errors.clone(),
+    };
+    let path = bundle_dir.join(MANIFEST_FILE);
+    fs::write(&path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+// Turns the bundle directory named name in output_dir into a tarball,
+// returning its path.
+fn archive_doctor_bundle(output_dir: &Path, name: &str) -> Result<String, KataCtlError> {
+    let tarball = output_dir.join(format!("{}.tar.gz", name));
+    let status = Command::new(TAR)
+        .arg("-czf")
+        .arg(&tarball)
+        .arg("-C")
+        .arg(output_dir)
+        .arg(name)
+        .status()
+        .map_err(|e| KataCtlError::MissingDependency(anyhow!("failed to run {}: {}", TAR, e)))?;
+    if !status.success() {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "failed to create {}: {} {}",
+            tarball.display(),
+            TAR,
+            status
+        )));
+    }
+
+    Ok(tarball.display().to_string())
+}
+
+// Writes the bundle to output_dir, returning the path of the bundle
+// directory or of its tarball. No partial bundle is left behind.
+fn write_doctor_output(
+    report: &DoctorReport,
+    output_dir: &Path,
+    archive: bool,
+) -> Result<String, KataCtlError> {
+    let name = format!("kata-doctor-{}", report.timestamp);
+    let bundle_dir = output_dir.join(&name);
+    fs::create_dir_all(&bundle_dir)
+        .with_context(|| format!("failed to create {}", bundle_dir.display()))?;
+
+    let result = write_doctor_bundle(report, &bundle_dir)
+        .map_err(KataCtlError::from)
+        .and_then(|_| {
+            if archive {
+                archive_doctor_bundle(output_dir, &name)
+            } else {
+                Ok(bundle_dir.display().to_string())
+            }
+        });
+
+    if archive || result.is_err() {
+        let _ = fs::remove_dir_all(&bundle_dir);
+    }
+
+    result
+}
+
+pub fn handle_doctor(
+    args: DoctorArgument,
+    config_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let report = get_doctor_report(config_file, args.network, args.output_dir.is_some());
+
+    if let Some(output_dir) = &args.output_dir {
+        let path = write_doctor_output(&report, Path::new(output_dir), args.archive)?;
+        println!("{}", path);
+
+        return Ok(());
+    }
+
+    // The report is meant to be attached as is, so it is always structured.
+    let format = if format.is_structured() {
+        format
+    } else {
+        OutputFormat::Json
+    };
+    output::print_structured(format, &report)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_collect() {
+        let mut errors = BTreeMap::new();
+
+        let value = collect(&mut errors, "ok", Ok(json!({ "token": "abc" })));
+        assert_eq!(value, Some(json!({ "token": utils::REDACTED })));
+
+        let value = collect::<Value>(&mut errors, "failed", Err(anyhow!("no sandbox")));
+        assert_eq!(value, None);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors["failed"], "no sandbox");
+    }
+
+    #[test]
+    fn test_write_doctor_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report = DoctorReport {
+            kata_ctl_version: "3.2.0".to_string(),
+            timestamp: 1700000000,
+            checks: Some(json!([{ "name": "Cpu", "result": "pass" }])),
+            sandboxes: Some(json!([])),
+            config: Some(json!({ "runtime": { "debug": true } })),
+            logs: Some(json!({ "sb1": ["[shim] a", "[agent] b"] })),
+            ..Default::default()
+        };
+        report
+            .errors
+            .insert("env".to_string(), "no configuration".to_string());
+
+        let path = write_doctor_output(&report, dir.path(), false).unwrap();
+        let bundle_dir = dir.path().join("kata-doctor-1700000000");
+        assert_eq!(path, bundle_dir.display().to_string());
+
+        let read = |name: &str| fs::read_to_string(bundle_dir.join(name)).unwrap();
+        let manifest: Value = serde_json::from_str(&read(MANIFEST_FILE)).unwrap();
+        assert_eq!(
+            manifest,
+            json!({
+                "kata_ctl_version": "3.2.0",
+                "timestamp": 1700000000,
+                "config_path": null,
+                "files": [CHECKS_FILE, SANDBOXES_FILE, CONFIG_FILE, "logs/sb1.log"],
+                "errors": { "env": "no configuration" },
+            })
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&read(CHECKS_FILE)).unwrap(),
+            report.checks.clone().unwrap()
+        );
+        assert!(!bundle_dir.join(ENV_FILE).exists());
+        assert_eq!(read(CONFIG_FILE), "[runtime]\ndebug = true\n");
+        assert_eq!(read("logs/sb1.log"), "[shim] a\n[agent] b\n");
+
+        // the logs are not part of the report printed without a bundle
+        report.logs = None;
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json.get("logs").is_none());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the dump command, saving the state of a sandbox in a
+// single JSON document for a later analysis: the guest mount table and
+// processes, read through the debug console, the iptables of the guest, the
+// version of the agent and the configuration of the sandbox persisted by its
+// shim. The sections which cannot be gathered are null, with the error in
+// the errors of the dump, like for 'kata-ctl doctor'.
+
+use crate::args::{DumpArgument, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::{agent_ops, doctor_ops, exec_ops, inspect_ops, iptables_ops, mounts_ops, version};
+use crate::output;
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Lists the guest processes from /proc, the guest ps being too minimal to
+// be relied upon. The kernel threads have no command line, their name is
+// shown in brackets like ps does.
+const GUEST_PROCESSES_SCRIPT: &str = r#"cd /proc && for pid in [0-9]*; do cmdline=$(tr '\0' ' ' < $pid/cmdline 2>/dev/null); [ -n "$cmdline" ] || cmdline="[$(cat $pid/comm 2>/dev/null)]"; printf '%s\t%s\n' "$pid" "$cmdline"; done"#;
+
+// GuestProcess is a process of the guest, in the processes of the dump.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct GuestProcess {
+    pub pid: u32,
+    pub cmdline: String,
+}
+
+// SandboxDump is the output of 'kata-ctl dump'.
+#[derive(Debug, Default, Serialize)]
+pub struct SandboxDump {
+    pub kata_ctl_version: String,
+    pub timestamp: u64,
+    pub sandbox_id: String,
+    pub shim_config: Option<Value>,
+    pub agent: Option<Value>,
+    pub mounts: Option<Value>,
+    pub processes: Option<Value>,
+    pub iptables: Option<Value>,
+    pub ip6tables: Option<Value>,
+    pub errors: BTreeMap<String, String>,
+}
+
+// The lines of the processes script are "<pid>\t<command line>", the
+// processes which exited while they were listed having none.
+fn parse_guest_processes(output: &str) -> Result<Vec<GuestProcess>> {
+    let mut processes = Vec::new();
+
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let (pid, cmdline) = line
+            .split_once('\t')
+            .ok_or_else(|| anyhow!("invalid guest process line {:?}", line))?;
+        let pid = pid
+            .parse()
+            .with_context(|| format!("invalid guest process ID {:?}", pid))?;
+        let cmdline = cmdline.trim_end();
+        if cmdline == "[]" {
+            continue;
+        }
+
+        processes.push(GuestProcess {
+            pid,
+            cmdline: cmdline.to_string(),
+        });
+    }
+
+    processes.sort_by_key(|process| process.pid);
+
+    Ok(processes)
+}
+
+fn get_guest_processes(
+    sandbox_id: &str,
+    vport: u32,
+    timeout: Duration,
+) -> Result<Vec<GuestProcess>> {
+    let command = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        GUEST_PROCESSES_SCRIPT.to_string(),
+    ];
+
+    let result = exec_ops::run_guest_command(sandbox_id, vport, None, &command, Some(timeout))?;
+    if result.exit_code != 0 {
+        return Err(anyhow!(
+            "failed to list the guest processes (exit status {}): {}",
+            result.exit_code,
+            result.output.trim()
+        ));
+    }
+
+    parse_guest_processes(&result.output)
+}
+
+fn get_sandbox_dump(sandbox_id: &str, vport: u32, timeout: Duration) -> SandboxDump {
+    let mut errors = BTreeMap::new();
+
+    let shim_config = doctor_ops::collect(
+        &mut errors,
+        "shim_config",
+        inspect_ops::inspect_sandbox(sandbox_id).map_err(anyhow::Error::from),
+    );
+    let agent = doctor_ops::collect(
+        &mut errors,
+        "agent",
+        agent_ops::ping_agent(sandbox_id, timeout).map_err(anyhow::Error::from),
+    );
+    let mounts = doctor_ops::collect(
+        &mut errors,
+        "mounts",
+        mounts_ops::get_guest_mounts(sandbox_id, vport, None, Some(timeout))
+            .map_err(anyhow::Error::from),
+    );
+    let processes = doctor_ops::collect(
+        &mut errors,
+        "processes",
+        get_guest_processes(sandbox_id, vport, timeout),
+    );
+    let iptables = doctor_ops::collect(
+        &mut errors,
+        "iptables",
+        iptables_ops::get_sandbox_iptables(sandbox_id, false),
+    );
+    let ip6tables = doctor_ops::collect(
+        &mut errors,
+        "ip6tables",
+        iptables_ops::get_sandbox_iptables(sandbox_id, true),
+    );
+
+    SandboxDump {
+        kata_ctl_version: version::get().unwrap_or_default(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        sandbox_id: sandbox_id.to_string(),
+        shim_config,
+        agent,
+        mounts,
+        processes,
+        iptables,
+        ip6tables,
+        errors,
+    }
+}
+
+pub fn handle_dump(args: DumpArgument, format: OutputFormat) -> Result<(), KataCtlError> {
+    utils::require_root("dumping the state of a sandbox")?;
+    let sandbox_id = utils::resolve_sandbox_id(&args.sandbox_id)?;
+
+    let dump = get_sandbox_dump(&sandbox_id, args.vport, Duration::from_secs(args.timeout));
+
+    // The dump is meant to be analysed by tools, so it is always structured.
+    match &args.file {
+        Some(file) => {
+            let contents = serde_json::to_string_pretty(&dump)?;
+            fs::write(file, contents).with_context(|| format!("failed to write {}", file))?;
+            println!("{}", file);
+        }
+        None => {
+            let format = if format.is_structured() {
+                format
+            } else {
+                OutputFormat::Json
+            };
+            output::print_structured(format, &dump)?;
+        }
+    }
+
+    // The dump is still written when the sandbox cannot be reached at all,
+    // for its errors.
+    if !dump.errors.is_empty() {
+        eprintln!(
+            "WARNING: {} of the sandbox state could not be dumped",
+            dump.errors.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_guest_processes() {
+        let output = "12\t/usr/bin/pause \n1\t/init \n2\t[kthreadd]\n40\t[]\n\n";
+        assert_eq!(
+            parse_guest_processes(output).unwrap(),
+            vec![
+                GuestProcess {
+                    pid: 1,
+                    cmdline: "/init".to_string(),
+                },
+                GuestProcess {
+                    pid: 2,
+                    cmdline: "[kthreadd]".to_string(),
+                },
+                GuestProcess {
+                    pid: 12,
+                    cmdline: "/usr/bin/pause".to_string(),
+                },
+            ]
+        );
+
+        assert!(parse_guest_processes("").unwrap().is_empty());
+        assert!(parse_guest_processes("1 /init\n").is_err());
+        assert!(parse_guest_processes("init\t/init\n").is_err());
+    }
+
+    #[test]
+    fn test_get_sandbox_dump() {
+        // Nothing can be reached for a sandbox which does not exist, but the
+        // dump is still made, each section with its error.
+        let dump = get_sandbox_dump("no-such-sandbox", 1026, Duration::from_secs(1));
+        let value = serde_json::to_value(&dump).unwrap();
+
+        for section in [
+            "shim_config",
+            "agent",
+            "mounts",
+            "processes",
+            "iptables",
+            "ip6tables",
+        ] {
+            assert!(value[section].is_null(), "{}", section);
+            assert!(dump.errors.contains_key(section), "{}", section);
+        }
+        assert_eq!(value["sandbox_id"], "no-such-sandbox");
+    }
+}
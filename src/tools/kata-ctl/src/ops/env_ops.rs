@@ -6,16 +6,25 @@
 // Contains checks that are not architecture-specific
 
 use crate::arch::arch_specific;
-use crate::args::EnvArgument;
+use crate::args::{EnvArgument, EnvSection, OutputFormat};
+use crate::check;
+use crate::error::{KataCtlError, EXIT_CODE_DIFFERENCES};
+use crate::ops::config_ops::{self, Change};
 use crate::ops::version;
+use crate::output;
 use crate::utils;
-use kata_types::config::TomlConfig;
+use kata_types::config::{Hypervisor, TomlConfig};
 
 use anyhow::{anyhow, Context, Result};
-use nix::unistd::Uid;
+use clap::ValueEnum;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process::Command;
 use sys_info;
 
@@ -37,6 +46,39 @@ pub struct HostInfo {
     cpu: CPUInfo,
     #[serde(default)]
     memory: MemoryInfo,
+    #[serde(default)]
+    virtualization: VirtualizationInfo,
+    #[serde(default)]
+    security: SecurityInfo,
+}
+
+// SecurityInfo is the Linux security module confining the containers of
+// the host, if any.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SecurityInfo {
+    // selinux, apparmor or none
+    #[serde(default)]
+    lsm: String,
+    // enforcing or permissive for SELinux, enforcing or complain for
+    // AppArmor, disabled without LSM
+    #[serde(default)]
+    mode: String,
+    // Whether the SELinux type or the AppArmor profile of the Kata
+    // Containers hypervisor is loaded
+    #[serde(default)]
+    kata_profile_loaded: bool,
+}
+
+// VirtualizationInfo tells whether the host is itself a VM, and whether it
+// can run nested VMs.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct VirtualizationInfo {
+    #[serde(default)]
+    is_nested: bool,
+    #[serde(default)]
+    nested_virt_enabled: bool,
+    #[serde(default)]
+    vendor: String,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -70,8 +112,98 @@ pub struct MemoryInfo {
 // Semantic version for the output of the command.
 //
 // XXX: Increment for every change to the output format
-// (meaning any change to the EnvInfo type).
-const FORMAT_VERSION: &str = "0.0.1-kata-ctl";
+// (meaning any change to the EnvInfo type), along with ENV_SCHEMA_VERSION.
+const FORMAT_VERSION: &str = "0.0.6-kata-ctl";
+
+// Version of the shape of the output, its top-level schema_version field.
+// The major version is bumped when a field is moved, renamed or removed,
+// the minor version when one is added.
+//
+// XXX: Any bump must be coupled with an entry of ENV_SCHEMA_CHANGES, and
+// with the help of 'env --schema-version'.
+pub const ENV_SCHEMA_VERSION: &str = "1.5";
+
+// The oldest schema version 'env --schema-version' still outputs.
+const ENV_OLDEST_SCHEMA_VERSION: &str = "1.0";
+
+// Turns an env output back into the shape of the previous schema version.
+type EnvSchemaDowngradeFp = fn(&mut Map<String, Value>);
+
+// The changes of the shape of the output, by the schema version which made
+// them, each with the function turning an output back into the shape of
+// the previous version.
+const ENV_SCHEMA_CHANGES: &[(&str, EnvSchemaDowngradeFp)] = &[
+    ("1.1", remove_virtualization_info),
+    ("1.2", remove_effective_kernel_params),
+    ("1.3", remove_security_info),
+    ("1.4", remove_env_notes),
+    ("1.5", remove_hypervisor_binaries),
+];
+
+// Passing this as the output file name writes the env output to stdout.
+const STDOUT_FILE: &str = "-";
+
+// The guest protection reported without root privileges.
+const UNKNOWN_GUEST_PROTECTION: &str = "unknown";
+
+// Files probed for the virtualization of the host, relative to the root.
+const SYS_HYPERVISOR_TYPE: &str = "sys/hypervisor/type";
+const DMI_FILES: &[&str] = &[
+    "sys/class/dmi/id/sys_vendor",
+    "sys/class/dmi/id/product_name",
+];
+const KVM_NESTED_PARAMS: &[&str] = &[
+    "sys/module/kvm_intel/parameters/nested",
+    "sys/module/kvm_amd/parameters/nested",
+    "sys/module/kvm/parameters/nested",
+];
+const DEV_KVM: &str = "dev/kvm";
+
+// The CPU flags of hardware virtualization, passed to a VM by a hypervisor
+// enabling nested virtualization.
+const VIRT_CPU_FLAGS: &[&str] = &["vmx", "svm"];
+
+// Hypervisor vendors, by the patterns found in their CPUID signature,
+// /sys/hypervisor/type or DMI vendor and product names.
+const HYPERVISOR_VENDORS: &[(&str, &str)] = &[
+    ("KVMKVMKVM", "KVM"),
+    ("TCGTCGTCGTCG", "QEMU"),
+    ("Microsoft Hv", "Microsoft Hyper-V"),
+    ("VMwareVMware", "VMware"),
+    ("XenVMMXenVMM", "Xen"),
+    ("ACRNACRNACRN", "ACRN"),
+    ("bhyve bhyve", "bhyve"),
+    ("lrpepyh", "Parallels"),
+    ("xen", "Xen"),
+    ("KVM", "KVM"),
+    ("QEMU", "QEMU"),
+    ("VMware", "VMware"),
+    ("VirtualBox", "VirtualBox"),
+    ("Virtual Machine", "Microsoft Hyper-V"),
+    ("Amazon EC2", "Amazon EC2"),
+    ("Google Compute Engine", "Google Compute Engine"),
+];
+
+// Files probed for the security module of the host, relative to the root.
+const SELINUX_ENFORCE: &str = "sys/fs/selinux/enforce";
+const SELINUX_CONTEXT: &str = "sys/fs/selinux/context";
+const APPARMOR_ENABLED: &str = "sys/module/apparmor/parameters/enabled";
+const APPARMOR_MODE: &str = "sys/module/apparmor/parameters/mode";
+const APPARMOR_PROFILES: &str = "sys/kernel/security/apparmor/profiles";
+
+// The SELinux context the hypervisor of Kata Containers runs with, from the
+// container-selinux policy.
+const KATA_SELINUX_CONTEXT: &str = "system_u:system_r:container_kvm_t:s0";
+
+// Fields of the host probed at run time, null with 'env --no-probe'.
+const PROBED_FIELDS: &[&str] = &["host", "hypervisor.version"];
+const NOT_PROBED_NOTE: &str = "not probed with --no-probe";
+
+// The hypervisor binaries keep their paths with 'env --no-probe'.
+const PROBED_HYPERVISORS_FIELD: &str = "hypervisors";
+
+// Fields that change from one run to the next, ignored by 'env --compare'.
+const VOLATILE_FIELDS: &[&str] = &["host.memory.available", "host.memory.free"];
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct MetaInfo {
@@ -133,6 +265,9 @@ pub struct KernelInfo {
     path: String,
     #[serde(default)]
     parameters: String,
+    // The parameters the runtime boots the guest with.
+    #[serde(default)]
+    effective_parameters: Vec<String>,
 }
 
 // InitrdInfo stores initrd image details
@@ -157,7 +292,7 @@ pub struct HypervisorInfo {
     #[serde(default)]
     machine_accelerators: String,
     #[serde(default)]
-    version: String,
+    pub version: String,
     #[serde(default)]
     path: String,
     #[serde(default)]
@@ -188,12 +323,33 @@ pub struct HypervisorInfo {
     cpu_features: String,
 }
 
+// HypervisorBinaryInfo is the binary of a hypervisor section of the
+// configuration, found, executable and version being null when it is not
+// probed, and error why it could not be run for its version.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HypervisorBinaryInfo {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    found: Option<bool>,
+    #[serde(default)]
+    executable: Option<bool>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
 // EnvInfo collects all information that will be displayed by the
 // env command.
 //
 // XXX: Any changes must be coupled with a change to formatVersion.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct EnvInfo {
+    #[serde(default)]
+    schema_version: String,
     #[serde(default)]
     kernel: KernelInfo,
     #[serde(default)]
@@ -210,6 +366,12 @@ pub struct EnvInfo {
     host: HostInfo,
     #[serde(default)]
     agent: AgentInfo,
+    // Why the null fields are null, by field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    notes: BTreeMap<String, String>,
+    // Last, as the TOML output needs the arrays of tables after the tables.
+    #[serde(default)]
+    hypervisors: Vec<HypervisorBinaryInfo>,
 }
 
 pub fn get_meta_info() -> MetaInfo {
@@ -227,6 +389,173 @@ pub fn get_memory_info() -> Result<MemoryInfo> {
     })
 }
 
+// Get the signature of the hypervisor the host runs on from CPUID, None on
+// bare metal.
+#[cfg(target_arch = "x86_64")]
+fn cpuid_hypervisor_signature() -> Option<String> {
+    use std::arch::x86_64::__cpuid;
+
+    // The hypervisor present bit, then the signature in the hypervisor leaf.
+    // SAFETY: CPUID is available on all the x86_64 CPUs.
+    if unsafe { __cpuid(1) }.ecx & (1 << 31) == 0 {
+        return None;
+    }
+    let leaf = unsafe { __cpuid(0x4000_0000) };
+
+    let signature: Vec<u8> = [leaf.ebx, leaf.ecx, leaf.edx]
+        .iter()
+        .flat_map(|reg| reg.to_le_bytes())
+        .collect();
+
+    Some(
+        String::from_utf8_lossy(&signature)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_hypervisor_signature() -> Option<String> {
+    None
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// Probe the virtualization of the host from the CPU flags of cpuinfo, the
+// CPUID signature and the files under root, which is / but for the tests.
+fn probe_virtualization(root: &Path, cpuinfo: &str, signature: Option<&str>) -> VirtualizationInfo {
+    let has_flag = |name: &str| {
+        cpuinfo
+            .lines()
+            .filter(|line| line.starts_with("flags"))
+            .any(|line| line.split_whitespace().any(|flag| flag == name))
+    };
+    let hypervisor_flag = has_flag("hypervisor");
+    let hypervisor_type = read_trimmed(&root.join(SYS_HYPERVISOR_TYPE));
+
+    // The CPUID signature is the most specific, DMI the least.
+    let mut sources: Vec<String> = signature.iter().map(|s| s.to_string()).collect();
+    sources.extend(hypervisor_type.clone());
+    let dmi: Vec<String> = DMI_FILES
+        .iter()
+        .filter_map(|file| read_trimmed(&root.join(file)))
+        .collect();
+    sources.push(dmi.join(" "));
+
+    let vendor = sources.iter().find_map(|source| {
+        HYPERVISOR_VENDORS
+            .iter()
+            .find(|(pattern, _)| source.contains(pattern))
+            .map(|(_, vendor)| vendor.to_string())
+    });
+
+    let is_nested =
+        hypervisor_flag || signature.is_some() || hypervisor_type.is_some() || vendor.is_some();
+
+    // The nested parameter of kvm is about the guests of this host: in a VM,
+    // what tells that it can run VMs is the virtualization flags of its CPU
+    // and a /dev/kvm it can use.
+    let nested_virt_enabled = if is_nested {
+        VIRT_CPU_FLAGS.iter().any(|flag| has_flag(flag))
+            && fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(root.join(DEV_KVM))
+                .is_ok()
+    } else {
+        KVM_NESTED_PARAMS.iter().any(|param| {
+            matches!(
+                read_trimmed(&root.join(param)).as_deref(),
+                Some("Y") | Some("y") | Some("1")
+            )
+        })
+    };
+
+    let vendor = match vendor {
+        Some(vendor) => vendor,
+        None if is_nested => "unknown".to_string(),
+        None => "none".to_string(),
+    };
+
+    VirtualizationInfo {
+        is_nested,
+        nested_virt_enabled,
+        vendor,
+    }
+}
+
+// Probe the security module of the host like getenforce and aa-status, from
+// the files under root, which is / but for the tests. SELinux is preferred
+// when both are enabled, as the distributions only use one of them.
+fn probe_security(root: &Path) -> SecurityInfo {
+    if let Some(enforce) = read_trimmed(&root.join(SELINUX_ENFORCE)) {
+        let mode = if enforce == "1" {
+            "enforcing"
+        } else {
+            "permissive"
+        };
+
+        // selinuxfs only accepts writing the contexts valid for the loaded
+        // policy.
+        let kata_profile_loaded = fs::OpenOptions::new()
+            .write(true)
+            .open(root.join(SELINUX_CONTEXT))
+            .and_then(|mut file| file.write_all(KATA_SELINUX_CONTEXT.as_bytes()))
+            .is_ok();
+
+        return SecurityInfo {
+            lsm: "selinux".to_string(),
+            mode: mode.to_string(),
+            kata_profile_loaded,
+        };
+    }
+
+    if read_trimmed(&root.join(APPARMOR_ENABLED)).as_deref() == Some("Y") {
+        // Each line of the profiles is "<name> (<mode>)".
+        let kata_profile = fs::read_to_string(root.join(APPARMOR_PROFILES))
+            .unwrap_or_default()
+            .lines()
+            .find(|line| line.contains("kata"))
+            .and_then(|line| {
+                line.rsplit_once(" (")
+                    .map(|(_, mode)| mode.trim_end_matches(')').to_string())
+            });
+
+        let mode = kata_profile
+            .clone()
+            .or_else(|| read_trimmed(&root.join(APPARMOR_MODE)))
+            .map(|mode| match mode.as_str() {
+                "enforce" => "enforcing".to_string(),
+                _ => mode,
+            })
+            .unwrap_or_else(|| "enforcing".to_string());
+
+        return SecurityInfo {
+            lsm: "apparmor".to_string(),
+            mode,
+            kata_profile_loaded: kata_profile.is_some(),
+        };
+    }
+
+    SecurityInfo {
+        lsm: "none".to_string(),
+        mode: "disabled".to_string(),
+        kata_profile_loaded: false,
+    }
+}
+
+fn get_virtualization_info() -> VirtualizationInfo {
+    let cpuinfo = fs::read_to_string(check::PROC_CPUINFO).unwrap_or_default();
+    let signature = cpuid_hypervisor_signature();
+
+    probe_virtualization(Path::new("/"), &cpuinfo, signature.as_deref())
+}
+
 fn get_host_info() -> Result<HostInfo> {
     let host_kernel_version = utils::get_kernel_version(utils::PROC_VERSION_FILE)?;
     let (host_distro_name, host_distro_version) =
@@ -250,10 +579,16 @@ fn get_host_info() -> Result<HostInfo> {
 
     let memory_info = get_memory_info()?;
 
-    let guest_protection =
-        arch_specific::available_guest_protection().map_err(|e| anyhow!(e.to_string()))?;
-
-    let guest_protection = guest_protection.to_string();
+    // Only root can tell the guest protection, the rest of the host
+    // information is available to all.
+    let guest_protection = match arch_specific::available_guest_protection() {
+        Ok(guest_protection) => guest_protection.to_string(),
+        Err(check::ProtectionError::NoPerms) => {
+            eprintln!("WARNING: the available guest protection is only reported to root");
+            UNKNOWN_GUEST_PROTECTION.to_string()
+        }
+        Err(e) => return Err(anyhow!(e.to_string())),
+    };
 
     let support_vsocks = utils::supports_vsocks(utils::VHOST_VSOCK_DEVICE)?;
 
@@ -267,25 +602,21 @@ fn get_host_info() -> Result<HostInfo> {
         // TODO: See https://github.com/kata-containers/kata-containers/issues/6727
         vm_container_capable: true,
         support_vsocks,
+        virtualization: get_virtualization_info(),
+        security: probe_security(Path::new("/")),
     })
 }
 
-pub fn get_runtime_info(toml_config: &TomlConfig) -> Result<RuntimeInfo> {
+pub fn get_runtime_info(toml_config: &TomlConfig, config_path: &Path) -> Result<RuntimeInfo> {
     let version = VersionInfo {
         semver: String::from(version::VERSION),
         commit: String::from(version::COMMIT),
     };
 
-    let config_path = TomlConfig::get_default_config_file();
-    let mut toml_path = String::new();
-    if config_path.is_ok() {
-        let p = config_path?;
-        let path_str = p.to_str();
-        toml_path = match path_str {
-            Some(s) => String::from(s),
-            None => String::new(),
-        };
-    }
+    let toml_path = match config_path.to_str() {
+        Some(s) => String::from(s),
+        None => String::new(),
+    };
 
     Ok(RuntimeInfo {
         // TODO: Needs to be implemented: https://github.com/kata-containers/kata-containers/issues/6518
@@ -344,9 +675,84 @@ pub fn get_command_version(cmd: &str) -> Result<String> {
     Ok(version)
 }
 
-pub fn get_hypervisor_info(
-    toml_config: &TomlConfig,
-) -> Result<(HypervisorInfo, ImageInfo, KernelInfo, InitrdInfo)> {
+// A binary which cannot be run is reported with the error, the other
+// binaries still being probed.
+fn probe_hypervisor_binary(name: &str, path: &str) -> HypervisorBinaryInfo {
+    let mut info = HypervisorBinaryInfo {
+        name: name.to_string(),
+        path: path.to_string(),
+        found: Some(false),
+        executable: Some(false),
+        ..Default::default()
+    };
+
+    // Dragonball is built into the runtime.
+    if path.is_empty() {
+        info.error = Some("no path is configured".to_string());
+        return info;
+    }
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            info.error = Some(e.to_string());
+            return info;
+        }
+    };
+    info.found = Some(true);
+
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        info.error = Some("not an executable file".to_string());
+        return info;
+    }
+    info.executable = Some(true);
+
+    match Command::new(path).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            // Only the first line, the others being the copyright.
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            info.version = stdout
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(String::from);
+        }
+        Ok(output) => {
+            info.error = Some(format!(
+                "--version failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Err(e) => info.error = Some(format!("failed to run --version: {}", e)),
+    }
+
+    info
+}
+
+// Every hypervisor section of the configuration, not only the default one.
+fn get_hypervisor_binaries(toml_config: &TomlConfig, probe: bool) -> Vec<HypervisorBinaryInfo> {
+    let mut names: Vec<&String> = toml_config.hypervisor.keys().collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let path = &toml_config.hypervisor[name].path;
+            if probe {
+                probe_hypervisor_binary(name, path)
+            } else {
+                HypervisorBinaryInfo {
+                    name: name.to_string(),
+                    path: path.to_string(),
+                    ..Default::default()
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn default_hypervisor_config(toml_config: &TomlConfig) -> Result<&Hypervisor> {
     // Assign the first entry in the hashmap to the hypervisor config, to make this
     // work for configs where hypervisor_name is absent.
     // This is a workaround for https://github.com/kata-containers/kata-containers/issues/5954
@@ -365,8 +771,45 @@ pub fn get_hypervisor_info(
             .map_err(|e| anyhow!(e))?;
     }
 
-    let version =
-        get_command_version(&hypervisor_config.path).context("error getting hypervisor version")?;
+    Ok(hypervisor_config)
+}
+
+// The guest kernel parameters as the runtime passes them: the ones of the
+// agent configuration, then the configured ones.
+pub fn get_effective_kernel_params(
+    toml_config: &TomlConfig,
+    kernel_params: &str,
+) -> Result<Vec<String>> {
+    let mut params: Vec<String> = toml_config
+        .get_agent_kernel_params()?
+        .into_iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key
+            } else {
+                format!("{}={}", key, value)
+            }
+        })
+        .collect();
+    // The agent parameters are not ordered.
+    params.sort();
+    params.extend(kernel_params.split_whitespace().map(String::from));
+
+    Ok(params)
+}
+
+// Without probing, the hypervisor is not run for its version.
+pub fn get_hypervisor_info(
+    toml_config: &TomlConfig,
+    probe: bool,
+) -> Result<(HypervisorInfo, ImageInfo, KernelInfo, InitrdInfo)> {
+    let hypervisor_config = default_hypervisor_config(toml_config)?;
+
+    let version = if probe {
+        get_command_version(&hypervisor_config.path).context("error getting hypervisor version")?
+    } else {
+        String::new()
+    };
 
     let hypervisor_info = HypervisorInfo {
         machine_type: hypervisor_config.machine_info.machine_type.to_string(),
@@ -405,6 +848,10 @@ pub fn get_hypervisor_info(
     let kernel_info = KernelInfo {
         path: hypervisor_config.boot_info.kernel.to_string(),
         parameters: hypervisor_config.boot_info.kernel_params.to_string(),
+        effective_parameters: get_effective_kernel_params(
+            toml_config,
+            &hypervisor_config.boot_info.kernel_params,
+        )?,
     };
 
     let initrd_info = InitrdInfo {
@@ -414,19 +861,54 @@ pub fn get_hypervisor_info(
     Ok((hypervisor_info, image_info, kernel_info, initrd_info))
 }
 
-pub fn get_env_info(toml_config: &TomlConfig) -> Result<EnvInfo> {
+pub fn get_env_info(toml_config: &TomlConfig, config_path: &Path) -> Result<EnvInfo> {
+    build_env_info(toml_config, config_path, true)
+}
+
+// Without probing, only the configuration is reported, the probed fields
+// being left out with a note. null_probed_fields turns them into nulls.
+fn build_env_info(toml_config: &TomlConfig, config_path: &Path, probe: bool) -> Result<EnvInfo> {
     let metainfo = get_meta_info();
 
-    let runtime_info = get_runtime_info(toml_config).context("get runtime info")?;
+    let runtime_info = get_runtime_info(toml_config, config_path).context("get runtime info")?;
 
     let agent_info = get_agent_info(toml_config).context("get agent configuration")?;
 
-    let host_info = get_host_info().context("get host information")?;
+    let host_info = if probe {
+        get_host_info().context("get host information")?
+    } else {
+        HostInfo::default()
+    };
+
+    let (mut hypervisor_info, _image_info, kernel_info, initrd_info) =
+        get_hypervisor_info(toml_config, false).context("get hypervisor configuration")?;
 
-    let (hypervisor_info, _image_info, kernel_info, initrd_info) =
-        get_hypervisor_info(toml_config).context("get hypervisor configuration")?;
+    let mut notes = if probe {
+        BTreeMap::new()
+    } else {
+        PROBED_FIELDS
+            .iter()
+            .chain(std::iter::once(&PROBED_HYPERVISORS_FIELD))
+            .map(|field| (field.to_string(), NOT_PROBED_NOTE.to_string()))
+            .collect()
+    };
+
+    // A hypervisor which cannot be run is still reported, with the error
+    // of its binary in the hypervisors.
+    if probe {
+        match get_command_version(&hypervisor_info.path) {
+            Ok(version) => hypervisor_info.version = version,
+            Err(e) => {
+                notes.insert(
+                    "hypervisor.version".to_string(),
+                    format!("error getting hypervisor version: {:#}", e),
+                );
+            }
+        }
+    }
 
     let env_info = EnvInfo {
+        schema_version: ENV_SCHEMA_VERSION.to_string(),
         meta: metainfo,
         runtime: runtime_info,
         kernel: kernel_info,
@@ -435,35 +917,848 @@ pub fn get_env_info(toml_config: &TomlConfig) -> Result<EnvInfo> {
         hypervisor: hypervisor_info,
         host: host_info,
         agent: agent_info,
+        notes,
+        hypervisors: get_hypervisor_binaries(toml_config, probe),
     };
 
     Ok(env_info)
 }
 
-pub fn handle_env(env_args: EnvArgument) -> Result<()> {
-    if !Uid::effective().is_root() {
-        return Err(anyhow!("kata-ctl env command requires root privileges to get host information. Please run as root or use sudo"));
+// Replace the probed fields of the env information with nulls, the TOML
+// output leaving them out.
+fn null_probed_fields(value: &mut Value) {
+    for field in PROBED_FIELDS {
+        let path: Vec<&str> = field.split('.').collect();
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let mut map = value.as_object_mut();
+        for parent in parents {
+            map = map
+                .and_then(|m| m.get_mut(*parent))
+                .and_then(Value::as_object_mut);
+        }
+        if let Some(value) = map.and_then(|m| m.get_mut(*last)) {
+            *value = Value::Null;
+        }
     }
+}
 
-    let mut file: Box<dyn Write> = if let Some(path) = env_args.file {
-        Box::new(
+pub fn handle_env(
+    env_args: EnvArgument,
+    config_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let mut file: Box<dyn Write> = match env_args.file {
+        Some(path) if path != STDOUT_FILE => Box::new(
             File::create(path.as_str()).with_context(|| format!("Error creating file {}", path))?,
-        )
+        ),
+        _ => Box::new(io::stdout()),
+    };
+
+    let (mut toml_config, config_path) =
+        utils::load_config(config_file).map_err(KataCtlError::Config)?;
+    if let Some(hypervisor) = env_args.hypervisor {
+        utils::select_hypervisor(&mut toml_config, hypervisor).map_err(KataCtlError::Config)?;
+    }
+
+    let env_info = build_env_info(&toml_config, &config_path, !env_args.no_probe)?;
+    let format = format.with_json(env_args.json).with_yaml(env_args.yaml);
+
+    if let Some(path) = env_args.compare {
+        let reference = load_env_report(&path)?;
+        let current = serde_json::to_value(&env_info)?;
+        let comparison = compare_env_info(&reference, &current, &env_args.filter)
+            .map_err(KataCtlError::Config)?;
+        print_env_comparison(&comparison, format)?;
+
+        // The differences were already reported.
+        if !comparison.is_empty() {
+            return Err(KataCtlError::Status(EXIT_CODE_DIFFERENCES));
+        }
+
+        return Ok(());
+    }
+
+    let schema_version = env_args
+        .schema_version
+        .as_deref()
+        .filter(|version| *version != ENV_SCHEMA_VERSION);
+
+    if env_args.redact
+        || env_args.no_probe
+        || schema_version.is_some()
+        || !env_args.filter.is_empty()
+    {
+        let mut value = serde_json::to_value(&env_info)?;
+        if env_args.no_probe {
+            null_probed_fields(&mut value);
+        }
+        if let Some(version) = schema_version {
+            downgrade_env_info(&mut value, version).map_err(KataCtlError::Config)?;
+            eprintln!(
+                "WARNING: env schema version {} is deprecated, the current one is {}",
+                version, ENV_SCHEMA_VERSION
+            );
+        }
+        if !env_args.filter.is_empty() {
+            value = filter_env_info(value, &env_args.filter)?;
+        }
+
+        if env_args.redact {
+            let mut patterns =
+                vec![Regex::new(utils::HOME_PATH_PATTERN).context("home path pattern")?];
+            patterns.extend(env_args.redact_extra);
+            utils::redact(&mut value, &patterns);
+        }
+
+        write_env_value(&mut file, value, format)?;
     } else {
-        Box::new(io::stdout())
+        write_env(&mut file, &env_info, format)?;
+    }
+
+    Ok(())
+}
+
+// TOML has no null, so the null fields are left out of the TOML output,
+// the JSON and YAML outputs keeping them.
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(values) => {
+            values.retain(|v| !v.is_null());
+            values.iter_mut().for_each(remove_nulls);
+        }
+        _ => {}
+    }
+}
+
+fn write_env_value(file: &mut dyn Write, mut value: Value, format: OutputFormat) -> Result<()> {
+    if format.is_structured() {
+        return write_env(file, &value, format);
+    }
+
+    // A TOML value puts the tables after the other settings, as TOML
+    // requires.
+    remove_nulls(&mut value);
+    write_env(file, &toml::Value::try_from(value)?, format)
+}
+
+// Only keep the given top-level sections of the env information, and its
+// schema version and notes.
+fn filter_env_info(mut value: Value, sections: &[EnvSection]) -> Result<Value> {
+    let map = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("invalid env information"))?;
+    let schema_version = map.remove("schema_version");
+    let notes = map.remove("notes");
+
+    map.retain(|name, _| sections.iter().any(|s| s.name() == name));
+    if map.is_empty() {
+        let names: Vec<&str> = EnvSection::value_variants()
+            .iter()
+            .map(|s| s.name())
+            .collect();
+        return Err(anyhow!(
+            "no env section matched, valid sections are: {}",
+            names.join(", ")
+        ));
+    }
+    if let Some(version) = schema_version {
+        map.insert("schema_version".to_string(), version);
+    }
+    if let Some(notes) = notes {
+        map.insert("notes".to_string(), notes);
+    }
+
+    Ok(value)
+}
+
+fn parse_schema_version(version: &str) -> Result<(u32, u32)> {
+    let parse = || {
+        let (major, minor) = version.split_once('.')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
     };
 
-    let (toml_config, _) = TomlConfig::load_from_default().context("load toml config")?;
+    parse().ok_or_else(|| {
+        anyhow!(
+            "invalid env schema version {:?}, expected MAJOR.MINOR",
+            version
+        )
+    })
+}
+
+// Turn the env information of the current schema version into the shape of
+// an older one, undoing the changes made since.
+fn downgrade_env_info(value: &mut Value, version: &str) -> Result<()> {
+    let requested = parse_schema_version(version)?;
+    if requested < parse_schema_version(ENV_OLDEST_SCHEMA_VERSION)?
+        || requested > parse_schema_version(ENV_SCHEMA_VERSION)?
+    {
+        return Err(anyhow!(
+            "unsupported env schema version {}, the supported versions are {} to {}",
+            version,
+            ENV_OLDEST_SCHEMA_VERSION,
+            ENV_SCHEMA_VERSION
+        ));
+    }
+
+    let map = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("invalid env information"))?;
+    for (changed_in, undo) in ENV_SCHEMA_CHANGES.iter().rev() {
+        if parse_schema_version(changed_in)? > requested {
+            undo(map);
+        }
+    }
+    map.insert(
+        "schema_version".to_string(),
+        Value::from(format!("{}.{}", requested.0, requested.1)),
+    );
+
+    Ok(())
+}
 
-    let env_info = get_env_info(&toml_config)?;
+// Schema version 1.5 added the hypervisor binaries of the configuration.
+fn remove_hypervisor_binaries(env: &mut Map<String, Value>) {
+    env.remove("hypervisors");
+    if let Some(Value::Object(notes)) = env.get_mut("notes") {
+        notes.remove(PROBED_HYPERVISORS_FIELD);
+    }
+    if let Some(Value::Object(meta)) = env.get_mut("meta") {
+        meta.insert("version".to_string(), Value::from("0.0.5-kata-ctl"));
+    }
+}
 
-    if env_args.json {
-        let serialized_json = serde_json::to_string_pretty(&env_info)?;
-        write!(file, "{}", serialized_json)?;
+// Schema version 1.4 added the notes of the null fields.
+fn remove_env_notes(env: &mut Map<String, Value>) {
+    env.remove("notes");
+    if let Some(Value::Object(meta)) = env.get_mut("meta") {
+        meta.insert("version".to_string(), Value::from("0.0.4-kata-ctl"));
+    }
+}
+
+// Schema version 1.3 added the security module of the host.
+fn remove_security_info(env: &mut Map<String, Value>) {
+    if let Some(Value::Object(host)) = env.get_mut("host") {
+        host.remove("security");
+    }
+    if let Some(Value::Object(meta)) = env.get_mut("meta") {
+        meta.insert("version".to_string(), Value::from("0.0.3-kata-ctl"));
+    }
+}
+
+// Schema version 1.2 added the effective guest kernel parameters.
+fn remove_effective_kernel_params(env: &mut Map<String, Value>) {
+    if let Some(Value::Object(kernel)) = env.get_mut("kernel") {
+        kernel.remove("effective_parameters");
+    }
+    if let Some(Value::Object(meta)) = env.get_mut("meta") {
+        meta.insert("version".to_string(), Value::from("0.0.2-kata-ctl"));
+    }
+}
+
+// Schema version 1.1 added the virtualization of the host.
+fn remove_virtualization_info(env: &mut Map<String, Value>) {
+    if let Some(Value::Object(host)) = env.get_mut("host") {
+        host.remove("virtualization");
+    }
+    if let Some(Value::Object(meta)) = env.get_mut("meta") {
+        meta.insert("version".to_string(), Value::from("0.0.1-kata-ctl"));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EnvReport {
+    Reference,
+    Current,
+}
+
+impl EnvReport {
+    fn name(&self) -> &'static str {
+        match self {
+            EnvReport::Reference => "reference",
+            EnvReport::Current => "current",
+        }
+    }
+}
+
+// MissingSection is a section only one of the compared reports has, so its
+// fields cannot be compared.
+#[derive(Debug, PartialEq, Serialize)]
+struct MissingSection {
+    section: String,
+    missing_from: EnvReport,
+}
+
+// EnvComparison is the output of 'kata-ctl env --compare', the changes going
+// from the reference report to the current one.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct EnvComparison {
+    missing_sections: Vec<MissingSection>,
+    changes: Vec<Change>,
+}
+
+impl EnvComparison {
+    fn is_empty(&self) -> bool {
+        self.missing_sections.is_empty() && self.changes.is_empty()
+    }
+}
+
+fn load_env_report(path: &str) -> Result<Value, KataCtlError> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read env report {}", path))?;
+
+    let report: Value = serde_json::from_str(&contents)
+        .with_context(|| {
+            format!(
+                "invalid env report {}, expected the output of 'env --json'",
+                path
+            )
+        })
+        .map_err(KataCtlError::Config)?;
+
+    Ok(report)
+}
+
+// Compare the given sections of two env reports, all of them if none is
+// given.
+fn compare_env_info(
+    reference: &Value,
+    current: &Value,
+    sections: &[EnvSection],
+) -> Result<EnvComparison> {
+    let sections = if sections.is_empty() {
+        EnvSection::value_variants()
     } else {
-        let toml = toml::to_string(&env_info)?;
-        write!(file, "{}", toml)?;
+        sections
+    };
+
+    let (reference, current) = match (reference.as_object(), current.as_object()) {
+        (Some(reference), Some(current)) => (reference, current),
+        _ => return Err(anyhow!("invalid env report, expected a JSON object")),
+    };
+
+    let mut comparison = EnvComparison::default();
+    for section in sections {
+        let name = section.name();
+        let (old, new) = match (reference.get(name), current.get(name)) {
+            (Some(old), Some(new)) => (old, new),
+            (old, new) => {
+                for (value, report) in [(old, EnvReport::Reference), (new, EnvReport::Current)] {
+                    if value.is_none() {
+                        comparison.missing_sections.push(MissingSection {
+                            section: name.to_string(),
+                            missing_from: report,
+                        });
+                    }
+                }
+                continue;
+            }
+        };
+
+        let changes = config_ops::diff_configs(
+            &serde_json::json!({ name: old }),
+            &serde_json::json!({ name: new }),
+        );
+        comparison.changes.extend(
+            changes
+                .into_iter()
+                .filter(|change| !VOLATILE_FIELDS.contains(&change.key.as_str())),
+        );
+    }
+
+    Ok(comparison)
+}
+
+fn print_env_comparison(comparison: &EnvComparison, format: OutputFormat) -> Result<()> {
+    if output::print_structured(format, comparison)? {
+        return Ok(());
+    }
+
+    for missing in &comparison.missing_sections {
+        println!(
+            "section {} is missing from the {} report",
+            missing.section,
+            missing.missing_from.name()
+        );
+    }
+
+    config_ops::print_changes(&comparison.changes, format)
+}
+
+// The env information is not a list, so the table format falls back to
+// the human readable TOML output.
+fn write_env<T: Serialize>(file: &mut dyn Write, env_info: &T, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let serialized_json = serde_json::to_string_pretty(env_info)?;
+            write!(file, "{}", serialized_json)?;
+        }
+        OutputFormat::Yaml => {
+            let serialized_yaml = serde_yaml::to_string(env_info)?;
+            write!(file, "{}", serialized_yaml)?;
+        }
+        OutputFormat::Human | OutputFormat::Table | OutputFormat::Csv => {
+            let toml = toml::to_string(env_info)?;
+            write!(file, "{}", toml)?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::write_file;
+    use clap::{CommandFactory, Parser};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_probe_virtualization() {
+        // bare metal
+        let root = tempdir().unwrap();
+        let info = probe_virtualization(root.path(), "flags\t\t: fpu vmx\n", None);
+        assert!(!info.is_nested);
+        assert!(!info.nested_virt_enabled);
+        assert_eq!(info.vendor, "none");
+
+        write_file(root.path(), KVM_NESTED_PARAMS[0], "Y\n");
+        write_file(root.path(), KVM_NESTED_PARAMS[1], "0\n");
+        let info = probe_virtualization(root.path(), "", None);
+        assert!(info.nested_virt_enabled);
+
+        // a KVM guest, the CPUID signature being preferred to DMI
+        let root = tempdir().unwrap();
+        write_file(root.path(), DMI_FILES[0], "QEMU\n");
+        let cpuinfo = "flags\t\t: fpu vmx hypervisor\n";
+        let info = probe_virtualization(root.path(), cpuinfo, Some("KVMKVMKVM"));
+        assert!(info.is_nested);
+        assert!(!info.nested_virt_enabled);
+        assert_eq!(info.vendor, "KVM");
+
+        let info = probe_virtualization(root.path(), cpuinfo, None);
+        assert_eq!(info.vendor, "QEMU");
+
+        // which can run VMs, whatever the nested parameter of its own kvm
+        write_file(root.path(), KVM_NESTED_PARAMS[0], "N\n");
+        write_file(root.path(), DEV_KVM, "");
+        let info = probe_virtualization(root.path(), cpuinfo, None);
+        assert!(info.nested_virt_enabled);
+        let info = probe_virtualization(root.path(), "flags\t\t: fpu hypervisor\n", None);
+        assert!(!info.nested_virt_enabled);
+        write_file(root.path(), KVM_NESTED_PARAMS[0], "Y\n");
+        fs::remove_file(root.path().join(DEV_KVM)).unwrap();
+        let info = probe_virtualization(root.path(), cpuinfo, None);
+        assert!(!info.nested_virt_enabled);
+
+        // a Hyper-V guest known by its DMI product name only
+        let root = tempdir().unwrap();
+        write_file(root.path(), DMI_FILES[0], "Microsoft Corporation\n");
+        write_file(root.path(), DMI_FILES[1], "Virtual Machine\n");
+        let info = probe_virtualization(root.path(), "", None);
+        assert!(info.is_nested);
+        assert_eq!(info.vendor, "Microsoft Hyper-V");
+
+        // a Xen guest
+        let root = tempdir().unwrap();
+        write_file(root.path(), SYS_HYPERVISOR_TYPE, "xen\n");
+        let info = probe_virtualization(root.path(), "", None);
+        assert!(info.is_nested);
+        assert_eq!(info.vendor, "Xen");
+
+        // an unknown hypervisor
+        let root = tempdir().unwrap();
+        let info = probe_virtualization(root.path(), cpuinfo, None);
+        assert!(info.is_nested);
+        assert_eq!(info.vendor, "unknown");
+    }
+
+    #[test]
+    fn test_probe_security() {
+        let root = tempdir().unwrap();
+        let info = probe_security(root.path());
+        assert_eq!(info.lsm, "none");
+        assert_eq!(info.mode, "disabled");
+        assert!(!info.kata_profile_loaded);
+
+        // AppArmor with the global mode only
+        write_file(root.path(), APPARMOR_ENABLED, "Y\n");
+        write_file(root.path(), APPARMOR_MODE, "enforce\n");
+        write_file(
+            root.path(),
+            APPARMOR_PROFILES,
+            "cri-containerd.apparmor.d (enforce)\n",
+        );
+        let info = probe_security(root.path());
+        assert_eq!(info.lsm, "apparmor");
+        assert_eq!(info.mode, "enforcing");
+        assert!(!info.kata_profile_loaded);
+
+        // the mode of the Kata profile is preferred
+        write_file(
+            root.path(),
+            APPARMOR_PROFILES,
+            "cri-containerd.apparmor.d (enforce)\n/usr/bin/kata-runtime (complain)\n",
+        );
+        let info = probe_security(root.path());
+        assert_eq!(info.mode, "complain");
+        assert!(info.kata_profile_loaded);
+
+        write_file(root.path(), APPARMOR_ENABLED, "N\n");
+        assert_eq!(probe_security(root.path()).lsm, "none");
+
+        // SELinux, the Kata type being valid when the context can be written
+        let root = tempdir().unwrap();
+        write_file(root.path(), SELINUX_ENFORCE, "0");
+        let info = probe_security(root.path());
+        assert_eq!(info.lsm, "selinux");
+        assert_eq!(info.mode, "permissive");
+        assert!(!info.kata_profile_loaded);
+
+        write_file(root.path(), SELINUX_ENFORCE, "1");
+        write_file(root.path(), SELINUX_CONTEXT, "");
+        let info = probe_security(root.path());
+        assert_eq!(info.mode, "enforcing");
+        assert!(info.kata_profile_loaded);
+    }
+
+    #[test]
+    fn test_null_probed_fields() {
+        let env_info = EnvInfo {
+            notes: PROBED_FIELDS
+                .iter()
+                .map(|field| (field.to_string(), NOT_PROBED_NOTE.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let mut value = serde_json::to_value(&env_info).unwrap();
+        null_probed_fields(&mut value);
+
+        assert!(value["host"].is_null());
+        assert!(value["hypervisor"]["version"].is_null());
+        assert!(value["hypervisor"]["path"].is_string());
+        assert_eq!(value["notes"]["host"], NOT_PROBED_NOTE);
+
+        // the notes are kept by the filters, the TOML output leaves the
+        // nulls out
+        let value = filter_env_info(value, &[EnvSection::Host, EnvSection::Hypervisor]).unwrap();
+        assert!(value["notes"].is_object());
+        let mut out = Vec::new();
+        write_env_value(&mut out, value, OutputFormat::Human).unwrap();
+        let toml = String::from_utf8(out).unwrap();
+        let value: toml::Value = toml::from_str(&toml).unwrap();
+        assert!(value.get("host").is_none(), "{}", toml);
+        assert!(value["hypervisor"].get("version").is_none(), "{}", toml);
+    }
+
+    // Runs 'kata-ctl env' with the given arguments and configuration,
+    // returning what it wrote.
+    fn run_env(config: &str, args: &[&str], format: OutputFormat) -> String {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("configuration.toml");
+        fs::write(&config_path, config).unwrap();
+        let out_path = dir.path().join("env.out");
+
+        let mut cmdline = vec!["kata-ctl", "env", "--file", out_path.to_str().unwrap()];
+        cmdline.extend_from_slice(args);
+        let cli = crate::args::KataCtlCli::try_parse_from(cmdline).unwrap();
+        let env_args = match cli.command {
+            crate::args::Commands::Env(env_args) => env_args,
+            command => panic!("unexpected command: {:?}", command),
+        };
+
+        handle_env(env_args, config_path.to_str(), format).unwrap();
+        fs::read_to_string(out_path).unwrap()
+    }
+
+    const TEST_CONFIG: &str = "[hypervisor.qemu]\npath = \"/no/such/qemu\"\n[agent.kata]\n[runtime]\nagent_name = \"kata\"\nhypervisor_name = \"qemu\"\n";
+
+    #[test]
+    fn test_handle_env_no_probe() {
+        let json = run_env(TEST_CONFIG, &["--no-probe"], OutputFormat::Json);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert!(value["host"].is_null());
+        assert!(value["hypervisor"]["version"].is_null());
+        assert_eq!(value["hypervisor"]["path"], "/no/such/qemu");
+        assert!(value["hypervisors"][0]["found"].is_null());
+        assert_eq!(value["notes"]["host"], NOT_PROBED_NOTE);
+
+        let yaml = run_env(TEST_CONFIG, &["--no-probe"], OutputFormat::Yaml);
+        let value: Value = serde_yaml::from_str(&yaml).unwrap();
+        assert!(value["host"].is_null());
+        assert!(value["hypervisors"][0]["version"].is_null());
+
+        let toml = run_env(TEST_CONFIG, &["--no-probe"], OutputFormat::Human);
+        let value: toml::Value = toml::from_str(&toml).unwrap();
+        assert!(value.get("host").is_none(), "{}", toml);
+        assert!(value["hypervisor"].get("version").is_none(), "{}", toml);
+        assert_eq!(
+            value["hypervisors"][0]["path"].as_str(),
+            Some("/no/such/qemu")
+        );
+        assert!(value["hypervisors"][0].get("found").is_none(), "{}", toml);
+    }
+
+    #[test]
+    fn test_handle_env_missing_hypervisor() {
+        // the report is still made, with the hypervisor which is not found
+        let json = run_env(TEST_CONFIG, &["--redact"], OutputFormat::Json);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let hypervisor = &value["hypervisors"][0];
+        assert_eq!(hypervisor["name"], "qemu");
+        assert_eq!(hypervisor["found"], false);
+        assert_eq!(hypervisor["executable"], false);
+        assert!(hypervisor["version"].is_null());
+        assert!(hypervisor["error"].is_string());
+        assert_eq!(value["hypervisor"]["version"], "");
+        assert!(value["notes"]["hypervisor.version"].is_string());
+
+        let toml = run_env(TEST_CONFIG, &["--redact"], OutputFormat::Human);
+        let value: toml::Value = toml::from_str(&toml).unwrap();
+        assert_eq!(value["hypervisors"][0]["found"].as_bool(), Some(false));
+        assert!(value["hypervisors"][0].get("version").is_none(), "{}", toml);
+    }
+
+    #[test]
+    fn test_get_hypervisor_binaries() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, contents: &str, mode: u32| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let qemu = write(
+            "qemu",
+            "#!/bin/sh\necho 'QEMU emulator version 7.2.0'\necho 'Copyright (c)'\n",
+            0o755,
+        );
+        let clh = write("clh", "#!/bin/sh\necho 'bad option' >&2\nexit 1\n", 0o755);
+        let fc = write("fc", "", 0o644);
+        let missing = dir.path().join("missing").to_str().unwrap().to_string();
+
+        let info = probe_hypervisor_binary("qemu", &qemu);
+        assert_eq!(info.found, Some(true));
+        assert_eq!(info.executable, Some(true));
+        assert_eq!(info.version.as_deref(), Some("QEMU emulator version 7.2.0"));
+        assert_eq!(info.error, None);
+
+        let info = probe_hypervisor_binary("clh", &clh);
+        assert_eq!(info.executable, Some(true));
+        assert_eq!(info.version, None);
+        assert!(info.error.unwrap().ends_with(": bad option"));
+
+        let info = probe_hypervisor_binary("fc", &fc);
+        assert_eq!(info.found, Some(true));
+        assert_eq!(info.executable, Some(false));
+        assert_eq!(info.error.as_deref(), Some("not an executable file"));
+
+        for path in [missing.as_str(), ""] {
+            let info = probe_hypervisor_binary("dragonball", path);
+            assert_eq!(info.found, Some(false));
+            assert_eq!(info.executable, Some(false));
+            assert!(info.error.is_some());
+        }
+
+        let toml_config: TomlConfig = toml::from_str(&format!(
+            "[hypervisor.qemu]\npath = \"{}\"\n[hypervisor.clh]\npath = \"{}\"\n",
+            qemu, clh
+        ))
+        .unwrap();
+        let binaries = get_hypervisor_binaries(&toml_config, true);
+        let names: Vec<&str> = binaries.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["clh", "qemu"]);
+
+        // without probing, only the paths are known
+        let binaries = get_hypervisor_binaries(&toml_config, false);
+        let value = serde_json::to_value(&binaries[1]).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "name": "qemu",
+                "path": qemu,
+                "found": null,
+                "executable": null,
+                "version": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_filter_env_info() {
+        let env_info = serde_json::to_value(EnvInfo::default()).unwrap();
+
+        let value = filter_env_info(
+            env_info.clone(),
+            &[EnvSection::Hypervisor, EnvSection::Agent],
+        )
+        .unwrap();
+        let map = value.as_object().unwrap();
+        assert_eq!(map.len(), 3);
+        assert!(map.contains_key("hypervisor"));
+        assert!(map.contains_key("agent"));
+        // the schema version is always kept
+        assert!(map.contains_key("schema_version"));
+
+        // the section names must match the serialized field names
+        for section in EnvSection::value_variants() {
+            assert!(
+                filter_env_info(env_info.clone(), &[*section]).is_ok(),
+                "{:?}",
+                section
+            );
+        }
+
+        let e = filter_env_info(env_info, &[]).unwrap_err();
+        assert!(format!("{}", e).contains("kernel, meta, image"));
+    }
+
+    #[test]
+    fn test_get_effective_kernel_params() {
+        let toml_config = TomlConfig::default();
+        assert_eq!(
+            get_effective_kernel_params(&toml_config, " console=hvc0  quiet ").unwrap(),
+            vec!["console=hvc0", "quiet"]
+        );
+
+        let toml_config: TomlConfig = toml::from_str(
+            "[runtime]\nagent_name = \"kata\"\n[agent.kata]\nenable_debug = true\ndebug_console_enabled = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            get_effective_kernel_params(&toml_config, "quiet").unwrap(),
+            vec![
+                "agent.debug_console",
+                "agent.debug_console_vport=1026",
+                "agent.log=debug",
+                "quiet"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_downgrade_env_info() {
+        let env_info = EnvInfo {
+            schema_version: ENV_SCHEMA_VERSION.to_string(),
+            meta: get_meta_info(),
+            ..Default::default()
+        };
+        let current = serde_json::to_value(&env_info).unwrap();
+        assert!(current["host"].get("virtualization").is_some());
+        assert!(current["kernel"].get("effective_parameters").is_some());
+        assert!(current["host"].get("security").is_some());
+        assert!(current["hypervisors"].is_array());
+
+        let mut value = current.clone();
+        downgrade_env_info(&mut value, ENV_SCHEMA_VERSION).unwrap();
+        assert_eq!(value, current);
+
+        downgrade_env_info(&mut value, "1.4").unwrap();
+        assert_eq!(value["schema_version"], "1.4");
+        assert_eq!(value["meta"]["version"], "0.0.5-kata-ctl");
+        assert!(value.get("hypervisors").is_none());
+
+        downgrade_env_info(&mut value, "1.3").unwrap();
+        assert_eq!(value["schema_version"], "1.3");
+        assert_eq!(value["meta"]["version"], "0.0.4-kata-ctl");
+        assert!(value["host"].get("security").is_some());
+
+        downgrade_env_info(&mut value, "1.2").unwrap();
+        assert_eq!(value["schema_version"], "1.2");
+        assert_eq!(value["meta"]["version"], "0.0.3-kata-ctl");
+        assert!(value["host"].get("security").is_none());
+        assert!(value["kernel"].get("effective_parameters").is_some());
+
+        downgrade_env_info(&mut value, "1.1").unwrap();
+        assert_eq!(value["schema_version"], "1.1");
+        assert_eq!(value["meta"]["version"], "0.0.2-kata-ctl");
+        assert!(value["kernel"].get("effective_parameters").is_none());
+        assert!(value["host"].get("virtualization").is_some());
+
+        let mut value = current.clone();
+        downgrade_env_info(&mut value, "1.0").unwrap();
+        assert_eq!(value["schema_version"], "1.0");
+        assert_eq!(value["meta"]["version"], "0.0.1-kata-ctl");
+        assert!(value["host"].get("virtualization").is_none());
+        assert_eq!(value["host"]["kernel"], current["host"]["kernel"]);
+
+        for version in ["0.9", "1.6", "2.0", "1", "latest"] {
+            let mut value = current.clone();
+            assert!(
+                downgrade_env_info(&mut value, version).is_err(),
+                "{}",
+                version
+            );
+        }
+
+        // the help of the command tells the current version
+        let help = crate::args::KataCtlCli::command()
+            .find_subcommand("env")
+            .and_then(|env| {
+                env.get_arguments()
+                    .find(|arg| arg.get_id() == "schema_version")
+                    .and_then(|arg| arg.get_help())
+                    .map(|help| help.to_string())
+            })
+            .unwrap();
+        assert!(
+            help.ends_with(&format!("is {}", ENV_SCHEMA_VERSION)),
+            "{}",
+            help
+        );
+    }
+
+    #[test]
+    fn test_compare_env_info() {
+        let reference = serde_json::json!({
+            "kernel": { "path": "/boot/vmlinuz", "parameters": "" },
+            "host": { "kernel": "6.1.0", "memory": { "total": 100, "free": 10 } },
+            "agent": { "debug": false },
+        });
+        let current = serde_json::json!({
+            "kernel": { "path": "/boot/vmlinuz", "parameters": "" },
+            "host": { "kernel": "6.2.0", "memory": { "total": 100, "free": 20 } },
+            "agent": { "debug": false },
+            "runtime": { "debug": false },
+        });
+
+        let sections = &[EnvSection::Kernel, EnvSection::Host, EnvSection::Agent];
+        let comparison = compare_env_info(&reference, &reference, sections).unwrap();
+        assert!(comparison.is_empty());
+
+        let comparison = compare_env_info(
+            &reference,
+            &current,
+            &[EnvSection::Kernel, EnvSection::Host, EnvSection::Runtime],
+        )
+        .unwrap();
+        assert_eq!(
+            comparison.missing_sections,
+            vec![MissingSection {
+                section: "runtime".to_string(),
+                missing_from: EnvReport::Reference,
+            }]
+        );
+        // the free memory is not compared
+        let keys: Vec<&str> = comparison.changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["host.kernel"]);
+
+        // the filter scopes the comparison
+        let comparison = compare_env_info(&reference, &current, &[EnvSection::Agent]).unwrap();
+        assert!(comparison.is_empty());
+
+        // a section missing from both reports is not equal in both
+        let comparison = compare_env_info(&reference, &current, &[EnvSection::Image]).unwrap();
+        assert_eq!(comparison.missing_sections.len(), 2);
+
+        assert!(compare_env_info(&serde_json::json!([]), &current, &[]).is_err());
+    }
+}
@@ -5,24 +5,52 @@
 // Description:
 // Implementation of entering into guest VM by debug console.
 // Ensure that `kata-debug-port` is consistent with the port
-// set in the configuration.
+// set in the configuration, or give the host socket the
+// console is exposed on with `--socket`.
+//
+// The interactive console forwards the signals kata-ctl receives to the
+// guest shell, whose terminal is a pseudo terminal of the agent the debug
+// console only exchanges bytes with: SIGINT is sent as its ^C character and
+// SIGTERM ends the session, hanging up the guest shell. The size of the
+// guest terminal is set with stty when the session starts, --rows and --cols
+// overriding the size of the host terminal.
+//
+// TODO: SIGWINCH is not forwarded, so that the guest terminal keeps its size
+// when the host terminal is resized. The agent's TtyWinResize request only
+// resizes the terminals of the container processes, not the one of the
+// debug console, and typing a stty command would go to the program in the
+// foreground. Forwarding the resize needs the agent to resize the debug
+// console terminal first.
 
 use std::{
+    convert::TryFrom,
+    fmt, fs,
     io::{self, BufRead, BufReader, Read, Write},
     os::unix::{
+        fs::FileTypeExt,
         io::{AsRawFd, FromRawFd, RawFd},
         net::UnixStream,
     },
+    sync::atomic::{AtomicI32, Ordering},
+    sync::mpsc,
+    thread,
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
+use nix::fcntl::OFlag;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+use nix::unistd::{close, pipe2};
 use reqwest::StatusCode;
-use slog::debug;
+use serde::Serialize;
+use slog::{debug, info};
 use vmm_sys_util::terminal::Terminal;
 
-use crate::args::ExecArguments;
+use crate::args::{ExecArguments, ExecStdoutEncoding, OutputFormat};
+use crate::error::KataCtlError;
+use crate::output;
+use crate::utils;
 use shim_interface::shim_mgmt::{client::MgmtClient, AGENT_URL};
 
 const CMD_CONNECT: &str = "CONNECT";
@@ -30,7 +58,24 @@ const CMD_OK: &str = "OK";
 const SCHEME_VSOCK: &str = "VSOCK";
 const SCHEME_HYBRID_VSOCK: &str = "HVSOCK";
 
+// Markers printed around the output of a non-interactive command, the end
+// marker being directly followed by the exit status of the command.
+const EXEC_BEGIN_MARKER: &str = "__KATA_CTL_EXEC_BEGIN__";
+const EXEC_END_MARKER: &str = "__KATA_CTL_EXEC_END__";
+// Marker printed between the standard output and error of the command when
+// they are captured separately.
+const EXEC_STDERR_MARKER: &str = "__KATA_CTL_EXEC_STDERR__";
+
 const EPOLL_EVENTS_LEN: usize = 16;
+
+// The signals forwarded to the guest shell of an interactive console.
+const FORWARDED_SIGNALS: &[Signal] = &[Signal::SIGINT, Signal::SIGTERM];
+// The character the guest terminal turns into SIGINT.
+const INTR_CHAR: u8 = 0x03;
+
+// The write end of the pipe the signal handler passes the signals through,
+// -1 when no signal is forwarded.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
 const KATA_AGENT_VSOCK_TIMEOUT: u64 = 5;
 const TIMEOUT: Duration = Duration::from_millis(2000);
 
@@ -51,12 +96,181 @@ pub enum Error {
     EpollAdd(io::Error),
     SocketWrite(io::Error),
     StdioErr(io::Error),
+    Timeout,
+}
+
+#[derive(Debug)]
+struct TimeoutError(Duration);
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out after {} seconds", self.0.as_secs())
+    }
 }
 
+impl std::error::Error for TimeoutError {}
+
 #[derive(Debug, PartialEq)]
 enum EpollDispatch {
     Stdin,
     ServerSock,
+    Signal,
+}
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signal as u8;
+        // SAFETY: write is async-signal-safe, a full pipe only loses the
+        // signal.
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+// SignalForwarder handles the forwarded signals for as long as it is alive,
+// passing them to the epoll loop through a pipe, the handlers restored when
+// it is dropped.
+struct SignalForwarder {
+    read_fd: RawFd,
+    write_fd: RawFd,
+    prev_actions: Vec<(Signal, SigAction)>,
+}
+
+impl SignalForwarder {
+    fn new() -> anyhow::Result<Self> {
+        let (read_fd, write_fd) = pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+        SIGNAL_PIPE.store(write_fd, Ordering::Relaxed);
+
+        let mut forwarder = SignalForwarder {
+            read_fd,
+            write_fd,
+            prev_actions: Vec::new(),
+        };
+
+        let action = SigAction::new(
+            SigHandler::Handler(forward_signal),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for signal in FORWARDED_SIGNALS {
+            // SAFETY: the handler only writes to a pipe.
+            let prev_action = unsafe { sigaction(*signal, &action)? };
+            forwarder.prev_actions.push((*signal, prev_action));
+        }
+
+        Ok(forwarder)
+    }
+
+    // The signals received since the last call.
+    fn read_signals(&self) -> Vec<Signal> {
+        let mut signals = Vec::new();
+        let mut buf = [0u8; 16];
+
+        while let Ok(count) = nix::unistd::read(self.read_fd, &mut buf) {
+            if count == 0 {
+                break;
+            }
+            signals.extend(
+                buf[..count]
+                    .iter()
+                    .filter_map(|b| Signal::try_from(*b as i32).ok()),
+            );
+        }
+
+        signals
+    }
+}
+
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        for (signal, prev_action) in &self.prev_actions {
+            // SAFETY: restores the handling of the signal from before the
+            // session.
+            let _ = unsafe { sigaction(*signal, prev_action) };
+        }
+        SIGNAL_PIPE.store(-1, Ordering::Relaxed);
+        let _ = close(self.read_fd);
+        let _ = close(self.write_fd);
+    }
+}
+
+// What is sent to the guest shell for a forwarded signal.
+#[derive(Debug, PartialEq)]
+enum SignalInput {
+    Send(Vec<u8>),
+    Hangup,
+    Ignore,
+}
+
+// The size of the guest terminal is set by a command of the guest shell,
+// only sent when the session starts, the guest shell being the only program
+// reading the terminal then.
+fn stty_size_command(rows: u16, cols: u16) -> String {
+    format!(" stty rows {} cols {}\r", rows, cols)
+}
+
+fn signal_input(signal: Signal) -> SignalInput {
+    match signal {
+        Signal::SIGINT => SignalInput::Send(vec![INTR_CHAR]),
+        Signal::SIGTERM => SignalInput::Hangup,
+        _ => SignalInput::Ignore,
+    }
+}
+
+// The size of the host terminal, in rows and columns.
+fn terminal_size() -> Option<(u16, u16)> {
+    // SAFETY: TIOCGWINSZ only fills the winsize structure.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret != 0 || size.ws_row == 0 || size.ws_col == 0 {
+        return None;
+    }
+
+    Some((size.ws_row, size.ws_col))
+}
+
+// WindowSize is the size of the guest terminal, the rows and columns given
+// on the command line overriding the ones of the host terminal, e.g. when
+// it is not a terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct WindowSize {
+    rows: Option<u16>,
+    cols: Option<u16>,
+}
+
+impl WindowSize {
+    fn resolve(&self, terminal: Option<(u16, u16)>) -> Option<(u16, u16)> {
+        let rows = self.rows.or_else(|| terminal.map(|(rows, _)| rows))?;
+        let cols = self.cols.or_else(|| terminal.map(|(_, cols)| cols))?;
+
+        Some((rows, cols))
+    }
+}
+
+// RawMode keeps the host terminal in raw mode for as long as it is alive,
+// so that the terminal is restored however the session ends. There is no
+// terminal to set when the input is not one, e.g. a pipe.
+struct RawMode;
+
+impl RawMode {
+    fn new() -> anyhow::Result<Option<Self>> {
+        if !nix::unistd::isatty(libc::STDIN_FILENO).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        io::stdin()
+            .lock()
+            .set_raw_mode()
+            .map_err(|e| anyhow!("failed to set the terminal to raw mode: {}", e))?;
+
+        Ok(Some(RawMode))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = io::stdin().lock().set_canon_mode();
+    }
 }
 
 struct EpollContext {
@@ -65,6 +279,7 @@ struct EpollContext {
     dispatch_table: Vec<EpollDispatch>,
     stdin_handle: io::Stdin,
     debug_console_sock: Option<UnixStream>,
+    signals: Option<SignalForwarder>,
 }
 
 impl EpollContext {
@@ -79,6 +294,7 @@ impl EpollContext {
             dispatch_table,
             stdin_handle: io::stdin(),
             debug_console_sock: None,
+            signals: None,
         })
     }
 
@@ -114,22 +330,40 @@ impl EpollContext {
         Ok(())
     }
 
-    fn do_exit(&self) {
-        self.stdin_handle
-            .lock()
-            .set_canon_mode()
-            .expect("Fail to set stdin to RAW mode");
+    fn enable_signal_event(&mut self, signals: SignalForwarder) -> Result<()> {
+        let dispatch_index = self.dispatch_table.len() as u64;
+        epoll::ctl(
+            self.epoll_raw_fd,
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            signals.read_fd,
+            epoll::Event::new(epoll::Events::EPOLLIN, dispatch_index),
+        )
+        .map_err(Error::EpollAdd)?;
+
+        self.dispatch_table.push(EpollDispatch::Signal);
+        self.signals = Some(signals);
+
+        Ok(())
     }
 
-    fn do_process_handler(&mut self) -> Result<()> {
+    fn do_process_handler(&mut self, timeout: Option<Duration>) -> Result<()> {
         let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
 
         let epoll_raw_fd = self.epoll_raw_fd;
         let debug_console_sock = self.debug_console_sock.as_mut().unwrap();
+        // The timeout is of each wait, so that it is only reached by a
+        // session idle for that long.
+        let epoll_timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
 
         loop {
-            let num_events =
-                epoll::wait(epoll_raw_fd, -1, &mut events[..]).map_err(Error::EpollWait)?;
+            // The signal handlers interrupt the wait.
+            let num_events = match epoll::wait(epoll_raw_fd, epoll_timeout, &mut events[..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => result.map_err(Error::EpollWait)?,
+            };
+            if num_events == 0 {
+                return Err(Error::Timeout);
+            }
 
             for event in events.iter().take(num_events) {
                 let dispatch_index = event.data as usize;
@@ -170,6 +404,21 @@ impl EpollContext {
                             }
                         }
                     }
+                    EpollDispatch::Signal => {
+                        let signals = match &self.signals {
+                            Some(signals) => signals.read_signals(),
+                            None => continue,
+                        };
+                        for signal in signals {
+                            match signal_input(signal) {
+                                SignalInput::Send(data) => debug_console_sock
+                                    .write_all(&data)
+                                    .map_err(Error::SocketWrite)?,
+                                SignalInput::Hangup => return Ok(()),
+                                SignalInput::Ignore => {}
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -344,6 +593,10 @@ async fn get_agent_socket(sandbox_id: &str) -> anyhow::Result<String> {
 
     let body = hyper::body::to_bytes(response.into_body()).await?;
     let agent_sock = String::from_utf8(body.to_vec())?;
+    debug!(
+        sl!(),
+        "agent socket of sandbox {}: {:?}", sandbox_id, agent_sock
+    );
 
     Ok(agent_sock)
 }
@@ -358,13 +611,155 @@ fn get_server_socket(sandbox_id: &str) -> anyhow::Result<String> {
     Ok(server_url)
 }
 
-fn do_run_exec(sandbox_id: &str, dbg_console_vport: u32) -> anyhow::Result<()> {
+// The agent serves its requests on the port of its socket URL.
+fn get_server_port(server_url: &str) -> anyhow::Result<u32> {
+    server_url
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse::<u32>().ok())
+        .ok_or_else(|| anyhow!("invalid agent socket URL {:?}", server_url))
+}
+
+// Connect to the agent of a sandbox, on the port of its requests rather than
+// on the one of its debug console.
+pub fn connect_agent(sandbox_id: &str) -> anyhow::Result<UnixStream> {
+    let server_url = get_server_socket(sandbox_id).context("get agent socket URL")?;
+    let port = get_server_port(&server_url)?;
+
+    setup_client(server_url, port)
+        .with_context(|| format!("cannot connect to the agent of sandbox {}", sandbox_id))
+}
+
+// DebugConsole is how the debug console of a sandbox is reached.
+#[derive(Debug, Clone, PartialEq)]
+enum DebugConsole {
+    // Through the agent socket of the sandbox, on the given vport
+    Vport(u32),
+    // Directly through a Unix socket of the host
+    Socket(String),
+}
+
+fn get_debug_console(vport: u32, socket: Option<&str>) -> anyhow::Result<DebugConsole> {
+    let path = match socket {
+        Some(path) => path,
+        None if vport == 0 => return Err(anyhow!("invalid debug console vport {}", vport)),
+        None => return Ok(DebugConsole::Vport(vport)),
+    };
+
+    if path.is_empty() {
+        return Err(anyhow!("debug console socket path is empty"));
+    }
+
+    let metadata =
+        fs::metadata(path).with_context(|| format!("debug console socket {:?}", path))?;
+    if !metadata.file_type().is_socket() {
+        return Err(anyhow!("debug console socket {:?} is not a socket", path));
+    }
+
+    Ok(DebugConsole::Socket(path.to_string()))
+}
+
+// A debug console socket does not need to be found from the sandbox, and
+// may be reachable without root privileges.
+fn get_console_sandbox_id(
+    sandbox_id: &str,
+    console: &DebugConsole,
+) -> std::result::Result<String, KataCtlError> {
+    match console {
+        DebugConsole::Vport(_) => {
+            utils::require_root("connecting to the debug console of a sandbox")?;
+            utils::resolve_sandbox_id(sandbox_id)
+        }
+        DebugConsole::Socket(_) => Ok(sandbox_id.to_string()),
+    }
+}
+
+fn debug_console_error(sandbox_id: &str, e: anyhow::Error) -> KataCtlError {
+    match e.downcast_ref::<TimeoutError>() {
+        Some(timeout) => KataCtlError::Timeout(anyhow!(
+            "debug console of sandbox {} {}",
+            sandbox_id,
+            timeout
+        )),
+        None => e.into(),
+    }
+}
+
+fn connect_debug_console(sandbox_id: &str, console: &DebugConsole) -> anyhow::Result<UnixStream> {
+    let dbg_console_vport = match console {
+        DebugConsole::Vport(vport) => *vport,
+        DebugConsole::Socket(path) => {
+            info!(
+                sl!(),
+                "connecting to debug console of sandbox {} via {}", sandbox_id, path
+            );
+            return UnixStream::connect(path)
+                .with_context(|| format!("failed to connect to debug console socket {:?}", path));
+        }
+    };
+
     // sandbox_id MUST be a long ID.
     let server_url = get_server_socket(sandbox_id).context("get debug console socket URL")?;
     if server_url.is_empty() {
         return Err(anyhow!("server url is empty."));
     }
+    info!(
+        sl!(),
+        "connecting to debug console of sandbox {} via {} (port {})",
+        sandbox_id,
+        server_url,
+        dbg_console_vport
+    );
     let sock_stream = setup_client(server_url, dbg_console_vport)?;
+    debug!(sl!(), "debug console connection established");
+
+    Ok(sock_stream)
+}
+
+// Connect to the debug console, giving up after the timeout if any. The
+// connect itself cannot be interrupted, so it runs in its own thread which
+// is abandoned on timeout.
+fn connect_debug_console_timeout(
+    sandbox_id: &str,
+    console: &DebugConsole,
+    timeout: Option<Duration>,
+) -> anyhow::Result<UnixStream> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None => return connect_debug_console(sandbox_id, console),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let id = sandbox_id.to_string();
+    let console = console.clone();
+    thread::spawn(move || {
+        let _ = tx.send(connect_debug_console(&id, &console));
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(TimeoutError(timeout).into()),
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(anyhow!("debug console connection thread exited"))
+        }
+    }
+}
+
+fn do_run_exec(
+    sandbox_id: &str,
+    console: &DebugConsole,
+    timeout: Option<Duration>,
+    window_size: WindowSize,
+    environment: &ExecEnvironment,
+) -> anyhow::Result<()> {
+    let mut sock_stream = connect_debug_console_timeout(sandbox_id, console, timeout)?;
+
+    // The guest terminal has no size until it is given one.
+    if let Some((rows, cols)) = window_size.resolve(terminal_size()) {
+        sock_stream.write_all(stty_size_command(rows, cols).as_bytes())?;
+    }
+    if let Some(setup) = environment.shell_setup() {
+        sock_stream.write_all(setup.as_bytes())?;
+    }
 
     let mut epoll_context = EpollContext::new().expect("create epoll context");
     epoll_context
@@ -373,30 +768,579 @@ fn do_run_exec(sandbox_id: &str, dbg_console_vport: u32) -> anyhow::Result<()> {
     epoll_context
         .init_debug_console_sock(sock_stream)
         .expect("enable debug console sock");
+    epoll_context
+        .enable_signal_event(SignalForwarder::new()?)
+        .expect("enable signal event");
 
-    let stdin_handle = io::stdin();
-    stdin_handle.lock().set_raw_mode().expect("set raw mode");
+    let raw_mode = RawMode::new()?;
+    let result = epoll_context.do_process_handler(timeout);
+    drop(raw_mode);
 
-    epoll_context
-        .do_process_handler()
-        .expect("do process handler");
-    epoll_context.do_exit();
+    match result {
+        Err(Error::Timeout) => Err(TimeoutError(timeout.unwrap_or_default()).into()),
+        Err(e) => Err(anyhow!("do process handler: {:?}", e)),
+        _ => Ok(()),
+    }
+}
 
-    Ok(())
+#[derive(Debug, PartialEq)]
+pub struct ExecOutput {
+    pub output: String,
+    pub exit_code: i32,
+}
+
+// ExecResult is the output of 'kata-ctl exec --json'.
+#[derive(Debug, PartialEq, Serialize)]
+struct ExecResult {
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+// Quote an argument so that the guest shell passes it on unchanged.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+// ExecEnvironment is the environment and working directory of the guest
+// command, the ones of the console shell being kept by default.
+#[derive(Debug, Default)]
+pub struct ExecEnvironment {
+    pub env: Vec<(String, String)>,
+    pub workdir: Option<String>,
+}
+
+impl ExecEnvironment {
+    // The shell command line running command in the environment, in a
+    // subshell for the working directory, failing when it does not exist.
+    fn command_line(&self, command: &[String]) -> String {
+        let line: Vec<String> = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+            .chain(command.iter().map(|arg| shell_quote(arg)))
+            .collect();
+        let line = line.join(" ");
+
+        match &self.workdir {
+            Some(workdir) => format!("(cd {} && {})", shell_quote(workdir), line),
+            None => line,
+        }
+    }
+
+    // The shell commands giving the interactive console the environment.
+    fn shell_setup(&self) -> Option<String> {
+        let mut commands: Vec<String> = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("export {}={}", key, shell_quote(value)))
+            .collect();
+        if let Some(workdir) = &self.workdir {
+            commands.push(format!("cd {}", shell_quote(workdir)));
+        }
+
+        if commands.is_empty() {
+            return None;
+        }
+
+        Some(format!("{}\n", commands.join("; ")))
+    }
+}
+
+// Split a marker with an empty quoted string, so that the command line echoed
+// back by the console never contains the marker itself.
+fn split_marker(marker: &str) -> String {
+    let (head, tail) = marker.split_at(marker.len() / 2);
+    format!("'{}''{}'", head, tail)
+}
+
+// Build the shell script sent to the debug console to run a single command.
+// The console is attached to a terminal in the guest, so echo is disabled and
+// the output of the command is delimited by markers.
+fn build_exec_script(command: &[String], environment: &ExecEnvironment) -> String {
+    format!(
+        "stty -echo 2>/dev/null; echo {}; {}; echo {}$?; exit\n",
+        split_marker(EXEC_BEGIN_MARKER),
+        environment.command_line(command),
+        split_marker(EXEC_END_MARKER)
+    )
+}
+
+// Build the shell script running a single command with its standard output
+// and error captured separately, as the debug console provides a single
+// stream. The output goes through the guest terminal, so base64 is the only
+// way to get binary output unchanged.
+fn build_exec_capture_script(
+    command: &[String],
+    environment: &ExecEnvironment,
+    encoding: ExecStdoutEncoding,
+) -> String {
+    let dump = match encoding {
+        ExecStdoutEncoding::Raw => "cat",
+        ExecStdoutEncoding::Base64 => "base64",
+    };
+
+    format!(
+        "stty -echo 2>/dev/null; o=$(mktemp); e=$(mktemp); {} >\"$o\" 2>\"$e\"; s=$?; \
+         echo {}; {} \"$o\"; echo {}; cat \"$e\"; echo {}$s; rm -f \"$o\" \"$e\"; exit\n",
+        environment.command_line(command),
+        split_marker(EXEC_BEGIN_MARKER),
+        dump,
+        split_marker(EXEC_STDERR_MARKER),
+        split_marker(EXEC_END_MARKER)
+    )
+}
+
+// Split the output of a script built by build_exec_capture_script, the
+// base64 encoded standard output being unwrapped.
+fn parse_exec_result(
+    output: &ExecOutput,
+    encoding: ExecStdoutEncoding,
+) -> anyhow::Result<ExecResult> {
+    let (stdout, stderr) = output
+        .output
+        .split_once(EXEC_STDERR_MARKER)
+        .ok_or_else(|| anyhow!("command error output not found in debug console output"))?;
+    let stderr = stderr.strip_prefix('\n').unwrap_or(stderr);
+
+    let stdout = match encoding {
+        ExecStdoutEncoding::Raw => stdout.to_string(),
+        ExecStdoutEncoding::Base64 => {
+            let encoded: String = stdout.split_whitespace().collect();
+            let decoded = base64::decode(&encoded).context("invalid base64 command output")?;
+            base64::encode(decoded)
+        }
+    };
+
+    Ok(ExecResult {
+        exit_code: output.exit_code,
+        stdout,
+        stderr: stderr.to_string(),
+    })
+}
+
+// Extract the output and the exit status of the command from everything the
+// debug console wrote back.
+fn parse_exec_output(console_output: &str) -> anyhow::Result<ExecOutput> {
+    let console_output = console_output.replace("\r\n", "\n");
+
+    let begin = console_output
+        .find(EXEC_BEGIN_MARKER)
+        .ok_or_else(|| anyhow!("command output not found in debug console output"))?;
+    let rest = &console_output[begin + EXEC_BEGIN_MARKER.len()..];
+    let rest = rest.strip_prefix('\n').unwrap_or(rest);
+
+    let end = rest
+        .rfind(EXEC_END_MARKER)
+        .ok_or_else(|| anyhow!("command exit status not found in debug console output"))?;
+    let status = rest[end + EXEC_END_MARKER.len()..]
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let exit_code = status
+        .parse::<i32>()
+        .with_context(|| format!("invalid command exit status: {:?}", status))?;
+
+    Ok(ExecOutput {
+        output: rest[..end].to_string(),
+        exit_code,
+    })
+}
+
+fn do_run_command(
+    sandbox_id: &str,
+    console: &DebugConsole,
+    script: &str,
+    timeout: Option<Duration>,
+) -> anyhow::Result<ExecOutput> {
+    let mut sock_stream = connect_debug_console_timeout(sandbox_id, console, timeout)
+        .with_context(|| {
+            format!(
+                "cannot connect to the debug console of sandbox {}",
+                sandbox_id
+            )
+        })?;
+    sock_stream.set_read_timeout(timeout)?;
+    debug!(sl!(), "debug console script: {:?}", script);
+    sock_stream
+        .write_all(script.as_bytes())
+        .context("send command to debug console")?;
+
+    // The console shell exits once the command is done.
+    let mut console_output = Vec::new();
+    if let Err(e) = sock_stream.read_to_end(&mut console_output) {
+        return match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                Err(TimeoutError(timeout.unwrap_or_default()).into())
+            }
+            _ => Err(anyhow!(e).context("read debug console output")),
+        };
+    }
+
+    parse_exec_output(&String::from_utf8_lossy(&console_output))
 }
 
 // kata-ctl handle exec command starts here.
-pub fn handle_exec(exec_args: ExecArguments) -> anyhow::Result<()> {
-    do_run_exec(exec_args.sandbox_id.as_str(), exec_args.vport)?;
+pub fn handle_exec(
+    exec_args: ExecArguments,
+    format: OutputFormat,
+) -> std::result::Result<(), KataCtlError> {
+    let timeout = exec_args.timeout.map(Duration::from_secs);
+    let format = format.with_json(exec_args.json);
+    let encoding = exec_args.stdout_encoding;
+    let console = get_debug_console(exec_args.vport, exec_args.socket.as_deref())
+        .map_err(KataCtlError::Config)?;
+    let sandbox_id = get_console_sandbox_id(&exec_args.sandbox_id, &console)?;
+    let sandbox_id = sandbox_id.as_str();
+    let environment = ExecEnvironment {
+        env: exec_args.env,
+        workdir: exec_args.workdir,
+    };
+
+    // The interactive console has no result to format.
+    let result = if exec_args.command.is_empty() {
+        let window_size = WindowSize {
+            rows: exec_args.rows,
+            cols: exec_args.cols,
+        };
+        do_run_exec(sandbox_id, &console, timeout, window_size, &environment).map(|_| None)
+    } else {
+        info!(
+            sl!(),
+            "running {:?} in sandbox {}", exec_args.command, sandbox_id
+        );
+        let script = if format.is_structured() {
+            build_exec_capture_script(&exec_args.command, &environment, encoding)
+        } else {
+            build_exec_script(&exec_args.command, &environment)
+        };
+        do_run_command(sandbox_id, &console, &script, timeout).map(Some)
+    };
+
+    let result = match result {
+        Ok(Some(result)) => result,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(debug_console_error(sandbox_id, e)),
+    };
+
+    if format.is_structured() {
+        let exec_result = parse_exec_result(&result, encoding)?;
+        output::print_structured(format, &exec_result)?;
+    } else {
+        // The debug console provides a single stream, so the standard error
+        // of the command is part of its output.
+        let mut stdout = io::stdout();
+        stdout.write_all(result.output.as_bytes())?;
+        stdout.flush()?;
+    }
+
+    // Propagate the exit status of the guest command.
+    if result.exit_code != 0 {
+        return Err(KataCtlError::Status(result.exit_code));
+    }
 
     Ok(())
 }
 
+// Runs a command in the guest through the debug console, for the commands
+// which only need its output. The debug console is served by the agent
+// whatever the state of the containers.
+pub fn run_guest_command(
+    sandbox_id: &str,
+    vport: u32,
+    socket: Option<&str>,
+    command: &[String],
+    timeout: Option<Duration>,
+) -> std::result::Result<ExecOutput, KataCtlError> {
+    let console = get_debug_console(vport, socket).map_err(KataCtlError::Config)?;
+    let sandbox_id = get_console_sandbox_id(sandbox_id, &console)?;
+
+    let script = build_exec_script(command, &ExecEnvironment::default());
+
+    do_run_command(&sandbox_id, &console, &script, timeout)
+        .map_err(|e| debug_console_error(&sandbox_id, e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use micro_http::HttpServer;
 
+    #[test]
+    fn test_get_server_port() {
+        assert_eq!(get_server_port("vsock://31513974:1024").unwrap(), 1024);
+        assert_eq!(
+            get_server_port("hvsock:///run/kata/abc/root/kata.hvsock:1024").unwrap(),
+            1024
+        );
+        assert!(get_server_port("vsock://31513974").is_err());
+        assert!(get_server_port("").is_err());
+    }
+
+    #[test]
+    fn test_connect_debug_console_timeout() {
+        // Without a timeout the connection error is returned as is.
+        let result =
+            connect_debug_console_timeout("no-such-sandbox", &DebugConsole::Vport(1026), None);
+        assert!(result.unwrap_err().downcast_ref::<TimeoutError>().is_none());
+
+        let e = anyhow::Error::from(TimeoutError(Duration::from_secs(3)));
+        assert_eq!(format!("{}", e), "timed out after 3 seconds");
+    }
+
+    #[test]
+    fn test_get_debug_console() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("console.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let socket = socket.to_str().unwrap().to_string();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let file = file.to_str().unwrap().to_string();
+        let missing = dir.path().join("missing").to_str().unwrap().to_string();
+
+        let tests = &[
+            (1026, None, Some(DebugConsole::Vport(1026))),
+            (1, None, Some(DebugConsole::Vport(1))),
+            (0, None, None),
+            (
+                1026,
+                Some(socket.clone()),
+                Some(DebugConsole::Socket(socket)),
+            ),
+            (1026, Some(String::new()), None),
+            (1026, Some(file), None),
+            (1026, Some(missing), None),
+        ];
+
+        for (i, (vport, socket, expected)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?} {:?}", i, vport, socket);
+            let result = get_debug_console(*vport, socket.as_deref());
+            match expected {
+                Some(console) => assert_eq!(&result.unwrap(), console, "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("ls"), "ls");
+        assert_eq!(shell_quote("/proc/self/mounts"), "/proc/self/mounts");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_build_exec_script() {
+        let command = vec!["ls".to_string(), "/proc".to_string()];
+        let script = build_exec_script(&command, &ExecEnvironment::default());
+
+        assert!(script.contains("; ls /proc; "));
+        assert!(script.ends_with("exit\n"));
+        // The markers must not appear in the echoed command line.
+        assert!(!script.contains(EXEC_BEGIN_MARKER));
+        assert!(!script.contains(EXEC_END_MARKER));
+
+        let environment = ExecEnvironment {
+            env: vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "x y".to_string()),
+            ],
+            workdir: Some("/tmp/a b".to_string()),
+        };
+        let script = build_exec_script(&command, &environment);
+        assert!(script.contains("; (cd '/tmp/a b' && A=1 B='x y' ls /proc); "));
+    }
+
+    #[test]
+    fn test_exec_environment_shell_setup() {
+        assert_eq!(ExecEnvironment::default().shell_setup(), None);
+
+        let environment = ExecEnvironment {
+            env: vec![("HOME".to_string(), "/root".to_string())],
+            workdir: Some("/run".to_string()),
+        };
+        assert_eq!(
+            environment.shell_setup().as_deref(),
+            Some("export HOME=/root; cd /run\n")
+        );
+
+        let environment = ExecEnvironment {
+            env: vec![("A".to_string(), "it's".to_string())],
+            workdir: None,
+        };
+        assert_eq!(
+            environment.shell_setup().as_deref(),
+            Some("export A='it'\\''s'\n")
+        );
+    }
+
+    #[test]
+    fn test_build_exec_capture_script() {
+        let command = vec!["cat".to_string(), "/tmp/a b".to_string()];
+
+        let script = build_exec_capture_script(
+            &command,
+            &ExecEnvironment::default(),
+            ExecStdoutEncoding::Raw,
+        );
+        assert!(script.contains("cat '/tmp/a b' >\"$o\" 2>\"$e\""));
+        assert!(script.contains("; cat \"$o\";"));
+        assert!(!script.contains(EXEC_STDERR_MARKER));
+        assert!(script.ends_with("exit\n"));
+
+        let environment = ExecEnvironment {
+            env: vec![("LANG".to_string(), "C".to_string())],
+            workdir: None,
+        };
+        let script = build_exec_capture_script(&command, &environment, ExecStdoutEncoding::Base64);
+        assert!(script.contains("; base64 \"$o\";"));
+        assert!(script.contains("LANG=C cat '/tmp/a b' >\"$o\""));
+    }
+
+    #[test]
+    fn test_parse_exec_result() {
+        let output = |output: String| ExecOutput {
+            output,
+            exit_code: 2,
+        };
+
+        let result = parse_exec_result(
+            &output(format!("out\n{}\nerr\n", EXEC_STDERR_MARKER)),
+            ExecStdoutEncoding::Raw,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            ExecResult {
+                exit_code: 2,
+                stdout: "out\n".to_string(),
+                stderr: "err\n".to_string(),
+            }
+        );
+
+        // the wrapped guest base64 output is unwrapped
+        let encoded = base64::encode([0u8, 255, 10, 13].repeat(30));
+        let wrapped = format!("{}\n{}\n", &encoded[..76], &encoded[76..]);
+        let result = parse_exec_result(
+            &output(format!("{}{}\n", wrapped, EXEC_STDERR_MARKER)),
+            ExecStdoutEncoding::Base64,
+        )
+        .unwrap();
+        assert_eq!(result.stdout, encoded);
+        assert_eq!(result.stderr, "");
+
+        assert!(parse_exec_result(&output("out\n".to_string()), ExecStdoutEncoding::Raw).is_err());
+        assert!(parse_exec_result(
+            &output(format!("not base64!{}\n", EXEC_STDERR_MARKER)),
+            ExecStdoutEncoding::Base64
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_exec_output() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            console_output: String,
+            result: Option<(&'a str, i32)>,
+        }
+
+        let tests = &[
+            TestData {
+                console_output: format!(
+                    "stty -echo; echo '__KATA_CTL_''EXEC_BEGIN__'; ls\r\n{}\r\n1\r\n2\r\n{}0\r\n",
+                    EXEC_BEGIN_MARKER, EXEC_END_MARKER
+                ),
+                result: Some(("1\n2\n", 0)),
+            },
+            TestData {
+                console_output: format!(
+                    "{}\nno newline{}127\n",
+                    EXEC_BEGIN_MARKER, EXEC_END_MARKER
+                ),
+                result: Some(("no newline", 127)),
+            },
+            TestData {
+                console_output: format!("{}\n{}1", EXEC_BEGIN_MARKER, EXEC_END_MARKER),
+                result: Some(("", 1)),
+            },
+            TestData {
+                console_output: "sh: not found\n".to_string(),
+                result: None,
+            },
+            TestData {
+                console_output: format!("{}\noutput\n", EXEC_BEGIN_MARKER),
+                result: None,
+            },
+            TestData {
+                console_output: format!("{}\n{}x\n", EXEC_BEGIN_MARKER, EXEC_END_MARKER),
+                result: None,
+            },
+        ];
+
+        for (i, d) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, d);
+            let result = parse_exec_output(&d.console_output);
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match d.result {
+                Some((output, exit_code)) => {
+                    let result = result.unwrap();
+                    assert_eq!(result.output, output, "{}", msg);
+                    assert_eq!(result.exit_code, exit_code, "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_signal_input() {
+        assert_eq!(
+            signal_input(Signal::SIGINT),
+            SignalInput::Send(vec![INTR_CHAR])
+        );
+        // a resize is never typed into the guest terminal, see the TODO of
+        // the header
+        assert!(!FORWARDED_SIGNALS.contains(&Signal::SIGWINCH));
+        assert_eq!(signal_input(Signal::SIGWINCH), SignalInput::Ignore);
+        assert_eq!(signal_input(Signal::SIGTERM), SignalInput::Hangup);
+        assert_eq!(signal_input(Signal::SIGHUP), SignalInput::Ignore);
+        assert_eq!(stty_size_command(50, 132), " stty rows 50 cols 132\r");
+    }
+
+    #[test]
+    fn test_window_size() {
+        let terminal = Some((24, 80));
+
+        let size = WindowSize::default();
+        assert_eq!(size.resolve(terminal), Some((24, 80)));
+        assert_eq!(size.resolve(None), None);
+
+        let size = WindowSize {
+            rows: Some(40),
+            cols: None,
+        };
+        assert_eq!(size.resolve(terminal), Some((40, 80)));
+        assert_eq!(size.resolve(None), None);
+
+        let size = WindowSize {
+            rows: Some(40),
+            cols: Some(100),
+        };
+        assert_eq!(size.resolve(None), Some((40, 100)));
+    }
+
     #[test]
     fn test_epoll_context_methods() {
         let kata_hybrid_addr = "/tmp/kata_hybrid_vsock01.hvsock";
@@ -0,0 +1,651 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the VM factory management commands. VM templating and
+// the VMCache server are provided by the kata-runtime, so init and destroy
+// are delegated to it, while the status is determined from the factory
+// configuration and the state it leaves on the host. The VMs of the VMCache
+// pool are the processes of the configured hypervisors running a Kata VM of
+// no sandbox.
+//
+// A VM template is shared through a manifest of its files and of the guest
+// assets it was booted from, with their checksums. The files themselves are
+// distributed by other means, the manifest ensuring they are intact and
+// match the configuration of the host before the template is activated.
+
+use crate::args::{
+    FactoryCommand, FactoryStatusArgs, FactorySubCommand, FactoryTemplateCommand,
+    FactoryTemplateSubCommand, OutputFormat,
+};
+use crate::error::KataCtlError;
+use crate::ops::ps_ops::{self, VmProcess};
+use crate::output::{self, Table};
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use kata_types::config::TomlConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Command;
+
+const KATA_RUNTIME_BINARY: &str = "kata-runtime";
+
+const DEFAULT_TEMPLATE_PATH: &str = "/run/vc/vm/template";
+const DEFAULT_VM_CACHE_ENDPOINT: &str = "/var/run/kata-containers/cache.sock";
+
+// Files created by the kata-runtime once a VM template is ready.
+const TEMPLATE_MEMORY_FILE: &str = "memory";
+const TEMPLATE_STATE_FILE: &str = "state";
+
+const ERR_FACTORY_DISABLED: &str = "vm factory or VMCache is not enabled";
+const ERR_TEMPLATE_DISABLED: &str = "vm template is not enabled";
+
+// XXX: Increment for every change to the TemplateManifest type.
+const TEMPLATE_MANIFEST_VERSION: u32 = 1;
+
+const CHECKSUM_BUFFER_SIZE: usize = 1 << 20;
+
+// FactoryConfig is the [factory] section of the runtime configuration.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FactoryConfig {
+    #[serde(default)]
+    pub enable_template: bool,
+    #[serde(default = "default_template_path")]
+    pub template_path: String,
+    #[serde(default)]
+    pub vm_cache_number: u32,
+    #[serde(default = "default_vm_cache_endpoint")]
+    pub vm_cache_endpoint: String,
+}
+
+impl Default for FactoryConfig {
+    fn default() -> Self {
+        FactoryConfig {
+            enable_template: false,
+            template_path: default_template_path(),
+            vm_cache_number: 0,
+            vm_cache_endpoint: default_vm_cache_endpoint(),
+        }
+    }
+}
+
+fn default_template_path() -> String {
+    String::from(DEFAULT_TEMPLATE_PATH)
+}
+
+fn default_vm_cache_endpoint() -> String {
+    String::from(DEFAULT_VM_CACHE_ENDPOINT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FactoryConfigFile {
+    #[serde(default)]
+    factory: FactoryConfig,
+}
+
+// FactoryStatus describes the current state of the VM factory, the output
+// of 'kata-ctl factory status --json'.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FactoryStatus {
+    // The VM template is ready or the VMCache server is running.
+    pub active: bool,
+    pub template_enabled: bool,
+    pub template_active: bool,
+    pub template_path: String,
+    pub vm_cache_enabled: bool,
+    pub vm_cache_running: bool,
+    pub vm_cache_endpoint: String,
+    pub cached_vm_count: usize,
+    // The size of the VMCache pool, vm_cache_number in the configuration
+    pub max_cache: u32,
+    pub cached_vms: Vec<VmProcess>,
+}
+
+fn parse_factory_config(contents: &str) -> Result<FactoryConfig> {
+    let config: FactoryConfigFile =
+        toml::from_str(contents).context("failed to parse factory configuration")?;
+
+    Ok(config.factory)
+}
+
+fn load_factory_config(config_file: Option<&str>) -> Result<FactoryConfig> {
+    let config_path = utils::get_config_file(config_file)?;
+    let contents = fs::read_to_string(&config_path)
+        .with_context(|| format!("failed to read {:?}", config_path))?;
+
+    parse_factory_config(&contents)
+}
+
+fn template_is_active(template_path: &str) -> bool {
+    let path = Path::new(template_path);
+
+    path.join(TEMPLATE_MEMORY_FILE).is_file() && path.join(TEMPLATE_STATE_FILE).is_file()
+}
+
+fn vm_cache_is_running(endpoint: &str) -> bool {
+    UnixStream::connect(endpoint).is_ok()
+}
+
+// The VMs of the pool are only looked for while the VMCache server runs,
+// with list_vms.
+pub fn get_factory_status<F>(config: &FactoryConfig, list_vms: F) -> Result<FactoryStatus>
+where
+    F: FnOnce() -> Result<Vec<VmProcess>>,
+{
+    let vm_cache_enabled = config.vm_cache_number > 0;
+    let vm_cache_running = vm_cache_enabled && vm_cache_is_running(&config.vm_cache_endpoint);
+    let template_active = config.enable_template && template_is_active(&config.template_path);
+    let cached_vms = if vm_cache_running {
+        list_vms()?
+    } else {
+        Vec::new()
+    };
+
+    Ok(FactoryStatus {
+        active: template_active || vm_cache_running,
+        template_enabled: config.enable_template,
+        template_active,
+        template_path: config.template_path.clone(),
+        vm_cache_enabled,
+        vm_cache_running,
+        vm_cache_endpoint: config.vm_cache_endpoint.clone(),
+        cached_vm_count: cached_vms.len(),
+        max_cache: config.vm_cache_number,
+        cached_vms,
+    })
+}
+
+fn factory_status_table(status: &FactoryStatus) -> Table {
+    let mut table = Table::new(&["COMPONENT", "STATE", "DETAIL"]);
+
+    if status.template_enabled {
+        let state = if status.template_active {
+            "active"
+        } else {
+            "inactive"
+        };
+        table.add_row(vec![
+            "vm template".to_string(),
+            state.to_string(),
+            status.template_path.clone(),
+        ]);
+    }
+
+    if status.vm_cache_enabled {
+        let state = if status.vm_cache_running {
+            "running"
+        } else {
+            "stopped"
+        };
+        table.add_row(vec![
+            "vm cache".to_string(),
+            state.to_string(),
+            format!(
+                "{} of {} cached VMs ({})",
+                status.cached_vm_count, status.max_cache, status.vm_cache_endpoint
+            ),
+        ]);
+    }
+
+    table
+}
+
+fn cached_vms_table(cached_vms: &[VmProcess]) -> Table {
+    let mut table = Table::new(&["PID", "HYPERVISOR", "STATE", "AGE"]);
+    for vm in cached_vms {
+        table.add_row(vec![
+            vm.pid.to_string(),
+            vm.hypervisor.clone(),
+            vm.state.clone(),
+            vm.age_secs
+                .map_or_else(|| "-".to_string(), ps_ops::format_uptime),
+        ]);
+    }
+
+    table
+}
+
+fn print_factory_status(status: &FactoryStatus) {
+    if !status.template_enabled && !status.vm_cache_enabled {
+        println!("{}", ERR_FACTORY_DISABLED);
+        return;
+    }
+
+    print!("{}", factory_status_table(status));
+    if !status.cached_vms.is_empty() {
+        println!();
+        print!("{}", cached_vms_table(&status.cached_vms));
+    }
+}
+
+// TemplateFile is a file of a VM template, or a guest asset it was booted
+// from.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TemplateFile {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// TemplateManifest is the output of 'kata-ctl factory template export'.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct TemplateManifest {
+    pub version: u32,
+    pub template_path: String,
+    pub files: Vec<TemplateFile>,
+    pub assets: Vec<TemplateFile>,
+}
+
+fn file_checksum(path: &Path) -> Result<(u64, String)> {
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+fn get_template_file(name: &str, path: &Path) -> Result<TemplateFile> {
+    let (size, sha256) = file_checksum(path)?;
+
+    Ok(TemplateFile {
+        name: name.to_string(),
+        path: path.display().to_string(),
+        size,
+        sha256,
+    })
+}
+
+// The guest assets of the configured hypervisor, the template memory
+// being only valid with the kernel and image it was booted from.
+fn get_template_assets(toml_config: &TomlConfig) -> Result<Vec<(&'static str, String)>> {
+    let name = &toml_config.runtime.hypervisor_name;
+    let hypervisor = toml_config
+        .hypervisor
+        .get(name)
+        .ok_or_else(|| anyhow!("hypervisor {:?} is not configured", name))?;
+
+    let boot_info = &hypervisor.boot_info;
+    Ok([
+        ("kernel", &boot_info.kernel),
+        ("image", &boot_info.image),
+        ("initrd", &boot_info.initrd),
+    ]
+    .iter()
+    .filter(|(_, path)| !path.is_empty())
+    .map(|(name, path)| (*name, path.to_string()))
+    .collect())
+}
+
+fn create_template_manifest(
+    template_path: &str,
+    assets: &[(&str, String)],
+) -> Result<TemplateManifest, KataCtlError> {
+    if !template_is_active(template_path) {
+        return Err(KataCtlError::NotFound(anyhow!(
+            "no vm template in {}, run 'factory init' first",
+            template_path
+        )));
+    }
+
+    let path = Path::new(template_path);
+    let files = [TEMPLATE_MEMORY_FILE, TEMPLATE_STATE_FILE]
+        .iter()
+        .map(|name| get_template_file(name, &path.join(name)))
+        .collect::<Result<Vec<_>>>()?;
+    let assets = assets
+        .iter()
+        .map(|(name, asset)| get_template_file(name, Path::new(asset)))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(TemplateManifest {
+        version: TEMPLATE_MANIFEST_VERSION,
+        template_path: template_path.to_string(),
+        files,
+        assets,
+    })
+}
+
+fn verify_template_file(file: &TemplateFile) -> Result<(), KataCtlError> {
+    let path = Path::new(&file.path);
+    if !path.is_file() {
+        return Err(KataCtlError::NotFound(anyhow!(
+            "template {} {} does not exist",
+            file.name,
+            file.path
+        )));
+    }
+
+    let (size, sha256) = file_checksum(path)?;
+    if size != file.size || sha256 != file.sha256 {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "template {} {} does not match its checksum",
+            file.name,
+            file.path
+        )));
+    }
+
+    Ok(())
+}
+
+// All the files of the template must be intact, and the assets the same as
+// the ones of the configuration.
+fn verify_template_manifest(
+    manifest: &TemplateManifest,
+    assets: &[(&str, String)],
+) -> Result<(), KataCtlError> {
+    if manifest.version != TEMPLATE_MANIFEST_VERSION {
+        return Err(KataCtlError::Config(anyhow!(
+            "unsupported template manifest version {}, expected {}",
+            manifest.version,
+            TEMPLATE_MANIFEST_VERSION
+        )));
+    }
+
+    for (name, asset) in assets {
+        match manifest.assets.iter().find(|file| file.name == *name) {
+            Some(file) if file.path == *asset => {}
+            Some(file) => {
+                return Err(KataCtlError::Config(anyhow!(
+                    "template was created with {} {}, the configuration uses {}",
+                    name,
+                    file.path,
+                    asset
+                )))
+            }
+            None => {
+                return Err(KataCtlError::Config(anyhow!(
+                    "template was created without {}, the configuration uses {}",
+                    name,
+                    asset
+                )))
+            }
+        }
+    }
+
+    for file in manifest.files.iter().chain(manifest.assets.iter()) {
+        verify_template_file(file)?;
+    }
+
+    Ok(())
+}
+
+// Copy the verified template files to the configured template path, unless
+// they already are there.
+fn activate_template(manifest: &TemplateManifest, template_path: &str) -> Result<()> {
+    if Path::new(&manifest.template_path) == Path::new(template_path) {
+        return Ok(());
+    }
+
+    fs::create_dir_all(template_path)
+        .with_context(|| format!("failed to create {}", template_path))?;
+
+    for file in &manifest.files {
+        let dest = Path::new(template_path).join(&file.name);
+        fs::copy(&file.path, &dest)
+            .with_context(|| format!("failed to copy {} to {:?}", file.path, dest))?;
+    }
+
+    Ok(())
+}
+
+fn handle_factory_template(
+    template_cmd: FactoryTemplateCommand,
+    config: &FactoryConfig,
+    config_file: Option<&str>,
+) -> Result<(), KataCtlError> {
+    if !config.enable_template {
+        return Err(KataCtlError::Config(anyhow!(ERR_TEMPLATE_DISABLED)));
+    }
+
+    let (toml_config, _) = utils::load_config(config_file).map_err(KataCtlError::Config)?;
+    let assets = get_template_assets(&toml_config).map_err(KataCtlError::Config)?;
+
+    match template_cmd.template_cmd {
+        FactoryTemplateSubCommand::Export(args) => {
+            let manifest = create_template_manifest(&config.template_path, &assets)?;
+            let contents = serde_json::to_string_pretty(&manifest)?;
+            fs::write(&args.file, contents)
+                .with_context(|| format!("failed to write {}", args.file))?;
+            println!("vm template exported to {}", args.file);
+        }
+        FactoryTemplateSubCommand::Import(args) => {
+            utils::require_root("importing a VM template")?;
+
+            let contents = fs::read_to_string(&args.file)
+                .with_context(|| format!("failed to read {}", args.file))?;
+            let manifest: TemplateManifest = serde_json::from_str(&contents)
+                .with_context(|| format!("invalid template manifest {}", args.file))
+                .map_err(KataCtlError::Config)?;
+
+            verify_template_manifest(&manifest, &assets)?;
+            activate_template(&manifest, &config.template_path)?;
+            println!("vm template imported to {}", config.template_path);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_runtime_factory_cmd(
+    config: &FactoryConfig,
+    config_file: Option<&str>,
+    subcmd: &str,
+) -> Result<(), KataCtlError> {
+    utils::require_root("managing the VM factory")?;
+
+    if !config.enable_template && config.vm_cache_number == 0 {
+        return Err(KataCtlError::Config(anyhow!(ERR_FACTORY_DISABLED)));
+    }
+
+    // The cached VMs and the template are lost.
+    if subcmd == "destroy" {
+        utils::confirm("destroy the VM factory")?;
+    }
+
+    let mut cmd = Command::new(KATA_RUNTIME_BINARY);
+    if let Some(path) = config_file {
+        cmd.arg("--config").arg(path);
+    }
+
+    let status = cmd
+        .arg("factory")
+        .arg(subcmd)
+        .status()
+        .with_context(|| format!("failed to run {} factory {}", KATA_RUNTIME_BINARY, subcmd))?;
+
+    if !status.success() {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "{} factory {} failed: {}",
+            KATA_RUNTIME_BINARY,
+            subcmd,
+            status
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn handle_factory(
+    factory_cmd: FactoryCommand,
+    config_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    let config = load_factory_config(config_file).map_err(KataCtlError::Config)?;
+
+    match factory_cmd.factory_cmd {
+        FactorySubCommand::Status(FactoryStatusArgs { json }) => {
+            let status = get_factory_status(&config, || ps_ops::list_idle_vms(config_file))?;
+            if !output::print_structured(format.with_json(json), &status)? {
+                print_factory_status(&status);
+            }
+        }
+        FactorySubCommand::Init => run_runtime_factory_cmd(&config, config_file, "init")?,
+        FactorySubCommand::Destroy => run_runtime_factory_cmd(&config, config_file, "destroy")?,
+        FactorySubCommand::Template(template_cmd) => {
+            handle_factory_template(template_cmd, &config, config_file)?
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_factory_config() {
+        let config = parse_factory_config("[runtime]\ndebug = true\n").unwrap();
+        assert_eq!(config, FactoryConfig::default());
+
+        let contents = r#"
+[factory]
+enable_template = true
+template_path = "/tmp/template"
+vm_cache_number = 3
+"#;
+        let config = parse_factory_config(contents).unwrap();
+        assert!(config.enable_template);
+        assert_eq!(config.template_path, "/tmp/template");
+        assert_eq!(config.vm_cache_number, 3);
+        assert_eq!(config.vm_cache_endpoint, DEFAULT_VM_CACHE_ENDPOINT);
+
+        assert!(parse_factory_config("[factory]\nvm_cache_number = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn test_get_factory_status() {
+        let dir = tempdir().unwrap();
+        let template_path = dir.path().to_str().unwrap().to_string();
+
+        let config = FactoryConfig {
+            enable_template: true,
+            template_path: template_path.clone(),
+            vm_cache_number: 2,
+            vm_cache_endpoint: dir.path().join("cache.sock").display().to_string(),
+        };
+
+        // the VMs are not looked for while the VMCache server is stopped
+        let no_vms = || -> Result<Vec<VmProcess>> { panic!("VMs listed") };
+        let status = get_factory_status(&config, no_vms).unwrap();
+        assert!(!status.active);
+        assert!(status.template_enabled);
+        assert!(!status.template_active);
+        assert!(status.vm_cache_enabled);
+        assert!(!status.vm_cache_running);
+        assert_eq!(status.cached_vm_count, 0);
+        assert_eq!(status.max_cache, 2);
+
+        fs::write(dir.path().join(TEMPLATE_MEMORY_FILE), "").unwrap();
+        fs::write(dir.path().join(TEMPLATE_STATE_FILE), "").unwrap();
+        let status = get_factory_status(&config, no_vms).unwrap();
+        assert!(status.active);
+        assert!(status.template_active);
+        assert_eq!(status.template_path, template_path);
+
+        // a listening VMCache server
+        let _listener = std::os::unix::net::UnixListener::bind(&config.vm_cache_endpoint).unwrap();
+        let vm = || VmProcess {
+            pid: 20,
+            hypervisor: "qemu".to_string(),
+            state: "sleeping".to_string(),
+            age_secs: Some(75),
+        };
+        let status = get_factory_status(&config, || Ok(vec![vm()])).unwrap();
+        assert!(status.vm_cache_running);
+        assert_eq!(status.cached_vm_count, 1);
+        assert_eq!(status.cached_vms, vec![vm()]);
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["active"], true);
+        assert_eq!(json["cached_vm_count"], 1);
+        assert_eq!(json["max_cache"], 2);
+        assert_eq!(json["cached_vms"][0]["age_secs"], 75);
+
+        assert_eq!(
+            cached_vms_table(&status.cached_vms)
+                .to_string()
+                .lines()
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            vec!["20", "qemu", "sleeping", "1m15s"]
+        );
+    }
+
+    #[test]
+    fn test_template_manifest() {
+        let dir = tempdir().unwrap();
+        let template_path = dir.path().join("template");
+        let template_path = template_path.to_str().unwrap();
+        let kernel = dir.path().join("vmlinux").display().to_string();
+        let assets = vec![("kernel", kernel.clone())];
+
+        assert!(create_template_manifest(template_path, &assets).is_err());
+
+        fs::create_dir(template_path).unwrap();
+        fs::write(
+            Path::new(template_path).join(TEMPLATE_MEMORY_FILE),
+            "memory",
+        )
+        .unwrap();
+        fs::write(Path::new(template_path).join(TEMPLATE_STATE_FILE), "state").unwrap();
+        fs::write(&kernel, "kernel").unwrap();
+
+        let manifest = create_template_manifest(template_path, &assets).unwrap();
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.assets[0].size, 6);
+        assert_eq!(
+            manifest.assets[0].sha256,
+            "6923dd1bc0460082c5d55a831908c24a282860b7f1cd6c2b79cf1bc8857c639c"
+        );
+
+        verify_template_manifest(&manifest, &assets).unwrap();
+
+        // the configuration must use the same assets
+        let other_assets = vec![("kernel", "/other/vmlinux".to_string())];
+        let e = verify_template_manifest(&manifest, &other_assets).unwrap_err();
+        assert_eq!(e.exit_code(), crate::error::EXIT_CODE_CONFIG);
+        let other_assets = vec![("image", "/kata.img".to_string())];
+        assert!(verify_template_manifest(&manifest, &other_assets).is_err());
+
+        // the template is activated in the configured template path
+        let dest = dir.path().join("imported");
+        let dest = dest.to_str().unwrap();
+        activate_template(&manifest, dest).unwrap();
+        assert_eq!(
+            fs::read_to_string(Path::new(dest).join(TEMPLATE_MEMORY_FILE)).unwrap(),
+            "memory"
+        );
+
+        fs::write(&kernel, "kernel2").unwrap();
+        let e = verify_template_manifest(&manifest, &assets).unwrap_err();
+        assert!(format!("{}", e).contains("checksum"));
+
+        fs::remove_file(&kernel).unwrap();
+        let e = verify_template_manifest(&manifest, &assets).unwrap_err();
+        assert_eq!(e.exit_code(), crate::error::EXIT_CODE_NOT_FOUND);
+    }
+}
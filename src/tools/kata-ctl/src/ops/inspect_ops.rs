@@ -0,0 +1,524 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the inspect command, showing the configuration a
+// sandbox was started with, from the state persisted by its shim: the
+// state.json of runtime-rs or the persist.json of the go runtime.
+//
+// The state is read as plain JSON rather than with the types of the
+// runtimes, so that the states of other versions can still be partly
+// inspected. The fields which cannot be found are null, with a note.
+
+use crate::args::{InspectArgument, OutputFormat};
+use crate::error::KataCtlError;
+use crate::output::{self, Table};
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROC_PATH: &str = "/proc";
+
+// The kernel command line options of the hypervisors.
+const KERNEL_CMDLINE_OPTIONS: &[&str] = &["-append", "--cmdline"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StateFormat {
+    RuntimeRs,
+    Go,
+}
+
+impl StateFormat {
+    fn runtime(&self) -> &'static str {
+        match self {
+            StateFormat::RuntimeRs => "runtime-rs",
+            StateFormat::Go => "go",
+        }
+    }
+}
+
+// Where the runtimes persist the state of their sandboxes.
+const STATE_FILES: &[(StateFormat, &str, &str)] = &[
+    (
+        StateFormat::RuntimeRs,
+        shim_interface::KATA_PATH,
+        "state.json",
+    ),
+    (StateFormat::Go, "/run/vc/sbs", "persist.json"),
+];
+
+// The JSON pointers of the fields in the states of the runtimes, the fields
+// without a pointer not being persisted.
+struct StateFields {
+    hypervisor: &'static str,
+    hypervisor_path: &'static str,
+    hypervisor_pid: &'static str,
+    kernel: &'static str,
+    kernel_cmdline: Option<&'static str>,
+    image: &'static str,
+    initrd: &'static str,
+    memory_mb: &'static str,
+    vcpus: &'static str,
+    max_vcpus: &'static str,
+}
+
+const RUNTIME_RS_FIELDS: StateFields = StateFields {
+    hypervisor: "/hypervisor/hypervisor_type",
+    hypervisor_path: "/hypervisor/config/path",
+    hypervisor_pid: "/hypervisor/pid",
+    kernel: "/hypervisor/config/kernel",
+    kernel_cmdline: Some("/hypervisor/config/kernel_params"),
+    image: "/hypervisor/config/image",
+    initrd: "/hypervisor/config/initrd",
+    memory_mb: "/hypervisor/config/default_memory",
+    vcpus: "/hypervisor/config/default_vcpus",
+    max_vcpus: "/hypervisor/config/default_maxvcpus",
+};
+
+const GO_FIELDS: StateFields = StateFields {
+    hypervisor: "/Config/HypervisorType",
+    hypervisor_path: "/Config/HypervisorConfig/HypervisorPath",
+    hypervisor_pid: "/HypervisorState/Pid",
+    kernel: "/Config/HypervisorConfig/KernelPath",
+    kernel_cmdline: None,
+    image: "/Config/HypervisorConfig/ImagePath",
+    initrd: "/Config/HypervisorConfig/InitrdPath",
+    memory_mb: "/Config/HypervisorConfig/MemorySize",
+    vcpus: "/Config/HypervisorConfig/NumVCPUs",
+    max_vcpus: "/Config/HypervisorConfig/DefaultMaxVCPUs",
+};
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SandboxDevice {
+    pub kind: String,
+    pub id: String,
+    pub path: Option<String>,
+}
+
+// SandboxInspection is the output of 'kata-ctl inspect', notes holding why
+// the null fields could not be read.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SandboxInspection {
+    pub sandbox_id: String,
+    pub runtime: String,
+    pub state_file: String,
+    pub hypervisor: Option<String>,
+    pub hypervisor_path: Option<String>,
+    pub kernel: Option<String>,
+    pub kernel_cmdline: Option<String>,
+    pub image: Option<String>,
+    pub initrd: Option<String>,
+    pub memory_mb: Option<u64>,
+    pub vcpus: Option<i64>,
+    pub max_vcpus: Option<i64>,
+    pub devices: Option<Vec<SandboxDevice>>,
+    pub notes: BTreeMap<String, String>,
+}
+
+fn get_string(state: &Value, pointer: &str) -> Option<String> {
+    state
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+// The kernel command line the hypervisor process was started with.
+fn kernel_cmdline_from_process(proc_path: &Path, pid: u64) -> Result<String> {
+    let cmdline = fs::read(proc_path.join(pid.to_string()).join("cmdline"))
+        .with_context(|| format!("the hypervisor process {} is not running", pid))?;
+    let args: Vec<String> = cmdline
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect();
+
+    args.iter()
+        .position(|arg| KERNEL_CMDLINE_OPTIONS.contains(&arg.as_str()))
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+        .ok_or_else(|| anyhow!("the hypervisor command line has no kernel parameters"))
+}
+
+fn runtime_rs_devices(state: &Value) -> Option<Vec<SandboxDevice>> {
+    let block_devices = state
+        .pointer("/hypervisor/cached_block_devices")?
+        .as_array()?;
+    let mut devices: Vec<SandboxDevice> = block_devices
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|id| SandboxDevice {
+            kind: "block".to_string(),
+            id: id.to_string(),
+            path: None,
+        })
+        .collect();
+
+    // Each network endpoint has a single field set, named after its kind.
+    let endpoints = state
+        .pointer("/resource/endpoint")
+        .and_then(Value::as_array);
+    for endpoint in endpoints.into_iter().flatten() {
+        let fields = endpoint.as_object().into_iter().flatten();
+        for (name, value) in fields.filter(|(_, value)| !value.is_null()) {
+            let id = get_string(value, "/if_name").or_else(|| get_string(value, "/bdf"));
+            devices.push(SandboxDevice {
+                kind: name.trim_end_matches("_endpoint").to_string(),
+                id: id.unwrap_or_default(),
+                path: None,
+            });
+        }
+    }
+
+    Some(devices)
+}
+
+fn go_devices(state: &Value) -> Option<Vec<SandboxDevice>> {
+    let devices = state.pointer("/Devices")?.as_array()?;
+
+    Some(
+        devices
+            .iter()
+            .map(|device| SandboxDevice {
+                kind: get_string(device, "/Type").unwrap_or_default(),
+                id: get_string(device, "/ID").unwrap_or_default(),
+                path: get_string(device, "/BlockDrive/File"),
+            })
+            .collect(),
+    )
+}
+
+// The path in the guest of a block device of the sandbox, given its path on
+// the host. Only the go runtime persists it.
+fn guest_device_path(format: StateFormat, state: &Value, host_device: &str) -> Option<String> {
+    if format != StateFormat::Go {
+        return None;
+    }
+
+    state
+        .pointer("/Devices")?
+        .as_array()?
+        .iter()
+        .find(|device| get_string(device, "/BlockDrive/File").as_deref() == Some(host_device))
+        .and_then(|device| get_string(device, "/BlockDrive/VirtPath"))
+}
+
+fn inspect_state(
+    sandbox_id: &str,
+    format: StateFormat,
+    state_file: &Path,
+    state: &Value,
+    proc_path: &Path,
+) -> SandboxInspection {
+    let fields = match format {
+        StateFormat::RuntimeRs => &RUNTIME_RS_FIELDS,
+        StateFormat::Go => &GO_FIELDS,
+    };
+    let mut notes = BTreeMap::new();
+
+    let kernel_cmdline = match fields.kernel_cmdline {
+        Some(pointer) => get_string(state, pointer),
+        None => {
+            let pid = state.pointer(fields.hypervisor_pid).and_then(Value::as_u64);
+            let cmdline = pid
+                .ok_or_else(|| anyhow!("the hypervisor PID is not in the state"))
+                .and_then(|pid| kernel_cmdline_from_process(proc_path, pid));
+            match cmdline {
+                Ok(cmdline) => Some(cmdline),
+                Err(e) => {
+                    notes.insert(
+                        "kernel_cmdline".to_string(),
+                        format!("not persisted by the {} runtime, {:#}", format.runtime(), e),
+                    );
+                    None
+                }
+            }
+        }
+    };
+
+    let inspection = SandboxInspection {
+        sandbox_id: sandbox_id.to_string(),
+        runtime: format.runtime().to_string(),
+        state_file: state_file.display().to_string(),
+        hypervisor: get_string(state, fields.hypervisor),
+        hypervisor_path: get_string(state, fields.hypervisor_path),
+        kernel: get_string(state, fields.kernel),
+        kernel_cmdline,
+        image: get_string(state, fields.image),
+        initrd: get_string(state, fields.initrd),
+        memory_mb: state.pointer(fields.memory_mb).and_then(Value::as_u64),
+        vcpus: state.pointer(fields.vcpus).and_then(Value::as_i64),
+        max_vcpus: state.pointer(fields.max_vcpus).and_then(Value::as_i64),
+        devices: match format {
+            StateFormat::RuntimeRs => runtime_rs_devices(state),
+            StateFormat::Go => go_devices(state),
+        },
+        notes: BTreeMap::new(),
+    };
+
+    // The other null fields are missing from the state.
+    if let Ok(Value::Object(values)) = serde_json::to_value(&inspection) {
+        for (name, _) in values.iter().filter(|(_, value)| value.is_null()) {
+            notes
+                .entry(name.clone())
+                .or_insert_with(|| format!("not set in {}", state_file.display()));
+        }
+    }
+
+    SandboxInspection {
+        notes,
+        ..inspection
+    }
+}
+
+fn find_state_file(sandbox_id: &str) -> Result<(StateFormat, PathBuf), KataCtlError> {
+    STATE_FILES
+        .iter()
+        .map(|(format, dir, file)| (*format, Path::new(dir).join(sandbox_id).join(file)))
+        .find(|(_, path)| path.exists())
+        .ok_or_else(|| {
+            KataCtlError::NotFound(anyhow!("no persisted state for sandbox {}", sandbox_id))
+        })
+}
+
+fn print_inspection(inspection: &SandboxInspection) {
+    let or_null = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    let mut table = Table::new(&["FIELD", "VALUE"]);
+    let rows = vec![
+        ("sandbox_id", Some(inspection.sandbox_id.clone())),
+        ("runtime", Some(inspection.runtime.clone())),
+        ("state_file", Some(inspection.state_file.clone())),
+        ("hypervisor", inspection.hypervisor.clone()),
+        ("hypervisor_path", inspection.hypervisor_path.clone()),
+        ("kernel", inspection.kernel.clone()),
+        ("kernel_cmdline", inspection.kernel_cmdline.clone()),
+        ("image", inspection.image.clone()),
+        ("initrd", inspection.initrd.clone()),
+        ("memory_mb", inspection.memory_mb.map(|v| v.to_string())),
+        ("vcpus", inspection.vcpus.map(|v| v.to_string())),
+        ("max_vcpus", inspection.max_vcpus.map(|v| v.to_string())),
+    ];
+    for (name, value) in rows {
+        table.add_row(vec![name.to_string(), or_null(value)]);
+    }
+    print!("{}", table);
+
+    match &inspection.devices {
+        Some(devices) if !devices.is_empty() => {
+            println!("\nDevices:");
+            let mut table = Table::new(&["KIND", "ID", "PATH"]);
+            for device in devices {
+                table.add_row(vec![
+                    device.kind.clone(),
+                    device.id.clone(),
+                    or_null(device.path.clone()),
+                ]);
+            }
+            print!("{}", table);
+        }
+        _ => {}
+    }
+
+    if !inspection.notes.is_empty() {
+        println!("\nNotes:");
+        for (name, note) in &inspection.notes {
+            println!(" - {}: {}", name, note);
+        }
+    }
+}
+
+fn read_state(state_file: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(state_file)
+        .with_context(|| format!("failed to read {}", state_file.display()))?;
+
+    serde_json::from_str(&contents)
+        .with_context(|| format!("invalid sandbox state {}", state_file.display()))
+}
+
+// Inspect the persisted state of a sandbox, given its full ID.
+pub fn inspect_sandbox(sandbox_id: &str) -> Result<SandboxInspection, KataCtlError> {
+    let (state_format, state_file) = find_state_file(sandbox_id)?;
+    let state = read_state(&state_file)?;
+
+    Ok(inspect_state(
+        sandbox_id,
+        state_format,
+        &state_file,
+        &state,
+        Path::new(PROC_PATH),
+    ))
+}
+
+// Find the path in the guest of the block device of the sandbox attached
+// from the given host device, from the persisted state of the sandbox.
+pub fn find_guest_device_path(
+    sandbox_id: &str,
+    host_device: &str,
+) -> Result<Option<String>, KataCtlError> {
+    let (state_format, state_file) = find_state_file(sandbox_id)?;
+    let state = read_state(&state_file)?;
+
+    Ok(guest_device_path(state_format, &state, host_device))
+}
+
+pub fn handle_inspect(args: InspectArgument, format: OutputFormat) -> Result<(), KataCtlError> {
+    let format = format.with_json(args.json);
+    let sandbox_id = utils::resolve_sandbox_id(&args.sandbox_id)?;
+    let inspection = inspect_sandbox(&sandbox_id)?;
+
+    if !output::print_structured(format, &inspection)? {
+        print_inspection(&inspection);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_inspect_runtime_rs_state() {
+        let state = json!({
+            "sandbox_type": "virt_container",
+            "resource": {
+                "endpoint": [{"veth_endpoint": {"if_name": "eth0", "network_qos": false}}],
+                "cgroup_state": null,
+            },
+            "hypervisor": {
+                "hypervisor_type": "dragonball",
+                "pid": null,
+                "cached_block_devices": ["blk0"],
+                "config": {
+                    "path": "",
+                    "kernel": "/opt/kata/vmlinux",
+                    "kernel_params": "console=hvc0 quiet",
+                    "image": "/opt/kata/kata.img",
+                    "initrd": "",
+                    "default_memory": 2048,
+                    "default_vcpus": 1,
+                    "default_maxvcpus": 8,
+                },
+            },
+        });
+
+        let state_file = Path::new("/run/kata/sb1/state.json");
+        let inspection = inspect_state(
+            "sb1",
+            StateFormat::RuntimeRs,
+            state_file,
+            &state,
+            Path::new("/nonexistent"),
+        );
+        assert_eq!(inspection.runtime, "runtime-rs");
+        assert_eq!(inspection.hypervisor.as_deref(), Some("dragonball"));
+        assert_eq!(
+            inspection.kernel_cmdline.as_deref(),
+            Some("console=hvc0 quiet")
+        );
+        assert_eq!(inspection.memory_mb, Some(2048));
+        assert_eq!(inspection.vcpus, Some(1));
+        assert_eq!(inspection.max_vcpus, Some(8));
+        assert_eq!(
+            inspection.devices.unwrap(),
+            vec![
+                SandboxDevice {
+                    kind: "block".to_string(),
+                    id: "blk0".to_string(),
+                    path: None,
+                },
+                SandboxDevice {
+                    kind: "veth".to_string(),
+                    id: "eth0".to_string(),
+                    path: None,
+                },
+            ]
+        );
+
+        // dragonball is built into the shim, and there is no initrd
+        assert_eq!(inspection.hypervisor_path, None);
+        assert_eq!(inspection.initrd, None);
+        assert_eq!(
+            inspection.notes.keys().collect::<Vec<_>>(),
+            vec!["hypervisor_path", "initrd"]
+        );
+    }
+
+    #[test]
+    fn test_inspect_go_state() {
+        let proc_path = tempdir().unwrap();
+        let process = proc_path.path().join("42");
+        fs::create_dir(&process).unwrap();
+        fs::write(
+            process.join("cmdline"),
+            b"/usr/bin/qemu-system-x86_64\0-name\0sandbox-sb2\0-append\0console=hvc0 quiet\0",
+        )
+        .unwrap();
+
+        let state = json!({
+            "Devices": [{
+                "ID": "drive-1",
+                "Type": "block",
+                "BlockDrive": {"File": "/dev/sdb", "VirtPath": "/dev/vda"},
+            }],
+            "HypervisorState": {"Pid": 42},
+            "Config": {
+                "HypervisorType": "qemu",
+                "HypervisorConfig": {
+                    "HypervisorPath": "/usr/bin/qemu-system-x86_64",
+                    "KernelPath": "/usr/share/kata-containers/vmlinux.container",
+                    "ImagePath": "",
+                    "InitrdPath": "/usr/share/kata-containers/kata-containers-initrd.img",
+                    "MemorySize": 2048,
+                    "NumVCPUs": 1,
+                    "DefaultMaxVCPUs": 4,
+                },
+            },
+        });
+
+        let state_file = Path::new("/run/vc/sbs/sb2/persist.json");
+        let inspection =
+            inspect_state("sb2", StateFormat::Go, state_file, &state, proc_path.path());
+        assert_eq!(inspection.runtime, "go");
+        assert_eq!(inspection.hypervisor.as_deref(), Some("qemu"));
+        assert_eq!(
+            inspection.kernel_cmdline.as_deref(),
+            Some("console=hvc0 quiet")
+        );
+        assert_eq!(inspection.max_vcpus, Some(4));
+        assert_eq!(
+            inspection.devices.unwrap(),
+            vec![SandboxDevice {
+                kind: "block".to_string(),
+                id: "drive-1".to_string(),
+                path: Some("/dev/sdb".to_string()),
+            }]
+        );
+        assert_eq!(inspection.notes.keys().collect::<Vec<_>>(), vec!["image"]);
+
+        assert_eq!(
+            guest_device_path(StateFormat::Go, &state, "/dev/sdb").as_deref(),
+            Some("/dev/vda")
+        );
+        assert_eq!(guest_device_path(StateFormat::Go, &state, "/dev/sdc"), None);
+        assert_eq!(
+            guest_device_path(StateFormat::RuntimeRs, &state, "/dev/sdb"),
+            None
+        );
+
+        // the kernel command line is only known while the hypervisor runs
+        fs::remove_dir_all(&process).unwrap();
+        let inspection =
+            inspect_state("sb2", StateFormat::Go, state_file, &state, proc_path.path());
+        assert_eq!(inspection.kernel_cmdline, None);
+        assert!(inspection.notes["kernel_cmdline"].contains("not running"));
+    }
+}
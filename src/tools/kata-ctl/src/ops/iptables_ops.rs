@@ -0,0 +1,1408 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the iptables commands, getting and setting the iptables
+// of the guest VM through the shim management server.
+//
+// The shim and the agent always transfer all the tables, in iptables-save
+// format, so a single table is selected from that data. This works for
+// setting too as iptables-restore only replaces the tables it is given.
+//
+// 'iptables get' for several sandboxes goes on after the sandboxes whose
+// iptables cannot be got, failing once the others are reported.
+//
+// 'iptables set' reads the iptables from a file or, with --stdin, from the
+// standard input, e.g. from a script generating them.
+//
+// 'iptables set --check' checks the syntax of an iptables-restore file
+// locally: the tables, chains and rule commands, not the rule matches and
+// targets, which depend on the guest kernel.
+//
+// 'iptables snapshot' saves a table of every running sandbox to a directory,
+// in a file named after the sandbox ID, with a .v6 suffix for ip6tables.
+// 'iptables restore' sets them back, skipping the sandboxes that are gone.
+//
+// 'iptables reset' sets every table of the guest back to the default of the
+// guest, no rules and chains accepting everything, which iptables-restore
+// does by flushing the tables it is given.
+
+use crate::args::{
+    IpTablesArguments, IpTablesGetArgs, IpTablesResetArgs, IpTablesRestoreArgs, IpTablesSetArgs,
+    IpTablesSnapshotArgs, IpTablesTable, IptablesCommand, OutputFormat,
+};
+use crate::error::{KataCtlError, EXIT_CODE_DIFFERENCES};
+use crate::ops::ps_ops;
+use crate::output;
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use shim_interface::shim_mgmt::client::MgmtClient;
+use shim_interface::shim_mgmt::{IP6_TABLE_URL, IP_TABLE_URL};
+
+const TIMEOUT: Duration = Duration::from_millis(2000);
+
+const TABLE_PREFIX: &str = "*";
+const TABLE_COMMIT: &str = "COMMIT";
+const CHAIN_PREFIX: &str = ":";
+
+// The commands of iptables-restore rule lines, with the number of chain
+// arguments iptables-restore requires.
+const RULE_COMMANDS: &[(&str, &str, usize)] = &[
+    ("-A", "--append", 1),
+    ("-D", "--delete", 1),
+    ("-I", "--insert", 1),
+    ("-R", "--replace", 1),
+    ("-N", "--new-chain", 1),
+    ("-X", "--delete-chain", 0),
+    ("-P", "--policy", 1),
+    ("-F", "--flush", 0),
+    ("-Z", "--zero", 0),
+    ("-E", "--rename-chain", 1),
+];
+
+// Headers of the tables of 'iptables get --both', as iptables-save comments
+// so that the output can still be restored.
+const IPV4_HEADER: &str = "# iptables";
+const IPV6_HEADER: &str = "# ip6tables";
+
+// Header of the tables of each sandbox of 'iptables get' for several
+// sandboxes.
+const SANDBOX_HEADER: &str = "# sandbox ";
+
+// Suffix of the ip6tables files of a snapshot directory.
+const IPV6_SNAPSHOT_SUFFIX: &str = ".v6";
+
+// IpTablesClient gets and sets the iptables of a sandbox.
+trait IpTablesClient {
+    fn get_iptables(&self, is_ipv6: bool) -> Result<Vec<u8>>;
+    fn set_iptables(&self, is_ipv6: bool, data: Vec<u8>) -> Result<()>;
+}
+
+struct ShimClient {
+    sandbox_id: String,
+}
+
+fn iptables_url(is_ipv6: bool) -> &'static str {
+    if is_ipv6 {
+        IP6_TABLE_URL
+    } else {
+        IP_TABLE_URL
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    Ok(rt.block_on(future))
+}
+
+// The agent runs iptables-save, whose addresses and ports are numeric, no
+// name being resolved in the guest.
+async fn shim_get_iptables(sandbox_id: &str, is_ipv6: bool) -> Result<Vec<u8>> {
+    let shim_client = MgmtClient::new(sandbox_id, Some(TIMEOUT))?;
+    let response = shim_client.get(iptables_url(is_ipv6)).await?;
+    let status = response.status();
+    if status != StatusCode::OK {
+        return Err(anyhow!("failed to get iptables ({:?})", status));
+    }
+
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+
+    Ok(body.to_vec())
+}
+
+async fn shim_set_iptables(sandbox_id: &str, is_ipv6: bool, data: Vec<u8>) -> Result<()> {
+    let shim_client = MgmtClient::new(sandbox_id, Some(TIMEOUT))?;
+    let response = shim_client.put(iptables_url(is_ipv6), data).await?;
+    let status = response.status();
+    if status != StatusCode::OK {
+        return Err(anyhow!("failed to set iptables ({:?})", status));
+    }
+
+    Ok(())
+}
+
+impl IpTablesClient for ShimClient {
+    fn get_iptables(&self, is_ipv6: bool) -> Result<Vec<u8>> {
+        block_on(shim_get_iptables(&self.sandbox_id, is_ipv6))?
+    }
+
+    fn set_iptables(&self, is_ipv6: bool, data: Vec<u8>) -> Result<()> {
+        block_on(shim_set_iptables(&self.sandbox_id, is_ipv6, data))?
+    }
+}
+
+// Extract a table from iptables-save formatted data, from its "*<table>"
+// line up to and including its "COMMIT" line. The lines are kept unchanged.
+fn select_table(data: &[u8], table: IpTablesTable) -> Result<Vec<u8>> {
+    let header = format!("{}{}", TABLE_PREFIX, table.name());
+    let mut selected = Vec::new();
+    let mut in_table = false;
+
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        let trimmed = String::from_utf8_lossy(line);
+        let trimmed = trimmed.trim();
+
+        if !in_table {
+            in_table = trimmed == header;
+        }
+
+        if in_table {
+            selected.extend_from_slice(line);
+            if trimmed == TABLE_COMMIT {
+                return Ok(selected);
+            }
+        }
+    }
+
+    if in_table {
+        return Err(anyhow!(
+            "table {} is not terminated by {}",
+            table.name(),
+            TABLE_COMMIT
+        ));
+    }
+
+    Err(anyhow!("table {} not found", table.name()))
+}
+
+// BothIpTables is the structured output of 'iptables get --both'.
+#[derive(Debug, Serialize)]
+struct BothIpTables {
+    ipv4: String,
+    ipv6: String,
+}
+
+fn format_both_iptables(ipv4: Vec<u8>, ipv6: Vec<u8>, format: OutputFormat) -> Result<Vec<u8>> {
+    let both = BothIpTables {
+        ipv4: String::from_utf8_lossy(&ipv4).to_string(),
+        ipv6: String::from_utf8_lossy(&ipv6).to_string(),
+    };
+    if let Some(output) = output::to_structured(format, &both)? {
+        return Ok(format!("{}\n", output).into_bytes());
+    }
+
+    let mut data = Vec::new();
+    for (header, table) in [(IPV4_HEADER, ipv4), (IPV6_HEADER, ipv6)] {
+        data.extend_from_slice(header.as_bytes());
+        data.push(b'\n');
+        data.extend_from_slice(&table);
+        if !table.ends_with(b"\n") {
+            data.push(b'\n');
+        }
+    }
+
+    Ok(data)
+}
+
+fn read_iptables(
+    client: &dyn IpTablesClient,
+    args: &IpTablesGetArgs,
+    format: OutputFormat,
+) -> Result<Vec<u8>> {
+    if args.both {
+        let ipv4 = select_table(&client.get_iptables(false)?, args.table)?;
+        let ipv6 = select_table(&client.get_iptables(true)?, args.table)?;
+        format_both_iptables(ipv4, ipv6, format)
+    } else {
+        select_table(&client.get_iptables(args.v6)?, args.table)
+    }
+}
+
+// The structured output of the iptables of a sandbox, among others.
+fn read_structured_iptables(
+    client: &dyn IpTablesClient,
+    args: &IpTablesGetArgs,
+) -> Result<serde_json::Value> {
+    let table = |is_ipv6| {
+        select_table(&client.get_iptables(is_ipv6)?, args.table)
+            .map(|data| String::from_utf8_lossy(&data).to_string())
+    };
+
+    if args.both {
+        let both = BothIpTables {
+            ipv4: table(false)?,
+            ipv6: table(true)?,
+        };
+        Ok(serde_json::to_value(both)?)
+    } else {
+        Ok(serde_json::Value::String(table(args.v6)?))
+    }
+}
+
+fn write_iptables(file: Option<&str>, data: &[u8]) -> Result<()> {
+    let mut writer: Box<dyn Write> = match file {
+        Some(path) => {
+            Box::new(File::create(path).with_context(|| format!("failed to create {:?}", path))?)
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    writer.write_all(data)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+// The table is written unchanged, so that it can be given back to
+// `iptables set` as is.
+fn get_iptables(
+    client: &dyn IpTablesClient,
+    args: &IpTablesGetArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let data = read_iptables(client, args, format)?;
+
+    write_iptables(args.file.as_deref(), &data)
+}
+
+// Gets the table of several sandboxes, going on after a failure so that as
+// many sandboxes as possible are reported. The tables are preceded by the
+// sandbox ID as an iptables-save comment, or keyed by the sandbox ID in the
+// structured output.
+fn get_sandboxes_iptables<'a>(
+    sandbox_ids: &[String],
+    args: &IpTablesGetArgs,
+    format: OutputFormat,
+    client: &dyn Fn(&str) -> Box<dyn IpTablesClient + 'a>,
+) -> Result<()> {
+    let mut data = Vec::new();
+    let mut tables = BTreeMap::new();
+    let mut failed = 0;
+
+    for sandbox_id in sandbox_ids {
+        let client = client(sandbox_id.as_str());
+        let result = if format.is_structured() {
+            read_structured_iptables(client.as_ref(), args).map(|table| {
+                tables.insert(sandbox_id.clone(), table);
+            })
+        } else {
+            read_iptables(client.as_ref(), args, format).map(|table| {
+                data.extend_from_slice(format!("{}{}\n", SANDBOX_HEADER, sandbox_id).as_bytes());
+                data.extend_from_slice(&table);
+                if !table.ends_with(b"\n") {
+                    data.push(b'\n');
+                }
+            })
+        };
+
+        if let Err(e) = result {
+            eprintln!("WARNING: sandbox {}: {:#}", sandbox_id, e);
+            failed += 1;
+        }
+    }
+
+    if let Some(output) = output::to_structured(format, &tables)? {
+        data = format!("{}\n", output).into_bytes();
+    }
+    write_iptables(args.file.as_deref(), &data)?;
+
+    report_failures(failed, sandbox_ids.len(), "get")
+}
+
+// The packet and byte counters of the chains change all the time, so they
+// are reset to compare the rules only.
+fn reset_counters(table: &[u8]) -> String {
+    String::from_utf8_lossy(table)
+        .lines()
+        .map(|line| match line.rsplit_once(" [") {
+            Some((chain, counters))
+                if line.starts_with(CHAIN_PREFIX) && counters.ends_with(']') =>
+            {
+                format!("{} [0:0]", chain)
+            }
+            _ => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Returns the differences between the baseline and the live table, as a
+// unified diff, or None if they are the same.
+fn diff_iptables(
+    client: &dyn IpTablesClient,
+    sandbox_id: &str,
+    args: &IpTablesGetArgs,
+    baseline: &str,
+) -> Result<Option<String>> {
+    let expected = fs::read(baseline).with_context(|| format!("failed to read {:?}", baseline))?;
+    let expected = select_table(&expected, args.table)
+        .with_context(|| format!("invalid iptables file {:?}", baseline))?;
+
+    let live = client.get_iptables(args.v6)?;
+    let live = select_table(&live, args.table)?;
+
+    let diff = utils::unified_diff(
+        baseline,
+        &format!("{} ({})", sandbox_id, args.table.name()),
+        &reset_counters(&expected),
+        &reset_counters(&live),
+    );
+
+    Ok(Some(diff).filter(|d| !d.is_empty()))
+}
+
+fn builtin_chains(table: &str) -> Option<&'static [&'static str]> {
+    let chains: &[&str] = match table {
+        "filter" | "security" => &["INPUT", "FORWARD", "OUTPUT"],
+        "nat" => &["PREROUTING", "INPUT", "OUTPUT", "POSTROUTING"],
+        "mangle" => &["PREROUTING", "INPUT", "FORWARD", "OUTPUT", "POSTROUTING"],
+        "raw" => &["PREROUTING", "OUTPUT"],
+        _ => return None,
+    };
+
+    Some(chains)
+}
+
+fn is_counters(s: &str) -> bool {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .and_then(|s| s.split_once(':'))
+        .map_or(false, |(packets, bytes)| {
+            [packets, bytes]
+                .iter()
+                .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+// Splits a rule line into its words, the double quoted words being kept
+// together.
+fn split_rule(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => word.extend(chars.next()),
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(anyhow!("unterminated quote"));
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+struct TableState {
+    name: String,
+    line: usize,
+    chains: Vec<String>,
+}
+
+fn check_chain(state: &mut TableState, line: &str) -> Result<()> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (chain, policy) = match words.as_slice() {
+        [chain, policy] | [chain, policy, _] => (*chain, *policy),
+        _ => return Err(anyhow!("expected \":<chain> <policy> [counters]\"")),
+    };
+
+    if !matches!(policy, "ACCEPT" | "DROP" | "-") {
+        return Err(anyhow!("invalid policy {:?} of chain {}", policy, chain));
+    }
+    if words.len() == 3 && !is_counters(words[2]) {
+        return Err(anyhow!("invalid counters {:?}", words[2]));
+    }
+
+    state.chains.push(chain.to_string());
+
+    Ok(())
+}
+
+fn check_rule(state: &mut TableState, line: &str) -> Result<()> {
+    let mut words = split_rule(line)?;
+    if words.first().map_or(false, |w| is_counters(w)) {
+        words.remove(0);
+    }
+
+    let command = words
+        .first()
+        .ok_or_else(|| anyhow!("missing rule command"))?;
+    let chain_args = RULE_COMMANDS
+        .iter()
+        .find(|(short, long, _)| command == short || command == long)
+        .map(|(_, _, chain_args)| *chain_args)
+        .ok_or_else(|| anyhow!("unknown command {:?}", command))?;
+
+    let chain = words.get(1).filter(|w| !w.starts_with('-'));
+    match chain {
+        None if chain_args > 0 => Err(anyhow!("{} needs a chain", command)),
+        Some(chain) if command == "-N" || command == "--new-chain" => {
+            state.chains.push(chain.to_string());
+            Ok(())
+        }
+        Some(chain)
+            if !state.chains.contains(chain)
+                && !builtin_chains(&state.name)
+                    .unwrap_or(&[])
+                    .contains(&chain.as_str()) =>
+        {
+            Err(anyhow!(
+                "chain {} is not declared in table {}",
+                chain,
+                state.name
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Checks the syntax of iptables-restore data, reporting the first error
+// with its line number.
+fn check_iptables_rules(data: &[u8]) -> Result<()> {
+    let data = String::from_utf8_lossy(data);
+    let mut table: Option<TableState> = None;
+
+    for (i, line) in data.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = if let Some(name) = line.strip_prefix(TABLE_PREFIX) {
+            match (&table, builtin_chains(name)) {
+                (Some(current), _) => Err(anyhow!(
+                    "table {} starts before the {} of table {}",
+                    name,
+                    TABLE_COMMIT,
+                    current.name
+                )),
+                (None, None) => Err(anyhow!("unknown table {:?}", name)),
+                (None, Some(_)) => {
+                    table = Some(TableState {
+                        name: name.to_string(),
+                        line: line_number,
+                        chains: Vec::new(),
+                    });
+                    Ok(())
+                }
+            }
+        } else if let Some(state) = table.as_mut() {
+            if line == TABLE_COMMIT {
+                table = None;
+                Ok(())
+            } else if let Some(chain) = line.strip_prefix(CHAIN_PREFIX) {
+                check_chain(state, chain)
+            } else {
+                check_rule(state, line)
+            }
+        } else {
+            Err(anyhow!("{:?} is outside of a table", line))
+        };
+
+        result.with_context(|| format!("line {}", line_number))?;
+    }
+
+    if let Some(state) = table {
+        return Err(
+            anyhow!("table {} is not terminated by {}", state.name, TABLE_COMMIT)
+                .context(format!("line {}", state.line)),
+        );
+    }
+
+    Ok(())
+}
+
+// Describes where 'iptables set' reads the iptables from, for the messages.
+fn rules_source(args: &IpTablesSetArgs) -> String {
+    match &args.file {
+        Some(file) => format!("iptables file {:?}", file),
+        None => "iptables from stdin".to_string(),
+    }
+}
+
+// Reads the iptables to set from the file or, without one, from stdin.
+fn read_rules<R: Read>(file: Option<&str>, mut stdin: R) -> Result<Vec<u8>> {
+    match file {
+        Some(file) => fs::read(file).with_context(|| format!("failed to read {:?}", file)),
+        None => {
+            let mut data = Vec::new();
+            stdin
+                .read_to_end(&mut data)
+                .context("failed to read stdin")?;
+            Ok(data)
+        }
+    }
+}
+
+// Checks an iptables file as 'iptables set' would read it, without setting
+// it. The syntax errors are configuration errors.
+fn check_iptables_file(args: &IpTablesSetArgs) -> Result<(), KataCtlError> {
+    let source = rules_source(args);
+    let data = read_rules(args.file.as_deref(), io::stdin())?;
+    if data.is_empty() {
+        return Err(KataCtlError::Config(anyhow!("{} is empty", source)));
+    }
+
+    check_iptables_rules(&data)
+        .and_then(|_| select_table(&data, args.table).map(|_| ()))
+        .with_context(|| format!("invalid {}", source))
+        .map_err(KataCtlError::Config)?;
+
+    println!("{} is valid", source);
+
+    Ok(())
+}
+
+fn set_iptables<R: Read>(
+    client: &dyn IpTablesClient,
+    args: &IpTablesSetArgs,
+    stdin: R,
+) -> Result<()> {
+    let source = rules_source(args);
+    let data = read_rules(args.file.as_deref(), stdin)?;
+    if data.is_empty() {
+        return Err(anyhow!("{} is empty", source));
+    }
+    let data = select_table(&data, args.table).with_context(|| format!("invalid {}", source))?;
+
+    client.set_iptables(args.v6, data)
+}
+
+fn iptables_name(is_ipv6: bool) -> &'static str {
+    if is_ipv6 {
+        "ip6tables"
+    } else {
+        "iptables"
+    }
+}
+
+// The IP versions selected by --v6 and --both, false being IPv4.
+fn ip_versions(v6: bool, both: bool) -> Vec<bool> {
+    if both {
+        vec![false, true]
+    } else {
+        vec![v6]
+    }
+}
+
+fn snapshot_file(dir: &Path, sandbox_id: &str, is_ipv6: bool) -> PathBuf {
+    if is_ipv6 {
+        dir.join(format!("{}{}", sandbox_id, IPV6_SNAPSHOT_SUFFIX))
+    } else {
+        dir.join(sandbox_id)
+    }
+}
+
+// Get the IDs of the sandboxes saved in a snapshot directory.
+fn list_snapshot_ids(dir: &Path) -> Result<BTreeSet<String>> {
+    let mut ids = BTreeSet::new();
+
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {:?}", dir))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        if let Some(name) = entry.file_name().to_str() {
+            let id = name.strip_suffix(IPV6_SNAPSHOT_SUFFIX).unwrap_or(name);
+            ids.insert(id.to_string());
+        }
+    }
+
+    Ok(ids)
+}
+
+fn report_failures(failed: usize, total: usize, action: &str) -> Result<()> {
+    if failed > 0 {
+        return Err(anyhow!(
+            "failed to {} {} of {} iptables",
+            action,
+            failed,
+            total
+        ));
+    }
+
+    Ok(())
+}
+
+// Saves the table of the sandboxes, going on after a failure so that as
+// many sandboxes as possible are saved.
+fn snapshot_iptables<'a>(
+    sandbox_ids: &BTreeSet<String>,
+    args: &IpTablesSnapshotArgs,
+    client: &dyn Fn(&str) -> Box<dyn IpTablesClient + 'a>,
+) -> Result<()> {
+    let dir = Path::new(&args.dir);
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+
+    let (mut total, mut failed) = (0, 0);
+    for sandbox_id in sandbox_ids {
+        let client = client(sandbox_id.as_str());
+        for is_ipv6 in ip_versions(args.v6, args.both) {
+            total += 1;
+            let file = snapshot_file(dir, sandbox_id, is_ipv6);
+            let result = client
+                .get_iptables(is_ipv6)
+                .and_then(|data| select_table(&data, args.table))
+                .and_then(|data| {
+                    fs::write(&file, data).with_context(|| format!("failed to write {:?}", file))
+                });
+
+            match result {
+                Ok(()) => println!(
+                    "saved {} of sandbox {} to {}",
+                    iptables_name(is_ipv6),
+                    sandbox_id,
+                    file.display()
+                ),
+                Err(e) => {
+                    eprintln!("WARNING: sandbox {}: {:#}", sandbox_id, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    report_failures(failed, total, "save")
+}
+
+// Sets the saved tables in the live sandboxes, going on after a failure so
+// that as many sandboxes as possible are restored.
+fn restore_iptables<'a>(
+    live_ids: &BTreeSet<String>,
+    args: &IpTablesRestoreArgs,
+    client: &dyn Fn(&str) -> Box<dyn IpTablesClient + 'a>,
+) -> Result<()> {
+    let dir = Path::new(&args.dir);
+
+    let (mut total, mut failed) = (0, 0);
+    for sandbox_id in list_snapshot_ids(dir)? {
+        let files: Vec<(bool, PathBuf)> = ip_versions(args.v6, args.both)
+            .into_iter()
+            .map(|is_ipv6| (is_ipv6, snapshot_file(dir, &sandbox_id, is_ipv6)))
+            .filter(|(_, file)| file.is_file())
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+
+        if !live_ids.contains(&sandbox_id) {
+            eprintln!(
+                "WARNING: sandbox {} is not running, skipping it",
+                sandbox_id
+            );
+            continue;
+        }
+
+        let client = client(sandbox_id.as_str());
+        for (is_ipv6, file) in files {
+            total += 1;
+            let result = fs::read(&file)
+                .with_context(|| format!("failed to read {:?}", file))
+                .and_then(|data| {
+                    select_table(&data, args.table)
+                        .with_context(|| format!("invalid iptables file {:?}", file))
+                })
+                .and_then(|data| client.set_iptables(is_ipv6, data));
+
+            match result {
+                Ok(()) => println!(
+                    "restored {} of sandbox {} from {}",
+                    iptables_name(is_ipv6),
+                    sandbox_id,
+                    file.display()
+                ),
+                Err(e) => {
+                    eprintln!("WARNING: sandbox {}: {:#}", sandbox_id, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    report_failures(failed, total, "restore")
+}
+
+// The rules of iptables-save formatted data, all the tables included.
+fn count_rules(data: &[u8]) -> usize {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter(|line| line.trim_start().starts_with("-A "))
+        .count()
+}
+
+// The default of each table of data: its built-in chains accepting
+// everything, and nothing else.
+fn default_iptables(data: &[u8]) -> Vec<u8> {
+    let mut default = String::new();
+
+    for line in String::from_utf8_lossy(data).lines() {
+        let table = match line.trim().strip_prefix(TABLE_PREFIX) {
+            Some(table) => table,
+            None => continue,
+        };
+
+        default.push_str(&format!("{}{}\n", TABLE_PREFIX, table));
+        for chain in builtin_chains(table).unwrap_or_default() {
+            default.push_str(&format!("{}{} ACCEPT [0:0]\n", CHAIN_PREFIX, chain));
+        }
+        default.push_str(&format!("{}\n", TABLE_COMMIT));
+    }
+
+    default.into_bytes()
+}
+
+// IpTablesReset is the structured output of 'iptables reset'.
+#[derive(Debug, PartialEq, Serialize)]
+struct IpTablesReset {
+    sandbox_id: String,
+    iptables: &'static str,
+    rules_before: usize,
+    rules_after: usize,
+}
+
+fn reset_iptables(
+    client: &dyn IpTablesClient,
+    sandbox_id: &str,
+    args: &IpTablesResetArgs,
+) -> Result<IpTablesReset> {
+    let data = client.get_iptables(args.v6)?;
+    let mut reset = IpTablesReset {
+        sandbox_id: sandbox_id.to_string(),
+        iptables: iptables_name(args.v6),
+        rules_before: count_rules(&data),
+        rules_after: 0,
+    };
+
+    let default = default_iptables(&data);
+    if default.is_empty() {
+        return Err(anyhow!("no table found in the {}", reset.iptables));
+    }
+    client.set_iptables(args.v6, default)?;
+
+    // The rules are read back, in case the guest kept some.
+    reset.rules_after = count_rules(&client.get_iptables(args.v6)?);
+
+    Ok(reset)
+}
+
+fn format_iptables_reset(reset: &IpTablesReset) -> String {
+    format!(
+        "reset {} of sandbox {}: {} rules before, {} after",
+        reset.iptables, reset.sandbox_id, reset.rules_before, reset.rules_after
+    )
+}
+
+// The iptables of a sandbox, for the reports of other commands.
+pub fn get_sandbox_iptables(sandbox_id: &str, is_ipv6: bool) -> Result<String> {
+    let data = shim_client(sandbox_id).get_iptables(is_ipv6)?;
+
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn list_live_sandbox_ids() -> Result<BTreeSet<String>> {
+    let ids = utils::list_sandbox_ids(utils::SANDBOX_STATE_PATHS)?;
+
+    ps_ops::find_live_sandboxes(&ids)
+}
+
+fn shim_client(sandbox_id: &str) -> Box<dyn IpTablesClient> {
+    Box::new(ShimClient {
+        sandbox_id: sandbox_id.to_string(),
+    })
+}
+
+pub fn handle_iptables(
+    iptables_cmd: IptablesCommand,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    // Checking a rules file does not need a sandbox.
+    if !matches!(&iptables_cmd.iptables, IpTablesArguments::Set(args) if args.check) {
+        utils::require_root("managing the iptables of a sandbox")?;
+    }
+
+    match iptables_cmd.iptables {
+        IpTablesArguments::Get(args) => {
+            // A mistyped sandbox ID is reported before getting any table.
+            let mut sandbox_ids = Vec::new();
+            for sandbox_id in &args.sandbox_id {
+                let sandbox_id = utils::resolve_sandbox_id(sandbox_id)?;
+                if !sandbox_ids.contains(&sandbox_id) {
+                    sandbox_ids.push(sandbox_id);
+                }
+            }
+
+            if sandbox_ids.len() > 1 {
+                if args.diff.is_some() {
+                    return Err(KataCtlError::Config(anyhow!(
+                        "--diff compares the iptables of a single sandbox"
+                    )));
+                }
+                return Ok(get_sandboxes_iptables(
+                    &sandbox_ids,
+                    &args,
+                    format,
+                    &shim_client,
+                )?);
+            }
+
+            let sandbox_id = sandbox_ids.remove(0);
+            let client = ShimClient {
+                sandbox_id: sandbox_id.clone(),
+            };
+
+            match &args.diff {
+                Some(baseline) => {
+                    if let Some(diff) = diff_iptables(&client, &sandbox_id, &args, baseline)? {
+                        print!("{}", output::paint_diff(&diff));
+                        return Err(KataCtlError::Status(EXIT_CODE_DIFFERENCES));
+                    }
+                    Ok(())
+                }
+                None => Ok(get_iptables(&client, &args, format)?),
+            }
+        }
+        IpTablesArguments::Set(args) if args.check => check_iptables_file(&args),
+        IpTablesArguments::Set(args) => {
+            let sandbox_id = args
+                .sandbox_id
+                .as_deref()
+                .ok_or_else(|| KataCtlError::Config(anyhow!("the sandbox ID is required")))?;
+            let client = ShimClient {
+                sandbox_id: utils::resolve_sandbox_id(sandbox_id)?,
+            };
+            Ok(set_iptables(&client, &args, io::stdin())?)
+        }
+        IpTablesArguments::Snapshot(args) => Ok(snapshot_iptables(
+            &list_live_sandbox_ids()?,
+            &args,
+            &shim_client,
+        )?),
+        IpTablesArguments::Restore(args) => Ok(restore_iptables(
+            &list_live_sandbox_ids()?,
+            &args,
+            &shim_client,
+        )?),
+        IpTablesArguments::Reset(args) => {
+            let sandbox_id = utils::resolve_sandbox_id(&args.sandbox_id)?;
+            let client = ShimClient {
+                sandbox_id: sandbox_id.clone(),
+            };
+
+            if !args.confirm {
+                utils::confirm(&format!(
+                    "reset the {} of sandbox {}",
+                    iptables_name(args.v6),
+                    sandbox_id
+                ))?;
+            }
+
+            let reset = reset_iptables(&client, &sandbox_id, &args)?;
+            if !output::print_structured(format, &reset)? {
+                println!("{}", format_iptables_reset(&reset));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[derive(Default)]
+    struct MockClient {
+        iptables: RefCell<HashMap<bool, Vec<u8>>>,
+    }
+
+    impl IpTablesClient for MockClient {
+        fn get_iptables(&self, is_ipv6: bool) -> Result<Vec<u8>> {
+            self.iptables
+                .borrow()
+                .get(&is_ipv6)
+                .cloned()
+                .ok_or_else(|| anyhow!("no iptables"))
+        }
+
+        fn set_iptables(&self, is_ipv6: bool, data: Vec<u8>) -> Result<()> {
+            self.iptables.borrow_mut().insert(is_ipv6, data);
+            Ok(())
+        }
+    }
+
+    impl IpTablesClient for &MockClient {
+        fn get_iptables(&self, is_ipv6: bool) -> Result<Vec<u8>> {
+            MockClient::get_iptables(self, is_ipv6)
+        }
+
+        fn set_iptables(&self, is_ipv6: bool, data: Vec<u8>) -> Result<()> {
+            MockClient::set_iptables(self, is_ipv6, data)
+        }
+    }
+
+    #[test]
+    fn test_iptables_get_set_round_trip() {
+        let dir = tempdir().unwrap();
+
+        // No trailing newline, to check that the content is kept as is.
+        let rules = b"*filter\r\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT".to_vec();
+
+        for is_ipv6 in [false, true] {
+            let client = MockClient::default();
+            client.set_iptables(is_ipv6, rules.clone()).unwrap();
+
+            let file = dir.path().join(format!("rules-{}", is_ipv6));
+            let file = file.to_str().unwrap().to_string();
+
+            // an existing file is truncated
+            fs::write(&file, "x".repeat(1024)).unwrap();
+
+            let get_args = IpTablesGetArgs {
+                sandbox_id: vec!["sandbox".to_string()],
+                v6: is_ipv6,
+                both: false,
+                table: IpTablesTable::Filter,
+                file: Some(file.clone()),
+                diff: None,
+            };
+            get_iptables(&client, &get_args, OutputFormat::Human).unwrap();
+            assert_eq!(fs::read(&file).unwrap(), rules);
+
+            let client = MockClient::default();
+            let set_args = IpTablesSetArgs {
+                sandbox_id: Some("sandbox".to_string()),
+                v6: is_ipv6,
+                table: IpTablesTable::Filter,
+                file: Some(file),
+                stdin: false,
+                check: false,
+            };
+            set_iptables(&client, &set_args, io::empty()).unwrap();
+            assert_eq!(client.get_iptables(is_ipv6).unwrap(), rules);
+            assert!(client.get_iptables(!is_ipv6).is_err());
+        }
+    }
+
+    #[test]
+    fn test_get_both_iptables() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("rules");
+
+        let client = MockClient::default();
+        client
+            .set_iptables(false, b"*filter\n-A INPUT -j DROP\nCOMMIT".to_vec())
+            .unwrap();
+        client
+            .set_iptables(true, b"*filter\n-A INPUT -j ACCEPT\nCOMMIT\n".to_vec())
+            .unwrap();
+
+        let args = IpTablesGetArgs {
+            sandbox_id: vec!["sandbox".to_string()],
+            v6: false,
+            both: true,
+            table: IpTablesTable::Filter,
+            file: Some(file.to_str().unwrap().to_string()),
+            diff: None,
+        };
+
+        get_iptables(&client, &args, OutputFormat::Human).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "# iptables\n*filter\n-A INPUT -j DROP\nCOMMIT\n# ip6tables\n*filter\n-A INPUT -j ACCEPT\nCOMMIT\n"
+        );
+
+        get_iptables(&client, &args, OutputFormat::Json).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "ipv4": "*filter\n-A INPUT -j DROP\nCOMMIT",
+                "ipv6": "*filter\n-A INPUT -j ACCEPT\nCOMMIT\n",
+            })
+        );
+    }
+
+    fn boxed(client: &MockClient) -> Box<dyn IpTablesClient + '_> {
+        Box::new(client)
+    }
+
+    #[test]
+    fn test_get_sandboxes_iptables() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("rules");
+        let table = |rule: &str| format!("*filter\n-A INPUT -j {}\nCOMMIT", rule).into_bytes();
+
+        // sb2 has no iptables, the others are still reported
+        let sandboxes: HashMap<&str, MockClient> = vec!["sb1", "sb2", "sb3"]
+            .into_iter()
+            .map(|id| (id, MockClient::default()))
+            .collect();
+        sandboxes["sb1"].set_iptables(false, table("DROP")).unwrap();
+        sandboxes["sb3"]
+            .set_iptables(false, table("ACCEPT"))
+            .unwrap();
+
+        let sandbox_ids: Vec<String> = vec!["sb3", "sb1", "sb2"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let args = IpTablesGetArgs {
+            sandbox_id: sandbox_ids.clone(),
+            v6: false,
+            both: false,
+            table: IpTablesTable::Filter,
+            file: Some(file.to_str().unwrap().to_string()),
+            diff: None,
+        };
+        let client = |id: &str| boxed(&sandboxes[id]);
+
+        let e =
+            get_sandboxes_iptables(&sandbox_ids, &args, OutputFormat::Human, &client).unwrap_err();
+        assert_eq!(e.to_string(), "failed to get 1 of 3 iptables");
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "# sandbox sb3\n*filter\n-A INPUT -j ACCEPT\nCOMMIT\n# sandbox sb1\n*filter\n-A INPUT -j DROP\nCOMMIT\n"
+        );
+
+        assert!(get_sandboxes_iptables(&sandbox_ids, &args, OutputFormat::Json, &client).is_err());
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "sb1": "*filter\n-A INPUT -j DROP\nCOMMIT",
+                "sb3": "*filter\n-A INPUT -j ACCEPT\nCOMMIT",
+            })
+        );
+
+        let sandbox_ids = &sandbox_ids[..2];
+        get_sandboxes_iptables(sandbox_ids, &args, OutputFormat::Human, &client).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_restore_iptables() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path().join("snapshot");
+        let table = |rule: &str| format!("*filter\n-A INPUT -j {}\nCOMMIT\n", rule).into_bytes();
+
+        let sandboxes: HashMap<&str, MockClient> = vec!["sb1", "sb2", "sb3"]
+            .into_iter()
+            .map(|id| (id, MockClient::default()))
+            .collect();
+        sandboxes["sb1"].set_iptables(false, table("DROP")).unwrap();
+        sandboxes["sb1"]
+            .set_iptables(true, table("ACCEPT"))
+            .unwrap();
+        sandboxes["sb2"]
+            .set_iptables(false, table("RETURN"))
+            .unwrap();
+        let client = |id: &str| boxed(&sandboxes[id]);
+
+        let ids: BTreeSet<String> = ["sb1", "sb2"].iter().map(|s| s.to_string()).collect();
+        let args = IpTablesSnapshotArgs {
+            dir: dir_path.to_str().unwrap().to_string(),
+            v6: false,
+            both: true,
+            table: IpTablesTable::Filter,
+        };
+        // sb2 has no ip6tables
+        assert!(snapshot_iptables(&ids, &args, &client).is_err());
+        assert_eq!(fs::read(dir_path.join("sb1")).unwrap(), table("DROP"));
+        assert_eq!(fs::read(dir_path.join("sb1.v6")).unwrap(), table("ACCEPT"));
+        assert_eq!(fs::read(dir_path.join("sb2")).unwrap(), table("RETURN"));
+        assert!(!dir_path.join("sb2.v6").exists());
+
+        // a sandbox that is gone
+        fs::write(dir_path.join("sb4"), table("DROP")).unwrap();
+
+        for (id, is_ipv6) in [("sb1", false), ("sb1", true), ("sb2", false)] {
+            sandboxes[id].iptables.borrow_mut().remove(&is_ipv6);
+        }
+
+        let live_ids: BTreeSet<String> = ["sb1", "sb2", "sb3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = IpTablesRestoreArgs {
+            dir: dir_path.to_str().unwrap().to_string(),
+            v6: true,
+            both: false,
+            table: IpTablesTable::Filter,
+        };
+        restore_iptables(&live_ids, &args, &client).unwrap();
+        assert_eq!(
+            sandboxes["sb1"].get_iptables(true).unwrap(),
+            table("ACCEPT")
+        );
+        assert!(sandboxes["sb1"].get_iptables(false).is_err());
+        assert!(sandboxes["sb2"].get_iptables(false).is_err());
+
+        let args = IpTablesRestoreArgs { v6: false, ..args };
+        restore_iptables(&live_ids, &args, &client).unwrap();
+        assert_eq!(sandboxes["sb1"].get_iptables(false).unwrap(), table("DROP"));
+        assert_eq!(
+            sandboxes["sb2"].get_iptables(false).unwrap(),
+            table("RETURN")
+        );
+        assert!(sandboxes["sb3"].get_iptables(false).is_err());
+
+        // an invalid file does not stop the other sandboxes from being restored
+        fs::write(dir_path.join("sb1"), "-A INPUT -j DROP\n").unwrap();
+        sandboxes["sb2"].iptables.borrow_mut().clear();
+        assert!(restore_iptables(&live_ids, &args, &client).is_err());
+        assert_eq!(
+            sandboxes["sb2"].get_iptables(false).unwrap(),
+            table("RETURN")
+        );
+
+        let args = IpTablesRestoreArgs {
+            dir: dir.path().join("missing").to_str().unwrap().to_string(),
+            ..args
+        };
+        assert!(restore_iptables(&live_ids, &args, &client).is_err());
+    }
+
+    #[test]
+    fn test_reset_iptables() {
+        let rules = b"*nat\n:PREROUTING ACCEPT [5:300]\n:POSTROUTING ACCEPT [0:0]\n-A POSTROUTING -j MASQUERADE\nCOMMIT\n*filter\n:INPUT DROP [0:0]\n:FORWARD ACCEPT [0:0]\n:OUTPUT ACCEPT [0:0]\n:custom - [0:0]\n-A INPUT -j custom\n-A custom -j ACCEPT\nCOMMIT\n";
+        let client = MockClient::default();
+        client.set_iptables(false, rules.to_vec()).unwrap();
+
+        let mut args = IpTablesResetArgs {
+            sandbox_id: "sb1".to_string(),
+            v6: false,
+            confirm: true,
+        };
+
+        let reset = reset_iptables(&client, "sb1", &args).unwrap();
+        assert_eq!(
+            reset,
+            IpTablesReset {
+                sandbox_id: "sb1".to_string(),
+                iptables: "iptables",
+                rules_before: 3,
+                rules_after: 0,
+            }
+        );
+        assert_eq!(
+            String::from_utf8(client.get_iptables(false).unwrap()).unwrap(),
+            "*nat\n:PREROUTING ACCEPT [0:0]\n:INPUT ACCEPT [0:0]\n:OUTPUT ACCEPT [0:0]\n:POSTROUTING ACCEPT [0:0]\nCOMMIT\n*filter\n:INPUT ACCEPT [0:0]\n:FORWARD ACCEPT [0:0]\n:OUTPUT ACCEPT [0:0]\nCOMMIT\n"
+        );
+        assert_eq!(
+            format_iptables_reset(&reset),
+            "reset iptables of sandbox sb1: 3 rules before, 0 after"
+        );
+
+        // the ip6tables are not touched
+        assert!(client.get_iptables(true).is_err());
+        args.v6 = true;
+        assert!(reset_iptables(&client, "sb1", &args).is_err());
+    }
+
+    #[test]
+    fn test_select_table() {
+        let data = b"# Generated by iptables-save\n*nat\n:PREROUTING ACCEPT [0:0]\nCOMMIT\n*filter\n-A INPUT -j DROP\nCOMMIT\n# Completed\n";
+
+        let tests = &[
+            (
+                IpTablesTable::Nat,
+                Some("*nat\n:PREROUTING ACCEPT [0:0]\nCOMMIT\n"),
+            ),
+            (
+                IpTablesTable::Filter,
+                Some("*filter\n-A INPUT -j DROP\nCOMMIT\n"),
+            ),
+            (IpTablesTable::Mangle, None),
+        ];
+
+        for (i, (table, result)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, table);
+            let selected = select_table(data, *table);
+            match result {
+                Some(expected) => {
+                    assert_eq!(selected.unwrap(), expected.as_bytes(), "{}", msg)
+                }
+                None => assert!(selected.is_err(), "{}", msg),
+            }
+        }
+
+        assert!(select_table(b"*nat\n:PREROUTING ACCEPT [0:0]\n", IpTablesTable::Nat).is_err());
+    }
+
+    #[test]
+    fn test_set_iptables_empty_file() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("empty");
+        fs::write(&file, "").unwrap();
+
+        let args = IpTablesSetArgs {
+            sandbox_id: Some("sandbox".to_string()),
+            v6: false,
+            table: IpTablesTable::Filter,
+            file: Some(file.to_str().unwrap().to_string()),
+            stdin: false,
+            check: false,
+        };
+        assert!(set_iptables(&MockClient::default(), &args, io::empty()).is_err());
+    }
+
+    #[test]
+    fn test_set_iptables_stdin() {
+        let client = MockClient::default();
+        let args = IpTablesSetArgs {
+            sandbox_id: Some("sandbox".to_string()),
+            v6: false,
+            table: IpTablesTable::Filter,
+            file: None,
+            stdin: true,
+            check: false,
+        };
+
+        let rules = "*nat\n:PREROUTING ACCEPT [0:0]\nCOMMIT\n*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n";
+        set_iptables(&client, &args, rules.as_bytes()).unwrap();
+        assert_eq!(
+            client.get_iptables(false).unwrap(),
+            b"*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n"
+        );
+
+        let e = set_iptables(&client, &args, io::empty()).unwrap_err();
+        assert_eq!(e.to_string(), "iptables from stdin is empty");
+    }
+
+    #[test]
+    fn test_check_iptables_rules() {
+        let valid = "# Generated by iptables-save\n*nat\n:PREROUTING ACCEPT [0:0]\n:POSTROUTING ACCEPT [3:180]\nCOMMIT\n*filter\n:INPUT DROP [0:0]\n:KATA - [0:0]\n-A INPUT -j KATA\n[5:300] -A KATA -m comment --comment \"allow \\\"ssh\\\"\" -p tcp --dport 22 -j ACCEPT\n-N OTHER\n-I OTHER 1 -j RETURN\n-A FORWARD -j DROP\n-F\nCOMMIT\n";
+        assert!(check_iptables_rules(valid.as_bytes()).is_ok());
+
+        let tests = &[
+            ("-A INPUT -j DROP\n", "line 1"),
+            ("*filter\n*nat\nCOMMIT\n", "line 2"),
+            ("*bogus\nCOMMIT\n", "line 1"),
+            ("*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\n", "line 1"),
+            ("*filter\n:INPUT ALLOW [0:0]\nCOMMIT\n", "line 2"),
+            ("*filter\n:INPUT ACCEPT [x:0]\nCOMMIT\n", "line 2"),
+            ("*filter\n\n-A KATA -j DROP\nCOMMIT\n", "line 3"),
+            (
+                "*filter\n-A INPUT -m comment --comment \"x -j DROP\nCOMMIT\n",
+                "line 2",
+            ),
+            ("*filter\n-Q INPUT\nCOMMIT\n", "line 2"),
+            ("*filter\n-A -j DROP\nCOMMIT\n", "line 2"),
+            ("*filter\nCOMMIT\nCOMMIT\n", "line 3"),
+        ];
+
+        for (i, (rules, line)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, rules);
+            let e = check_iptables_rules(rules.as_bytes()).expect_err(&msg);
+            assert!(format!("{:#}", e).starts_with(line), "{}: {:#}", msg, e);
+        }
+    }
+
+    #[test]
+    fn test_diff_iptables() {
+        let dir = tempdir().unwrap();
+        let baseline = dir.path().join("baseline");
+        let baseline = baseline.to_str().unwrap().to_string();
+        fs::write(
+            &baseline,
+            "*nat\n:PREROUTING ACCEPT [0:0]\nCOMMIT\n*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n",
+        )
+        .unwrap();
+
+        let client = MockClient::default();
+        let args = |v6, table| IpTablesGetArgs {
+            sandbox_id: vec!["sandbox".to_string()],
+            v6,
+            both: false,
+            table,
+            file: None,
+            diff: Some(baseline.clone()),
+        };
+
+        // only the counters differ
+        client
+            .set_iptables(
+                false,
+                b"*filter\n:INPUT ACCEPT [12:3456]\n-A INPUT -j DROP\nCOMMIT\n".to_vec(),
+            )
+            .unwrap();
+        let result = diff_iptables(
+            &client,
+            "sandbox",
+            &args(false, IpTablesTable::Filter),
+            &baseline,
+        );
+        assert_eq!(result.unwrap(), None);
+
+        client
+            .set_iptables(
+                false,
+                b"*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j ACCEPT\nCOMMIT\n".to_vec(),
+            )
+            .unwrap();
+        let diff = diff_iptables(
+            &client,
+            "sandbox",
+            &args(false, IpTablesTable::Filter),
+            &baseline,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            diff,
+            format!(
+                "--- {}\n+++ sandbox (filter)\n@@ -1,4 +1,4 @@\n *filter\n :INPUT ACCEPT [0:0]\n--A INPUT -j DROP\n+-A INPUT -j ACCEPT\n COMMIT\n",
+                baseline
+            )
+        );
+
+        // the live rules have no nat table
+        assert!(diff_iptables(
+            &client,
+            "sandbox",
+            &args(false, IpTablesTable::Nat),
+            &baseline
+        )
+        .is_err());
+
+        // the ip6tables are compared separately
+        client
+            .set_iptables(true, b"*nat\n:PREROUTING ACCEPT [3:4]\nCOMMIT\n".to_vec())
+            .unwrap();
+        let result = diff_iptables(
+            &client,
+            "sandbox",
+            &args(true, IpTablesTable::Nat),
+            &baseline,
+        );
+        assert_eq!(result.unwrap(), None);
+    }
+}
@@ -0,0 +1,677 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the logs command. The shim logs to the journal, along
+// with the agent and hypervisor logs it forwards, so the entries of a sandbox
+// are selected from the journal and tagged with their source.
+//
+// The --since and --until window applies to the time field of the log lines
+// rather than to the journal timestamps, the lines having no time field
+// being excluded when a window is set.
+
+use crate::args::LogsArgument;
+use crate::error::KataCtlError;
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const JOURNALCTL: &str = "journalctl";
+const KATA_SYSLOG_IDENTIFIER: &str = "kata";
+const JOURNAL_CURSOR_PREFIX: &str = "-- cursor: ";
+
+// Fields holding the time of a log line, for the go and rust runtimes.
+const TIME_FIELDS: &[&str] = &["time", "ts"];
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+const SECS_PER_DAY: i64 = 86400;
+
+// Log subsystems of the hypervisors.
+const HYPERVISOR_SUBSYSTEMS: &[&str] = &[
+    "hypervisor",
+    "qemu",
+    "cloudHypervisor",
+    "firecracker",
+    "dragonball",
+    "acrn",
+    "stratovirt",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LogSource {
+    Shim,
+    Agent,
+    Hypervisor,
+}
+
+impl LogSource {
+    fn tag(&self) -> &'static str {
+        match self {
+            LogSource::Shim => "[shim]",
+            LogSource::Agent => "[agent]",
+            LogSource::Hypervisor => "[hypervisor]",
+        }
+    }
+}
+
+// Get the value of a field of a log line, which is either in logfmt
+// (key=value) or JSON ("key":"value") format.
+fn get_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let logfmt_key = format!("{}=", key);
+    let json_key = format!("\"{}\":\"", key);
+
+    if let Some(start) = line.find(&json_key) {
+        let value = &line[start + json_key.len()..];
+        return value.split('"').next();
+    }
+
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix(logfmt_key.as_str()))
+        .map(|value| value.trim_matches('"'))
+}
+
+fn log_source(line: &str) -> LogSource {
+    if get_field(line, "source") == Some("agent") {
+        return LogSource::Agent;
+    }
+
+    match get_field(line, "subsystem") {
+        Some(subsystem) if HYPERVISOR_SUBSYSTEMS.contains(&subsystem) => LogSource::Hypervisor,
+        _ => LogSource::Shim,
+    }
+}
+
+fn format_line(line: &str) -> String {
+    format!("{} {}", log_source(line).tag(), line)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Parse an RFC3339 timestamp, e.g. 2023-01-01T10:00:00.5+01:00, to
+// nanoseconds since the epoch.
+fn parse_rfc3339(s: &str) -> Result<i64> {
+    let invalid = || anyhow!("invalid RFC3339 timestamp {:?}", s);
+    let bytes = s.as_bytes();
+
+    let number = |start: usize, len: usize| -> Result<i64> {
+        match bytes.get(start..start + len) {
+            Some(digits) if digits.iter().all(u8::is_ascii_digit) => {
+                Ok(digits.iter().fold(0, |n, d| n * 10 + i64::from(d - b'0')))
+            }
+            _ => Err(invalid()),
+        }
+    };
+    let separator = |i: usize, expected: &[u8]| -> Result<()> {
+        match bytes.get(i) {
+            Some(c) if expected.contains(c) => Ok(()),
+            _ => Err(invalid()),
+        }
+    };
+
+    let (year, month, day) = (number(0, 4)?, number(5, 2)?, number(8, 2)?);
+    let (hour, minute, second) = (number(11, 2)?, number(14, 2)?, number(17, 2)?);
+    separator(4, b"-")?;
+    separator(7, b"-")?;
+    separator(10, b"Tt ")?;
+    separator(13, b":")?;
+    separator(16, b":")?;
+
+    // A leap second is accepted, the time being that of the next second.
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    let mut i = 19;
+    let mut nanos = 0;
+    if bytes.get(i) == Some(&b'.') {
+        let start = i + 1;
+        i = start;
+        while bytes.get(i).map_or(false, u8::is_ascii_digit) {
+            i += 1;
+        }
+        if i == start {
+            return Err(invalid());
+        }
+
+        // The digits beyond the nanoseconds are dropped.
+        let digits = (i - start).min(9);
+        nanos = number(start, digits)? * 10_i64.pow(9 - digits as u32);
+    }
+
+    let offset = match bytes.get(i) {
+        Some(b'Z') | Some(b'z') if i + 1 == bytes.len() => 0,
+        Some(sign @ (b'+' | b'-')) if i + 6 == bytes.len() => {
+            separator(i + 3, b":")?;
+            let offset = (number(i + 1, 2)? * 60 + number(i + 4, 2)?) * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let secs = utils::days_from_civil(year, month, day) * SECS_PER_DAY
+        + hour * 3600
+        + minute * 60
+        + second
+        - offset;
+
+    Ok(secs * NANOS_PER_SEC + nanos)
+}
+
+// Parse a duration before now, e.g. -10m, to nanoseconds, the minus sign
+// being optional. None is returned when s is not a duration.
+fn parse_relative_time(s: &str) -> Option<Result<i64>> {
+    let duration = s.strip_prefix('-').unwrap_or(s);
+    let unit = match duration.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => SECS_PER_DAY,
+        _ => return None,
+    };
+    let count = duration[..duration.len() - 1].parse::<u64>().ok()?;
+
+    Some(
+        i64::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(unit))
+            .and_then(|secs| secs.checked_mul(NANOS_PER_SEC))
+            .ok_or_else(|| anyhow!("duration {:?} is out of range", s)),
+    )
+}
+
+fn parse_time(s: &str, now: i64) -> Result<i64> {
+    match parse_relative_time(s) {
+        Some(duration) => Ok(now - duration?),
+        None => parse_rfc3339(s).map_err(|_| {
+            anyhow!(
+                "invalid time {:?}: expected an RFC3339 timestamp or a duration such as -10m",
+                s
+            )
+        }),
+    }
+}
+
+fn line_time(line: &str) -> Option<i64> {
+    TIME_FIELDS
+        .iter()
+        .find_map(|field| get_field(line, field))
+        .and_then(|time| parse_rfc3339(time).ok())
+}
+
+// TimeWindow selects the log lines by their time, in nanoseconds since the
+// epoch.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct TimeWindow {
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl TimeWindow {
+    fn new(since: Option<&str>, until: Option<&str>, now: i64) -> Result<Self> {
+        let window = TimeWindow {
+            since: since.map(|s| parse_time(s, now)).transpose()?,
+            until: until.map(|s| parse_time(s, now)).transpose()?,
+        };
+
+        if let (Some(since), Some(until)) = (window.since, window.until) {
+            if since > until {
+                return Err(anyhow!("--since must not be later than --until"));
+            }
+        }
+
+        Ok(window)
+    }
+
+    fn is_set(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    // Whether the line is in the window, None if it has no time and a
+    // window is set.
+    fn contains(&self, line: &str) -> Option<bool> {
+        if !self.is_set() {
+            return Some(true);
+        }
+
+        let time = line_time(line)?;
+
+        Some(
+            self.since.map_or(true, |since| time >= since)
+                && self.until.map_or(true, |until| time <= until),
+        )
+    }
+}
+
+// The lines of a sandbox selected from the journal history, along with the
+// journal cursor, from which following starts, and the number of lines
+// excluded for lacking a time.
+#[derive(Debug, Default)]
+struct History {
+    lines: Vec<String>,
+    cursor: Option<String>,
+    untimed: usize,
+}
+
+fn warn_untimed(count: usize) {
+    eprintln!(
+        "WARNING: {} log line(s) without a timestamp excluded by the time window",
+        count
+    );
+}
+
+fn sandbox_exists(sandbox_id: &str) -> bool {
+    utils::SANDBOX_STATE_PATHS
+        .iter()
+        .any(|path| Path::new(path).join(sandbox_id).exists())
+}
+
+fn journalctl_args(follow: bool, cursor: Option<&str>) -> Vec<String> {
+    let mut args = vec![
+        format!("--identifier={}", KATA_SYSLOG_IDENTIFIER),
+        "--output=cat".to_string(),
+        "--no-pager".to_string(),
+    ];
+
+    if follow {
+        args.push("--follow".to_string());
+        match cursor {
+            Some(cursor) => args.push(format!("--after-cursor={}", cursor)),
+            None => args.push("--lines=0".to_string()),
+        }
+    } else {
+        args.push("--show-cursor".to_string());
+    }
+
+    args
+}
+
+// Select the last lines of the sandbox in the time window from the journal
+// history.
+fn select_history<R: BufRead>(
+    reader: R,
+    sandbox_id: &str,
+    lines: Option<usize>,
+    window: &TimeWindow,
+) -> Result<History> {
+    let mut selected = VecDeque::new();
+    let mut cursor = None;
+    let mut untimed = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if let Some(c) = line.strip_prefix(JOURNAL_CURSOR_PREFIX) {
+            cursor = Some(c.to_string());
+            continue;
+        }
+
+        if !line.contains(sandbox_id) {
+            continue;
+        }
+
+        match window.contains(&line) {
+            Some(true) => {}
+            Some(false) => continue,
+            None => {
+                untimed += 1;
+                continue;
+            }
+        }
+
+        selected.push_back(line);
+        if let Some(max) = lines {
+            if selected.len() > max {
+                selected.pop_front();
+            }
+        }
+    }
+
+    Ok(History {
+        lines: selected.into_iter().collect(),
+        cursor,
+        untimed,
+    })
+}
+
+fn read_journal() -> Result<Vec<u8>> {
+    let output = Command::new(JOURNALCTL)
+        .args(journalctl_args(false, None))
+        .output()
+        .with_context(|| format!("failed to run {}", JOURNALCTL))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            JOURNALCTL,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+fn read_history(sandbox_id: &str, lines: Option<usize>, window: &TimeWindow) -> Result<History> {
+    let journal = read_journal()?;
+
+    select_history(journal.as_slice(), sandbox_id, lines, window)
+}
+
+// The last lines of the logs of each sandbox, tagged with their source, as
+// included in the reports of other commands. The journal is read once for
+// all the sandboxes.
+pub fn get_sandboxes_logs(
+    sandbox_ids: &[String],
+    lines: usize,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let journal = read_journal()?;
+
+    sandbox_ids
+        .iter()
+        .map(|sandbox_id| {
+            let history = select_history(
+                journal.as_slice(),
+                sandbox_id,
+                Some(lines),
+                &TimeWindow::default(),
+            )?;
+            let lines = history.lines.iter().map(|line| format_line(line)).collect();
+
+            Ok((sandbox_id.clone(), lines))
+        })
+        .collect()
+}
+
+fn follow(
+    sandbox_id: &str,
+    cursor: Option<&str>,
+    window: &TimeWindow,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut child = Command::new(JOURNALCTL)
+        .args(journalctl_args(true, cursor))
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {}", JOURNALCTL))?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to read {} output", JOURNALCTL))?;
+
+    let mut warned = false;
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        if !line.contains(sandbox_id) {
+            continue;
+        }
+
+        match window.contains(&line) {
+            Some(true) => {
+                writeln!(out, "{}", format_line(&line))?;
+                out.flush()?;
+            }
+            Some(false) => {}
+            None if !warned => {
+                warn_untimed(1);
+                warned = true;
+            }
+            None => {}
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} failed: {}", JOURNALCTL, status));
+    }
+
+    Ok(())
+}
+
+pub fn handle_logs(args: LogsArgument) -> Result<(), KataCtlError> {
+    let sandbox_id = args.sandbox_id.as_str();
+    if sandbox_id.is_empty() {
+        return Err(KataCtlError::Config(anyhow!(
+            "sandbox ID must not be empty"
+        )));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i64);
+    let window = TimeWindow::new(args.since.as_deref(), args.until.as_deref(), now)
+        .map_err(KataCtlError::Config)?;
+
+    let history = read_history(sandbox_id, args.lines, &window)?;
+
+    // Logs remain after the sandbox is gone, so the sandbox is only unknown
+    // when it neither exists nor has logged anything.
+    if history.lines.is_empty() && history.untimed == 0 && !sandbox_exists(sandbox_id) {
+        return Err(KataCtlError::NotFound(anyhow!(
+            "sandbox {} not found",
+            sandbox_id
+        )));
+    }
+
+    if history.untimed > 0 {
+        warn_untimed(history.untimed);
+    }
+
+    let mut out = io::stdout();
+    for line in &history.lines {
+        writeln!(out, "{}", format_line(line))?;
+    }
+
+    if args.follow {
+        follow(sandbox_id, history.cursor.as_deref(), &window, &mut out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_source() {
+        let tests = &[
+            (
+                r#"time="2023-01-01T00:00:00Z" level=info msg="hello" source=agent sandbox=sb1"#,
+                LogSource::Agent,
+            ),
+            (
+                r#"level=info msg="launching" source=virtcontainers subsystem=qemu sandbox=sb1"#,
+                LogSource::Hypervisor,
+            ),
+            (
+                r#"level=info msg="started" source=virtcontainers subsystem=sandbox sandbox=sb1"#,
+                LogSource::Shim,
+            ),
+            (
+                r#"{"msg":"boot","source":"agent","sid":"sb1"}"#,
+                LogSource::Agent,
+            ),
+            (
+                r#"{"msg":"vm started","subsystem":"hypervisor","sid":"sb1"}"#,
+                LogSource::Hypervisor,
+            ),
+            (r#"{"msg":"hello","sid":"sb1"}"#, LogSource::Shim),
+        ];
+
+        for (i, (line, source)) in tests.iter().enumerate() {
+            assert_eq!(log_source(line), *source, "test[{}]: {:?}", i, line);
+        }
+    }
+
+    #[test]
+    fn test_select_history() {
+        let journal =
+            "a sandbox=sb1\nb sandbox=sb2\nc sandbox=sb1\nd sandbox=sb1\n-- cursor: s=abc\n";
+
+        let window = TimeWindow::default();
+
+        let history = select_history(journal.as_bytes(), "sb1", None, &window).unwrap();
+        assert_eq!(
+            history.lines,
+            vec!["a sandbox=sb1", "c sandbox=sb1", "d sandbox=sb1"]
+        );
+        assert_eq!(history.cursor.as_deref(), Some("s=abc"));
+
+        let history = select_history(journal.as_bytes(), "sb1", Some(2), &window).unwrap();
+        assert_eq!(history.lines, vec!["c sandbox=sb1", "d sandbox=sb1"]);
+
+        let history = select_history("".as_bytes(), "sb1", Some(2), &window).unwrap();
+        assert!(history.lines.is_empty());
+        assert!(history.cursor.is_none());
+    }
+
+    #[test]
+    fn test_select_history_window() {
+        let journal = concat!(
+            "time=\"2023-01-01T09:59:59Z\" sandbox=sb1 msg=a\n",
+            "time=\"2023-01-01T10:00:00Z\" sandbox=sb1 msg=b\n",
+            "{\"ts\":\"2023-01-01T10:30:00.5Z\",\"sid\":\"sb1\",\"msg\":\"c\"}\n",
+            "sandbox=sb1 msg=untimed\n",
+            "time=\"2023-01-01T11:00:01Z\" sandbox=sb1 msg=d\n",
+        );
+        let window = TimeWindow {
+            since: Some(parse_rfc3339("2023-01-01T10:00:00Z").unwrap()),
+            until: Some(parse_rfc3339("2023-01-01T12:00:00+01:00").unwrap()),
+        };
+
+        let history = select_history(journal.as_bytes(), "sb1", None, &window).unwrap();
+        assert_eq!(history.lines.len(), 2);
+        assert!(history.lines[0].ends_with("msg=b"));
+        assert!(history.lines[1].contains("\"msg\":\"c\""));
+        assert_eq!(history.untimed, 1);
+
+        // without a window, the lines without a time are kept
+        let history =
+            select_history(journal.as_bytes(), "sb1", None, &TimeWindow::default()).unwrap();
+        assert_eq!(history.lines.len(), 5);
+        assert_eq!(history.untimed, 0);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let tests = &[
+            ("1970-01-01T00:00:00Z", Some(0)),
+            ("2023-01-01T10:00:00Z", Some(1672567200 * NANOS_PER_SEC)),
+            ("2023-01-01t10:00:00z", Some(1672567200 * NANOS_PER_SEC)),
+            (
+                "2023-01-01 11:30:00+01:30",
+                Some(1672567200 * NANOS_PER_SEC),
+            ),
+            (
+                "2023-01-01T09:00:00-01:00",
+                Some(1672567200 * NANOS_PER_SEC),
+            ),
+            (
+                "2023-01-01T10:00:00.25Z",
+                Some(1672567200 * NANOS_PER_SEC + 250_000_000),
+            ),
+            (
+                "2023-01-01T10:00:00.1234567891Z",
+                Some(1672567200 * NANOS_PER_SEC + 123_456_789),
+            ),
+            ("2024-02-29T00:00:00Z", Some(1709164800 * NANOS_PER_SEC)),
+            ("1969-12-31T23:59:59Z", Some(-NANOS_PER_SEC)),
+            ("2023-02-29T00:00:00Z", None),
+            ("2023-13-01T00:00:00Z", None),
+            ("2023-01-01T24:00:00Z", None),
+            ("2023-01-01T10:00:00", None),
+            ("2023-01-01T10:00:00.Z", None),
+            ("2023-01-01T10:00:00+0100", None),
+            ("2023-01-01", None),
+            ("", None),
+        ];
+
+        for (i, (s, expected)) in tests.iter().enumerate() {
+            let result = parse_rfc3339(s);
+            assert_eq!(result.ok(), *expected, "test[{}]: {:?}", i, s);
+        }
+    }
+
+    #[test]
+    fn test_time_window() {
+        let now = 1672567200 * NANOS_PER_SEC;
+
+        let window = TimeWindow::new(Some("-10m"), Some("5s"), now).unwrap();
+        assert_eq!(window.since, Some(now - 600 * NANOS_PER_SEC));
+        assert_eq!(window.until, Some(now - 5 * NANOS_PER_SEC));
+
+        let window = TimeWindow::new(Some("2023-01-01T09:00:00Z"), None, now).unwrap();
+        assert_eq!(window.since, Some(now - 3600 * NANOS_PER_SEC));
+        assert!(window
+            .contains(r#"time="2023-01-01T09:30:00Z" msg=a"#)
+            .unwrap());
+        assert!(!window
+            .contains(r#"time="2023-01-01T08:30:00Z" msg=a"#)
+            .unwrap());
+        assert_eq!(window.contains("msg=a"), None);
+
+        assert_eq!(TimeWindow::default().contains("msg=a"), Some(true));
+
+        assert!(TimeWindow::new(Some("-1m"), Some("-2m"), now).is_err());
+        assert!(TimeWindow::new(Some("yesterday"), None, now).is_err());
+        assert!(TimeWindow::new(Some("-10x"), None, now).is_err());
+        assert!(TimeWindow::new(Some("-m"), None, now).is_err());
+
+        // 106751d is the longest duration of nanoseconds
+        let window = TimeWindow::new(Some("-106751d"), None, now).unwrap();
+        assert_eq!(
+            window.since,
+            Some(now - 106751 * SECS_PER_DAY * NANOS_PER_SEC)
+        );
+        for duration in ["-106752d", "-99999999999h", "-18446744073709551615s"] {
+            let e = TimeWindow::new(Some(duration), None, now).unwrap_err();
+            assert!(
+                format!("{}", e).contains("out of range"),
+                "{}: {}",
+                duration,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_journalctl_args() {
+        let args = journalctl_args(false, None);
+        assert!(args.contains(&"--show-cursor".to_string()));
+        assert!(!args.contains(&"--follow".to_string()));
+
+        let args = journalctl_args(true, Some("s=abc"));
+        assert!(args.contains(&"--follow".to_string()));
+        assert!(args.contains(&"--after-cursor=s=abc".to_string()));
+
+        let args = journalctl_args(true, None);
+        assert!(args.contains(&"--lines=0".to_string()));
+    }
+}
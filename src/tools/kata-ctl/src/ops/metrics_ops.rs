@@ -0,0 +1,1158 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the metrics commands. The CPU and memory usage of the
+// shim and hypervisor processes of a sandbox are sampled from procfs.
+//
+// 'metrics diff' compares two samples: the CPU time is a counter, which is
+// reset when the processes are restarted, while the memory is a gauge whose
+// delta may be negative.
+
+use crate::args::{
+    MetricsCollectArgs, MetricsCommand, MetricsDiffArgs, MetricsFormat, MetricsSubCommand,
+    OutputFormat,
+};
+use crate::error::KataCtlError;
+use crate::ops::ps_ops;
+use crate::output::{self, Table};
+
+use anyhow::{anyhow, Context, Result};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::{sysconf, SysconfVar};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const PROC_PATH: &str = "/proc";
+
+pub const SHIM_COMM_PREFIX: &str = "containerd-shim";
+
+// Prefixes of the command names of the supported hypervisors, with their
+// names. The command name is truncated by the kernel, hence "cloud-hyperviso".
+const HYPERVISOR_COMM_PREFIXES: &[(&str, &str)] = &[
+    ("qemu", "qemu"),
+    ("cloud-hyperviso", "cloud-hypervisor"),
+    ("firecracker", "firecracker"),
+    ("stratovirt", "stratovirt"),
+    ("acrn-dm", "acrn"),
+];
+
+// Hypervisor name used when no hypervisor process exists, the hypervisor
+// being built into the shim (e.g. dragonball).
+pub const BUILTIN_HYPERVISOR: &str = "builtin";
+
+const PROMETHEUS_NAMESPACE: &str = "kata";
+
+// The longest time 'metrics collect' may take without --force, and at all,
+// for a typo in --count not to tie up the tool for days.
+const COLLECT_FORCE_THRESHOLD: Duration = Duration::from_secs(3600);
+const COLLECT_MAX_DURATION: Duration = Duration::from_secs(7 * 86400);
+
+const METRICS: &[(&str, &str)] = &[
+    (
+        "shim_cpu_percent",
+        "CPU usage of the shim, in percent of one CPU",
+    ),
+    ("shim_memory_bytes", "Resident memory of the shim"),
+    (
+        "hypervisor_cpu_percent",
+        "CPU usage of the hypervisor, in percent of one CPU (0 if built into the shim)",
+    ),
+    (
+        "hypervisor_memory_bytes",
+        "Resident memory of the hypervisor (0 if built into the shim)",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProcessKind {
+    Shim,
+    Hypervisor,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ProcessUsage {
+    cpu_ticks: u64,
+    memory_bytes: u64,
+}
+
+struct SandboxProcesses {
+    pids: Vec<(ProcessKind, u32)>,
+    hypervisor: &'static str,
+}
+
+// UsageSample is the usage of the processes of a sandbox at a point in
+// time.
+#[derive(Debug, Clone, Copy)]
+struct UsageSample {
+    time: Instant,
+    timestamp: u64,
+    shim: ProcessUsage,
+    hypervisor: ProcessUsage,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MetricsSample {
+    pub timestamp: u64,
+    pub hypervisor: String,
+    pub shim_cpu_percent: f64,
+    pub shim_memory_bytes: u64,
+    pub hypervisor_cpu_percent: f64,
+    pub hypervisor_memory_bytes: u64,
+}
+
+pub fn hypervisor_name(comm: &str) -> Option<&'static str> {
+    HYPERVISOR_COMM_PREFIXES
+        .iter()
+        .find(|(prefix, _)| comm.starts_with(prefix))
+        .map(|(_, name)| *name)
+}
+
+fn process_kind(comm: &str) -> Option<ProcessKind> {
+    if comm.starts_with(SHIM_COMM_PREFIX) {
+        return Some(ProcessKind::Shim);
+    }
+
+    hypervisor_name(comm).map(|_| ProcessKind::Hypervisor)
+}
+
+// Find the shim and hypervisor processes of a sandbox, which both have the
+// sandbox ID on their command line.
+fn find_sandbox_processes(sandbox_id: &str) -> Result<SandboxProcesses> {
+    let mut processes = Vec::new();
+    let mut hypervisor = BUILTIN_HYPERVISOR;
+
+    for entry in fs::read_dir(PROC_PATH)? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        // processes may exit while scanning
+        let cmdline = match fs::read(entry.path().join("cmdline")) {
+            Ok(cmdline) => String::from_utf8_lossy(&cmdline).to_string(),
+            Err(_) => continue,
+        };
+        if !cmdline.contains(sandbox_id) {
+            continue;
+        }
+
+        let comm = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+        if let Some(kind) = process_kind(comm.trim()) {
+            if let Some(name) = hypervisor_name(comm.trim()) {
+                hypervisor = name;
+            }
+            processes.push((kind, pid));
+        }
+    }
+
+    if !processes.iter().any(|(kind, _)| *kind == ProcessKind::Shim) {
+        return Err(anyhow!("no shim process found for sandbox {}", sandbox_id));
+    }
+
+    Ok(SandboxProcesses {
+        pids: processes,
+        hypervisor,
+    })
+}
+
+// Get the user and system CPU time of a process from /proc/<pid>/stat. The
+// command name, in parentheses, may contain spaces so the fields are counted
+// from the closing parenthesis.
+fn parse_proc_stat_cpu_ticks(stat: &str) -> Result<u64> {
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .ok_or_else(|| anyhow!("invalid stat format"))?
+        .1
+        .split_whitespace()
+        .collect();
+
+    // utime and stime are the 14th and 15th fields, the state being the 3rd.
+    let ticks = |index: usize| -> Result<u64> {
+        fields
+            .get(index)
+            .ok_or_else(|| anyhow!("missing stat field {}", index + 3))?
+            .parse::<u64>()
+            .context("invalid stat field")
+    };
+
+    Ok(ticks(11)? + ticks(12)?)
+}
+
+// Get the resident memory of a process from /proc/<pid>/status.
+fn parse_proc_status_rss(status: &str) -> Result<u64> {
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix("VmRSS:") {
+            let kb = value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .context("invalid VmRSS value")?;
+            return Ok(kb * 1024);
+        }
+    }
+
+    // kernel threads have no memory
+    Ok(0)
+}
+
+fn get_process_usage(pid: u32) -> Result<ProcessUsage> {
+    let proc_path = Path::new(PROC_PATH).join(pid.to_string());
+    let stat = fs::read_to_string(proc_path.join("stat"))
+        .with_context(|| format!("failed to read stat of process {}", pid))?;
+    let status = fs::read_to_string(proc_path.join("status"))
+        .with_context(|| format!("failed to read status of process {}", pid))?;
+
+    Ok(ProcessUsage {
+        cpu_ticks: parse_proc_stat_cpu_ticks(&stat)?,
+        memory_bytes: parse_proc_status_rss(&status)?,
+    })
+}
+
+fn get_usage(processes: &[(ProcessKind, u32)], kind: ProcessKind) -> Result<ProcessUsage> {
+    let mut usage = ProcessUsage::default();
+
+    for (_, pid) in processes.iter().filter(|(k, _)| *k == kind) {
+        let process_usage = get_process_usage(*pid)?;
+        usage.cpu_ticks += process_usage.cpu_ticks;
+        usage.memory_bytes += process_usage.memory_bytes;
+    }
+
+    Ok(usage)
+}
+
+fn sample_usage(processes: &SandboxProcesses) -> Result<UsageSample> {
+    Ok(UsageSample {
+        shim: get_usage(&processes.pids, ProcessKind::Shim)?,
+        hypervisor: get_usage(&processes.pids, ProcessKind::Hypervisor)?,
+        time: Instant::now(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    })
+}
+
+fn get_ticks_per_sec() -> Result<u64> {
+    Ok(sysconf(SysconfVar::CLK_TCK)?
+        .ok_or_else(|| anyhow!("failed to get clock ticks per second"))? as u64)
+}
+
+fn cpu_percent(prev_ticks: u64, ticks: u64, ticks_per_sec: u64, elapsed: Duration) -> f64 {
+    let elapsed = elapsed.as_secs_f64();
+    if ticks_per_sec == 0 || elapsed <= 0.0 {
+        return 0.0;
+    }
+
+    ticks.saturating_sub(prev_ticks) as f64 / ticks_per_sec as f64 / elapsed * 100.0
+}
+
+// Set by the SIGINT handler of 'metrics collect --watch'.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Step of the sleeps between two samples, for an interruption to be seen
+// without waiting for the end of the interval.
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+// Sleep for duration, returning false if interrupted.
+pub fn sleep_unless_interrupted(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep(SLEEP_STEP.min(deadline - now));
+    }
+}
+
+fn collect_metrics<F>(args: &MetricsCollectArgs, mut on_sample: F) -> Result<()>
+where
+    F: FnMut(MetricsSample) -> Result<()>,
+{
+    let processes = find_sandbox_processes(&args.sandbox_id)?;
+    let ticks_per_sec = get_ticks_per_sec()?;
+
+    let mut prev = sample_usage(&processes)?;
+
+    // --watch samples until interrupted.
+    let count = if args.watch { u64::MAX } else { args.count };
+    for _ in 0..count {
+        if !sleep_unless_interrupted(Duration::from_secs(args.interval)) {
+            break;
+        }
+
+        let usage = sample_usage(&processes)?;
+        let elapsed = usage.time - prev.time;
+
+        let sample = MetricsSample {
+            timestamp: usage.timestamp,
+            hypervisor: processes.hypervisor.to_string(),
+            shim_cpu_percent: cpu_percent(
+                prev.shim.cpu_ticks,
+                usage.shim.cpu_ticks,
+                ticks_per_sec,
+                elapsed,
+            ),
+            shim_memory_bytes: usage.shim.memory_bytes,
+            hypervisor_cpu_percent: cpu_percent(
+                prev.hypervisor.cpu_ticks,
+                usage.hypervisor.cpu_ticks,
+                ticks_per_sec,
+                elapsed,
+            ),
+            hypervisor_memory_bytes: usage.hypervisor.memory_bytes,
+        };
+        on_sample(sample)?;
+
+        prev = usage;
+    }
+
+    Ok(())
+}
+
+// MetricDelta is the change of a metric between the two samples of
+// 'metrics diff', the delta and rate of a counter that was reset being
+// unknown.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MetricDelta {
+    pub name: &'static str,
+    pub before: f64,
+    pub after: f64,
+    pub delta: Option<f64>,
+    pub rate_per_sec: Option<f64>,
+    pub reset: bool,
+}
+
+// MetricsDelta is the output of 'kata-ctl metrics diff'.
+#[derive(Debug, Serialize)]
+pub struct MetricsDelta {
+    pub sandbox_id: String,
+    pub hypervisor: String,
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub duration_secs: f64,
+    pub metrics: Vec<MetricDelta>,
+}
+
+fn metric_delta(
+    name: &'static str,
+    counter: bool,
+    before: f64,
+    after: f64,
+    elapsed: f64,
+) -> MetricDelta {
+    let reset = counter && after < before;
+    let delta = if reset { None } else { Some(after - before) };
+
+    MetricDelta {
+        name,
+        before,
+        after,
+        delta,
+        rate_per_sec: delta.filter(|_| elapsed > 0.0).map(|delta| delta / elapsed),
+        reset,
+    }
+}
+
+fn diff_usage(before: &UsageSample, after: &UsageSample, ticks_per_sec: u64) -> Vec<MetricDelta> {
+    let elapsed = after
+        .time
+        .saturating_duration_since(before.time)
+        .as_secs_f64();
+    let cpu_secs = |usage: &ProcessUsage| {
+        if ticks_per_sec == 0 {
+            return 0.0;
+        }
+        usage.cpu_ticks as f64 / ticks_per_sec as f64
+    };
+
+    vec![
+        metric_delta(
+            "shim_cpu_seconds",
+            true,
+            cpu_secs(&before.shim),
+            cpu_secs(&after.shim),
+            elapsed,
+        ),
+        metric_delta(
+            "shim_memory_bytes",
+            false,
+            before.shim.memory_bytes as f64,
+            after.shim.memory_bytes as f64,
+            elapsed,
+        ),
+        metric_delta(
+            "hypervisor_cpu_seconds",
+            true,
+            cpu_secs(&before.hypervisor),
+            cpu_secs(&after.hypervisor),
+            elapsed,
+        ),
+        metric_delta(
+            "hypervisor_memory_bytes",
+            false,
+            before.hypervisor.memory_bytes as f64,
+            after.hypervisor.memory_bytes as f64,
+            elapsed,
+        ),
+    ]
+}
+
+fn metrics_delta_table(delta: &MetricsDelta) -> Table {
+    let mut table = Table::new(&["METRIC", "BEFORE", "AFTER", "DELTA", "RATE/S"]);
+    for metric in &delta.metrics {
+        let (change, rate) = match (metric.delta, metric.rate_per_sec) {
+            (None, _) => ("reset".to_string(), "-".to_string()),
+            (Some(change), rate) => (
+                format!("{:+.2}", change),
+                rate.map(|rate| format!("{:+.2}", rate))
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+        };
+        table.add_row(vec![
+            metric.name.to_string(),
+            format!("{:.2}", metric.before),
+            format!("{:.2}", metric.after),
+            change,
+            rate,
+        ]);
+    }
+
+    table
+}
+
+// The CSV output only has the table, without the sandbox line.
+fn print_metrics_delta(delta: &MetricsDelta, format: OutputFormat) {
+    if format != OutputFormat::Csv {
+        println!(
+            "sandbox {} ({}), {:.1}s between samples",
+            delta.sandbox_id, delta.hypervisor, delta.duration_secs
+        );
+    }
+
+    output::print_table(format, &metrics_delta_table(delta));
+}
+
+// The processes are looked up for each sample, so that a restarted shim
+// shows as a reset counter rather than an error.
+fn handle_metrics_diff(args: &MetricsDiffArgs, format: OutputFormat) -> Result<()> {
+    let format = format.with_json(args.json);
+    let ticks_per_sec = get_ticks_per_sec()?;
+
+    let processes = find_sandbox_processes(&args.sandbox_id)?;
+    let before = sample_usage(&processes)?;
+
+    match args.duration {
+        Some(duration) => thread::sleep(Duration::from_secs(duration)),
+        None => {
+            eprintln!("Baseline captured, press Enter to take the second sample");
+            io::stdin().lock().read_line(&mut String::new())?;
+        }
+    }
+
+    let processes = find_sandbox_processes(&args.sandbox_id)?;
+    let after = sample_usage(&processes)?;
+
+    let delta = MetricsDelta {
+        sandbox_id: args.sandbox_id.clone(),
+        hypervisor: processes.hypervisor.to_string(),
+        start_timestamp: before.timestamp,
+        end_timestamp: after.timestamp,
+        duration_secs: after
+            .time
+            .saturating_duration_since(before.time)
+            .as_secs_f64(),
+        metrics: diff_usage(&before, &after, ticks_per_sec),
+    };
+
+    if !output::print_structured(format, &delta)? {
+        print_metrics_delta(&delta, format);
+    }
+
+    Ok(())
+}
+
+fn format_memory(bytes: u64) -> String {
+    format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+// The values of a sample, in the order of METRICS.
+fn sample_values(sample: &MetricsSample) -> [f64; 4] {
+    [
+        sample.shim_cpu_percent,
+        sample.shim_memory_bytes as f64,
+        sample.hypervisor_cpu_percent,
+        sample.hypervisor_memory_bytes as f64,
+    ]
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Format a sample in the Prometheus text exposition format, all metrics
+// being gauges.
+fn format_prometheus(sandbox_id: &str, sample: &MetricsSample) -> String {
+    let labels = format!(
+        "sandbox_id=\"{}\",hypervisor=\"{}\"",
+        escape_label_value(sandbox_id),
+        escape_label_value(&sample.hypervisor)
+    );
+
+    let mut output = String::new();
+    for ((name, description), value) in METRICS.iter().zip(sample_values(sample).iter()) {
+        let name = format!("{}_{}", PROMETHEUS_NAMESPACE, name);
+        output.push_str(&format!("# HELP {} {}\n", name, description));
+        output.push_str(&format!("# TYPE {} gauge\n", name));
+        output.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+    }
+
+    output
+}
+
+// The --format and --json options of the command take precedence over the
+// global --output. The samples are a list, so the human output is already
+// a table.
+fn metrics_format(args: &MetricsCollectArgs, output: OutputFormat) -> MetricsFormat {
+    if args.json {
+        return MetricsFormat::Json;
+    }
+
+    if let Some(format) = args.format {
+        return format;
+    }
+
+    match output {
+        OutputFormat::Json => MetricsFormat::Json,
+        OutputFormat::Yaml => MetricsFormat::Yaml,
+        OutputFormat::Human | OutputFormat::Table => MetricsFormat::Human,
+        OutputFormat::Csv => MetricsFormat::Csv,
+    }
+}
+
+// The columns of the human output, the memory being in bytes in the CSV
+// output.
+const METRICS_COLUMNS: &[&str] = &[
+    "TIMESTAMP",
+    "SHIM CPU%",
+    "SHIM MEMORY",
+    "HYPERVISOR CPU%",
+    "HYPERVISOR MEMORY",
+];
+
+fn metrics_csv_record(sample: &MetricsSample) -> String {
+    output::csv_record(&[
+        sample.timestamp.to_string(),
+        format!("{:.1}", sample.shim_cpu_percent),
+        sample.shim_memory_bytes.to_string(),
+        format!("{:.1}", sample.hypervisor_cpu_percent),
+        sample.hypervisor_memory_bytes.to_string(),
+    ])
+}
+
+fn format_metrics_header() -> String {
+    format!(
+        "{:<12} {:>10} {:>12} {:>16} {:>18}",
+        "TIMESTAMP", "SHIM CPU%", "SHIM MEMORY", "HYPERVISOR CPU%", "HYPERVISOR MEMORY"
+    )
+}
+
+fn format_metrics_row(sample: &MetricsSample) -> String {
+    format!(
+        "{:<12} {:>10.1} {:>12} {:>16.1} {:>18}",
+        sample.timestamp,
+        sample.shim_cpu_percent,
+        format_memory(sample.shim_memory_bytes),
+        sample.hypervisor_cpu_percent,
+        format_memory(sample.hypervisor_memory_bytes)
+    )
+}
+
+// --stream prints the samples one JSON object per line.
+fn check_stream(args: &MetricsCollectArgs, output: OutputFormat) -> Result<()> {
+    if metrics_format(args, output) != MetricsFormat::Json {
+        return Err(anyhow!("--stream only supports the JSON output format"));
+    }
+
+    Ok(())
+}
+
+// Each line is flushed for the next command of a pipeline to get the
+// samples as they are collected.
+fn write_json_line(out: &mut dyn Write, sample: &MetricsSample) -> Result<()> {
+    writeln!(out, "{}", serde_json::to_string(sample)?)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+// A sample is collected every interval, so that the samples take count
+// times the interval.
+fn check_collect_duration(args: &MetricsCollectArgs) -> Result<()> {
+    let secs = args.count.saturating_mul(args.interval);
+    let estimate = format!(
+        "--count {} --interval {} would collect for {}",
+        args.count,
+        args.interval,
+        ps_ops::format_uptime(secs)
+    );
+
+    if secs > COLLECT_MAX_DURATION.as_secs() {
+        return Err(anyhow!(
+            "{}, more than the limit of {}",
+            estimate,
+            ps_ops::format_uptime(COLLECT_MAX_DURATION.as_secs())
+        ));
+    }
+
+    if secs > COLLECT_FORCE_THRESHOLD.as_secs() && !args.force {
+        return Err(anyhow!(
+            "{}, add --force to collect for more than {}",
+            estimate,
+            ps_ops::format_uptime(COLLECT_FORCE_THRESHOLD.as_secs())
+        ));
+    }
+
+    Ok(())
+}
+
+// --watch redraws the whole terminal, which only makes sense for the human
+// output to a terminal.
+fn check_watch(args: &MetricsCollectArgs, output: OutputFormat) -> Result<()> {
+    if metrics_format(args, output) != MetricsFormat::Human {
+        return Err(anyhow!("--watch only supports the human output format"));
+    }
+
+    if !output::stdout_is_tty() {
+        return Err(anyhow!("--watch needs the output to be a terminal"));
+    }
+
+    Ok(())
+}
+
+const ENTER_ALT_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALT_SCREEN: &str = "\x1b[?1049l";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+// The view of 'metrics collect --watch', like the one of watch(1).
+fn format_watch_view(args: &MetricsCollectArgs, sample: Option<&MetricsSample>) -> String {
+    let metrics = match sample {
+        Some(sample) => format!(
+            "{}\n{}",
+            format_metrics_header(),
+            format_metrics_row(sample)
+        ),
+        None => String::from("waiting for the first sample..."),
+    };
+
+    format!(
+        "Every {}s: kata-ctl metrics collect {}\n\n{}\n",
+        args.interval, args.sandbox_id, metrics
+    )
+}
+
+// WatchScreen shows the view of a --watch option on the alternate screen of
+// the terminal, Ctrl-C stopping the watch. The screen and the handling of
+// SIGINT are restored when it is dropped, whether the watch was interrupted
+// or failed.
+pub struct WatchScreen {
+    prev_sigint: SigAction,
+}
+
+impl WatchScreen {
+    pub fn new() -> Result<Self> {
+        let action = SigAction::new(
+            SigHandler::Handler(handle_sigint),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        // SAFETY: the handler only sets an atomic flag.
+        let prev_sigint = unsafe { sigaction(Signal::SIGINT, &action)? };
+
+        print!("{}{}", ENTER_ALT_SCREEN, HIDE_CURSOR);
+        io::stdout().flush()?;
+
+        Ok(WatchScreen { prev_sigint })
+    }
+
+    pub fn render(&self, view: &str) {
+        print!("{}{}", CLEAR_SCREEN, view);
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Drop for WatchScreen {
+    fn drop(&mut self) {
+        print!("{}{}", SHOW_CURSOR, LEAVE_ALT_SCREEN);
+        let _ = io::stdout().flush();
+        // SAFETY: restores the handling of SIGINT from before the watch.
+        let _ = unsafe { sigaction(Signal::SIGINT, &self.prev_sigint) };
+    }
+}
+
+fn watch_metrics(args: &MetricsCollectArgs) -> Result<()> {
+    let screen = WatchScreen::new()?;
+    screen.render(&format_watch_view(args, None));
+
+    collect_metrics(args, |sample| {
+        screen.render(&format_watch_view(args, Some(&sample)));
+        Ok(())
+    })
+}
+
+fn handle_metrics_collect(args: &MetricsCollectArgs, output: OutputFormat) -> Result<()> {
+    if args.watch {
+        return watch_metrics(args);
+    }
+
+    match metrics_format(args, output) {
+        MetricsFormat::Json if args.stream => {
+            let mut stdout = io::stdout();
+            return collect_metrics(args, |sample| write_json_line(&mut stdout, &sample));
+        }
+        MetricsFormat::Json => {
+            let mut samples = Vec::new();
+            collect_metrics(args, |sample| {
+                samples.push(sample);
+                Ok(())
+            })?;
+            println!("{}", serde_json::to_string_pretty(&samples)?);
+            return Ok(());
+        }
+        MetricsFormat::Yaml => {
+            let mut samples = Vec::new();
+            collect_metrics(args, |sample| {
+                samples.push(sample);
+                Ok(())
+            })?;
+            print!("{}", serde_yaml::to_string(&samples)?);
+            return Ok(());
+        }
+        // Only the last sample is printed, as a scrape holds a single value
+        // per metric.
+        MetricsFormat::Prometheus => {
+            let mut last_sample = None;
+            collect_metrics(args, |sample| {
+                last_sample = Some(sample);
+                Ok(())
+            })?;
+            if let Some(sample) = last_sample {
+                print!("{}", format_prometheus(&args.sandbox_id, &sample));
+            }
+            return Ok(());
+        }
+        // Like the human output, the CSV records are printed as the samples
+        // are collected.
+        MetricsFormat::Csv => {
+            let headers: Vec<String> = METRICS_COLUMNS.iter().map(|c| c.to_string()).collect();
+            print!("{}", output::csv_record(&headers));
+            return collect_metrics(args, |sample| {
+                print!("{}", metrics_csv_record(&sample));
+                io::stdout().flush()?;
+                Ok(())
+            });
+        }
+        MetricsFormat::Human => {}
+    }
+
+    println!("{}", format_metrics_header());
+    collect_metrics(args, |sample| {
+        println!("{}", format_metrics_row(&sample));
+        Ok(())
+    })
+}
+
+// MetricInfo describes a metric in the structured output of 'metrics list'.
+#[derive(Debug, Serialize)]
+struct MetricInfo {
+    name: &'static str,
+    description: &'static str,
+}
+
+fn print_metrics_list(format: OutputFormat) -> Result<()> {
+    let metrics: Vec<MetricInfo> = METRICS
+        .iter()
+        .map(|&(name, description)| MetricInfo { name, description })
+        .collect();
+
+    if output::print_structured(format, &metrics)? {
+        return Ok(());
+    }
+
+    if format == OutputFormat::Table || format == OutputFormat::Csv {
+        let mut table = Table::new(&["NAME", "DESCRIPTION"]);
+        for metric in metrics {
+            table.add_row(vec![
+                metric.name.to_string(),
+                metric.description.to_string(),
+            ]);
+        }
+        output::print_table(format, &table);
+    } else {
+        for metric in metrics {
+            println!("{:<24} {}", metric.name, metric.description);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn handle_metrics(
+    metrics_cmd: MetricsCommand,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
+    match metrics_cmd.metrics_cmd {
+        MetricsSubCommand::Collect(args) => {
+            if args.watch {
+                check_watch(&args, format).map_err(KataCtlError::Config)?;
+            } else {
+                check_collect_duration(&args).map_err(KataCtlError::Config)?;
+            }
+            if args.stream {
+                check_stream(&args, format).map_err(KataCtlError::Config)?;
+            }
+            handle_metrics_collect(&args, format)?
+        }
+        MetricsSubCommand::List => print_metrics_list(format)?,
+        MetricsSubCommand::Diff(args) => handle_metrics_diff(&args, format)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_kind() {
+        assert_eq!(
+            process_kind("containerd-shim-kata-v2"),
+            Some(ProcessKind::Shim)
+        );
+        assert_eq!(
+            process_kind("qemu-system-x86"),
+            Some(ProcessKind::Hypervisor)
+        );
+        assert_eq!(
+            process_kind("cloud-hyperviso"),
+            Some(ProcessKind::Hypervisor)
+        );
+        assert_eq!(process_kind("kata-ctl"), None);
+
+        assert_eq!(hypervisor_name("cloud-hyperviso"), Some("cloud-hypervisor"));
+        assert_eq!(hypervisor_name("containerd-shim"), None);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_cpu_ticks() {
+        let stat = "1234 (qemu (x) y) S 1 1234 1234 0 -1 4194560 100 0 0 0 250 50 0 0 20 0 5 0 100 1000 200";
+        assert_eq!(parse_proc_stat_cpu_ticks(stat).unwrap(), 300);
+
+        assert!(parse_proc_stat_cpu_ticks("1234 qemu S 1").is_err());
+        assert!(parse_proc_stat_cpu_ticks("1234 (qemu) S 1 2 3").is_err());
+    }
+
+    #[test]
+    fn test_parse_proc_status_rss() {
+        let status = "Name:\tqemu\nVmPeak:\t  2048 kB\nVmRSS:\t  1024 kB\nThreads:\t4\n";
+        assert_eq!(parse_proc_status_rss(status).unwrap(), 1024 * 1024);
+        assert_eq!(parse_proc_status_rss("Name:\tkthreadd\n").unwrap(), 0);
+        assert!(parse_proc_status_rss("VmRSS:\tx kB\n").is_err());
+    }
+
+    #[test]
+    fn test_format_prometheus() {
+        let sample = MetricsSample {
+            timestamp: 1,
+            hypervisor: "qemu".to_string(),
+            shim_cpu_percent: 2.5,
+            shim_memory_bytes: 1024,
+            hypervisor_cpu_percent: 50.0,
+            hypervisor_memory_bytes: 4096,
+        };
+
+        let output = format_prometheus("sb\"1", &sample);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), METRICS.len() * 3);
+        assert_eq!(
+            lines[0],
+            "# HELP kata_shim_cpu_percent CPU usage of the shim, in percent of one CPU"
+        );
+        assert_eq!(lines[1], "# TYPE kata_shim_cpu_percent gauge");
+        assert_eq!(
+            lines[2],
+            "kata_shim_cpu_percent{sandbox_id=\"sb\\\"1\",hypervisor=\"qemu\"} 2.5"
+        );
+        assert_eq!(
+            lines[11],
+            "kata_hypervisor_memory_bytes{sandbox_id=\"sb\\\"1\",hypervisor=\"qemu\"} 4096"
+        );
+    }
+
+    #[test]
+    fn test_cpu_percent() {
+        let second = Duration::from_secs(1);
+        assert_eq!(cpu_percent(100, 150, 100, second), 50.0);
+        assert_eq!(cpu_percent(100, 300, 100, Duration::from_secs(2)), 100.0);
+        assert_eq!(cpu_percent(150, 100, 100, second), 0.0);
+        assert_eq!(cpu_percent(100, 150, 0, second), 0.0);
+        assert_eq!(cpu_percent(100, 150, 100, Duration::from_secs(0)), 0.0);
+    }
+
+    #[test]
+    fn test_diff_usage() {
+        let usage = |cpu_ticks, memory_bytes| ProcessUsage {
+            cpu_ticks,
+            memory_bytes,
+        };
+        let now = Instant::now();
+        let before = UsageSample {
+            time: now,
+            timestamp: 100,
+            shim: usage(100, 4096),
+            hypervisor: usage(1000, 8192),
+        };
+        let after = UsageSample {
+            time: now + Duration::from_secs(2),
+            timestamp: 102,
+            shim: usage(300, 2048),
+            // the hypervisor was restarted
+            hypervisor: usage(50, 8192),
+        };
+
+        let metrics = diff_usage(&before, &after, 100);
+        assert_eq!(
+            metrics[0],
+            MetricDelta {
+                name: "shim_cpu_seconds",
+                before: 1.0,
+                after: 3.0,
+                delta: Some(2.0),
+                rate_per_sec: Some(1.0),
+                reset: false,
+            }
+        );
+
+        // a gauge may decrease
+        assert_eq!(metrics[1].delta, Some(-2048.0));
+        assert_eq!(metrics[1].rate_per_sec, Some(-1024.0));
+        assert!(!metrics[1].reset);
+
+        assert!(metrics[2].reset);
+        assert_eq!(metrics[2].delta, None);
+        assert_eq!(metrics[2].rate_per_sec, None);
+
+        assert_eq!(metrics[3].delta, Some(0.0));
+
+        // no rate without time between the samples
+        let metrics = diff_usage(&before, &before, 100);
+        assert!(metrics.iter().all(|m| m.rate_per_sec.is_none()));
+    }
+
+    #[test]
+    fn test_format_watch_view() {
+        let args = MetricsCollectArgs {
+            sandbox_id: "sb1".to_string(),
+            interval: 2,
+            count: 1,
+            format: None,
+            json: false,
+            watch: true,
+            stream: false,
+            force: false,
+        };
+        let sample = MetricsSample {
+            timestamp: 1700000000,
+            hypervisor: "qemu".to_string(),
+            shim_cpu_percent: 1.25,
+            shim_memory_bytes: 0,
+            hypervisor_cpu_percent: 12.5,
+            hypervisor_memory_bytes: 0,
+        };
+
+        let view = format_watch_view(&args, Some(&sample));
+        let lines: Vec<&str> = view.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "Every 2s: kata-ctl metrics collect sb1");
+        assert_eq!(lines[1], "");
+        assert_eq!(lines[2], format_metrics_header());
+        assert_eq!(lines[3], format_metrics_row(&sample));
+
+        assert!(format_watch_view(&args, None).ends_with("\n\nwaiting for the first sample...\n"));
+    }
+
+    #[test]
+    fn test_write_json_line() {
+        let sample = MetricsSample {
+            timestamp: 1700000000,
+            hypervisor: "qemu".to_string(),
+            shim_memory_bytes: 1024,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_json_line(&mut out, &sample).unwrap();
+        write_json_line(&mut out, &sample).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["timestamp"], 1700000000);
+            assert_eq!(value["shim_memory_bytes"], 1024);
+        }
+
+        let args = |json: bool| MetricsCollectArgs {
+            sandbox_id: "sb1".to_string(),
+            interval: 1,
+            count: 1,
+            format: None,
+            json,
+            watch: false,
+            stream: true,
+            force: false,
+        };
+        assert!(check_stream(&args(true), OutputFormat::Human).is_ok());
+        assert!(check_stream(&args(false), OutputFormat::Json).is_ok());
+        assert!(check_stream(&args(false), OutputFormat::Human).is_err());
+    }
+
+    #[test]
+    fn test_sleep_unless_interrupted() {
+        assert!(sleep_unless_interrupted(Duration::from_millis(1)));
+
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let interrupted = !sleep_unless_interrupted(Duration::from_secs(10));
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        assert!(interrupted);
+    }
+
+    #[test]
+    fn test_metrics_format() {
+        let args = |format: Option<MetricsFormat>, json: bool| MetricsCollectArgs {
+            sandbox_id: "sb1".to_string(),
+            interval: 1,
+            count: 1,
+            format,
+            json,
+            watch: false,
+            stream: false,
+            force: false,
+        };
+
+        assert_eq!(
+            metrics_format(&args(None, false), OutputFormat::Human),
+            MetricsFormat::Human
+        );
+        assert_eq!(
+            metrics_format(&args(None, false), OutputFormat::Table),
+            MetricsFormat::Human
+        );
+        assert_eq!(
+            metrics_format(&args(None, false), OutputFormat::Yaml),
+            MetricsFormat::Yaml
+        );
+        assert_eq!(
+            metrics_format(&args(None, true), OutputFormat::Yaml),
+            MetricsFormat::Json
+        );
+        assert_eq!(
+            metrics_format(
+                &args(Some(MetricsFormat::Prometheus), false),
+                OutputFormat::Json
+            ),
+            MetricsFormat::Prometheus
+        );
+        assert_eq!(
+            metrics_format(&args(None, false), OutputFormat::Csv),
+            MetricsFormat::Csv
+        );
+    }
+
+    #[test]
+    fn test_check_collect_duration() {
+        let args = |count: u64, interval: u64, force: bool| MetricsCollectArgs {
+            sandbox_id: "sb1".to_string(),
+            interval,
+            count,
+            format: None,
+            json: false,
+            watch: false,
+            stream: false,
+            force,
+        };
+
+        assert!(check_collect_duration(&args(1, 1, false)).is_ok());
+        assert!(check_collect_duration(&args(60, 60, false)).is_ok());
+
+        let e = check_collect_duration(&args(61, 60, false)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "--count 61 --interval 60 would collect for 1h1m0s, add --force to collect for more than 1h0m0s"
+        );
+        assert!(check_collect_duration(&args(61, 60, true)).is_ok());
+        assert!(check_collect_duration(&args(7 * 24, 3600, true)).is_ok());
+
+        let e = check_collect_duration(&args(1000000, 1, true)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "--count 1000000 --interval 1 would collect for 11d13h46m, more than the limit of 7d0h0m"
+        );
+        assert!(check_collect_duration(&args(u64::MAX, u64::MAX, true)).is_err());
+    }
+
+    #[test]
+    fn test_metrics_csv() {
+        let sample = MetricsSample {
+            timestamp: 1700000000,
+            hypervisor: "qemu".to_string(),
+            shim_cpu_percent: 1.5,
+            shim_memory_bytes: 41943040,
+            hypervisor_cpu_percent: 12.0,
+            hypervisor_memory_bytes: 2147483648,
+        };
+        assert_eq!(
+            metrics_csv_record(&sample),
+            "\"1700000000\",\"1.5\",\"41943040\",\"12.0\",\"2147483648\"\r\n"
+        );
+
+        let delta = MetricsDelta {
+            sandbox_id: "sb1".to_string(),
+            hypervisor: "qemu".to_string(),
+            start_timestamp: 1,
+            end_timestamp: 2,
+            duration_secs: 1.0,
+            metrics: vec![metric_delta("shim_memory_bytes", false, 10.0, 4.0, 1.0)],
+        };
+        assert_eq!(
+            metrics_delta_table(&delta).to_csv(),
+            "\"METRIC\",\"BEFORE\",\"AFTER\",\"DELTA\",\"RATE/S\"\r\n\"shim_memory_bytes\",\"10.00\",\"4.00\",\"-6.00\",\"-6.00\"\r\n"
+        );
+    }
+}
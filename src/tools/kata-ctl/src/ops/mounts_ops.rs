@@ -0,0 +1,178 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the mounts command, listing the filesystems mounted in
+// the guest. The agent has no request for the mount table, so it is read
+// from the guest /proc/mounts through the debug console, which the agent
+// serves even when the containers of the sandbox are broken.
+
+use crate::args::{MountsArgument, OutputFormat};
+use crate::error::KataCtlError;
+use crate::ops::exec_ops;
+use crate::output::{self, Table};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::time::Duration;
+
+pub const GUEST_MOUNTS_FILE: &str = "/proc/mounts";
+
+// GuestMount is a line of the guest mount table, the output of
+// 'kata-ctl mounts --json' being a list of them.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct GuestMount {
+    pub source: String,
+    pub target: String,
+    pub fstype: String,
+    pub options: String,
+}
+
+// The spaces, tabs, newlines and backslashes of the fields of /proc/mounts
+// are escaped as octal numbers, e.g. "\040" for a space.
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+
+    while let Some(i) = rest.find('\\') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        match rest.get(1..4).and_then(|n| u8::from_str_radix(n, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    unescaped
+}
+
+pub fn parse_mounts(contents: &str) -> Result<Vec<GuestMount>> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return Err(anyhow!("invalid mount table line {:?}", line));
+            }
+
+            Ok(GuestMount {
+                source: unescape_mount_field(fields[0]),
+                target: unescape_mount_field(fields[1]),
+                fstype: fields[2].to_string(),
+                options: fields[3].to_string(),
+            })
+        })
+        .collect()
+}
+
+// The mounts are a list, so the human output is already a table.
+fn print_mounts(mounts: &[GuestMount]) {
+    let mut table = Table::new(&["SOURCE", "TARGET", "FSTYPE", "OPTIONS"]);
+    for mount in mounts {
+        table.add_row(vec![
+            mount.source.clone(),
+            mount.target.clone(),
+            mount.fstype.clone(),
+            mount.options.clone(),
+        ]);
+    }
+
+    print!("{}", table);
+}
+
+// Read the guest mount table of a sandbox through the debug console.
+pub fn get_guest_mounts(
+    sandbox_id: &str,
+    vport: u32,
+    socket: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Vec<GuestMount>, KataCtlError> {
+    let command = vec!["cat".to_string(), GUEST_MOUNTS_FILE.to_string()];
+
+    let result = exec_ops::run_guest_command(sandbox_id, vport, socket, &command, timeout)?;
+    if result.exit_code != 0 {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "failed to read the guest {} of sandbox {} (exit status {}): {}",
+            GUEST_MOUNTS_FILE,
+            sandbox_id,
+            result.exit_code,
+            result.output.trim()
+        )));
+    }
+
+    Ok(parse_mounts(&result.output)?)
+}
+
+pub fn handle_mounts(args: MountsArgument, format: OutputFormat) -> Result<(), KataCtlError> {
+    let mounts = get_guest_mounts(
+        &args.sandbox_id,
+        args.vport,
+        args.socket.as_deref(),
+        args.timeout.map(Duration::from_secs),
+    )?;
+    if !output::print_structured(format, &mounts)? {
+        print_mounts(&mounts);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape_mount_field() {
+        let tests = &[
+            ("/run/kata-containers", "/run/kata-containers"),
+            (r"/mnt/my\040volume", "/mnt/my volume"),
+            (r"a\011b\012c\134d", "a\tb\nc\\d"),
+            // not an escape
+            (r"trailing\", r"trailing\"),
+            (r"\9999", r"\9999"),
+        ];
+
+        for (i, (field, expected)) in tests.iter().enumerate() {
+            assert_eq!(
+                unescape_mount_field(field),
+                *expected,
+                "test[{}]: {:?}",
+                i,
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_mounts() {
+        let contents = "rootfs / rootfs rw 0 0\n\
+                        kataShared /run/kata-containers/shared/containers virtiofs rw,relatime 0 0\n\
+                        /dev/vda1 /mnt/my\\040volume ext4 ro,noatime 0 0\n\
+                        \n";
+
+        let mounts = parse_mounts(contents).unwrap();
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(
+            mounts[1],
+            GuestMount {
+                source: "kataShared".to_string(),
+                target: "/run/kata-containers/shared/containers".to_string(),
+                fstype: "virtiofs".to_string(),
+                options: "rw,relatime".to_string(),
+            }
+        );
+        assert_eq!(mounts[2].target, "/mnt/my volume");
+
+        assert!(parse_mounts("none /proc\n").is_err());
+    }
+}
@@ -0,0 +1,733 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Implementation of the ps command, listing the sandboxes of the host. The
+// sandboxes are found from their state directories, and their shim and
+// hypervisor processes from procfs. A sandbox whose shim is gone while its
+// state directory remains is reported as dead.
+//
+// The wide listing adds the details persisted in the state of the
+// sandboxes, as shown by the inspect command.
+
+use crate::args::{OutputFormat, PsArgument};
+use crate::error::KataCtlError;
+use crate::ops::check_ops::parse_command_version;
+use crate::ops::env_ops::get_command_version;
+use crate::ops::inspect_ops::inspect_sandbox;
+use crate::ops::metrics_ops::{hypervisor_name, BUILTIN_HYPERVISOR, PROC_PATH, SHIM_COMM_PREFIX};
+use crate::output::{self, Color, Table, Truncate};
+use crate::utils;
+
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{sysconf, SysconfVar};
+use serde::Serialize;
+use shim_interface::SHIM_MGMT_SOCK_NAME;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+const PROC_UPTIME: &str = "/proc/uptime";
+
+// The directories of the VMs, whose sockets are on the command line of their
+// hypervisor, for runtime-rs and the go runtime.
+const KATA_VM_PATHS: &[&str] = &[shim_interface::KATA_PATH, "/run/vc/vm"];
+
+// The widest cells of the wide listing on a terminal.
+const MAX_VERSION_WIDTH: usize = 24;
+const MAX_PATH_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxState {
+    Running,
+    Dead,
+}
+
+impl SandboxState {
+    fn name(&self) -> &'static str {
+        match self {
+            SandboxState::Running => "running",
+            SandboxState::Dead => "dead",
+        }
+    }
+
+    fn painted_name(&self) -> String {
+        match self {
+            SandboxState::Running => output::paint(Color::Green, self.name()),
+            SandboxState::Dead => output::paint(Color::Red, self.name()),
+        }
+    }
+}
+
+// SandboxInfo is an entry of 'kata-ctl ps', the hypervisor, shim PID and
+// uptime being null for dead sandboxes.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SandboxInfo {
+    pub sandbox_id: String,
+    pub hypervisor: Option<String>,
+    pub state: SandboxState,
+    pub shim_pid: Option<u32>,
+    pub uptime_secs: Option<u64>,
+    #[serde(flatten)]
+    pub details: Option<SandboxDetails>,
+}
+
+// SandboxDetails are added to the entries of 'kata-ctl ps --wide', a field
+// being null when it is not known.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SandboxDetails {
+    pub hypervisor_version: Option<String>,
+    pub kernel: Option<String>,
+    pub memory_mb: Option<u64>,
+    pub vcpus: Option<i64>,
+    pub socket: Option<String>,
+}
+
+// VmProcess is a hypervisor process running the VM of no sandbox, e.g. a VM
+// of the VMCache pool shown by 'kata-ctl factory status'.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VmProcess {
+    pub pid: u32,
+    pub hypervisor: String,
+    pub state: String,
+    pub age_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+struct ProcessInfo {
+    pid: u32,
+    comm: String,
+    cmdline: String,
+    // State, as the one letter of /proc/<pid>/stat
+    state: char,
+    // Start time, in clock ticks since boot
+    start_ticks: u64,
+}
+
+// The process states of /proc/<pid>/stat, see proc(5).
+fn process_state_name(state: char) -> &'static str {
+    match state {
+        'R' => "running",
+        'S' => "sleeping",
+        'D' => "waiting",
+        'T' | 't' => "stopped",
+        'Z' => "zombie",
+        'X' | 'x' => "dead",
+        'I' => "idle",
+        _ => "unknown",
+    }
+}
+
+// Get the start time of a process from /proc/<pid>/stat. The command name,
+// in parentheses, may contain spaces so the fields are counted from the
+// closing parenthesis.
+fn parse_proc_stat_start_ticks(stat: &str) -> Result<u64> {
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .ok_or_else(|| anyhow!("invalid stat format"))?
+        .1
+        .split_whitespace()
+        .collect();
+
+    // starttime is the 22nd field, the state being the 3rd.
+    fields
+        .get(19)
+        .ok_or_else(|| anyhow!("missing stat field 22"))?
+        .parse::<u64>()
+        .context("invalid stat field")
+}
+
+// Get the state of a process from /proc/<pid>/stat, the field following the
+// command name.
+fn parse_proc_stat_state(stat: &str) -> Option<char> {
+    stat.rsplit_once(')')?.1.trim_start().chars().next()
+}
+
+// Get the time since boot, in seconds, from /proc/uptime.
+fn parse_proc_uptime(uptime: &str) -> Result<f64> {
+    uptime
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("invalid uptime format"))?
+        .parse::<f64>()
+        .context("invalid uptime")
+}
+
+fn list_processes() -> Result<Vec<ProcessInfo>> {
+    let mut processes = Vec::new();
+
+    for entry in fs::read_dir(PROC_PATH)? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        // processes may exit while scanning
+        let read = |name: &str| fs::read(entry.path().join(name)).ok();
+        let (comm, cmdline, stat) = match (read("comm"), read("cmdline"), read("stat")) {
+            (Some(comm), Some(cmdline), Some(stat)) => (comm, cmdline, stat),
+            _ => continue,
+        };
+
+        let start_ticks = match parse_proc_stat_start_ticks(&String::from_utf8_lossy(&stat)) {
+            Ok(ticks) => ticks,
+            Err(_) => continue,
+        };
+
+        let stat = String::from_utf8_lossy(&stat);
+        processes.push(ProcessInfo {
+            pid,
+            comm: String::from_utf8_lossy(&comm).trim().to_string(),
+            // the arguments are separated by NUL characters
+            cmdline: String::from_utf8_lossy(&cmdline).replace('\0', " "),
+            state: parse_proc_stat_state(&stat).unwrap_or('?'),
+            start_ticks,
+        });
+    }
+
+    Ok(processes)
+}
+
+// Both the shim and the hypervisor have the sandbox ID on their command
+// line: the shim as its -id argument, the hypervisor in the paths and
+// names it is given.
+fn find_shim<'a>(sandbox_id: &str, processes: &'a [ProcessInfo]) -> Option<&'a ProcessInfo> {
+    // The shim started by containerd forks the shim daemon and exits, the
+    // newest one is kept in case both are seen.
+    processes
+        .iter()
+        .filter(|p| p.comm.starts_with(SHIM_COMM_PREFIX))
+        .filter(|p| p.cmdline.split_whitespace().any(|arg| arg == sandbox_id))
+        .max_by_key(|p| p.start_ticks)
+}
+
+// A hypervisor left behind by a crashed shim still uses the resources of
+// the sandbox.
+fn sandbox_is_alive(sandbox_id: &str, processes: &[ProcessInfo]) -> bool {
+    find_shim(sandbox_id, processes).is_some()
+        || processes
+            .iter()
+            .any(|p| hypervisor_name(&p.comm).is_some() && p.cmdline.contains(sandbox_id))
+}
+
+fn get_sandbox_info(
+    sandbox_id: &str,
+    processes: &[ProcessInfo],
+    boot_uptime: f64,
+    ticks_per_sec: u64,
+) -> SandboxInfo {
+    let shim = match find_shim(sandbox_id, processes) {
+        Some(shim) => shim,
+        None => {
+            return SandboxInfo {
+                sandbox_id: sandbox_id.to_string(),
+                hypervisor: None,
+                state: SandboxState::Dead,
+                shim_pid: None,
+                uptime_secs: None,
+                details: None,
+            }
+        }
+    };
+
+    let hypervisor = processes
+        .iter()
+        .filter(|p| p.cmdline.contains(sandbox_id))
+        .find_map(|p| hypervisor_name(&p.comm))
+        .unwrap_or(BUILTIN_HYPERVISOR);
+
+    SandboxInfo {
+        sandbox_id: sandbox_id.to_string(),
+        hypervisor: Some(hypervisor.to_string()),
+        state: SandboxState::Running,
+        shim_pid: Some(shim.pid),
+        uptime_secs: process_age_secs(shim, boot_uptime, ticks_per_sec),
+        details: None,
+    }
+}
+
+// The time since a process started, in seconds.
+fn process_age_secs(process: &ProcessInfo, boot_uptime: f64, ticks_per_sec: u64) -> Option<u64> {
+    if ticks_per_sec == 0 {
+        return None;
+    }
+
+    let started = process.start_ticks as f64 / ticks_per_sec as f64;
+    Some((boot_uptime - started).max(0.0) as u64)
+}
+
+// A VM of Kata is run by a configured hypervisor binary, given the sockets
+// of its VM directory, unlike the other VMs of the host.
+fn is_kata_vm(process: &ProcessInfo, hypervisor_paths: &[String]) -> bool {
+    let binary = process
+        .cmdline
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+
+    hypervisor_paths.iter().any(|path| path == binary)
+        && KATA_VM_PATHS
+            .iter()
+            .any(|dir| process.cmdline.contains(&format!("{}/", dir)))
+}
+
+// The Kata VMs of no sandbox, any other hypervisor running a sandbox having
+// the sandbox ID on its command line.
+fn find_idle_vms(
+    sandbox_ids: &BTreeSet<String>,
+    hypervisor_paths: &[String],
+    processes: &[ProcessInfo],
+    boot_uptime: f64,
+    ticks_per_sec: u64,
+) -> Vec<VmProcess> {
+    processes
+        .iter()
+        .filter(|p| !sandbox_ids.iter().any(|id| p.cmdline.contains(id.as_str())))
+        .filter(|p| is_kata_vm(p, hypervisor_paths))
+        .filter_map(|p| {
+            hypervisor_name(&p.comm).map(|hypervisor| VmProcess {
+                pid: p.pid,
+                hypervisor: hypervisor.to_string(),
+                state: process_state_name(p.state).to_string(),
+                age_secs: process_age_secs(p, boot_uptime, ticks_per_sec),
+            })
+        })
+        .collect()
+}
+
+fn read_boot_uptime() -> Result<f64> {
+    let uptime = fs::read_to_string(PROC_UPTIME)
+        .with_context(|| format!("failed to read {:?}", PROC_UPTIME))?;
+
+    parse_proc_uptime(&uptime)
+}
+
+pub fn list_idle_vms(config_file: Option<&str>) -> Result<Vec<VmProcess>> {
+    let (toml_config, _) = utils::load_config(config_file)?;
+    let hypervisor_paths: Vec<String> = toml_config
+        .hypervisor
+        .values()
+        .map(|hypervisor| hypervisor.path.clone())
+        .filter(|path| !path.is_empty())
+        .collect();
+    let ids = utils::list_sandbox_ids(utils::SANDBOX_STATE_PATHS)?;
+    let processes = list_processes()?;
+    let ticks_per_sec = sysconf(SysconfVar::CLK_TCK)?.unwrap_or(0) as u64;
+
+    Ok(find_idle_vms(
+        &ids,
+        &hypervisor_paths,
+        &processes,
+        read_boot_uptime()?,
+        ticks_per_sec,
+    ))
+}
+
+// The shim management socket is in the state directory of the sandbox.
+fn find_shim_socket(sandbox_id: &str, state_paths: &[&str]) -> Option<String> {
+    state_paths
+        .iter()
+        .map(|dir| Path::new(dir).join(sandbox_id).join(SHIM_MGMT_SOCK_NAME))
+        .find(|path| path.exists())
+        .map(|path| path.display().to_string())
+}
+
+// Several sandboxes usually share a hypervisor binary, which is only asked
+// its version once.
+fn get_sandbox_details(
+    sandbox_id: &str,
+    versions: &mut BTreeMap<String, Option<String>>,
+) -> SandboxDetails {
+    let socket = find_shim_socket(sandbox_id, utils::SANDBOX_STATE_PATHS);
+    let inspection = match inspect_sandbox(sandbox_id) {
+        Ok(inspection) => inspection,
+        Err(_) => {
+            return SandboxDetails {
+                socket,
+                ..Default::default()
+            }
+        }
+    };
+
+    let hypervisor_version = inspection.hypervisor_path.and_then(|path| {
+        versions
+            .entry(path)
+            .or_insert_with_key(|path| {
+                get_command_version(path)
+                    .ok()
+                    .and_then(|output| parse_command_version(&output))
+            })
+            .clone()
+    });
+
+    SandboxDetails {
+        hypervisor_version,
+        kernel: inspection.kernel,
+        memory_mb: inspection.memory_mb,
+        vcpus: inspection.vcpus,
+        socket,
+    }
+}
+
+pub fn list_sandboxes() -> Result<Vec<SandboxInfo>> {
+    let ids = utils::list_sandbox_ids(utils::SANDBOX_STATE_PATHS)?;
+    let processes = list_processes()?;
+    let boot_uptime = read_boot_uptime()?;
+    let ticks_per_sec = sysconf(SysconfVar::CLK_TCK)?.unwrap_or(0) as u64;
+
+    Ok(ids
+        .iter()
+        .map(|id| get_sandbox_info(id, &processes, boot_uptime, ticks_per_sec))
+        .collect())
+}
+
+// Get the sandboxes among ids that still have a shim or hypervisor process.
+pub fn find_live_sandboxes(ids: &BTreeSet<String>) -> Result<BTreeSet<String>> {
+    let processes = list_processes()?;
+
+    Ok(ids
+        .iter()
+        .filter(|id| sandbox_is_alive(id, &processes))
+        .cloned()
+        .collect())
+}
+
+pub fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes, secs) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+
+    if days > 0 {
+        format!("{}d{}h{}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h{}m{}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m{}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// The sandboxes are a list, so the human output is already a table.
+fn sandboxes_table(sandboxes: &[SandboxInfo], wide: bool, truncate: bool) -> Table {
+    let mut headers = vec!["SANDBOX ID", "HYPERVISOR", "STATE", "UPTIME", "SHIM PID"];
+    if wide {
+        headers.extend(["HYPERVISOR VERSION", "KERNEL", "MEMORY", "VCPUS", "SOCKET"]);
+    }
+    let mut table = Table::new(&headers);
+
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    for sandbox in sandboxes {
+        let mut row = vec![
+            sandbox.sandbox_id.clone(),
+            or_dash(sandbox.hypervisor.clone()),
+            sandbox.state.painted_name(),
+            or_dash(sandbox.uptime_secs.map(format_uptime)),
+            or_dash(sandbox.shim_pid.map(|pid| pid.to_string())),
+        ];
+        if wide {
+            let details = sandbox.details.as_ref();
+            row.extend(vec![
+                or_dash(details.and_then(|d| d.hypervisor_version.clone())),
+                or_dash(details.and_then(|d| d.kernel.clone())),
+                or_dash(details.and_then(|d| d.memory_mb.map(|mb| format!("{}M", mb)))),
+                or_dash(details.and_then(|d| d.vcpus.map(|vcpus| vcpus.to_string()))),
+                or_dash(details.and_then(|d| d.socket.clone())),
+            ]);
+        }
+        table.add_row(row);
+    }
+
+    if wide && truncate {
+        table.set_max_width(5, MAX_VERSION_WIDTH, Truncate::End);
+        table.set_max_width(6, MAX_PATH_WIDTH, Truncate::Start);
+        table.set_max_width(9, MAX_PATH_WIDTH, Truncate::Start);
+    }
+
+    table
+}
+
+pub fn handle_ps(args: PsArgument, format: OutputFormat) -> Result<(), KataCtlError> {
+    let mut sandboxes = list_sandboxes()?;
+
+    if args.wide {
+        let mut versions = BTreeMap::new();
+        for sandbox in sandboxes.iter_mut() {
+            sandbox.details = Some(get_sandbox_details(&sandbox.sandbox_id, &mut versions));
+        }
+    }
+
+    if !output::print_structured(format.with_json(args.json), &sandboxes)? {
+        let table = sandboxes_table(&sandboxes, args.wide, output::stdout_is_tty());
+        output::print_table(format, &table);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_stat_start_ticks() {
+        let stat = "1234 (containerd-shim) S 1 1234 1234 0 -1 4194560 1000 0 0 0 \
+                    15 25 0 0 20 0 10 0 98765 1000000 500 18446744073709551615";
+        assert_eq!(parse_proc_stat_start_ticks(stat).unwrap(), 98765);
+
+        let stat = "1234 (a (b) c) S 1 1234 1234 0 -1 4194560 1000 0 0 0 \
+                    15 25 0 0 20 0 10 0 42 1000000";
+        assert_eq!(parse_proc_stat_start_ticks(stat).unwrap(), 42);
+
+        assert!(parse_proc_stat_start_ticks("1234 (shim) S 1 2").is_err());
+        assert!(parse_proc_stat_start_ticks("no parenthesis").is_err());
+
+        assert_eq!(parse_proc_stat_state("1234 (a (b) c) T 1 1234"), Some('T'));
+        assert_eq!(parse_proc_stat_state("no parenthesis"), None);
+    }
+
+    #[test]
+    fn test_find_idle_vms() {
+        let process = |pid, comm: &str, cmdline: &str, state| ProcessInfo {
+            pid,
+            comm: comm.to_string(),
+            cmdline: cmdline.to_string(),
+            state,
+            start_ticks: 1000,
+        };
+        let processes = vec![
+            process(
+                10,
+                "containerd-shim",
+                "containerd-shim-kata-v2 -id sb1",
+                'S',
+            ),
+            process(
+                11,
+                "qemu-system-x86",
+                "qemu-system-x86_64 -name sandbox-sb1",
+                'S',
+            ),
+            process(
+                20,
+                "qemu-system-x86",
+                "/usr/bin/qemu-system-x86_64 -name vm-1 -qmp unix:/run/vc/vm/vm-1/qmp.sock,server=on",
+                'S',
+            ),
+            process(
+                21,
+                "cloud-hypervisor",
+                "/usr/bin/cloud-hypervisor --api-socket /run/kata/vm-2/clh-api.sock",
+                'T',
+            ),
+            // VMs which are not Kata's
+            process(
+                22,
+                "qemu-system-x86",
+                "/usr/bin/qemu-system-x86_64 -name other -qmp unix:/tmp/qmp.sock",
+                'S',
+            ),
+            process(
+                23,
+                "qemu-system-x86",
+                "/opt/qemu/bin/qemu-system-x86_64 -qmp unix:/run/vc/vm/vm-3/qmp.sock",
+                'S',
+            ),
+            process(30, "bash", "bash", 'R'),
+        ];
+        let ids: BTreeSet<String> = vec!["sb1".to_string()].into_iter().collect();
+        let paths = vec![
+            "/usr/bin/qemu-system-x86_64".to_string(),
+            "/usr/bin/cloud-hypervisor".to_string(),
+        ];
+
+        assert_eq!(
+            find_idle_vms(&ids, &paths, &processes, 30.0, 100),
+            vec![
+                VmProcess {
+                    pid: 20,
+                    hypervisor: "qemu".to_string(),
+                    state: "sleeping".to_string(),
+                    age_secs: Some(20),
+                },
+                VmProcess {
+                    pid: 21,
+                    hypervisor: "cloud-hypervisor".to_string(),
+                    state: "stopped".to_string(),
+                    age_secs: Some(20),
+                },
+            ]
+        );
+        assert_eq!(
+            find_idle_vms(&ids, &paths, &processes, 30.0, 0)[0].age_secs,
+            None
+        );
+        assert!(find_idle_vms(&ids, &[], &processes, 30.0, 100).is_empty());
+    }
+
+    #[test]
+    fn test_parse_proc_uptime() {
+        assert_eq!(
+            parse_proc_uptime("350735.47 234388.90\n").unwrap(),
+            350735.47
+        );
+        assert!(parse_proc_uptime("").is_err());
+        assert!(parse_proc_uptime("x 1").is_err());
+    }
+
+    #[test]
+    fn test_get_sandbox_info() {
+        let process = |pid, comm: &str, cmdline: &str, start_ticks| ProcessInfo {
+            pid,
+            comm: comm.to_string(),
+            cmdline: cmdline.to_string(),
+            state: 'S',
+            start_ticks,
+        };
+        let processes = vec![
+            process(
+                10,
+                "containerd-shim",
+                "containerd-shim-kata-v2 -id sb1 start",
+                100,
+            ),
+            process(
+                11,
+                "containerd-shim",
+                "containerd-shim-kata-v2 -id sb1",
+                200,
+            ),
+            process(
+                12,
+                "qemu-system-x86",
+                "qemu-system-x86_64 -name sandbox-sb1",
+                300,
+            ),
+            process(
+                20,
+                "containerd-shim",
+                "containerd-shim-kata-v2 -id sb2",
+                1000,
+            ),
+            process(30, "bash", "bash -c sb3", 100),
+        ];
+
+        let info = get_sandbox_info("sb1", &processes, 30.0, 100);
+        assert_eq!(
+            info,
+            SandboxInfo {
+                sandbox_id: "sb1".to_string(),
+                hypervisor: Some("qemu".to_string()),
+                state: SandboxState::Running,
+                shim_pid: Some(11),
+                uptime_secs: Some(28),
+                details: None,
+            }
+        );
+
+        let info = get_sandbox_info("sb2", &processes, 30.0, 100);
+        assert_eq!(info.hypervisor.as_deref(), Some(BUILTIN_HYPERVISOR));
+        assert_eq!(info.shim_pid, Some(20));
+        assert_eq!(info.uptime_secs, Some(20));
+
+        // not a shim, and the ID must match a whole argument
+        for id in ["sb3", "sb"] {
+            let info = get_sandbox_info(id, &processes, 30.0, 100);
+            assert_eq!(info.state, SandboxState::Dead, "{}", id);
+            assert_eq!(info.hypervisor, None, "{}", id);
+            assert_eq!(info.shim_pid, None, "{}", id);
+            assert_eq!(info.uptime_secs, None, "{}", id);
+        }
+
+        // the hypervisor of a sandbox whose shim crashed keeps it alive
+        let processes = vec![process(
+            12,
+            "qemu-system-x86",
+            "qemu-system-x86_64 -name sandbox-sb4",
+            300,
+        )];
+        assert!(sandbox_is_alive("sb4", &processes));
+        assert!(!sandbox_is_alive("sb5", &processes));
+    }
+
+    #[test]
+    fn test_sandboxes_table() {
+        let sandboxes = vec![
+            SandboxInfo {
+                sandbox_id: "sb1".to_string(),
+                hypervisor: Some("qemu".to_string()),
+                state: SandboxState::Running,
+                shim_pid: Some(11),
+                uptime_secs: Some(28),
+                details: Some(SandboxDetails {
+                    hypervisor_version: Some(
+                        "QEMU emulator version 7.2.0 (kata-static)".to_string(),
+                    ),
+                    kernel: Some("/opt/kata/share/kata-containers/vmlinux.container".to_string()),
+                    memory_mb: Some(2048),
+                    vcpus: Some(1),
+                    socket: None,
+                }),
+            },
+            SandboxInfo {
+                sandbox_id: "sb2".to_string(),
+                hypervisor: None,
+                state: SandboxState::Dead,
+                shim_pid: None,
+                uptime_secs: None,
+                details: None,
+            },
+        ];
+
+        let table = sandboxes_table(&sandboxes, false, true).to_string();
+        assert_eq!(table.lines().count(), 3);
+        assert!(!table.contains("KERNEL"), "{}", table);
+
+        let table = sandboxes_table(&sandboxes, true, true).to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].ends_with("MEMORY  VCPUS  SOCKET"), "{}", table);
+        // the version and the paths are truncated
+        assert!(
+            lines[1].contains("  QEMU emulator version 7…  "),
+            "{}",
+            table
+        );
+        assert!(
+            lines[1].contains("  …share/kata-containers/vmlinux.container  2048M "),
+            "{}",
+            table
+        );
+        assert!(lines[2].starts_with("sb2 "), "{}", table);
+
+        // without a terminal, nothing is truncated
+        let table = sandboxes_table(&sandboxes, true, false).to_string();
+        assert!(table.contains("(kata-static)"), "{}", table);
+        assert!(table.contains("/opt/kata/share"), "{}", table);
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        let tests = &[
+            (0, "0s"),
+            (59, "59s"),
+            (61, "1m1s"),
+            (3600, "1h0m0s"),
+            (3725, "1h2m5s"),
+            (90061, "1d1h1m"),
+        ];
+
+        for (secs, expected) in tests {
+            assert_eq!(format_uptime(*secs), *expected, "{}", secs);
+        }
+    }
+}
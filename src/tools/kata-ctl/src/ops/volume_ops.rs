@@ -3,17 +3,38 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::args::{DirectVolSubcommand, DirectVolumeCommand};
+use crate::args::{
+    DirectVolAddArgs, DirectVolResizeArgs, DirectVolStatsArgs, DirectVolSubcommand,
+    DirectVolumeCommand, OutputFormat, RetryArgs,
+};
+use crate::error::KataCtlError;
+use crate::ops::metrics_ops::{self, WatchScreen};
+use crate::ops::mounts_ops::{self, GuestMount};
+use crate::ops::{exec_ops, inspect_ops};
+use crate::output::{self, Table};
+use crate::utils;
 
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, Context, Ok, Result};
 use futures::executor;
 use kata_types::mount::{
     DirectVolumeMountInfo, KATA_DIRECT_VOLUME_ROOT_PATH, KATA_MOUNT_INFO_FILE_NAME,
 };
-use nix;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::stat::{major, minor};
 use reqwest::StatusCode;
 use safe_path;
-use std::{fs, path::PathBuf, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    future::Future,
+    io::{self, Write},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use url;
 
 use agent::ResizeVolumeRequest;
@@ -25,42 +46,362 @@ use shim_interface::shim_mgmt::{
 const TIMEOUT: Duration = Duration::from_millis(2000);
 const CONTENT_TYPE_JSON: &str = "application/json";
 
-pub fn handle_direct_volume(vol_cmd: DirectVolumeCommand) -> Result<()> {
-    if !nix::unistd::Uid::effective().is_root() {
-        return Err(anyhow!(
-            "super-user privileges are required for the direct-volume subcommand"
-        ));
+// Upper bound of the delay between two attempts of a request to the runtime.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+const SYS_CLASS_BLOCK_PATH: &str = "/sys/class/block";
+const SECTOR_SIZE: u64 = 512;
+
+// Interval between two checks of whether an added volume is ready.
+const VOLUME_WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+// Growing a large filesystem takes a while, in particular for ext4.
+const GUEST_FS_GROW_TIMEOUT: Duration = Duration::from_secs(300);
+
+// The guest sees the new size of a resized device some time after the
+// runtime returns.
+const GUEST_RESIZE_TIMEOUT: Duration = Duration::from_secs(30);
+const GUEST_RESIZE_INTERVAL: Duration = Duration::from_secs(1);
+
+// The lock file of the volumes, in their root directory. Being a file, it is
+// not mistaken for a volume.
+const VOLUME_LOCK_FILE_NAME: &str = ".kata-ctl.lock";
+const VOLUME_LOCK_INTERVAL: Duration = Duration::from_millis(50);
+
+// VolumeLock is a flock of the volumes, released when it is dropped. The
+// commands changing the volumes hold it exclusively, the ones reading them
+// shared, so that the volumes added or removed by concurrent kata-ctl
+// processes, e.g. of a CSI driver, are not lost.
+struct VolumeLock {
+    _file: fs::File,
+}
+
+impl VolumeLock {
+    fn acquire(root_path: &Path, exclusive: bool, timeout: Duration) -> Result<Self, KataCtlError> {
+        let lock_path = root_path.join(VOLUME_LOCK_FILE_NAME);
+        let file = fs::create_dir_all(root_path)
+            .and_then(|_| {
+                fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(false)
+                    .open(&lock_path)
+            })
+            .map_err(|e| anyhow!("failed to open {}: {}", lock_path.display(), e))?;
+
+        let arg = if exclusive {
+            FlockArg::LockExclusiveNonblock
+        } else {
+            FlockArg::LockSharedNonblock
+        };
+
+        // Polling rather than blocking in flock() bounds the wait.
+        let deadline = Instant::now() + timeout;
+        loop {
+            match flock(file.as_raw_fd(), arg) {
+                std::result::Result::Ok(()) => {
+                    return std::result::Result::Ok(VolumeLock { _file: file })
+                }
+                Err(Errno::EWOULDBLOCK) if Instant::now() < deadline => {
+                    thread::sleep(VOLUME_LOCK_INTERVAL)
+                }
+                Err(Errno::EWOULDBLOCK) => {
+                    return Err(KataCtlError::Timeout(anyhow!(
+                        "timed out after {}s waiting for {}, locked by another kata-ctl",
+                        timeout.as_secs(),
+                        lock_path.display()
+                    )))
+                }
+                Err(e) => {
+                    return Err(KataCtlError::Runtime(anyhow!(
+                        "failed to lock {}: {}",
+                        lock_path.display(),
+                        e
+                    )))
+                }
+            }
+        }
     }
+}
+
+pub fn handle_direct_volume(
+    vol_cmd: DirectVolumeCommand,
+    format: OutputFormat,
+) -> Result<(), KataCtlError> {
     let command = vol_cmd.directvol_cmd;
+    let lock_timeout = Duration::from_secs(vol_cmd.lock_timeout);
+
+    // Validating the mount info does not touch any host state, so it does
+    // not need elevated privileges.
+    if let DirectVolSubcommand::Add(args) = &command {
+        if args.validate_only {
+            let mount_info = read_mount_info(args)?;
+            parse_mount_info(&mount_info).map_err(KataCtlError::Config)?;
+            println!("mount info is valid");
+            return std::result::Result::Ok(());
+        }
+    }
+
+    // Nor do the dry runs, which change nothing, not even the lock file.
+    let dry_run = match &command {
+        DirectVolSubcommand::Add(args) => args.dry_run,
+        DirectVolSubcommand::Remove(args) => args.dry_run,
+        DirectVolSubcommand::Resize(args) => args.dry_run,
+        DirectVolSubcommand::List(_) | DirectVolSubcommand::Stats(_) => false,
+    };
+    if dry_run {
+        return run_direct_volume(command, format, None).map_err(KataCtlError::from);
+    }
+
+    utils::require_root("the direct-volume subcommand")?;
+
+    // Asked before locking the volumes, not to keep the others waiting.
+    if let DirectVolSubcommand::Remove(args) = &command {
+        if args.volume_path.is_none() {
+            utils::confirm("remove all the direct volumes")?;
+        }
+    }
+
+    let root_path = Path::new(KATA_DIRECT_VOLUME_ROOT_PATH);
+    let lock = match &command {
+        DirectVolSubcommand::Add(_) | DirectVolSubcommand::Remove(_) => {
+            Some(VolumeLock::acquire(root_path, true, lock_timeout)?)
+        }
+        DirectVolSubcommand::List(_) | DirectVolSubcommand::Resize(_) => {
+            Some(VolumeLock::acquire(root_path, false, lock_timeout)?)
+        }
+        DirectVolSubcommand::Stats(_) => None,
+    };
+
+    // Waiting for an added volume times out with its own exit code.
+    if let DirectVolSubcommand::Add(args) = &command {
+        if let Some(wait) = args.wait {
+            add(&args.volume_path, &read_mount_info(args)?)?;
+            // The other commands need not wait for the device.
+            drop(lock);
+            return wait_volume_ready(&args.volume_path, Duration::from_secs(wait));
+        }
+    }
+
+    // A volume removed during the watch is reported with its own exit code.
+    if let DirectVolSubcommand::Stats(args) = &command {
+        if args.watch {
+            return watch_stats(args, format.with_json(args.json));
+        }
+    }
+
+    // The volume is only read under the lock, which is not held through the
+    // resize and the growing of the filesystem, like for add --wait.
+    if let DirectVolSubcommand::Resize(args) = &command {
+        let sandbox_id = get_sandbox_id_for_volume(&args.volume_path)?;
+        let mount_info = get_volume_mount_info(&args.volume_path)?;
+        drop(lock);
+
+        // Growing the filesystem goes through the debug console, whose
+        // errors keep their own exit codes.
+        if args.fs_grow {
+            return resize_and_grow_fs(args, &sandbox_id, &mount_info);
+        }
+        return executor::block_on(resize(
+            &sandbox_id,
+            &mount_info,
+            args.resize_size,
+            &args.retry,
+        ))
+        .map_err(KataCtlError::from);
+    }
+
+    run_direct_volume(command, format, lock).map_err(KataCtlError::from)
+}
+
+fn run_direct_volume(
+    command: DirectVolSubcommand,
+    format: OutputFormat,
+    lock: Option<VolumeLock>,
+) -> Result<()> {
     let cmd_result: Option<String> = match command {
-        DirectVolSubcommand::Add(args) => add(&args.volume_path, &args.mount_info)?,
-        DirectVolSubcommand::Remove(args) => remove(&args.volume_path)?,
-        DirectVolSubcommand::Stats(args) => executor::block_on(stats(&args.volume_path))?,
+        DirectVolSubcommand::Add(args) if args.dry_run => {
+            dry_run_add(&args.volume_path, &read_mount_info(&args)?)?
+        }
+        DirectVolSubcommand::Add(args) => add(&args.volume_path, &read_mount_info(&args)?)?,
+        DirectVolSubcommand::Remove(args) => match args.volume_path {
+            Some(volume_path) if args.dry_run => dry_run_remove(&volume_path)?,
+            Some(volume_path) => remove(&volume_path)?,
+            None => remove_all(Path::new(KATA_DIRECT_VOLUME_ROOT_PATH), args.dry_run)?,
+        },
+        DirectVolSubcommand::Stats(args) => {
+            let format = format.with_json(args.json);
+            // The structured outputs are for machines, which want bytes.
+            if args.human && format.is_structured() {
+                eprintln!("WARNING: --human is ignored by the JSON and YAML outputs");
+            }
+            executor::block_on(stats(
+                &args.volume_path,
+                format,
+                args.human,
+                args.vport,
+                &args.retry,
+            ))?
+        }
+        // The volume is only resized by handle_direct_volume, out of the lock.
         DirectVolSubcommand::Resize(args) => {
-            executor::block_on(resize(&args.volume_path, args.resize_size))?
+            dry_run_resize(&args.volume_path, args.resize_size, args.fs_grow)?
         }
+        DirectVolSubcommand::List(args) => list(
+            format.with_json(args.json),
+            args.guest_devices.then_some(args.vport),
+            lock,
+        )?,
     };
-    if let Some(cmd_result) = cmd_result {
-        println!("{:?}", cmd_result);
+    // The CSV output already ends with the CRLF of its last record.
+    match cmd_result {
+        Some(cmd_result) if cmd_result.ends_with('\n') => print!("{}", cmd_result),
+        Some(cmd_result) => println!("{}", cmd_result),
+        None => {}
     }
 
     Ok(())
 }
 
-async fn resize(volume_path: &str, size: u64) -> Result<Option<String>> {
+// The dry run functions validate their inputs like the real operations, then
+// describe what would be done without changing anything or contacting the
+// runtime.
+fn dry_run_add(volume_path: &str, mount_info: &str) -> Result<Option<String>> {
+    let mount_info = parse_mount_info(mount_info)?;
+    let mount_info_file_path =
+        join_path(KATA_DIRECT_VOLUME_ROOT_PATH, volume_path)?.join(KATA_MOUNT_INFO_FILE_NAME);
+
+    Ok(Some(format!(
+        "would add volume {} by writing {}:\n{}",
+        volume_path,
+        mount_info_file_path.display(),
+        serde_json::to_string_pretty(&mount_info)?
+    )))
+}
+
+fn dry_run_remove(volume_path: &str) -> Result<Option<String>> {
+    let mount_info_dir_path = join_path(KATA_DIRECT_VOLUME_ROOT_PATH, volume_path)?;
+    if !mount_info_dir_path.exists() {
+        return Err(anyhow!("volume {} has not been added", volume_path));
+    }
+
+    Ok(Some(format!(
+        "would remove volume {} by deleting {}",
+        volume_path,
+        mount_info_dir_path.display()
+    )))
+}
+
+// Get the current size of a host block device from sysfs.
+fn get_block_device_size(device: &str) -> Result<u64> {
+    let device_path = fs::canonicalize(device)?;
+    let name = device_path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid device {}", device))?;
+    let sectors = fs::read_to_string(Path::new(SYS_CLASS_BLOCK_PATH).join(name).join("size"))?;
+
+    Ok(sectors.trim().parse::<u64>()? * SECTOR_SIZE)
+}
+
+fn format_resize_delta(current_size: Option<u64>, size: u64) -> String {
+    match current_size {
+        Some(current) if size >= current => {
+            format!("from {} bytes (+{} bytes)", current, size - current)
+        }
+        Some(current) => format!("from {} bytes (-{} bytes)", current, current - size),
+        None => String::from("from an unknown size"),
+    }
+}
+
+fn dry_run_resize(volume_path: &str, size: u64, fs_grow: bool) -> Result<Option<String>> {
     let sandbox_id = get_sandbox_id_for_volume(volume_path)?;
     let mount_info = get_volume_mount_info(volume_path)?;
+    let current_size = get_block_device_size(&mount_info.device).ok();
+
+    let mut result = format!(
+        "would resize volume {} (device {}, sandbox {}) to {} bytes, {}",
+        volume_path,
+        mount_info.device,
+        sandbox_id,
+        size,
+        format_resize_delta(current_size, size)
+    );
+    if fs_grow {
+        let tool = get_fs_grow_tool(&mount_info.fs_type)?;
+        result.push_str(&format!(
+            ", then grow its {} filesystem with {}",
+            mount_info.fs_type,
+            tool.name()
+        ));
+    }
+
+    Ok(Some(result))
+}
+
+// The shim is not reachable until it has created its management socket, on
+// which it may then not be listening yet. Any other error, and in particular
+// a request rejected by the runtime, is final.
+fn is_transient_rpc_error(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|cause| match cause.downcast_ref::<io::Error>() {
+            Some(err) => matches!(
+                err.kind(),
+                io::ErrorKind::NotFound
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            ),
+            None => matches!(cause.downcast_ref::<hyper::Error>(), Some(err) if err.is_connect()),
+        })
+}
+
+// Run a request to the runtime, retrying it with an exponential backoff
+// while the runtime cannot be reached.
+async fn retry_rpc<T, F, Fut>(retry: &RetryArgs, mut rpc: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut interval = Duration::from_millis(retry.retry_interval);
+    let mut attempts = 1;
+
+    loop {
+        let e = match rpc().await {
+            Err(e) => e,
+            result => return result,
+        };
+
+        if !is_transient_rpc_error(&e) {
+            return Err(e);
+        }
+
+        if attempts > retry.retries {
+            return Err(e.context(format!("runtime unreachable after {} attempts", attempts)));
+        }
+
+        // The commands run on a single threaded executor, so blocking it
+        // delays nothing else.
+        thread::sleep(interval);
+        interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+        attempts += 1;
+    }
+}
+
+async fn resize(
+    sandbox_id: &str,
+    mount_info: &DirectVolumeMountInfo,
+    size: u64,
+    retry: &RetryArgs,
+) -> Result<()> {
     let resize_req = ResizeVolumeRequest {
         size,
-        volume_guest_path: mount_info.device,
+        volume_guest_path: mount_info.device.clone(),
     };
     let encoded = serde_json::to_string(&resize_req)?;
-    let shim_client = MgmtClient::new(&sandbox_id, Some(TIMEOUT))?;
+    let shim_client = MgmtClient::new(sandbox_id, Some(TIMEOUT))?;
 
     let url = DIRECT_VOLUME_RESIZE_URL;
-    let response = shim_client
-        .post(url, &String::from(CONTENT_TYPE_JSON), &encoded)
-        .await?;
+    let response = retry_rpc(retry, || shim_client.post(url, CONTENT_TYPE_JSON, &encoded)).await?;
     let status = response.status();
     if status != StatusCode::OK {
         let body = format!("{:?}", response.into_body());
@@ -71,10 +412,281 @@ async fn resize(volume_path: &str, size: u64) -> Result<Option<String>> {
         ));
     }
 
-    Ok(None)
+    Ok(())
 }
 
-async fn stats(volume_path: &str) -> Result<Option<String>> {
+// The filesystems which can be grown while they are mounted, by the guest
+// tools growing them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FsGrowTool {
+    Resize2fs,
+    XfsGrowfs,
+}
+
+impl FsGrowTool {
+    fn name(self) -> &'static str {
+        match self {
+            FsGrowTool::Resize2fs => "resize2fs",
+            FsGrowTool::XfsGrowfs => "xfs_growfs",
+        }
+    }
+
+    // resize2fs takes the device of the filesystem, xfs_growfs its mount
+    // point.
+    fn command(self, mount: &GuestMount) -> Vec<String> {
+        let arg = match self {
+            FsGrowTool::Resize2fs => &mount.source,
+            FsGrowTool::XfsGrowfs => &mount.target,
+        };
+
+        vec![self.name().to_string(), arg.clone()]
+    }
+}
+
+fn get_fs_grow_tool(fs_type: &str) -> Result<FsGrowTool> {
+    match fs_type {
+        "ext2" | "ext3" | "ext4" => Ok(FsGrowTool::Resize2fs),
+        "xfs" => Ok(FsGrowTool::XfsGrowfs),
+        _ => Err(anyhow!(
+            "filesystem type {:?} cannot be grown online, only ext2, ext3, ext4 and xfs can",
+            fs_type
+        )),
+    }
+}
+
+// Run a command in the guest through the debug console, failing unless it
+// succeeds.
+fn run_guest(
+    sandbox_id: &str,
+    vport: u32,
+    command: &[String],
+) -> std::result::Result<String, KataCtlError> {
+    let result = exec_ops::run_guest_command(
+        sandbox_id,
+        vport,
+        None,
+        command,
+        Some(GUEST_FS_GROW_TIMEOUT),
+    )?;
+    if result.exit_code != 0 {
+        return Err(KataCtlError::Runtime(anyhow!(
+            "{} failed in the guest (exit status {}): {}",
+            command.join(" "),
+            result.exit_code,
+            result.output.trim()
+        )));
+    }
+
+    std::result::Result::Ok(result.output)
+}
+
+// The guest mounts which may be the volume: the block devices with its
+// filesystem type, each device only once as it may also be bind mounted.
+fn volume_mount_candidates(mounts: Vec<GuestMount>, fs_type: &str) -> Vec<GuestMount> {
+    let mut candidates: Vec<GuestMount> = Vec::new();
+    for mount in mounts {
+        if mount.fstype != fs_type || !mount.source.starts_with("/dev/") {
+            continue;
+        }
+        if candidates.iter().any(|c| c.source == mount.source) {
+            continue;
+        }
+        candidates.push(mount);
+    }
+
+    candidates
+}
+
+// The device of the volume has another name in the guest, so its mount is
+// found as the candidate whose device has the size the volume was resized to.
+fn find_guest_volume_mount(
+    sandbox_id: &str,
+    vport: u32,
+    fs_type: &str,
+    size: u64,
+) -> std::result::Result<GuestMount, KataCtlError> {
+    let command = vec!["cat".to_string(), mounts_ops::GUEST_MOUNTS_FILE.to_string()];
+    let mounts = mounts_ops::parse_mounts(&run_guest(sandbox_id, vport, &command)?)?;
+
+    let mut found: Option<GuestMount> = None;
+    for mount in volume_mount_candidates(mounts, fs_type) {
+        let name = match Path::new(&mount.source).file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let size_path = Path::new(SYS_CLASS_BLOCK_PATH).join(name).join("size");
+        let command = vec!["cat".to_string(), size_path.display().to_string()];
+
+        // The devices without a sysfs entry, e.g. device mapper names, are
+        // not volumes.
+        let result = exec_ops::run_guest_command(
+            sandbox_id,
+            vport,
+            None,
+            &command,
+            Some(GUEST_FS_GROW_TIMEOUT),
+        )?;
+        let sectors = match result.output.trim().parse::<u64>() {
+            std::result::Result::Ok(sectors) if result.exit_code == 0 => sectors,
+            _ => continue,
+        };
+        if sectors * SECTOR_SIZE != size {
+            continue;
+        }
+
+        if let Some(found) = &found {
+            return Err(KataCtlError::Runtime(anyhow!(
+                "cannot tell the guest mount of the volume from {} ({}) and {} ({}), both {} filesystems of {} bytes",
+                found.target,
+                found.source,
+                mount.target,
+                mount.source,
+                fs_type,
+                size
+            )));
+        }
+        found = Some(mount);
+    }
+
+    found.ok_or_else(|| {
+        KataCtlError::NotFound(anyhow!(
+            "no {} filesystem of {} bytes is mounted in the guest of sandbox {}",
+            fs_type,
+            size,
+            sandbox_id
+        ))
+    })
+}
+
+// The size of a filesystem from the POSIX output of 'df -P -k': a header,
+// then the filesystem, its size in KiB being the second field.
+fn parse_df_size(output: &str) -> Result<u64> {
+    let size = output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|size| size.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("invalid df output {:?}", output))?;
+
+    Ok(size * 1024)
+}
+
+// Poll until ready returns true, for at most timeout.
+fn poll_until<F>(timeout: Duration, interval: Duration, mut ready: F) -> bool
+where
+    F: FnMut() -> bool,
+{
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if ready() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep(interval.min(deadline - now));
+    }
+}
+
+// A volume is ready once a sandbox uses it, the runtime having attached its
+// device, and the guest reports its stats, which it can only do once the
+// device is mounted.
+fn is_volume_ready(volume_path: &str) -> bool {
+    // Each poll is an attempt of its own.
+    let retry = RetryArgs {
+        retries: 0,
+        retry_interval: 0,
+    };
+
+    get_sandbox_id_for_volume(volume_path).is_ok()
+        && executor::block_on(get_volume_stats(volume_path, &retry)).is_ok()
+}
+
+fn wait_volume_ready(
+    volume_path: &str,
+    timeout: Duration,
+) -> std::result::Result<(), KataCtlError> {
+    let ready = poll_until(timeout, VOLUME_WAIT_INTERVAL, || {
+        is_volume_ready(volume_path)
+    });
+    if !ready {
+        // The volume stays added, the sandbox may still be starting.
+        return Err(KataCtlError::Timeout(anyhow!(
+            "volume {} was added but is not ready after {} seconds, run 'kata-ctl direct-volume stats {}' to check its status",
+            volume_path,
+            timeout.as_secs(),
+            volume_path
+        )));
+    }
+
+    println!(
+        "volume {} is ready in sandbox {}",
+        volume_path,
+        get_sandbox_id_for_volume(volume_path)?
+    );
+
+    std::result::Result::Ok(())
+}
+
+fn resize_and_grow_fs(
+    args: &DirectVolResizeArgs,
+    sandbox_id: &str,
+    mount_info: &DirectVolumeMountInfo,
+) -> std::result::Result<(), KataCtlError> {
+    // Fail before the block device is resized rather than leaving it larger
+    // than its filesystem.
+    let tool = get_fs_grow_tool(&mount_info.fs_type).map_err(KataCtlError::Config)?;
+
+    executor::block_on(resize(
+        sandbox_id,
+        mount_info,
+        args.resize_size,
+        &args.retry,
+    ))?;
+
+    // Until the guest sees the new size, no mount is found, the other errors
+    // not going away by polling.
+    let mut found = None;
+    poll_until(GUEST_RESIZE_TIMEOUT, GUEST_RESIZE_INTERVAL, || {
+        let result = find_guest_volume_mount(
+            sandbox_id,
+            args.vport,
+            &mount_info.fs_type,
+            args.resize_size,
+        );
+        let done = !matches!(result, Err(KataCtlError::NotFound(_)));
+        found = Some(result);
+        done
+    });
+    let mount = found.expect("the guest mounts were read at least once")?;
+    run_guest(sandbox_id, args.vport, &tool.command(&mount))?;
+
+    let command = vec![
+        "df".to_string(),
+        "-P".to_string(),
+        "-k".to_string(),
+        mount.target.clone(),
+    ];
+    let fs_size = parse_df_size(&run_guest(sandbox_id, args.vport, &command)?)?;
+
+    println!(
+        "resized volume {} to {} bytes, its filesystem mounted on {} in the guest is now {} ({} bytes)",
+        args.volume_path,
+        args.resize_size,
+        mount.target,
+        format_iec_size(fs_size),
+        fs_size
+    );
+
+    std::result::Result::Ok(())
+}
+
+// Get the stats of a volume as reported by the guest.
+async fn get_volume_stats(volume_path: &str, retry: &RetryArgs) -> Result<String> {
     let sandbox_id = get_sandbox_id_for_volume(volume_path)?;
     let mount_info = get_volume_mount_info(volume_path)?;
 
@@ -83,16 +695,311 @@ async fn stats(volume_path: &str) -> Result<Option<String>> {
         .finish();
 
     let shim_client = MgmtClient::new(&sandbox_id, Some(TIMEOUT))?;
-    let response = shim_client.get(&req_url).await?;
+    let response = retry_rpc(retry, || shim_client.get(&req_url)).await?;
+    let status = response.status();
+    if status != StatusCode::OK {
+        return Err(anyhow!("failed to get volume stats ({:?})", status));
+    }
+
     // turn body into string
-    let body = format!("{:?}", response.into_body());
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+
+    Ok(String::from_utf8(body.to_vec())?)
+}
+
+// VolumeStatsReport is the output of 'direct-volume stats', the usage of
+// the volume along with its devices.
+#[derive(Debug, Serialize)]
+struct VolumeStatsReport {
+    #[serde(flatten)]
+    stats: VolumeStats,
+    #[serde(flatten)]
+    devices: VolumeDevices,
+}
+
+async fn stats(
+    volume_path: &str,
+    format: OutputFormat,
+    human: bool,
+    vport: u32,
+    retry: &RetryArgs,
+) -> Result<Option<String>> {
+    let body = get_volume_stats(volume_path, retry).await?;
+
+    // The stats of a single volume are not a list, so the table format
+    // falls back to the raw output of the guest.
+    if format.is_structured() || human {
+        let mount_info = get_volume_mount_info(volume_path)?;
+        let sandbox_id = get_sandbox_id_for_volume(volume_path).ok();
+        let report = VolumeStatsReport {
+            stats: parse_volume_stats(&body)?,
+            devices: get_volume_devices(&mount_info, sandbox_id.as_deref(), vport),
+        };
+        if format.is_structured() {
+            return output::to_structured(format, &report);
+        }
+
+        let mut lines = vec![format_volume_stats(&report.stats)];
+        lines.extend(format_volume_devices(&report.devices));
+        return Ok(Some(lines.join("\n")));
+    }
 
     Ok(Some(body))
 }
 
+// VolumeStatsSample is a line of 'direct-volume stats --watch --json'.
+#[derive(Debug, Serialize)]
+struct VolumeStatsSample {
+    timestamp: u64,
+    #[serde(flatten)]
+    stats: VolumeStats,
+}
+
+// Write the stats reported by the guest as a line of the JSON stream.
+fn write_stats_sample(out: &mut dyn Write, body: &str) -> Result<()> {
+    let sample = VolumeStatsSample {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        stats: parse_volume_stats(body)?,
+    };
+    writeln!(out, "{}", serde_json::to_string(&sample)?)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+// The view of 'direct-volume stats --watch', like the one of watch(1).
+fn format_stats_watch_view(args: &DirectVolStatsArgs, body: &str) -> Result<String> {
+    let stats = if args.human {
+        format_volume_stats(&parse_volume_stats(body)?)
+    } else {
+        body.to_string()
+    };
+
+    Ok(format!(
+        "Every {}s: kata-ctl direct-volume stats {}\n\n{}\n",
+        args.interval, args.volume_path, stats
+    ))
+}
+
+fn volume_exists(root: &str, volume_path: &str) -> Result<bool> {
+    Ok(join_path(root, volume_path)?
+        .join(KATA_MOUNT_INFO_FILE_NAME)
+        .exists())
+}
+
+// The stats are queried again every interval, the human output redrawing a
+// terminal and the JSON output streaming one object per line. The volume
+// being removed ends the watch.
+fn watch_stats(args: &DirectVolStatsArgs, format: OutputFormat) -> Result<(), KataCtlError> {
+    match format {
+        OutputFormat::Yaml => {
+            return Err(KataCtlError::Config(anyhow!(
+                "--watch only supports the human and JSON outputs"
+            )))
+        }
+        OutputFormat::Human | OutputFormat::Table if !output::stdout_is_tty() => {
+            return Err(KataCtlError::Config(anyhow!(
+                "--watch needs the output to be a terminal, use --output json otherwise"
+            )))
+        }
+        _ => {}
+    }
+
+    let interval = Duration::from_secs(args.interval);
+    let query = |first: bool| -> Result<String, KataCtlError> {
+        if !first && !volume_exists(KATA_DIRECT_VOLUME_ROOT_PATH, &args.volume_path)? {
+            return Err(KataCtlError::NotFound(anyhow!(
+                "volume {} was removed, stopping the watch",
+                args.volume_path
+            )));
+        }
+
+        let body = executor::block_on(get_volume_stats(&args.volume_path, &args.retry))?;
+        std::result::Result::Ok(body)
+    };
+
+    // Without a terminal to restore, Ctrl-C just ends the stream.
+    if format == OutputFormat::Json {
+        let mut stdout = io::stdout();
+        let mut first = true;
+        loop {
+            write_stats_sample(&mut stdout, &query(first)?)?;
+            first = false;
+            thread::sleep(interval);
+        }
+    }
+
+    let screen = WatchScreen::new()?;
+    let mut first = true;
+    loop {
+        screen.render(&format_stats_watch_view(args, &query(first)?)?);
+        first = false;
+
+        if !metrics_ops::sleep_unless_interrupted(interval) {
+            return std::result::Result::Ok(());
+        }
+    }
+}
+
+const IEC_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+// Format a size with the largest IEC unit it has at least one of.
+fn format_iec_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < IEC_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} {}", bytes, IEC_UNITS[0]);
+    }
+
+    format!("{:.2} {}", size, IEC_UNITS[unit])
+}
+
+fn format_volume_stats(stats: &VolumeStats) -> String {
+    let mut lines = vec![
+        format!("total: {}", format_iec_size(stats.total)),
+        format!("used: {}", format_iec_size(stats.used)),
+        format!("available: {}", format_iec_size(stats.available)),
+    ];
+
+    let inodes = [
+        ("inodes total", stats.inodes_total),
+        ("inodes used", stats.inodes_used),
+        ("inodes available", stats.inodes_available),
+    ];
+    for (name, value) in inodes {
+        if let Some(value) = value {
+            lines.push(format!("{}: {}", name, value));
+        }
+    }
+
+    lines.join("\n")
+}
+
+// VolumeStats is the JSON representation of the usage of a direct volume,
+// following the CSI VolumeUsage capacity and inode reporting. The inode
+// fields are null when the guest did not report them.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct VolumeStats {
+    pub available: u64,
+    pub total: u64,
+    pub used: u64,
+    pub inodes_available: Option<u64>,
+    pub inodes_total: Option<u64>,
+    pub inodes_used: Option<u64>,
+}
+
+// VolumeUsage mirrors a single CSI VolumeUsage entry.
+#[derive(Debug, Default, Deserialize)]
+struct VolumeUsage {
+    #[serde(default)]
+    available: u64,
+    #[serde(default)]
+    total: u64,
+    #[serde(default)]
+    used: u64,
+    #[serde(default)]
+    unit: serde_json::Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct VolumeStatsResponse {
+    #[serde(default)]
+    usage: Vec<VolumeUsage>,
+}
+
+const CSI_UNIT_BYTES: &str = "BYTES";
+const CSI_UNIT_INODES: &str = "INODES";
+
+// CSI VolumeUsage.Unit enum values
+const CSI_UNIT_BYTES_VALUE: u64 = 1;
+const CSI_UNIT_INODES_VALUE: u64 = 2;
+
+impl VolumeUsage {
+    fn unit(&self) -> &str {
+        match &self.unit {
+            serde_json::Value::Number(n) if n.as_u64() == Some(CSI_UNIT_BYTES_VALUE) => {
+                CSI_UNIT_BYTES
+            }
+            serde_json::Value::Number(n) if n.as_u64() == Some(CSI_UNIT_INODES_VALUE) => {
+                CSI_UNIT_INODES
+            }
+            serde_json::Value::String(s) => s.as_str(),
+            _ => "",
+        }
+    }
+}
+
+// parse_text_volume_usage parses the protobuf text representation of the
+// usage returned by the runtime-rs shim, for example:
+// "Usage: [available: 10 total: 20 used: 10 unit: BYTES] Volume Condition: ..."
+// Fields holding a default value are omitted in this representation.
+fn parse_text_volume_usage(data: &str) -> Result<Vec<VolumeUsage>> {
+    let usage = data
+        .split_once("Usage: [")
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(usage, _)| usage)
+        .ok_or_else(|| anyhow!("unexpected volume stats format: {:?}", data))?;
+
+    let mut result = Vec::new();
+    for entry in usage.split(", ").filter(|e| !e.trim().is_empty()) {
+        let mut volume_usage = VolumeUsage::default();
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        for pair in fields.chunks(2) {
+            let (key, value) = match pair {
+                [key, value] => (key.trim_end_matches(':'), *value),
+                _ => return Err(anyhow!("invalid volume usage entry: {:?}", entry)),
+            };
+            match key {
+                "available" => volume_usage.available = value.parse()?,
+                "total" => volume_usage.total = value.parse()?,
+                "used" => volume_usage.used = value.parse()?,
+                "unit" => volume_usage.unit = serde_json::Value::from(value),
+                _ => {}
+            }
+        }
+        result.push(volume_usage);
+    }
+
+    Ok(result)
+}
+
+// parse_volume_stats converts the volume stats returned by the shim into
+// VolumeStats. The go runtime returns JSON, while runtime-rs returns the
+// protobuf text representation.
+pub fn parse_volume_stats(data: &str) -> Result<VolumeStats> {
+    let usage = match serde_json::from_str::<VolumeStatsResponse>(data) {
+        std::result::Result::Ok(resp) => resp.usage,
+        Err(_) => parse_text_volume_usage(data)?,
+    };
+
+    let mut stats = VolumeStats::default();
+    for u in usage {
+        match u.unit() {
+            CSI_UNIT_BYTES => {
+                stats.available = u.available;
+                stats.total = u.total;
+                stats.used = u.used;
+            }
+            CSI_UNIT_INODES => {
+                stats.inodes_available = Some(u.available);
+                stats.inodes_total = Some(u.total);
+                stats.inodes_used = Some(u.used);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(stats)
+}
+
 // join_path joins user provided volumepath with kata direct-volume root path
 // the volume_path is base64-encoded and then safely joined to the end of path prefix
-fn join_path(prefix: &str, volume_path: &str) -> Result<PathBuf> {
+fn join_path<P: AsRef<Path>>(prefix: P, volume_path: &str) -> Result<PathBuf> {
     if volume_path.is_empty() {
         return Err(anyhow!("volume path must not be empty"));
     }
@@ -101,17 +1008,109 @@ fn join_path(prefix: &str, volume_path: &str) -> Result<PathBuf> {
     Ok(safe_path::scoped_join(prefix, b64_encoded_path)?)
 }
 
+enum MountInfoFieldType {
+    String,
+    StringArray,
+    StringMap,
+}
+
+impl MountInfoFieldType {
+    fn is_valid(&self, value: &serde_json::Value) -> bool {
+        match self {
+            MountInfoFieldType::String => value.is_string(),
+            MountInfoFieldType::StringArray => value
+                .as_array()
+                .map_or(false, |a| a.iter().all(|v| v.is_string())),
+            MountInfoFieldType::StringMap => value
+                .as_object()
+                .map_or(false, |m| m.values().all(|v| v.is_string())),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            MountInfoFieldType::String => "a string",
+            MountInfoFieldType::StringArray => "an array of strings",
+            MountInfoFieldType::StringMap => "an object with string values",
+        }
+    }
+}
+
+// Fields of the DirectVolumeMountInfo JSON representation.
+const MOUNT_INFO_FIELDS: &[(&str, MountInfoFieldType)] = &[
+    ("volume_type", MountInfoFieldType::String),
+    ("device", MountInfoFieldType::String),
+    ("fs_type", MountInfoFieldType::String),
+    ("metadata", MountInfoFieldType::StringMap),
+    ("options", MountInfoFieldType::StringArray),
+];
+
+// read_mount_info returns the mount info given on the command line, or
+// read from the --mount-info-file.
+fn read_mount_info(args: &DirectVolAddArgs) -> Result<String> {
+    match (&args.mount_info, &args.mount_info_file) {
+        (Some(mount_info), None) => Ok(mount_info.clone()),
+        (None, Some(path)) => fs::read_to_string(path)
+            .with_context(|| format!("failed to read mount info file {}", path)),
+        _ => Err(anyhow!(
+            "exactly one of the mount info or --mount-info-file must be given"
+        )),
+    }
+}
+
+// parse_mount_info validates the mount info (json string) of a direct volume,
+// reporting the first field which is missing or has an unexpected type.
+pub fn parse_mount_info(mount_info: &str) -> Result<DirectVolumeMountInfo> {
+    let value: serde_json::Value = serde_json::from_str(mount_info)
+        .map_err(|e| anyhow!("invalid mount info: not valid JSON: {}", e))?;
+
+    let fields = value
+        .as_object()
+        .ok_or_else(|| anyhow!("invalid mount info: expected a JSON object"))?;
+
+    for (name, field_type) in MOUNT_INFO_FIELDS {
+        match fields.get(*name) {
+            None => return Err(anyhow!("invalid mount info: field `{}` is missing", name)),
+            Some(v) if !field_type.is_valid(v) => {
+                return Err(anyhow!(
+                    "invalid mount info: field `{}` must be {}",
+                    name,
+                    field_type.description()
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let mount_info: DirectVolumeMountInfo = serde_json::from_value(value)?;
+    if mount_info.device.is_empty() {
+        return Err(anyhow!(
+            "invalid mount info: field `device` must not be empty"
+        ));
+    }
+
+    Ok(mount_info)
+}
+
 // add writes the mount info (json string) of a direct volume into a filesystem path known to Kata Containers.
 pub fn add(volume_path: &str, mount_info: &str) -> Result<Option<String>> {
-    let mount_info_dir_path = join_path(KATA_DIRECT_VOLUME_ROOT_PATH, volume_path)?;
-
-    // create directory if missing
-    fs::create_dir_all(&mount_info_dir_path)?;
+    add_volume(
+        Path::new(KATA_DIRECT_VOLUME_ROOT_PATH),
+        volume_path,
+        mount_info,
+    )
+}
 
+fn add_volume(root_path: &Path, volume_path: &str, mount_info: &str) -> Result<Option<String>> {
     // This behavior of deserializing and serializing comes from
     // https://github.com/kata-containers/kata-containers/blob/cd27ad144e1a111cb606015c5c9671431535e644/src/runtime/pkg/direct-volume/utils.go#L57-L79
     // Assuming that this is for the purpose of validating the json schema.
-    let unserialized_mount_info: DirectVolumeMountInfo = serde_json::from_str(mount_info)?;
+    let unserialized_mount_info = parse_mount_info(mount_info)?;
+
+    let mount_info_dir_path = join_path(root_path, volume_path)?;
+
+    // create directory if missing
+    fs::create_dir_all(&mount_info_dir_path)?;
 
     let mount_info_file_path = mount_info_dir_path.join(KATA_MOUNT_INFO_FILE_NAME);
     let serialized_mount_info = serde_json::to_string(&unserialized_mount_info)?;
@@ -142,6 +1141,13 @@ pub fn get_volume_mount_info(volume_path: &str) -> Result<DirectVolumeMountInfo>
 // We expect a direct-assigned volume is associated with only a sandbox at a time.
 pub fn get_sandbox_id_for_volume(volume_path: &str) -> Result<String> {
     let dir_path = join_path(KATA_DIRECT_VOLUME_ROOT_PATH, volume_path)?;
+
+    find_sandbox_id(&dir_path)?.ok_or_else(|| anyhow!("no sandbox found for {}", volume_path))
+}
+
+// The sandbox using a volume is recorded as a file named after the sandbox ID
+// in the volume directory.
+fn find_sandbox_id(dir_path: &Path) -> Result<Option<String>> {
     let paths = fs::read_dir(dir_path)?;
     for path in paths {
         let path = path?;
@@ -159,10 +1165,266 @@ pub fn get_sandbox_id_for_volume(volume_path: &str) -> Result<String> {
             )
         })?;
 
-        return Ok(String::from(file_name));
+        return Ok(Some(String::from(file_name)));
+    }
+
+    Ok(None)
+}
+
+// Get the volume paths and directories of the volumes added to root_path.
+fn list_volume_dirs(root_path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut dirs = Vec::new();
+
+    // nothing has been added yet
+    if !root_path.exists() {
+        return Ok(dirs);
+    }
+
+    for entry in fs::read_dir(root_path)? {
+        let entry = entry?;
+        let dir_path = entry.path();
+        if !dir_path.is_dir() {
+            continue;
+        }
+
+        // the directory name is the base64 encoded volume path
+        let volume_path = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| base64::decode(name).ok())
+            .and_then(|path| String::from_utf8(path).ok())
+        {
+            Some(path) => path,
+            None => continue,
+        };
+
+        dirs.push((volume_path, dir_path));
+    }
+
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+// remove_all removes every volume added to root_path, going on after a
+// failure so that as many volumes as possible are removed.
+fn remove_all(root_path: &Path, dry_run: bool) -> Result<Option<String>> {
+    let dirs = list_volume_dirs(root_path)?;
+
+    let mut failed = 0;
+    for (volume_path, dir_path) in &dirs {
+        if dry_run {
+            println!(
+                "would remove volume {} by deleting {}",
+                volume_path,
+                dir_path.display()
+            );
+            continue;
+        }
+
+        match fs::remove_dir_all(dir_path) {
+            std::result::Result::Ok(()) => println!("removed volume {}", volume_path),
+            Err(e) => {
+                eprintln!("WARNING: volume {}: {}", volume_path, e);
+                failed += 1;
+            }
+        }
     }
 
-    return Err(anyhow!("no sandbox found for {}", volume_path));
+    if failed > 0 {
+        return Err(anyhow!(
+            "failed to remove {} of {} volumes",
+            failed,
+            dirs.len()
+        ));
+    }
+
+    Ok(None)
+}
+
+// VolumeDevices are the major:minor numbers of the device of a volume on the
+// host and in the guest, null while they are not known, e.g. before a
+// sandbox using the volume is started.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct VolumeDevices {
+    pub host_device: Option<String>,
+    pub guest_device: Option<String>,
+}
+
+// A "major:minor" device number, as in the dev files of sysfs.
+fn parse_device_number(data: &str) -> Option<String> {
+    let (major, minor) = data.trim().split_once(':')?;
+    let major: u64 = major.parse().ok()?;
+    let minor: u64 = minor.parse().ok()?;
+
+    Some(format!("{}:{}", major, minor))
+}
+
+fn host_device_number(device: &str) -> Option<String> {
+    let metadata = fs::metadata(device).ok()?;
+    if !metadata.file_type().is_block_device() {
+        return None;
+    }
+
+    Some(format!(
+        "{}:{}",
+        major(metadata.rdev()),
+        minor(metadata.rdev())
+    ))
+}
+
+// The device of the volume in the guest is found from the device info
+// persisted by the runtime, its number being read from the sysfs of the
+// guest through the debug console.
+fn guest_device_number(sandbox_id: &str, device: &str, vport: u32) -> Option<String> {
+    let guest_path = inspect_ops::find_guest_device_path(sandbox_id, device).ok()??;
+    let name = Path::new(&guest_path)
+        .file_name()?
+        .to_string_lossy()
+        .to_string();
+    let dev_path = Path::new(SYS_CLASS_BLOCK_PATH).join(name).join("dev");
+    let command = vec!["cat".to_string(), dev_path.display().to_string()];
+
+    let result =
+        exec_ops::run_guest_command(sandbox_id, vport, None, &command, Some(TIMEOUT)).ok()?;
+    if result.exit_code != 0 {
+        return None;
+    }
+
+    parse_device_number(&result.output)
+}
+
+fn get_volume_devices(
+    mount_info: &DirectVolumeMountInfo,
+    sandbox_id: Option<&str>,
+    vport: u32,
+) -> VolumeDevices {
+    VolumeDevices {
+        host_device: host_device_number(&mount_info.device),
+        guest_device: sandbox_id
+            .and_then(|sandbox_id| guest_device_number(sandbox_id, &mount_info.device, vport)),
+    }
+}
+
+fn format_volume_devices(devices: &VolumeDevices) -> Vec<String> {
+    let devices = [
+        ("host device", &devices.host_device),
+        ("guest device", &devices.guest_device),
+    ];
+
+    devices
+        .iter()
+        .filter_map(|(name, device)| device.as_ref().map(|d| format!("{}: {}", name, d)))
+        .collect()
+}
+
+// DirectVolume describes a volume managed by 'kata-ctl direct-volume'.
+#[derive(Debug, Serialize)]
+pub struct DirectVolume {
+    pub volume_path: String,
+    pub sandbox_id: Option<String>,
+    pub mount_info: DirectVolumeMountInfo,
+    #[serde(flatten)]
+    pub devices: VolumeDevices,
+}
+
+fn list_volumes(root_path: &Path) -> Result<Vec<DirectVolume>> {
+    let mut volumes = Vec::new();
+
+    for (volume_path, dir_path) in list_volume_dirs(root_path)? {
+        let mount_info_file = fs::read_to_string(dir_path.join(KATA_MOUNT_INFO_FILE_NAME))
+            .map_err(|e| anyhow!("failed to read mount info of {}: {}", volume_path, e))?;
+        let mount_info: DirectVolumeMountInfo = serde_json::from_str(&mount_info_file)
+            .map_err(|e| anyhow!("invalid mount info of {}: {}", volume_path, e))?;
+
+        volumes.push(DirectVolume {
+            sandbox_id: find_sandbox_id(&dir_path)?,
+            volume_path,
+            mount_info,
+            devices: VolumeDevices::default(),
+        });
+    }
+
+    Ok(volumes)
+}
+
+fn format_volume(volume: &DirectVolume) -> String {
+    format!(
+        "{} (type: {}, device: {}, fs_type: {}, sandbox: {}, host_device: {}, guest_device: {})",
+        volume.volume_path,
+        volume.mount_info.volume_type,
+        volume.mount_info.device,
+        volume.mount_info.fs_type,
+        volume.sandbox_id.as_deref().unwrap_or("none"),
+        volume.devices.host_device.as_deref().unwrap_or("none"),
+        volume.devices.guest_device.as_deref().unwrap_or("none")
+    )
+}
+
+// list the volumes added to the filesystem path known to Kata Containers.
+// The volumes are read under the lock, which is released before the guest
+// devices are looked up, with vport, through the debug console of each
+// sandbox.
+fn list(
+    format: OutputFormat,
+    vport: Option<u32>,
+    lock: Option<VolumeLock>,
+) -> Result<Option<String>> {
+    let mut volumes = list_volumes(Path::new(KATA_DIRECT_VOLUME_ROOT_PATH))?;
+    drop(lock);
+
+    for volume in &mut volumes {
+        let sandbox_id = volume.sandbox_id.as_deref().filter(|_| vport.is_some());
+        volume.devices =
+            get_volume_devices(&volume.mount_info, sandbox_id, vport.unwrap_or_default());
+    }
+
+    format_volume_list(&volumes, format)
+}
+
+// The CSV output of no volumes still has its header, for the parsers
+// expecting one.
+fn format_volume_list(volumes: &[DirectVolume], format: OutputFormat) -> Result<Option<String>> {
+    if let Some(output) = output::to_structured(format, volumes)? {
+        return Ok(Some(output));
+    }
+
+    if volumes.is_empty() && format != OutputFormat::Csv {
+        return Ok(None);
+    }
+
+    if format == OutputFormat::Table || format == OutputFormat::Csv {
+        let mut table = Table::new(&[
+            "VOLUME",
+            "TYPE",
+            "DEVICE",
+            "FS TYPE",
+            "SANDBOX",
+            "HOST DEVICE",
+            "GUEST DEVICE",
+        ]);
+        let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+        for volume in volumes {
+            table.add_row(vec![
+                volume.volume_path.clone(),
+                volume.mount_info.volume_type.clone(),
+                volume.mount_info.device.clone(),
+                volume.mount_info.fs_type.clone(),
+                or_none(&volume.sandbox_id),
+                or_none(&volume.devices.host_device),
+                or_none(&volume.devices.guest_device),
+            ]);
+        }
+
+        if format == OutputFormat::Csv {
+            return Ok(Some(table.to_csv()));
+        }
+        return Ok(Some(table.to_string().trim_end().to_string()));
+    }
+
+    let lines: Vec<String> = volumes.iter().map(format_volume).collect();
+
+    Ok(Some(lines.join("\n")))
 }
 
 #[cfg(test)]
@@ -260,6 +1522,503 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_format_resize_delta() {
+        assert_eq!(
+            format_resize_delta(Some(1024), 4096),
+            "from 1024 bytes (+3072 bytes)"
+        );
+        assert_eq!(
+            format_resize_delta(Some(4096), 1024),
+            "from 4096 bytes (-3072 bytes)"
+        );
+        assert_eq!(
+            format_resize_delta(Some(1024), 1024),
+            "from 1024 bytes (+0 bytes)"
+        );
+        assert_eq!(format_resize_delta(None, 1024), "from an unknown size");
+    }
+
+    #[test]
+    fn test_poll_until() {
+        let interval = Duration::from_millis(1);
+
+        let mut polls = 0;
+        assert!(poll_until(Duration::from_secs(10), interval, || {
+            polls += 1;
+            polls == 3
+        }));
+        assert_eq!(polls, 3);
+
+        // polled at least once, even without any time left
+        let mut polls = 0;
+        assert!(!poll_until(Duration::from_millis(0), interval, || {
+            polls += 1;
+            false
+        }));
+        assert_eq!(polls, 1);
+
+        let start = Instant::now();
+        assert!(!poll_until(Duration::from_millis(20), interval, || false));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_get_fs_grow_tool() {
+        assert_eq!(get_fs_grow_tool("ext4").unwrap(), FsGrowTool::Resize2fs);
+        assert_eq!(get_fs_grow_tool("ext2").unwrap(), FsGrowTool::Resize2fs);
+        assert_eq!(get_fs_grow_tool("xfs").unwrap(), FsGrowTool::XfsGrowfs);
+        assert!(get_fs_grow_tool("btrfs").is_err());
+        assert!(get_fs_grow_tool("").is_err());
+
+        let mount = GuestMount {
+            source: "/dev/vdb".to_string(),
+            target: "/run/kata-containers/sandbox/storage/vol".to_string(),
+            fstype: "xfs".to_string(),
+            options: "rw".to_string(),
+        };
+        assert_eq!(
+            FsGrowTool::Resize2fs.command(&mount),
+            vec!["resize2fs", "/dev/vdb"]
+        );
+        assert_eq!(
+            FsGrowTool::XfsGrowfs.command(&mount),
+            vec!["xfs_growfs", "/run/kata-containers/sandbox/storage/vol"]
+        );
+    }
+
+    #[test]
+    fn test_volume_mount_candidates() {
+        let mounts = mounts_ops::parse_mounts(
+            "/dev/vda1 / ext4 ro 0 0\n\
+             /dev/vdb /run/kata-containers/sandbox/storage/vol ext4 rw 0 0\n\
+             /dev/vdb /run/kata-containers/abc/rootfs/data ext4 rw 0 0\n\
+             /dev/vdc /mnt/xfs xfs rw 0 0\n\
+             tmpfs /tmp ext4 rw 0 0\n",
+        )
+        .unwrap();
+
+        let targets: Vec<String> = volume_mount_candidates(mounts, "ext4")
+            .into_iter()
+            .map(|mount| mount.target)
+            .collect();
+        assert_eq!(
+            targets,
+            vec!["/", "/run/kata-containers/sandbox/storage/vol"]
+        );
+    }
+
+    #[test]
+    fn test_parse_df_size() {
+        let output = "Filesystem     1024-blocks  Used Available Capacity Mounted on\n\
+                      /dev/vdb           2031440    24   1910176       1% /data\n";
+        assert_eq!(parse_df_size(output).unwrap(), 2031440 * 1024);
+
+        assert!(parse_df_size("").is_err());
+        assert!(parse_df_size("Filesystem 1024-blocks\n/dev/vdb -\n").is_err());
+    }
+
+    #[test]
+    fn test_list_volumes() {
+        let root = tempdir().expect("failed to create tmpdir");
+
+        let volumes = list_volumes(&root.path().join("missing")).unwrap();
+        assert!(volumes.is_empty());
+
+        let mount_info = DirectVolumeMountInfo {
+            volume_type: String::from("block"),
+            device: String::from("/dev/sda"),
+            fs_type: String::from("ext4"),
+            metadata: HashMap::new(),
+            options: vec![],
+        };
+        for (volume_path, sandbox_id) in [("/b/vol", None), ("/a/vol", Some("sandbox1"))] {
+            let dir_path = root.path().join(base64::encode(volume_path));
+            fs::create_dir_all(&dir_path).unwrap();
+            fs::write(
+                dir_path.join(KATA_MOUNT_INFO_FILE_NAME),
+                serde_json::to_string(&mount_info).unwrap(),
+            )
+            .unwrap();
+            if let Some(sandbox_id) = sandbox_id {
+                fs::write(dir_path.join(sandbox_id), "").unwrap();
+            }
+        }
+
+        let volumes = list_volumes(root.path()).unwrap();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0].volume_path, "/a/vol");
+        assert_eq!(volumes[0].sandbox_id.as_deref(), Some("sandbox1"));
+        assert_eq!(volumes[0].mount_info.device, "/dev/sda");
+        assert_eq!(volumes[1].volume_path, "/b/vol");
+        assert_eq!(volumes[1].sandbox_id, None);
+        assert_eq!(
+            format_volume(&volumes[1]),
+            "/b/vol (type: block, device: /dev/sda, fs_type: ext4, sandbox: none, host_device: none, guest_device: none)"
+        );
+
+        let csv = format_volume_list(&volumes, OutputFormat::Csv)
+            .unwrap()
+            .unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        // every record ends with a CRLF, the last one too
+        assert_eq!(csv.matches("\r\n").count(), 3);
+        assert!(csv.ends_with("\r\n"), "{:?}", csv);
+
+        // no volumes
+        let header = "\"VOLUME\",\"TYPE\",\"DEVICE\",\"FS TYPE\",\"SANDBOX\",\"HOST DEVICE\",\"GUEST DEVICE\"\r\n";
+        assert_eq!(
+            format_volume_list(&[], OutputFormat::Csv)
+                .unwrap()
+                .as_deref(),
+            Some(header)
+        );
+        assert_eq!(format_volume_list(&[], OutputFormat::Human).unwrap(), None);
+        assert_eq!(format_volume_list(&[], OutputFormat::Table).unwrap(), None);
+        assert_eq!(
+            format_volume_list(&[], OutputFormat::Json)
+                .unwrap()
+                .as_deref(),
+            Some("[]")
+        );
+    }
+
+    #[test]
+    fn test_volume_devices() {
+        assert_eq!(parse_device_number("253:16\n").as_deref(), Some("253:16"));
+        for data in ["", "253", "253:", "vda:16", "253:16:1"] {
+            assert_eq!(parse_device_number(data), None, "{:?}", data);
+        }
+
+        // only block devices have a device number
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("disk.img");
+        fs::write(&file, "").unwrap();
+        assert_eq!(host_device_number(file.to_str().unwrap()), None);
+        assert_eq!(host_device_number("/nonexistent"), None);
+
+        // the device of a volume not used by a sandbox is only known on the
+        // host
+        let mount_info = DirectVolumeMountInfo {
+            volume_type: String::from("block"),
+            device: file.display().to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_volume_devices(&mount_info, None, 1026),
+            VolumeDevices::default()
+        );
+        assert_eq!(
+            get_volume_devices(&mount_info, Some("no-such-sandbox"), 1026),
+            VolumeDevices::default()
+        );
+
+        let devices = VolumeDevices {
+            host_device: Some("8:16".to_string()),
+            guest_device: None,
+        };
+        assert_eq!(format_volume_devices(&devices), vec!["host device: 8:16"]);
+        let value = serde_json::to_value(&devices).unwrap();
+        assert_eq!(value["host_device"], "8:16");
+        assert!(value["guest_device"].is_null());
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let root = tempdir().expect("failed to create tmpdir");
+
+        remove_all(&root.path().join("missing"), false).unwrap();
+
+        // a volume with an invalid mount info is removed too
+        for volume_path in ["/a/vol", "/b/vol"] {
+            let dir_path = root.path().join(base64::encode(volume_path));
+            fs::create_dir_all(&dir_path).unwrap();
+            fs::write(dir_path.join(KATA_MOUNT_INFO_FILE_NAME), "{").unwrap();
+        }
+        // not a volume
+        fs::write(root.path().join("file"), "").unwrap();
+
+        remove_all(root.path(), true).unwrap();
+        assert_eq!(list_volume_dirs(root.path()).unwrap().len(), 2);
+
+        remove_all(root.path(), false).unwrap();
+        assert!(list_volume_dirs(root.path()).unwrap().is_empty());
+        assert!(root.path().join("file").exists());
+    }
+
+    #[test]
+    fn test_volume_lock() {
+        let root = tempdir().expect("failed to create tmpdir");
+        let root_path = root.path().to_path_buf();
+        let timeout = Duration::from_secs(10);
+        let mount_info = r#"{"volume_type":"block","device":"/dev/sda","fs_type":"ext4","metadata":{},"options":[]}"#;
+
+        // Each add also increments a counter under the lock, which would
+        // lose increments if two adds held it at once.
+        let counter_path = root_path.join("counter");
+        fs::write(&counter_path, "0").unwrap();
+
+        let adds: Vec<_> = (0..8)
+            .map(|i| {
+                let root_path = root_path.clone();
+                let counter_path = counter_path.clone();
+                thread::spawn(move || {
+                    let _lock = VolumeLock::acquire(&root_path, true, timeout).unwrap();
+                    let counter: u32 = fs::read_to_string(&counter_path).unwrap().parse().unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                    add_volume(&root_path, &format!("/vol{}", i), mount_info).unwrap();
+                    fs::write(&counter_path, (counter + 1).to_string()).unwrap();
+                })
+            })
+            .collect();
+        for add in adds {
+            add.join().unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&counter_path).unwrap(), "8");
+        let volumes = list_volumes(&root_path).unwrap();
+        assert_eq!(volumes.len(), 8);
+        assert_eq!(volumes[0].volume_path, "/vol0");
+
+        // The shared locks do not exclude each other, but the exclusive one
+        // times out rather than going on unlocked.
+        let _shared = VolumeLock::acquire(&root_path, false, timeout).unwrap();
+        let _other = VolumeLock::acquire(&root_path, false, Duration::ZERO).unwrap();
+        let start = Instant::now();
+        let e = VolumeLock::acquire(&root_path, true, Duration::from_millis(200)).err();
+        assert!(matches!(e, Some(KataCtlError::Timeout(_))));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_parse_mount_info() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            mount_info: &'a str,
+            result: Result<&'a str>,
+        }
+
+        let tests = &[
+            TestData {
+                mount_info: r#"{"volume_type":"block","device":"/dev/sda","fs_type":"ext4","metadata":{},"options":["noload"]}"#,
+                result: Ok("/dev/sda"),
+            },
+            TestData {
+                mount_info: "{",
+                result: Err(anyhow!(
+                    "invalid mount info: not valid JSON: EOF while parsing an object at line 1 column 1"
+                )),
+            },
+            TestData {
+                mount_info: "[]",
+                result: Err(anyhow!("invalid mount info: expected a JSON object")),
+            },
+            TestData {
+                mount_info: r#"{"volume_type":"block","fs_type":"ext4","metadata":{},"options":[]}"#,
+                result: Err(anyhow!("invalid mount info: field `device` is missing")),
+            },
+            TestData {
+                mount_info: r#"{"volume_type":"block","device":"/dev/sda","fs_type":"ext4","metadata":{},"options":"noload"}"#,
+                result: Err(anyhow!(
+                    "invalid mount info: field `options` must be an array of strings"
+                )),
+            },
+            TestData {
+                mount_info: r#"{"volume_type":"block","device":"/dev/sda","fs_type":"ext4","metadata":{"a":1},"options":[]}"#,
+                result: Err(anyhow!(
+                    "invalid mount info: field `metadata` must be an object with string values"
+                )),
+            },
+            TestData {
+                mount_info: r#"{"volume_type":"block","device":"","fs_type":"ext4","metadata":{},"options":[]}"#,
+                result: Err(anyhow!(
+                    "invalid mount info: field `device` must not be empty"
+                )),
+            },
+        ];
+
+        for (i, d) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, d);
+            let result = parse_mount_info(d.mount_info);
+            let msg = format!("{}, result: {:?}", msg, result);
+            if d.result.is_ok() {
+                assert_eq!(
+                    &result.as_ref().unwrap().device,
+                    d.result.as_ref().unwrap(),
+                    "{}",
+                    msg
+                );
+                continue;
+            }
+            let expected_error = format!("{}", d.result.as_ref().unwrap_err());
+            let actual_error = format!("{}", result.unwrap_err());
+            assert!(actual_error == expected_error, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_watch_stats_output() {
+        let body = "Usage: [available: 10 total: 30 used: 20 unit: BYTES] Volume Condition: message: \"OK\"";
+
+        let mut out = Vec::new();
+        write_stats_sample(&mut out, body).unwrap();
+        write_stats_sample(&mut out, body).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let sample: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(sample["used"], 20);
+        assert!(sample["timestamp"].as_u64().unwrap() > 0);
+
+        let mut args = DirectVolStatsArgs {
+            volume_path: "/vol".to_string(),
+            json: false,
+            human: false,
+            watch: true,
+            interval: 5,
+            vport: 1026,
+            retry: RetryArgs {
+                retries: 0,
+                retry_interval: 0,
+            },
+        };
+        assert_eq!(
+            format_stats_watch_view(&args, body).unwrap(),
+            format!("Every 5s: kata-ctl direct-volume stats /vol\n\n{}\n", body)
+        );
+        args.human = true;
+        assert!(format_stats_watch_view(&args, body)
+            .unwrap()
+            .ends_with("\n\ntotal: 30 B\nused: 20 B\navailable: 10 B\n"));
+    }
+
+    #[test]
+    fn test_volume_exists() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_str().unwrap();
+        assert!(!volume_exists(root_path, "/vol").unwrap());
+
+        let dir = join_path(root_path, "/vol").unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(KATA_MOUNT_INFO_FILE_NAME), "{}").unwrap();
+        assert!(volume_exists(root_path, "/vol").unwrap());
+    }
+
+    #[test]
+    fn test_parse_volume_stats() {
+        // runtime-rs shim output
+        let data = "Usage: [available: 10 total: 30 used: 20 unit: BYTES, available: 5 total: 8 used: 3 unit: INODES] Volume Condition: message: \"OK\"";
+        let stats = parse_volume_stats(data).unwrap();
+        assert_eq!(
+            stats,
+            VolumeStats {
+                available: 10,
+                total: 30,
+                used: 20,
+                inodes_available: Some(5),
+                inodes_total: Some(8),
+                inodes_used: Some(3),
+            }
+        );
+
+        // default values are omitted and inodes are not reported
+        let data = "Usage: [total: 30 used: 30 unit: BYTES] Volume Condition: message: \"OK\"";
+        let stats = parse_volume_stats(data).unwrap();
+        assert_eq!(stats.available, 0);
+        assert_eq!(stats.total, 30);
+        assert_eq!(stats.used, 30);
+        assert_eq!(stats.inodes_total, None);
+
+        // go runtime shim output
+        let data = r#"{"usage":[{"available":1,"total":3,"used":2,"unit":1},{"available":4,"total":6,"used":2,"unit":2}],"volume_condition":{"message":"OK"}}"#;
+        let stats = parse_volume_stats(data).unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.inodes_total, Some(6));
+
+        let json = serde_json::to_string(&parse_volume_stats(r#"{"usage":[]}"#).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"available":0,"total":0,"used":0,"inodes_available":null,"inodes_total":null,"inodes_used":null}"#
+        );
+
+        assert!(parse_volume_stats("garbage").is_err());
+        assert!(parse_volume_stats("Usage: [total: x unit: BYTES]").is_err());
+    }
+
+    #[test]
+    fn test_retry_rpc() {
+        let retry = RetryArgs {
+            retries: 2,
+            retry_interval: 0,
+        };
+        let refused = || anyhow!(io::Error::from(io::ErrorKind::ConnectionRefused));
+
+        // the runtime becomes reachable
+        let mut attempts = 0;
+        let result = executor::block_on(retry_rpc(&retry, || {
+            attempts += 1;
+            let result = if attempts < 3 {
+                Err(refused())
+            } else {
+                Ok(attempts)
+            };
+            async move { result }
+        }));
+        assert_eq!(result.unwrap(), 3);
+
+        // the runtime never becomes reachable
+        let mut attempts = 0;
+        let result: Result<()> = executor::block_on(retry_rpc(&retry, || {
+            attempts += 1;
+            let e = refused();
+            async move { Err(e) }
+        }));
+        assert_eq!(attempts, 3);
+        let e = result.unwrap_err();
+        assert!(format!("{:#}", e).contains("after 3 attempts"), "{:#}", e);
+
+        // a rejected request is not retried
+        let mut attempts = 0;
+        let result: Result<()> = executor::block_on(retry_rpc(&retry, || {
+            attempts += 1;
+            async { Err(anyhow!("failed to resize volume (400)")) }
+        }));
+        assert_eq!(attempts, 1);
+        assert!(!format!("{:#}", result.unwrap_err()).contains("attempts"));
+    }
+
+    #[test]
+    fn test_format_volume_stats() {
+        assert_eq!(format_iec_size(0), "0 B");
+        assert_eq!(format_iec_size(1023), "1023 B");
+        assert_eq!(format_iec_size(1024), "1.00 KiB");
+        assert_eq!(format_iec_size(1536), "1.50 KiB");
+        assert_eq!(format_iec_size(10 * 1024 * 1024 * 1024 + 1), "10.00 GiB");
+        assert_eq!(format_iec_size(u64::MAX), "16.00 EiB");
+
+        let stats = VolumeStats {
+            available: 3 * 1024 * 1024,
+            total: 4 * 1024 * 1024,
+            used: 1024 * 1024,
+            inodes_available: Some(5),
+            inodes_total: Some(8),
+            inodes_used: Some(3),
+        };
+        assert_eq!(
+            format_volume_stats(&stats),
+            "total: 4.00 MiB\nused: 1.00 MiB\navailable: 3.00 MiB\ninodes total: 8\ninodes used: 3\ninodes available: 5"
+        );
+
+        let stats = VolumeStats {
+            total: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_volume_stats(&stats),
+            "total: 100 B\nused: 0 B\navailable: 0 B"
+        );
+    }
+
     #[test]
     #[serial]
     fn test_add_remove() {
@@ -0,0 +1,378 @@
+// Copyright (c) 2023 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Description:
+// Rendering of the command output in the format selected by --output. The
+// JSON and YAML outputs are the serialized results of the commands, while
+// the human and table outputs are rendered by each command, table falling
+// back to human for the commands that do not output a list. The csv output
+// is the table of the commands outputting a list, the others refusing it.
+//
+// Only the human and table outputs are colored, --color being applied once
+// for all the commands.
+
+use crate::args::{ColorChoice, OutputFormat};
+
+use anyhow::Result;
+use serde::Serialize;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(false);
+
+const COLOR_RESET: &str = "\x1b[0m";
+
+const ELLIPSIS: char = '…';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+        }
+    }
+}
+
+// NO_COLOR disables the color when it is set to any non empty value.
+fn color_enabled(choice: ColorChoice, is_tty: bool, no_color: Option<OsString>) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_tty && no_color.map_or(true, |v| v.is_empty()),
+    }
+}
+
+pub fn stdout_is_tty() -> bool {
+    nix::unistd::isatty(libc::STDOUT_FILENO).unwrap_or(false)
+}
+
+pub fn init_color(choice: ColorChoice, format: OutputFormat) {
+    let enabled = !format.is_structured()
+        && format != OutputFormat::Csv
+        && color_enabled(choice, stdout_is_tty(), env::var_os("NO_COLOR"));
+
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Returns text in color when the output is colored, as is otherwise.
+pub fn paint(color: Color, text: &str) -> String {
+    if COLOR_ENABLED.load(Ordering::Relaxed) {
+        format!("{}{}{}", color.code(), text, COLOR_RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+// Colors the added and removed lines of a unified diff.
+pub fn paint_diff(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| {
+            let color = if line.starts_with("+++") || line.starts_with("---") {
+                None
+            } else if line.starts_with('+') {
+                Some(Color::Green)
+            } else if line.starts_with('-') {
+                Some(Color::Red)
+            } else {
+                None
+            };
+
+            match color {
+                Some(color) => match line.strip_suffix('\n') {
+                    Some(line) => format!("{}\n", paint(color, line)),
+                    None => paint(color, line),
+                },
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+// The width of text on the terminal, not counting the color codes.
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c == 'm' {
+                    break;
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+
+    width
+}
+
+// Serialize value as JSON or YAML, returning None for the other formats.
+pub fn to_structured<T: Serialize + ?Sized>(
+    format: OutputFormat,
+    value: &T,
+) -> Result<Option<String>> {
+    let output = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value)?,
+        OutputFormat::Yaml => serde_yaml::to_string(value)?.trim_end().to_string(),
+        OutputFormat::Human | OutputFormat::Table | OutputFormat::Csv => return Ok(None),
+    };
+
+    Ok(Some(output))
+}
+
+// Print value as JSON or YAML, returning false for the other formats.
+pub fn print_structured<T: Serialize + ?Sized>(format: OutputFormat, value: &T) -> Result<bool> {
+    match to_structured(format, value)? {
+        Some(output) => println!("{}", output),
+        None => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// A CSV record as of RFC 4180, every field being quoted.
+pub fn csv_record(fields: &[String]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| format!("\"{}\"", field.replace('"', "\"\"")))
+        .collect();
+
+    format!("{}\r\n", fields.join(","))
+}
+
+// Which end of a cell is replaced with an ellipsis when it is truncated,
+// the start of the paths being the least telling part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truncate {
+    End,
+    Start,
+}
+
+// Shortens text to width characters, the colored cells being left as is.
+fn truncate(text: &str, width: usize, side: Truncate) -> String {
+    let count = text.chars().count();
+    if count <= width || width == 0 || text.contains('\x1b') {
+        return text.to_string();
+    }
+
+    match side {
+        Truncate::End => text
+            .chars()
+            .take(width - 1)
+            .chain(std::iter::once(ELLIPSIS))
+            .collect(),
+        Truncate::Start => std::iter::once(ELLIPSIS)
+            .chain(text.chars().skip(count - width + 1))
+            .collect(),
+    }
+}
+
+// Table lays out rows in columns aligned on their widest cell.
+#[derive(Debug, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    max_widths: Vec<(usize, usize, Truncate)>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Table {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    // Truncates the cells of a column wider than max_width. Only the
+    // output to a terminal should be truncated, the other output being
+    // used by scripts.
+    pub fn set_max_width(&mut self, column: usize, max_width: usize, side: Truncate) {
+        self.max_widths.retain(|(i, _, _)| *i != column);
+        self.max_widths.push((column, max_width, side));
+    }
+
+    fn cell(&self, column: usize, cell: &str) -> String {
+        match self.max_widths.iter().find(|(i, _, _)| *i == column) {
+            Some((_, max_width, side)) => truncate(cell, *max_width, *side),
+            None => cell.to_string(),
+        }
+    }
+
+    // The header and rows as CSV, the cells not being truncated.
+    pub fn to_csv(&self) -> String {
+        std::iter::once(&self.headers)
+            .chain(self.rows.iter())
+            .map(|row| csv_record(row))
+            .collect()
+    }
+}
+
+// Print table as CSV with the csv output, aligned otherwise.
+pub fn print_table(format: OutputFormat, table: &Table) {
+    if format == OutputFormat::Csv {
+        print!("{}", table.to_csv());
+    } else {
+        print!("{}", table);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, cell)| self.cell(i, cell))
+                    .collect()
+            })
+            .collect();
+
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| display_width(h)).collect();
+        for row in &rows {
+            for (i, cell) in row.iter().enumerate() {
+                let width = display_width(cell);
+                match widths.get_mut(i) {
+                    Some(w) => *w = (*w).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+
+        for row in std::iter::once(&self.headers).chain(rows.iter()) {
+            let line: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    let padding = widths[i] - display_width(cell);
+                    format!("{}{}", cell, " ".repeat(padding))
+                })
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["NAME", "STATE"]);
+        assert_eq!(table.to_string(), "NAME  STATE\n");
+
+        table.add_row(vec!["sandbox-1".to_string(), "running".to_string()]);
+        table.add_row(vec!["sb2".to_string(), "dead".to_string()]);
+        assert_eq!(
+            table.to_string(),
+            "NAME       STATE\nsandbox-1  running\nsb2        dead\n"
+        );
+
+        // rows longer than the headers get their own columns
+        let mut table = Table::new(&["A"]);
+        table.add_row(vec!["x".to_string(), "y".to_string()]);
+        assert_eq!(table.to_string(), "A\nx  y\n");
+
+        // the color codes take no room
+        let mut table = Table::new(&["NAME", "STATE"]);
+        table.add_row(vec![
+            "sb1".to_string(),
+            "\x1b[32mrunning\x1b[0m".to_string(),
+        ]);
+        table.add_row(vec!["\x1b[31msb2\x1b[0m".to_string(), "dead".to_string()]);
+        assert_eq!(
+            table.to_string(),
+            "NAME  STATE\nsb1   \x1b[32mrunning\x1b[0m\n\x1b[31msb2\x1b[0m   dead\n"
+        );
+    }
+
+    #[test]
+    fn test_table_max_width() {
+        let mut table = Table::new(&["ID", "KERNEL"]);
+        table.add_row(vec![
+            "0123456789".to_string(),
+            "/opt/kata/share/kata-containers/vmlinux.container".to_string(),
+        ]);
+        table.add_row(vec!["ab".to_string(), "/vmlinux".to_string()]);
+        table.set_max_width(0, 6, Truncate::End);
+        table.set_max_width(1, 20, Truncate::Start);
+        assert_eq!(
+            table.to_string(),
+            "ID      KERNEL\n01234…  …s/vmlinux.container\nab      /vmlinux\n"
+        );
+
+        // the colored cells are not truncated
+        assert_eq!(
+            truncate("\x1b[32mrunning\x1b[0m", 3, Truncate::End),
+            "\x1b[32mrunning\x1b[0m"
+        );
+        assert_eq!(truncate("abc", 3, Truncate::End), "abc");
+        assert_eq!(truncate("abcd", 3, Truncate::Start), "…cd");
+    }
+
+    #[test]
+    fn test_table_csv() {
+        let mut table = Table::new(&["NAME", "ABOUT"]);
+        assert_eq!(table.to_csv(), "\"NAME\",\"ABOUT\"\r\n");
+
+        table.add_row(vec![
+            "ps".to_string(),
+            "List the \"sandboxes\", all".to_string(),
+        ]);
+        table.add_row(vec!["env".to_string(), "Show\nthe env".to_string()]);
+        table.set_max_width(1, 4, Truncate::End);
+        assert_eq!(
+            table.to_csv(),
+            "\"NAME\",\"ABOUT\"\r\n\"ps\",\"List the \"\"sandboxes\"\", all\"\r\n\"env\",\"Show\nthe env\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_color_enabled() {
+        let tests = &[
+            (ColorChoice::Always, false, Some("1"), true),
+            (ColorChoice::Never, true, None, false),
+            (ColorChoice::Auto, true, None, true),
+            (ColorChoice::Auto, false, None, false),
+            (ColorChoice::Auto, true, Some("1"), false),
+            // an empty NO_COLOR is ignored
+            (ColorChoice::Auto, true, Some(""), true),
+        ];
+
+        for (i, (choice, is_tty, no_color, enabled)) in tests.iter().enumerate() {
+            assert_eq!(
+                color_enabled(*choice, *is_tty, no_color.map(OsString::from)),
+                *enabled,
+                "test[{}]",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("pass"), 4);
+        assert_eq!(display_width("\x1b[31mfail\x1b[0m"), 4);
+    }
+}
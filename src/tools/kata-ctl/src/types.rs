@@ -4,17 +4,31 @@
 //
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
 // Builtin check command handler type.
 pub type BuiltinCmdFp = fn(args: &str) -> Result<()>;
 
 // CheckType encodes the name of each check provided by kata-ctl.
-#[derive(Debug, strum_macros::Display, EnumString, PartialEq)]
+#[derive(Debug, Clone, Copy, strum_macros::Display, EnumString, PartialEq)]
 pub enum CheckType {
     Cpu,
     Network,
     KernelModules,
+    Cgroups,
+    KernelParams,
+    Hugepages,
+}
+
+// CpuFeature is a CPU flag examined by the CPU check, shown by
+// 'kata-ctl check all --cpu-features'. The flags that are not required
+// improve the performance of the guests.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CpuFeature {
+    pub name: String,
+    pub present: bool,
+    pub required: bool,
 }
 
 // PermissionType is used to show whether a check needs to run with elevated (super-user)